@@ -0,0 +1,152 @@
+//! Latency of shortening, redirecting and reading stats through the router,
+//! on a file database seeded with 1M visits. Run with `cargo bench`: each
+//! step prints its median, p95 and slowest run, and the bench fails when a
+//! median is over its budget.
+
+use axum::{
+    body::Body,
+    http::{header, Request, StatusCode},
+    Router,
+};
+use chrono::Utc;
+use short_url_service::{build_app, build_state, config::Config};
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tower::ServiceExt;
+
+/// Visits of each seeded link: one busy link, one typical one.
+const LINKS: &[(&str, i64)] = &[("busy", 900_000), ("quiet", 100_000)];
+const RUNS: usize = 50;
+
+struct Step {
+    name: String,
+    budget: Duration,
+    request: Box<dyn Fn(usize) -> Request<Body>>,
+    expect: StatusCode,
+}
+
+#[tokio::main]
+async fn main() {
+    let path = std::env::temp_dir().join(format!("short-url-bench-{}.db", std::process::id()));
+    let env: HashMap<&str, String> = [
+        ("DATABASE_URL", format!("sqlite:{}?mode=rwc", path.display())),
+        ("BASE_URL", "http://sho.rt".into()),
+        ("VISITOR_HASH_SALT", "bench-salt".into()),
+        ("PURGE_EXPIRED", "false".into()),
+        ("ALLOWED_PRIVATE_DESTINATIONS", "example.com".into()),
+    ]
+    .into();
+    let config = Config::from_lookup(|name| env.get(name).cloned()).expect("bench config is valid");
+    let (state, visit_worker) = build_state(Arc::new(config)).await.expect("state builds");
+    let app = build_app(state.clone());
+
+    let started = Instant::now();
+    for (code, visits) in LINKS {
+        let created = send(&app, shorten(&format!(r#"{{"url": "https://example.com/{0}", "custom_code": "{0}", "stats_visibility": "public"}}"#, code))).await;
+        assert_eq!(created, StatusCode::CREATED);
+        seed_visits(&state.pool, code, *visits).await;
+    }
+    println!("seeded {} visits in {:?}", LINKS.iter().map(|(_, n)| n).sum::<i64>(), started.elapsed());
+
+    let steps = [
+        Step {
+            name: "shorten".into(),
+            budget: Duration::from_millis(10),
+            request: Box::new(|run| shorten(&format!(r#"{{"url": "https://example.com/page/{}"}}"#, run))),
+            expect: StatusCode::CREATED,
+        },
+        Step {
+            name: "redirect".into(),
+            budget: Duration::from_millis(5),
+            request: Box::new(|_| Request::get("/quiet").body(Body::empty()).unwrap()),
+            expect: StatusCode::TEMPORARY_REDIRECT,
+        },
+        Step {
+            name: "stats, 100k visits".into(),
+            budget: Duration::from_millis(500),
+            request: Box::new(|_| Request::get("/stats/quiet").body(Body::empty()).unwrap()),
+            expect: StatusCode::OK,
+        },
+        Step {
+            name: "stats, 900k visits".into(),
+            budget: Duration::from_secs(3),
+            request: Box::new(|_| Request::get("/stats/busy").body(Body::empty()).unwrap()),
+            expect: StatusCode::OK,
+        },
+    ];
+    let mut over_budget = Vec::new();
+    for step in &steps {
+        let mut times = Vec::with_capacity(RUNS);
+        for run in 0..RUNS {
+            let start = Instant::now();
+            let status = send(&app, (step.request)(run)).await;
+            times.push(start.elapsed());
+            assert_eq!(status, step.expect, "{}", step.name);
+        }
+        times.sort_unstable();
+        let median = times[RUNS / 2];
+        println!(
+            "{:<20} median {:>10.2?}  p95 {:>10.2?}  max {:>10.2?}  (budget {:?})",
+            step.name,
+            median,
+            times[RUNS * 95 / 100],
+            times[RUNS - 1],
+            step.budget
+        );
+        if median > step.budget {
+            over_budget.push(step.name.as_str());
+        }
+    }
+
+    visit_worker.shutdown().await;
+    state.pool.close().await;
+    for suffix in ["", "-wal", "-shm"] {
+        let _ = std::fs::remove_file(format!("{}{}", path.display(), suffix));
+    }
+    assert!(over_budget.is_empty(), "over budget: {}", over_budget.join(", "));
+}
+
+fn shorten(body: &str) -> Request<Body> {
+    Request::post("/shorten")
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+async fn send(app: &Router, request: Request<Body>) -> StatusCode {
+    app.clone().oneshot(request).await.expect("router is infallible").status()
+}
+
+// Visits spread over the last 30 days from 5000 visitors, a twentieth of them
+// bots, written in one statement and counted on the link like recorded ones.
+async fn seed_visits(pool: &short_url_service::db::DbPool, code: &str, visits: i64) {
+    let now = Utc::now();
+    let mut tx = pool.begin().await.unwrap();
+    sqlx::query(
+        "WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < ?)
+         INSERT INTO visits (url_id, ip_address, user_agent, referrer, visitor_hash, source, platform, visited_at, is_bot)
+         SELECT ?, '198.51.100.' || (i % 250),
+                CASE WHEN i % 20 = 0 THEN 'Twitterbot/1.0' ELSE 'Mozilla/5.0 (X11; Linux x86_64; rv:120.0) Gecko/20100101 Firefox/120.0' END,
+                CASE i % 4 WHEN 0 THEN NULL WHEN 1 THEN 'https://news.ycombinator.com/' WHEN 2 THEN 'https://www.reddit.com/r/rust/' ELSE 'https://www.google.com/' END,
+                printf('%016x', i % 5000), 'redirect', 'other',
+                strftime('%Y-%m-%dT%H:%M:%S+00:00', ?, '-' || (i * 7 % 43200) || ' minutes'), i % 20 = 0
+         FROM n",
+    )
+    .bind(visits)
+    .bind(code)
+    .bind(now)
+    .execute(&mut *tx)
+    .await
+    .unwrap();
+    sqlx::query("UPDATE urls SET total_visits = ?, bot_visits = ? WHERE id = ?")
+        .bind(visits)
+        .bind(visits / 20)
+        .bind(code)
+        .execute(&mut *tx)
+        .await
+        .unwrap();
+    tx.commit().await.unwrap();
+}
@@ -0,0 +1,7 @@
+-- Normalized form of original_url (lowercase scheme/host, no trailing slash),
+-- used to find existing links when deduplicating.
+ALTER TABLE urls ADD COLUMN normalized_url TEXT;
+
+UPDATE urls SET normalized_url = RTRIM(original_url, '/') WHERE normalized_url IS NULL;
+
+CREATE INDEX IF NOT EXISTS idx_urls_normalized_url ON urls(normalized_url);
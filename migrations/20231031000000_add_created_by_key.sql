@@ -0,0 +1 @@
+ALTER TABLE urls ADD COLUMN created_by_key TEXT;
@@ -0,0 +1 @@
+ALTER TABLE visits ADD COLUMN referrer TEXT;
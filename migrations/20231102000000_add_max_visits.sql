@@ -0,0 +1,3 @@
+-- visit_count is claimed atomically by redirects of links that have a max_visits limit.
+ALTER TABLE urls ADD COLUMN max_visits INTEGER;
+ALTER TABLE urls ADD COLUMN visit_count INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,3 @@
+-- Salted hash of IP + user agent. Existing rows can't be hashed in SQL and
+-- stay NULL, which COUNT(DISTINCT visitor_hash) ignores.
+ALTER TABLE visits ADD COLUMN visitor_hash TEXT;
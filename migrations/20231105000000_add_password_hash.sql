@@ -0,0 +1,2 @@
+-- Argon2 PHC string for password-protected links; never returned by the API.
+ALTER TABLE urls ADD COLUMN password_hash TEXT;
@@ -0,0 +1,9 @@
+-- Every stats query filters visits by url_id. visited_at serves the recent
+-- visits list and the daily window in order; the other two make the referrer
+-- breakdown and the unique-visitor count covering index scans.
+CREATE INDEX IF NOT EXISTS idx_visits_url_id_visited_at ON visits(url_id, visited_at);
+CREATE INDEX IF NOT EXISTS idx_visits_url_id_referrer ON visits(url_id, referrer);
+CREATE INDEX IF NOT EXISTS idx_visits_url_id_visitor_hash ON visits(url_id, visitor_hash);
+
+-- Used by the expired-link purge job.
+CREATE INDEX IF NOT EXISTS idx_urls_expires_at ON urls(expires_at) WHERE expires_at IS NOT NULL;
@@ -0,0 +1,2 @@
+-- Disabled links keep their stats and QR code but stop redirecting.
+ALTER TABLE urls ADD COLUMN is_active BOOLEAN NOT NULL DEFAULT 1;
@@ -0,0 +1,2 @@
+-- 'redirect' for visits counted while redirecting, 'beacon' for hits reported via /hit/{code}.
+ALTER TABLE visits ADD COLUMN source TEXT NOT NULL DEFAULT 'redirect';
@@ -0,0 +1,2 @@
+-- Set when expires_at came from MAX_LINK_TTL_DAYS rather than from the client.
+ALTER TABLE urls ADD COLUMN expiry_defaulted BOOLEAN NOT NULL DEFAULT 0;
@@ -0,0 +1,9 @@
+-- Free-form labels for grouping links, e.g. by campaign.
+CREATE TABLE IF NOT EXISTS url_tags (
+    url_id TEXT NOT NULL,
+    tag TEXT NOT NULL,
+    PRIMARY KEY (url_id, tag),
+    FOREIGN KEY(url_id) REFERENCES urls(id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_url_tags_tag ON url_tags(tag);
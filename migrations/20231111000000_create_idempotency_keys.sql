@@ -0,0 +1,13 @@
+-- Idempotency-Key values seen on POST /shorten. status and response stay NULL
+-- while the first request is still being processed.
+CREATE TABLE IF NOT EXISTS idempotency_keys (
+    scope TEXT NOT NULL,
+    key TEXT NOT NULL,
+    fingerprint TEXT NOT NULL,
+    status INTEGER,
+    response TEXT,
+    created_at DATETIME NOT NULL,
+    PRIMARY KEY (scope, key)
+);
+
+CREATE INDEX IF NOT EXISTS idx_idempotency_keys_created_at ON idempotency_keys(created_at);
@@ -0,0 +1,2 @@
+-- Whether query parameters on the short link are passed on to the destination.
+ALTER TABLE urls ADD COLUMN forward_query BOOLEAN NOT NULL DEFAULT 1;
@@ -0,0 +1,4 @@
+-- Title and description scraped from the destination when fetch_metadata is requested.
+ALTER TABLE urls ADD COLUMN title TEXT;
+ALTER TABLE urls ADD COLUMN description TEXT;
+ALTER TABLE urls ADD COLUMN metadata_fetched_at DATETIME;
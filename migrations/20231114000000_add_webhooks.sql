@@ -0,0 +1,16 @@
+-- Per-link visit webhooks. The secret signs deliveries and is only returned at creation.
+ALTER TABLE urls ADD COLUMN webhook_url TEXT;
+ALTER TABLE urls ADD COLUMN webhook_secret TEXT;
+
+-- Dead letters: deliveries that still failed after every retry.
+CREATE TABLE IF NOT EXISTS webhook_failures (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    url_id TEXT NOT NULL,
+    webhook_url TEXT NOT NULL,
+    payload TEXT NOT NULL,
+    attempts INTEGER NOT NULL,
+    last_error TEXT NOT NULL,
+    failed_at DATETIME NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_webhook_failures_url_id ON webhook_failures(url_id);
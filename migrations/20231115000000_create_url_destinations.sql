@@ -0,0 +1,14 @@
+-- Weighted destinations for links that split traffic (A/B rotation).
+CREATE TABLE IF NOT EXISTS url_destinations (
+    url_id TEXT NOT NULL,
+    position INTEGER NOT NULL,
+    url TEXT NOT NULL,
+    weight INTEGER NOT NULL,
+    PRIMARY KEY (url_id, position)
+);
+
+-- 'random' or 'sticky' for rotating links, NULL for single-destination ones
+ALTER TABLE urls ADD COLUMN rotation TEXT;
+
+-- Destination each visit was sent to, for rotating links
+ALTER TABLE visits ADD COLUMN destination TEXT;
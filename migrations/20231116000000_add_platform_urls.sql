@@ -0,0 +1,5 @@
+-- App-store destinations for iOS and Android visitors, and the platform each visit came from.
+ALTER TABLE urls ADD COLUMN ios_url TEXT;
+ALTER TABLE urls ADD COLUMN android_url TEXT;
+
+ALTER TABLE visits ADD COLUMN platform TEXT;
@@ -0,0 +1,2 @@
+-- Visits counted without a stored row (ANALYTICS_MODE=off or Do Not Track).
+ALTER TABLE urls ADD COLUMN untracked_visits INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,4 @@
+-- Lets the retention task find visits that still carry personal data by age
+-- without scanning rows it already scrubbed.
+CREATE INDEX IF NOT EXISTS idx_visits_unscrubbed_visited_at ON visits(visited_at)
+    WHERE ip_address IS NOT NULL OR user_agent IS NOT NULL;
@@ -0,0 +1,4 @@
+-- Stats of private links need the token returned at creation. Existing links
+-- stay public so their stats URLs keep working.
+ALTER TABLE urls ADD COLUMN stats_visibility TEXT NOT NULL DEFAULT 'public';
+ALTER TABLE urls ADD COLUMN stats_token_hash TEXT;
@@ -0,0 +1,5 @@
+-- Running total of visits per link, recorded rows plus untracked ones, kept
+-- in step with each visit batch so stats don't count rows. `visit_count`
+-- stays the counter of redirects claimed against `max_visits`.
+ALTER TABLE urls ADD COLUMN total_visits INTEGER NOT NULL DEFAULT 0;
+UPDATE urls SET total_visits = untracked_visits + (SELECT COUNT(*) FROM visits WHERE visits.url_id = urls.id);
@@ -0,0 +1,4 @@
+-- The admin summary ranks links by visits in a recent window and counts
+-- recently created links; both become range scans instead of full scans.
+CREATE INDEX IF NOT EXISTS idx_visits_visited_at_url_id ON visits(visited_at, url_id);
+CREATE INDEX IF NOT EXISTS idx_urls_created_at ON urls(created_at);
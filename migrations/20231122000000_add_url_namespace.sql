@@ -0,0 +1,5 @@
+-- Namespace of the API key a link was created with, for multi-tenant
+-- deployments. Custom codes of namespaced keys are prefixed with it.
+ALTER TABLE urls ADD COLUMN namespace TEXT;
+CREATE INDEX IF NOT EXISTS idx_urls_namespace ON urls(namespace);
+CREATE INDEX IF NOT EXISTS idx_urls_created_by_key ON urls(created_by_key);
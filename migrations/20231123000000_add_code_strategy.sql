@@ -0,0 +1,3 @@
+-- How each link's code was chosen: random, hash or custom. Links created
+-- before this was recorded stay NULL.
+ALTER TABLE urls ADD COLUMN code_strategy TEXT;
@@ -0,0 +1,14 @@
+-- Record of administrative changes, written in the same transaction as the
+-- change itself. `actor` is a fingerprint of the API key used, never the key,
+-- and the JSON columns describe the change without personal data.
+CREATE TABLE IF NOT EXISTS audit_log (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    created_at DATETIME NOT NULL,
+    actor TEXT NOT NULL,
+    action TEXT NOT NULL,
+    target_code TEXT,
+    before_json TEXT,
+    after_json TEXT
+);
+
+CREATE INDEX IF NOT EXISTS idx_audit_log_target_code ON audit_log(target_code, id);
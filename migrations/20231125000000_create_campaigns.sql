@@ -0,0 +1,10 @@
+-- Named groups of links whose stats are read together. Deleting a campaign
+-- detaches its links rather than deleting them.
+CREATE TABLE IF NOT EXISTS campaigns (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    name TEXT NOT NULL,
+    created_at DATETIME NOT NULL
+);
+
+ALTER TABLE urls ADD COLUMN campaign_id INTEGER REFERENCES campaigns(id) ON DELETE SET NULL;
+CREATE INDEX IF NOT EXISTS idx_urls_campaign_id ON urls(campaign_id);
@@ -0,0 +1,6 @@
+-- Results of the background dead-link check (LINK_CHECK_INTERVAL_SECS).
+-- `health` is unknown until a link's destination has been checked.
+ALTER TABLE urls ADD COLUMN last_checked_at DATETIME;
+ALTER TABLE urls ADD COLUMN last_status INTEGER;
+ALTER TABLE urls ADD COLUMN health TEXT NOT NULL DEFAULT 'unknown';
+CREATE INDEX IF NOT EXISTS idx_urls_health ON urls(health);
@@ -0,0 +1,3 @@
+-- Visitor country (ISO 3166-1 alpha-2), resolved from GEOIP_DB_PATH when the
+-- visit is written. Null without a database or for unknown addresses.
+ALTER TABLE visits ADD COLUMN country TEXT;
@@ -0,0 +1,6 @@
+-- Visits from crawlers and link preview fetchers, classified by user agent
+-- when recorded. `bot_visits` counts them among the link's `total_visits`
+-- (untracked ones included), so stats can leave them out without counting
+-- rows. Visits from before this migration are not classified.
+ALTER TABLE visits ADD COLUMN is_bot BOOLEAN NOT NULL DEFAULT 0;
+ALTER TABLE urls ADD COLUMN bot_visits INTEGER NOT NULL DEFAULT 0;
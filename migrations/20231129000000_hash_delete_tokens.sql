@@ -0,0 +1,4 @@
+-- Delete tokens are kept as their SHA-256 in hex, like stats tokens.
+-- SQLite can't hash, so existing tokens are moved over by the service at
+-- startup, which then clears `delete_token`.
+ALTER TABLE urls ADD COLUMN delete_token_hash TEXT;
@@ -0,0 +1,10 @@
+-- The stats breakdowns filter on is_bot, so the per-link indexes carry it
+-- (and the columns each breakdown groups by) to stay covering index scans.
+DROP INDEX IF EXISTS idx_visits_url_id_visited_at;
+DROP INDEX IF EXISTS idx_visits_url_id_referrer;
+DROP INDEX IF EXISTS idx_visits_url_id_visitor_hash;
+CREATE INDEX idx_visits_url_id_visited_at ON visits(url_id, visited_at, is_bot, source);
+CREATE INDEX idx_visits_url_id_referrer ON visits(url_id, referrer, is_bot);
+CREATE INDEX idx_visits_url_id_visitor_hash ON visits(url_id, visitor_hash, is_bot);
+CREATE INDEX idx_visits_url_id_user_agent ON visits(url_id, user_agent, is_bot);
+CREATE INDEX idx_visits_url_id_platform ON visits(url_id, platform, country, is_bot);
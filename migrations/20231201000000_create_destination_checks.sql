@@ -0,0 +1,10 @@
+-- Last dead-link check of each destination of a link: its URL, app-store
+-- URLs and rotation destinations. The link's `health` is broken when any is.
+CREATE TABLE IF NOT EXISTS destination_checks (
+    url_id TEXT NOT NULL,
+    url TEXT NOT NULL,
+    health TEXT NOT NULL,
+    last_status INTEGER,
+    checked_at DATETIME NOT NULL,
+    PRIMARY KEY (url_id, url)
+);
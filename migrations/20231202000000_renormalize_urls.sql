@@ -0,0 +1,5 @@
+-- The normalized_url backfill only trimmed trailing slashes, so links from
+-- before it don't match new ones that differ in the case of the scheme or
+-- host. The values it left are cleared here and recomputed by the service at
+-- startup with the same normalization new links get.
+UPDATE urls SET normalized_url = NULL WHERE normalized_url = RTRIM(original_url, '/');
@@ -0,0 +1,407 @@
+use crate::{
+    audit::{self, Entry},
+    auth::AdminKey,
+    db::{self, Traced},
+    error::AppError,
+    models::{
+        AdminUrlItem, AuditEntry, AuditLogResponse, AuditParams, ErrorResponse, LinkHealth, ListUrlsParams, ResetVisitsResponse,
+        SummaryParams, SummaryResponse, TopLink, UrlListResponse,
+    },
+    state::AppState,
+    tags::load_tags_for,
+};
+use axum::{
+    extract::{Path, Query, State},
+    Json,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Duration, Utc};
+use sqlx::{QueryBuilder, Sqlite};
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 500;
+const DEFAULT_SUMMARY_WINDOW: &str = "7d";
+const MAX_SUMMARY_WINDOW_DAYS: i64 = 365;
+const TOP_LINKS: i64 = 10;
+
+// Escape LIKE wildcards so user input only matches literally.
+fn like_pattern(raw: &str) -> String {
+    let escaped = raw
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    format!("%{}%", escaped)
+}
+
+fn search_term(params: &ListUrlsParams) -> Option<&str> {
+    params.q.as_deref().map(str::trim).filter(|s| !s.is_empty())
+}
+
+// The fields `q` and `code_prefix` matched on a listed link.
+fn matched_fields(item: &AdminUrlItem, params: &ListUrlsParams) -> Vec<String> {
+    let mut fields = Vec::new();
+    if let Some(term) = search_term(params) {
+        let term = term.to_ascii_lowercase();
+        if item.original_url.to_ascii_lowercase().contains(&term) {
+            fields.push("original_url".to_string());
+        }
+        if item.id.to_ascii_lowercase().contains(&term) {
+            fields.push("id".to_string());
+        }
+    }
+    if params.code_prefix.as_deref().is_some_and(|s| !s.is_empty()) && !fields.iter().any(|f| f == "id") {
+        fields.push("id".to_string());
+    }
+    fields
+}
+
+// Append the WHERE clause shared by the count and page queries.
+fn push_url_filters(query: &mut QueryBuilder<'_, Sqlite>, params: &ListUrlsParams) {
+    query.push(" WHERE 1 = 1");
+    if let Some(after) = params.created_after {
+        query.push(" AND u.created_at >= ").push_bind(after);
+    }
+    if let Some(before) = params.created_before {
+        query.push(" AND u.created_at < ").push_bind(before);
+    }
+    match params.expired {
+        Some(true) => {
+            query
+                .push(" AND u.expires_at IS NOT NULL AND u.expires_at <= ")
+                .push_bind(Utc::now());
+        }
+        Some(false) => {
+            query
+                .push(" AND (u.expires_at IS NULL OR u.expires_at > ")
+                .push_bind(Utc::now())
+                .push(")");
+        }
+        None => {}
+    }
+    if let Some(needle) = params.url_contains.as_deref().filter(|s| !s.is_empty()) {
+        query
+            .push(" AND u.original_url LIKE ")
+            .push_bind(like_pattern(needle))
+            .push(" ESCAPE '\\'");
+    }
+    // LIKE ignores ASCII case in SQLite. A substring can't use an index, so
+    // this scans the links (the page size still caps what is returned).
+    if let Some(term) = search_term(params) {
+        let pattern = like_pattern(term);
+        query
+            .push(" AND (u.original_url LIKE ")
+            .push_bind(pattern.clone())
+            .push(" ESCAPE '\\' OR u.id LIKE ")
+            .push_bind(pattern)
+            .push(" ESCAPE '\\')");
+    }
+    // A range rather than LIKE, so the primary key index is used and case
+    // matters as it does for codes
+    if let Some(prefix) = params.code_prefix.as_deref().filter(|s| !s.is_empty()) {
+        query
+            .push(" AND u.id >= ")
+            .push_bind(prefix.to_string())
+            .push(" AND u.id < ")
+            .push_bind(format!("{}\u{10FFFF}", prefix));
+    }
+    if let Some(tag) = params.tag.as_deref().filter(|s| !s.is_empty()) {
+        query
+            .push(" AND EXISTS (SELECT 1 FROM url_tags t WHERE t.url_id = u.id AND t.tag = ")
+            .push_bind(tag.to_ascii_lowercase())
+            .push(")");
+    }
+    if let Some(key) = params.owner_key.as_deref().filter(|s| !s.is_empty()) {
+        query.push(" AND u.created_by_key = ").push_bind(key.trim().to_string());
+    }
+    if let Some(namespace) = params.namespace.as_deref().filter(|s| !s.is_empty()) {
+        query.push(" AND u.namespace = ").push_bind(namespace.to_string());
+    }
+    if let Some(health) = params.health.as_deref().filter(|s| !s.is_empty()) {
+        query.push(" AND u.health = ").push_bind(health.to_string());
+    }
+}
+
+// A listing cursor is the `(created_at, id)` of the last link on a page.
+fn encode_cursor(item: &AdminUrlItem) -> String {
+    let tuple = serde_json::to_vec(&(item.created_at, &item.id)).expect("cursor tuples serialize");
+    URL_SAFE_NO_PAD.encode(tuple)
+}
+
+fn decode_cursor(raw: &str) -> Result<(DateTime<Utc>, String), AppError> {
+    URL_SAFE_NO_PAD
+        .decode(raw.trim())
+        .ok()
+        .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+        .ok_or_else(|| AppError::BadRequest("cursor is not a next_cursor from this listing".into()))
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/urls",
+    params(ListUrlsParams),
+    responses(
+        (status = 200, description = "Page of links", body = UrlListResponse),
+        (status = 400, description = "Malformed cursor or health, or cursor combined with offset", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid admin API key", body = ErrorResponse)
+    ),
+    security(
+        ("api_key" = [])
+    )
+)]
+#[tracing::instrument(skip_all)]
+pub async fn list_urls(
+    State(state): State<AppState>,
+    _admin: AdminKey,
+    Query(params): Query<ListUrlsParams>,
+) -> Result<Json<UrlListResponse>, AppError> {
+    let pool = &state.pool;
+    let limit = params.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+    let offset = params.offset.unwrap_or(0).max(0);
+    let cursor = params.cursor.as_deref().map(decode_cursor).transpose()?;
+    if let Some(health) = params.health.as_deref().filter(|s| !s.is_empty())
+        && LinkHealth::parse(health).is_none()
+    {
+        return Err(AppError::BadRequest("health must be unknown, healthy or broken".into()));
+    }
+    if cursor.is_some() && params.offset.is_some() {
+        return Err(AppError::BadRequest("cursor and offset can't be combined".into()));
+    }
+
+    let mut count_query = QueryBuilder::new("SELECT COUNT(*) FROM urls u");
+    push_url_filters(&mut count_query, &params);
+    let total: i64 = count_query.build_query_scalar().fetch_one(pool).traced_one("count_urls", None).await?;
+
+    let mut page_query = QueryBuilder::new(
+        "SELECT u.id, u.original_url, u.created_at, u.expires_at, u.redirect_type, u.max_visits, u.title, u.description, \
+         u.total_visits, u.namespace, u.health FROM urls u",
+    );
+    push_url_filters(&mut page_query, &params);
+    // Rows after the cursor in the listing order: links created since the
+    // previous page sort before it and can't shift the rest
+    if let Some((created_at, id)) = &cursor {
+        page_query
+            .push(" AND (u.created_at < ")
+            .push_bind(*created_at)
+            .push(" OR (u.created_at = ")
+            .push_bind(*created_at)
+            .push(" AND u.id > ")
+            .push_bind(id.clone())
+            .push("))");
+    }
+    // One extra row tells whether there is a next page
+    page_query.push(" ORDER BY u.created_at DESC, u.id LIMIT ").push_bind(limit + 1);
+    if cursor.is_none() {
+        page_query.push(" OFFSET ").push_bind(offset);
+    }
+    let mut items: Vec<AdminUrlItem> = page_query.build_query_as().fetch_all(pool).traced("list_urls", None).await?;
+    let has_more = items.len() as i64 > limit;
+    items.truncate(limit as usize);
+
+    let ids: Vec<String> = items.iter().map(|item| item.id.clone()).collect();
+    let mut tags = load_tags_for(pool, &ids).await?;
+    for item in &mut items {
+        item.tags = tags.remove(&item.id).unwrap_or_default();
+        item.matched = matched_fields(item, &params);
+    }
+
+    let next_offset = (has_more && cursor.is_none()).then_some(offset + limit);
+    let next_cursor = items.last().filter(|_| has_more).map(encode_cursor);
+
+    Ok(Json(UrlListResponse {
+        items,
+        total,
+        limit,
+        offset,
+        next_offset,
+        next_cursor,
+    }))
+}
+
+// Parse a summary window such as `24h` or `7d`.
+fn parse_window(raw: &str) -> Option<Duration> {
+    let raw = raw.trim();
+    let (value, hours_per_unit) = match raw.chars().last()? {
+        'h' => (&raw[..raw.len() - 1], 1),
+        'd' => (&raw[..raw.len() - 1], 24),
+        _ => return None,
+    };
+    let hours = value.parse::<i64>().ok()?.checked_mul(hours_per_unit)?;
+    (1..=MAX_SUMMARY_WINDOW_DAYS * 24)
+        .contains(&hours)
+        .then(|| Duration::hours(hours))
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/summary",
+    params(SummaryParams),
+    responses(
+        (status = 200, description = "Service-wide totals and the top links", body = SummaryResponse),
+        (status = 400, description = "Malformed window", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid admin API key", body = ErrorResponse)
+    ),
+    security(
+        ("api_key" = [])
+    )
+)]
+#[tracing::instrument(skip_all)]
+pub async fn summary(
+    State(state): State<AppState>,
+    _admin: AdminKey,
+    Query(params): Query<SummaryParams>,
+) -> Result<Json<SummaryResponse>, AppError> {
+    let pool = &state.pool;
+    let window = params.window.unwrap_or_else(|| DEFAULT_SUMMARY_WINDOW.to_string());
+    let window_length = parse_window(&window).ok_or_else(|| {
+        AppError::BadRequest(format!(
+            "window must be <n>h or <n>d, at most {}d, e.g. 24h or 7d",
+            MAX_SUMMARY_WINDOW_DAYS
+        ))
+    })?;
+
+    let now = Utc::now();
+    let (day_ago, week_ago) = (now - Duration::hours(24), now - Duration::days(7));
+    // Visit totals come from the per-link counters rather than counting rows
+    let totals = sqlx::query!(
+        r#"SELECT COUNT(*) AS "total_links!: i64", COALESCE(SUM(CASE WHEN ? THEN total_visits ELSE total_visits - bot_visits END), 0) AS "total_visits!: i64", COALESCE(SUM(created_at >= ?), 0) AS "links_created_24h!: i64", COALESCE(SUM(created_at >= ?), 0) AS "links_created_7d!: i64" FROM urls"#,
+        params.include_bots,
+        day_ago,
+        week_ago
+    )
+    .fetch_one(pool)
+    .traced_one("summary_totals", None)
+    .await?;
+
+    let since = now - window_length;
+    let top_links = sqlx::query_as!(
+        TopLink,
+        r#"SELECT v.url_id AS short_code, u.original_url, COUNT(*) AS "visits!: i64" FROM visits v JOIN urls u ON u.id = v.url_id WHERE v.visited_at >= ? AND (? OR NOT v.is_bot) GROUP BY v.url_id ORDER BY COUNT(*) DESC, v.url_id LIMIT ?"#,
+        since,
+        params.include_bots,
+        TOP_LINKS
+    )
+    .fetch_all(pool)
+    .traced("top_links", None)
+    .await?;
+
+    Ok(Json(SummaryResponse {
+        total_links: totals.total_links,
+        total_visits: totals.total_visits,
+        links_created_24h: totals.links_created_24h,
+        links_created_7d: totals.links_created_7d,
+        window,
+        top_links,
+    }))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/admin/urls/{code}/visits",
+    params(
+        ("code" = String, Path, description = "Short code")
+    ),
+    responses(
+        (status = 200, description = "Visits deleted and counters reset; the link itself is kept", body = ResetVisitsResponse),
+        (status = 401, description = "Missing or invalid admin API key", body = ErrorResponse),
+        (status = 404, description = "URL not found", body = ErrorResponse)
+    ),
+    security(
+        ("api_key" = [])
+    )
+)]
+#[tracing::instrument(skip_all, fields(code = %code))]
+pub async fn reset_visits(
+    State(state): State<AppState>,
+    AdminKey(actor): AdminKey,
+    Path(code): Path<String>,
+) -> Result<Json<ResetVisitsResponse>, AppError> {
+    let pool = &state.pool;
+    if db::find_url(pool, &code).await?.is_none() {
+        return Err(AppError::UrlNotFound);
+    }
+
+    // Rows and counters go together so stats never show one without the
+    // other. `visit_count` is reset too, which gives a `max_visits` link its
+    // full allowance again.
+    let mut tx = pool.begin().await?;
+    let before = audit::snapshot(&mut tx, &code).await?;
+    let deleted = sqlx::query!("DELETE FROM visits WHERE url_id = ?", code)
+        .execute(&mut *tx)
+        .traced("delete_visits", Some(code.as_str()))
+        .await?
+        .rows_affected();
+    sqlx::query!(
+        "UPDATE urls SET total_visits = 0, untracked_visits = 0, bot_visits = 0, visit_count = 0 WHERE id = ?",
+        code
+    )
+    .execute(&mut *tx)
+    .traced("reset_counters", Some(code.as_str()))
+    .await?;
+    let after = audit::snapshot(&mut tx, &code).await?;
+    audit::record(
+        &mut tx,
+        Entry {
+            actor: &actor,
+            action: "reset_visits",
+            code: Some(&code),
+            before,
+            after,
+        },
+    )
+    .await?;
+    tx.commit().await?;
+
+    // Cached records carry the old visit_count
+    if let Some(cache) = &state.redirect_cache {
+        cache.invalidate(&code);
+    }
+    tracing::info!(deleted, "visits reset");
+
+    Ok(Json(ResetVisitsResponse { deleted }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/audit",
+    params(AuditParams),
+    responses(
+        (status = 200, description = "Audit entries, newest first", body = AuditLogResponse),
+        (status = 401, description = "Missing or invalid admin API key", body = ErrorResponse)
+    ),
+    security(
+        ("api_key" = [])
+    )
+)]
+#[tracing::instrument(skip_all)]
+pub async fn audit_log(
+    State(state): State<AppState>,
+    _admin: AdminKey,
+    Query(params): Query<AuditParams>,
+) -> Result<Json<AuditLogResponse>, AppError> {
+    let limit = params.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+    let code = params.code.as_deref().filter(|code| !code.is_empty());
+    let rows = sqlx::query!(
+        r#"SELECT id AS "id!", created_at AS "created_at: DateTime<Utc>", actor, action, target_code, before_json, after_json FROM audit_log WHERE ?1 IS NULL OR target_code = ?1 ORDER BY id DESC LIMIT ?2"#,
+        code,
+        limit
+    )
+    .fetch_all(&state.pool)
+    .traced("list_audit", code)
+    .await?;
+
+    // Stored by `audit::record`, so the JSON columns always parse
+    let parse = |json: Option<String>| json.and_then(|json| serde_json::from_str(&json).ok());
+    let items = rows
+        .into_iter()
+        .map(|row| AuditEntry {
+            id: row.id,
+            created_at: row.created_at,
+            actor: row.actor,
+            action: row.action,
+            code: row.target_code,
+            before: parse(row.before_json),
+            after: parse(row.after_json),
+        })
+        .collect();
+    Ok(Json(AuditLogResponse { items }))
+}
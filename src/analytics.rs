@@ -0,0 +1,182 @@
+use crate::{
+    db::{DbPool, Traced},
+    models::UrlRecord,
+    state::AppState,
+    telemetry,
+    visits::NewVisit,
+};
+use rand::Rng;
+use sqlx::{QueryBuilder, Sqlite};
+use std::{collections::HashMap, future::Future, time::Duration};
+
+/// Tries per write, including the first, while SQLite reports the database
+/// as busy or locked.
+const WRITE_ATTEMPTS: u32 = 4;
+/// First retry delay; each further retry doubles it, plus up to the same
+/// again in jitter so writers that collided don't collide again.
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(25);
+
+// Primary SQLite result codes; extended codes such as SQLITE_BUSY_SNAPSHOT
+// carry them in the low byte.
+const SQLITE_BUSY: i32 = 5;
+const SQLITE_LOCKED: i32 = 6;
+
+/// Queues the visit for the background recorder and the link's webhook, if any.
+pub fn record_visit(state: &AppState, url: &UrlRecord, visit: NewVisit) {
+    state.webhooks.visit(url, &visit);
+    state.visits.record(visit);
+}
+
+/// Whether the error is lock contention that's worth retrying.
+pub fn is_busy(err: &sqlx::Error) -> bool {
+    let sqlx::Error::Database(db_err) = err else {
+        return false;
+    };
+    db_err
+        .code()
+        .and_then(|code| code.parse::<i32>().ok())
+        .is_some_and(|code| matches!(code & 0xff, SQLITE_BUSY | SQLITE_LOCKED))
+}
+
+// Run `write`, retrying with jittered exponential backoff while the
+// database is busy. Any other error is returned straight away.
+async fn with_busy_retry<T, F, Fut>(mut write: F) -> Result<T, sqlx::Error>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, sqlx::Error>>,
+{
+    let mut attempt = 1;
+    loop {
+        match write().await {
+            Err(e) if is_busy(&e) && attempt < WRITE_ATTEMPTS => {
+                let delay = RETRY_BASE_DELAY * 2u32.pow(attempt - 1);
+                let jitter = rand::thread_rng().gen_range(Duration::ZERO..=delay);
+                tracing::debug!(attempt, "database busy, retrying visit write in {:?}", delay + jitter);
+                tokio::time::sleep(delay + jitter).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Writes a batch of visits: count-only ones bump their link's
+/// `untracked_visits`, the rest become `visits` rows in one multi-row insert.
+/// Either way the link's `total_visits` moves in the same statement or
+/// transaction, so it always matches what was stored.
+///
+/// Visits of links deleted since they were queued are skipped without
+/// failing the rest of the batch. Busy and locked errors are retried; visits
+/// that still can't be written are logged and left out. Both kinds are
+/// counted in `visits_dropped_total` and the total is returned. The batch is
+/// empty afterwards.
+pub async fn write_visits(pool: &DbPool, pending: &mut Vec<NewVisit>) -> u64 {
+    let mut dropped = 0;
+    let mut deleted = 0;
+
+    // Visits and the bots among them, per link
+    let mut untracked: HashMap<&str, (i64, i64)> = HashMap::new();
+    for visit in pending.iter().filter(|visit| !visit.detailed) {
+        let (count, bots) = untracked.entry(&visit.url_id).or_default();
+        *count += 1;
+        *bots += i64::from(visit.is_bot);
+    }
+    for (url_id, (count, bots)) in untracked {
+        let counted = with_busy_retry(|| {
+            sqlx::query!(
+                "UPDATE urls SET untracked_visits = untracked_visits + ?, total_visits = total_visits + ?, bot_visits = bot_visits + ? WHERE id = ?",
+                count,
+                count,
+                bots,
+                url_id
+            )
+            .execute(pool)
+            .traced("count_untracked_visits", Some(url_id))
+        })
+        .await;
+        match counted {
+            Ok(done) if done.rows_affected() == 0 => deleted += count as u64,
+            Ok(_) => {}
+            Err(e) => {
+                tracing::warn!(code = url_id, count, "failed to count untracked visits: {}", e);
+                dropped += count as u64;
+            }
+        }
+    }
+
+    pending.retain(|visit| visit.detailed);
+    if !pending.is_empty() {
+        let mut recorded: HashMap<&str, (i64, i64)> = HashMap::new();
+        for visit in pending.iter() {
+            let (count, bots) = recorded.entry(&visit.url_id).or_default();
+            *count += 1;
+            *bots += i64::from(visit.is_bot);
+        }
+        let (recorded, batch) = (&recorded, &*pending);
+        let inserted = with_busy_retry(|| async move {
+            let mut tx = pool.begin().await?;
+            // Rows whose link is gone would fail the foreign key, and with it
+            // the whole statement, so they're filtered out here
+            let mut query: QueryBuilder<Sqlite> = QueryBuilder::new(
+                "INSERT INTO visits (url_id, ip_address, user_agent, referrer, visitor_hash, source, destination, platform, visited_at, country, is_bot) SELECT * FROM (",
+            );
+            query.push_values(batch.iter(), |mut row, visit| {
+                row.push_bind(&visit.url_id)
+                    .push_bind(&visit.ip_address)
+                    .push_bind(&visit.user_agent)
+                    .push_bind(&visit.referrer)
+                    .push_bind(&visit.visitor_hash)
+                    .push_bind(visit.source.as_str())
+                    .push_bind(&visit.destination)
+                    .push_bind(visit.platform.as_str())
+                    .push_bind(visit.visited_at)
+                    .push_bind(&visit.country)
+                    .push_bind(visit.is_bot);
+            });
+            query.push(") AS batch WHERE EXISTS (SELECT 1 FROM urls WHERE urls.id = batch.column1)");
+            query.build().execute(&mut *tx).traced("insert_visits", None).await?;
+            let mut skipped = 0;
+            for (url_id, (count, bots)) in recorded {
+                let done = sqlx::query!(
+                    "UPDATE urls SET total_visits = total_visits + ?, bot_visits = bot_visits + ? WHERE id = ?",
+                    count,
+                    bots,
+                    url_id
+                )
+                .execute(&mut *tx)
+                .traced("add_total_visits", Some(url_id))
+                .await?;
+                if done.rows_affected() == 0 {
+                    skipped += *count as u64;
+                }
+            }
+            tx.commit().await?;
+            Ok(skipped)
+        })
+        .await;
+
+        match inserted {
+            Ok(skipped) => {
+                deleted += skipped;
+                tracing::debug!(count = pending.len() as u64 - skipped, "flushed visits");
+            }
+            Err(e) => {
+                let mut codes: Vec<&str> = pending.iter().map(|visit| visit.url_id.as_str()).collect();
+                codes.sort_unstable();
+                codes.dedup();
+                tracing::warn!(count = pending.len(), codes = codes.join(","), "failed to record visits: {}", e);
+                dropped += pending.len() as u64;
+            }
+        }
+    }
+    pending.clear();
+
+    if dropped > 0 {
+        metrics::counter!(telemetry::VISITS_DROPPED_TOTAL, "reason" => "write_failed").increment(dropped);
+    }
+    if deleted > 0 {
+        tracing::debug!(count = deleted, "skipped visits of deleted links");
+        metrics::counter!(telemetry::VISITS_DROPPED_TOTAL, "reason" => "link_deleted").increment(deleted);
+    }
+    dropped + deleted
+}
@@ -0,0 +1,64 @@
+//! Audit trail of link changes and administrative actions. Entries are
+//! written on the transaction making the change, and a failed write fails
+//! the change, so nothing is changed without its entry.
+
+use crate::{backup, db::Traced, models::LinkExport};
+use chrono::Utc;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use sqlx::SqliteConnection;
+
+/// Actor for requests that proved ownership with a link's delete token.
+pub const DELETE_TOKEN_ACTOR: &str = "delete_token";
+/// Actor for links created without an API key.
+pub const ANONYMOUS_ACTOR: &str = "anonymous";
+
+/// One `audit_log` row, minus the id and timestamp.
+pub struct Entry<'a> {
+    /// Who made the change: `actor_for_key`, `DELETE_TOKEN_ACTOR` or
+    /// `ANONYMOUS_ACTOR`
+    pub actor: &'a str,
+    pub action: &'a str,
+    pub code: Option<&'a str>,
+    pub before: Option<Value>,
+    pub after: Option<Value>,
+}
+
+/// The name an API key is logged under: a short hash, so the audit log can
+/// tell keys apart without storing them.
+pub fn actor_for_key(key: &str) -> String {
+    let digest = Sha256::digest(key.as_bytes());
+    let hex: String = digest[..6].iter().map(|b| format!("{:02x}", b)).collect();
+    format!("key:{}", hex)
+}
+
+/// `actor_for_key` for the key a request was made with, if any.
+pub fn actor(api_key: Option<&str>) -> String {
+    api_key.map_or_else(|| ANONYMOUS_ACTOR.to_string(), actor_for_key)
+}
+
+/// The link as `/admin/export` would write it, minus its secrets, for an
+/// entry's `before` or `after`.
+pub async fn snapshot(conn: &mut SqliteConnection, code: &str) -> Result<Option<Value>, sqlx::Error> {
+    let link = backup::snapshot(conn, code).await?.map(LinkExport::without_secrets);
+    Ok(link.map(|link| serde_json::to_value(link).expect("link exports serialize")))
+}
+
+pub async fn record(conn: &mut SqliteConnection, entry: Entry<'_>) -> Result<(), sqlx::Error> {
+    let now = Utc::now();
+    let before = entry.before.map(|value| value.to_string());
+    let after = entry.after.map(|value| value.to_string());
+    sqlx::query!(
+        "INSERT INTO audit_log (created_at, actor, action, target_code, before_json, after_json) VALUES (?, ?, ?, ?, ?, ?)",
+        now,
+        entry.actor,
+        entry.action,
+        entry.code,
+        before,
+        after
+    )
+    .execute(conn)
+    .traced("record_audit", entry.code)
+    .await?;
+    Ok(())
+}
@@ -0,0 +1,193 @@
+use crate::{
+    audit,
+    error::AppError,
+    models::{StatsVisibility, UrlRecord},
+    state::AppState,
+};
+use axum::{
+    extract::FromRequestParts,
+    http::{header, request::Parts, HeaderMap},
+};
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+
+pub const API_KEY_HEADER: &str = "x-api-key";
+pub const STATS_TOKEN_HEADER: &str = "x-stats-token";
+/// Joins a key's namespace and a custom code, e.g. `team-a~promo`. Custom
+/// codes can't contain it, so namespaced codes never collide with plain ones.
+pub const NAMESPACE_SEPARATOR: char = '~';
+const MAX_NAMESPACE_LENGTH: usize = 20;
+
+/// An `API_KEYS` entry: the key and, for `namespace:key` entries, the
+/// namespace its custom codes are scoped to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApiKeyConfig {
+    pub key: String,
+    pub namespace: Option<String>,
+}
+
+/// Extractor guarding mutating endpoints. When `API_KEYS` is configured the
+/// request must carry one of them in `X-Api-Key`; otherwise every request is
+/// accepted anonymously.
+pub struct ApiKey(pub Option<String>);
+
+impl FromRequestParts<AppState> for ApiKey {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        let Some(keys) = &state.api_keys else {
+            return Ok(ApiKey(None));
+        };
+
+        let provided = parts
+            .headers
+            .get(API_KEY_HEADER)
+            .and_then(|h| h.to_str().ok())
+            .map(str::trim);
+
+        match provided {
+            Some(key) if keys.contains_key(key) => Ok(ApiKey(Some(key.to_string()))),
+            _ => Err(AppError::Unauthorized),
+        }
+    }
+}
+
+/// Extractor guarding `/admin` endpoints: the request must carry one of the
+/// `ADMIN_API_KEYS` in `X-Api-Key`. With no admin keys configured every admin
+/// request is rejected. Carries the key's `audit::actor_for_key` name.
+pub struct AdminKey(pub String);
+
+impl FromRequestParts<AppState> for AdminKey {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        let provided = parts
+            .headers
+            .get(API_KEY_HEADER)
+            .and_then(|h| h.to_str().ok())
+            .map(str::trim);
+
+        match provided {
+            Some(key) if state.admin_keys.contains(key) => Ok(AdminKey(audit::actor_for_key(key))),
+            _ => Err(AppError::Unauthorized),
+        }
+    }
+}
+
+pub fn parse_api_keys(raw: &str) -> Vec<String> {
+    raw.split(',')
+        .map(str::trim)
+        .filter(|k| !k.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Parses `API_KEYS`: comma-separated keys, each optionally preceded by a
+/// namespace as `namespace:key`. Namespaces use the custom code charset.
+pub fn parse_namespaced_api_keys(raw: &str) -> Option<Vec<ApiKeyConfig>> {
+    parse_api_keys(raw)
+        .into_iter()
+        .map(|entry| match entry.split_once(':') {
+            Some((namespace, key)) => {
+                let (namespace, key) = (namespace.trim(), key.trim());
+                let valid = !key.is_empty()
+                    && (1..=MAX_NAMESPACE_LENGTH).contains(&namespace.len())
+                    && namespace.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+                valid.then(|| ApiKeyConfig {
+                    key: key.to_string(),
+                    namespace: Some(namespace.to_string()),
+                })
+            }
+            None => Some(ApiKeyConfig { key: entry, namespace: None }),
+        })
+        .collect()
+}
+
+/// The short code a custom code is stored under for a key's namespace.
+pub fn namespaced_code(namespace: Option<&str>, custom: &str) -> String {
+    match namespace {
+        Some(namespace) => format!("{}{}{}", namespace, NAMESPACE_SEPARATOR, custom),
+        None => custom.to_string(),
+    }
+}
+
+// The configured API key sent in `X-Api-Key`, if any.
+fn provided_api_key<'h>(state: &AppState, headers: &'h HeaderMap) -> Option<&'h str> {
+    let key = headers
+        .get(API_KEY_HEADER)
+        .and_then(|h| h.to_str().ok())
+        .map(str::trim)?;
+    state.api_keys.as_ref()?.contains_key(key).then_some(key)
+}
+
+/// Checks that the caller owns `url`: either the link's delete token (as
+/// `Authorization: Bearer <token>` or the `token` query param) or the
+/// configured API key the link was created with. Returns the caller's name
+/// for the audit log.
+pub fn verify_owner(
+    state: &AppState,
+    url: &UrlRecord,
+    headers: &HeaderMap,
+    token_param: Option<String>,
+) -> Result<String, AppError> {
+    let token = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .map(|s| s.strip_prefix("Bearer ").unwrap_or(s).trim().to_string())
+        .or(token_param);
+    if let (Some(expected), Some(token)) = (&url.delete_token_hash, &token)
+        && token_matches(expected, token)
+    {
+        return Ok(audit::DELETE_TOKEN_ACTOR.to_string());
+    }
+
+    if let (Some(key), Some(creator)) = (provided_api_key(state, headers), &url.created_by_key)
+        && key == creator
+    {
+        return Ok(audit::actor_for_key(key));
+    }
+
+    Err(AppError::Forbidden)
+}
+
+/// Delete and stats tokens are stored as their SHA-256 in hex. They're
+/// random, so an unsalted fast hash is enough to keep a database dump from
+/// revealing them.
+pub fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+// Compares in constant time, so response timing doesn't leak how much of the
+// stored hash a guess shares.
+fn token_matches(expected_hash: &str, token: &str) -> bool {
+    expected_hash.as_bytes().ct_eq(hash_token(token).as_bytes()).into()
+}
+
+/// Checks that the caller may read `url`'s stats: public links are open,
+/// private ones need their stats token in `X-Stats-Token` or the
+/// `stats_token` query param, or the API key that created them.
+pub fn verify_stats_access(
+    state: &AppState,
+    url: &UrlRecord,
+    headers: &HeaderMap,
+    token_param: Option<&str>,
+) -> Result<(), AppError> {
+    if url.stats_visibility() == StatsVisibility::Public {
+        return Ok(());
+    }
+    if let (Some(key), Some(creator)) = (provided_api_key(state, headers), &url.created_by_key)
+        && key == creator
+    {
+        return Ok(());
+    }
+
+    let token = headers
+        .get(STATS_TOKEN_HEADER)
+        .and_then(|h| h.to_str().ok())
+        .or(token_param)
+        .map(str::trim);
+    match (&url.stats_token_hash, token) {
+        (Some(expected), Some(token)) if token_matches(expected, token) => Ok(()),
+        _ => Err(AppError::Forbidden),
+    }
+}
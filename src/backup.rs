@@ -0,0 +1,495 @@
+use crate::{
+    audit::{self, Entry},
+    auth::AdminKey,
+    db::{self, DbPool, Traced},
+    error::AppError,
+    models::{
+        CodeStrategy, ErrorResponse, ImportConflict, ImportLineResult, ImportParams, ImportResponse, ImportStatus, LinkExport,
+        RedirectType, Rotation, StatsVisibility,
+    },
+    rotation,
+    state::AppState,
+    tags,
+    utils::normalize_url,
+};
+use axum::{
+    body::{Body, Bytes},
+    extract::{
+        rejection::{QueryRejection, StringRejection},
+        Query, State,
+    },
+    http::{header, HeaderValue},
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{DateTime, Utc};
+use futures_util::{stream, StreamExt};
+use sqlx::{FromRow, QueryBuilder, Sqlite, SqliteConnection};
+use std::collections::HashSet;
+use tokio::sync::mpsc;
+use tracing::{Instrument, Span};
+
+// Lines are written out in chunks of roughly this many bytes
+const CHUNK_SIZE: usize = 64 * 1024;
+// Imported lines written per transaction
+const IMPORT_BATCH: usize = 500;
+/// Largest `/admin/import` body accepted.
+pub const MAX_IMPORT_BYTES: usize = 64 * 1024 * 1024;
+const MAX_IMPORTED_CODE_LENGTH: usize = 64;
+
+#[derive(FromRow)]
+struct ExportRow {
+    id: String,
+    original_url: String,
+    created_at: DateTime<Utc>,
+    expires_at: Option<DateTime<Utc>>,
+    starts_at: Option<DateTime<Utc>>,
+    redirect_type: i64,
+    max_visits: Option<i64>,
+    is_active: bool,
+    forward_query: bool,
+    stats_visibility: String,
+    namespace: Option<String>,
+    code_strategy: Option<String>,
+    // JSON array, sorted
+    tags: String,
+    visit_count: i64,
+    untracked_visits: i64,
+    total_visits: i64,
+    bot_visits: i64,
+    ios_url: Option<String>,
+    android_url: Option<String>,
+    rotation: Option<String>,
+    // JSON array of {url, weight}, in position order
+    destinations: String,
+    webhook_url: Option<String>,
+    webhook_secret: Option<String>,
+    campaign_id: Option<i64>,
+    created_by_key: Option<String>,
+    password_hash: Option<String>,
+    delete_token_hash: Option<String>,
+    stats_token_hash: Option<String>,
+}
+
+impl From<ExportRow> for LinkExport {
+    fn from(row: ExportRow) -> Self {
+        LinkExport {
+            short_code: row.id,
+            original_url: row.original_url,
+            created_at: row.created_at,
+            expires_at: row.expires_at,
+            starts_at: row.starts_at,
+            redirect_type: u16::try_from(row.redirect_type)
+                .ok()
+                .and_then(|code| RedirectType::try_from(code).ok())
+                .unwrap_or_default(),
+            max_visits: row.max_visits,
+            active: row.is_active,
+            forward_query: row.forward_query,
+            stats_visibility: StatsVisibility::parse(&row.stats_visibility).unwrap_or(StatsVisibility::Private),
+            namespace: row.namespace,
+            code_strategy: row.code_strategy.as_deref().and_then(CodeStrategy::parse),
+            tags: serde_json::from_str(&row.tags).unwrap_or_default(),
+            visit_count: row.visit_count,
+            untracked_visits: row.untracked_visits,
+            total_visits: row.total_visits,
+            bot_visits: row.bot_visits,
+            ios_url: row.ios_url,
+            android_url: row.android_url,
+            rotation: row.rotation.as_deref().and_then(Rotation::parse),
+            destinations: serde_json::from_str(&row.destinations).unwrap_or_default(),
+            webhook_url: row.webhook_url,
+            webhook_secret: row.webhook_secret,
+            campaign_id: row.campaign_id,
+            created_by_key: row.created_by_key,
+            password_hash: row.password_hash,
+            delete_token_hash: row.delete_token_hash,
+            stats_token_hash: row.stats_token_hash,
+        }
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/export",
+    responses(
+        (status = 200, description = "Every link, oldest first, one `LinkExport` JSON object per line", content_type = "application/x-ndjson"),
+        (status = 401, description = "Missing or invalid admin API key", body = ErrorResponse)
+    ),
+    security(
+        ("api_key" = [])
+    )
+)]
+#[tracing::instrument(skip_all)]
+pub async fn export_links(State(state): State<AppState>, _admin: AdminKey) -> Response {
+    // Same shape as the visit CSV export: the query runs in its own task and
+    // hands over finished chunks
+    let (tx, rx) = mpsc::channel::<Result<Bytes, sqlx::Error>>(2);
+    tokio::spawn(stream_links(state.pool.clone(), tx).instrument(db::query_span("export_links", None)));
+
+    let body = Body::from_stream(stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|chunk| (chunk, rx))
+    }));
+
+    (
+        [
+            (header::CONTENT_TYPE, HeaderValue::from_static("application/x-ndjson")),
+            (header::CONTENT_DISPOSITION, HeaderValue::from_static("attachment; filename=\"links.jsonl\"")),
+        ],
+        body,
+    )
+        .into_response()
+}
+
+const EXPORT_SELECT: &str = "SELECT u.id, u.original_url, u.created_at, u.expires_at, u.starts_at, u.redirect_type, \
+     u.max_visits, u.is_active, u.forward_query, u.stats_visibility, u.namespace, u.code_strategy, \
+     (SELECT json_group_array(tag) FROM (SELECT tag FROM url_tags t WHERE t.url_id = u.id ORDER BY tag)) AS tags, \
+     u.visit_count, u.untracked_visits, u.total_visits, u.bot_visits, u.ios_url, u.android_url, u.rotation, \
+     (SELECT json_group_array(json_object('url', url, 'weight', weight)) FROM (SELECT url, weight FROM url_destinations d WHERE d.url_id = u.id ORDER BY position)) AS destinations, \
+     u.webhook_url, u.webhook_secret, u.campaign_id, u.created_by_key, u.password_hash, u.delete_token_hash, u.stats_token_hash \
+     FROM urls u";
+
+/// One link in its export form, as the audit log records it.
+pub async fn snapshot(conn: &mut SqliteConnection, code: &str) -> Result<Option<LinkExport>, sqlx::Error> {
+    let row = sqlx::query_as::<_, ExportRow>(&format!("{} WHERE u.id = ?", EXPORT_SELECT))
+        .bind(code)
+        .fetch_optional(conn)
+        .traced("snapshot_link", Some(code))
+        .await?;
+    Ok(row.map(LinkExport::from))
+}
+
+/// Sends every link to `tx` as `LinkExport` JSON lines, oldest first, in
+/// chunks. A failed query is sent last and ends the export.
+pub async fn stream_links(pool: DbPool, tx: mpsc::Sender<Result<Bytes, sqlx::Error>>) {
+    let sql = format!("{} ORDER BY u.created_at, u.id", EXPORT_SELECT);
+    let mut rows = sqlx::query_as::<_, ExportRow>(&sql).fetch(&pool);
+    let mut chunk = String::with_capacity(CHUNK_SIZE + 1024);
+    let mut count = 0;
+
+    while let Some(row) = rows.next().await {
+        match row {
+            Ok(row) => {
+                count += 1;
+                let line = serde_json::to_string(&LinkExport::from(row)).expect("link exports serialize");
+                chunk.push_str(&line);
+                chunk.push('\n');
+            }
+            Err(e) => {
+                tracing::error!("link export failed: {:?}", e);
+                db::record_failure(&Span::current(), &e);
+                // Aborts the response instead of silently truncating the dump
+                let _ = tx.send(Err(e)).await;
+                return;
+            }
+        }
+        if chunk.len() >= CHUNK_SIZE {
+            let full = std::mem::replace(&mut chunk, String::with_capacity(CHUNK_SIZE + 1024));
+            if tx.send(Ok(Bytes::from(full))).await.is_err() {
+                return;
+            }
+        }
+    }
+
+    db::record_rows(&Span::current(), count);
+    if !chunk.is_empty() {
+        let _ = tx.send(Ok(Bytes::from(chunk))).await;
+    }
+}
+
+// A parsed and validated import line.
+struct ImportLine {
+    line: usize,
+    link: LinkExport,
+    normalized_url: String,
+}
+
+fn parse_line(line: usize, raw: &str) -> Result<ImportLine, ImportLineResult> {
+    let invalid = |short_code: Option<String>, error: String| ImportLineResult {
+        line,
+        short_code,
+        status: ImportStatus::Invalid,
+        error: Some(error),
+    };
+    let link: LinkExport = serde_json::from_str(raw).map_err(|e| invalid(None, e.to_string()))?;
+
+    let code_chars = |c: char| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '~');
+    if link.short_code.is_empty()
+        || link.short_code.len() > MAX_IMPORTED_CODE_LENGTH
+        || !link.short_code.chars().all(code_chars)
+    {
+        return Err(invalid(
+            Some(link.short_code),
+            format!(
+                "short_code must be 1 to {} letters, digits, '-', '_' or '~'",
+                MAX_IMPORTED_CODE_LENGTH
+            ),
+        ));
+    }
+    let Some(normalized_url) = normalize_url(&link.original_url) else {
+        return Err(invalid(Some(link.short_code), "original_url is not a valid URL".into()));
+    };
+    if link.visit_count < 0 || link.untracked_visits < 0 || link.total_visits < 0 || link.bot_visits < 0 {
+        return Err(invalid(Some(link.short_code), "visit counters can't be negative".into()));
+    }
+    if link.rotation.is_some() == link.destinations.is_empty() {
+        return Err(invalid(Some(link.short_code), "rotation and destinations must be set together".into()));
+    }
+    let urls = link
+        .destinations
+        .iter()
+        .map(|destination| &destination.url)
+        .chain(link.ios_url.iter())
+        .chain(link.android_url.iter())
+        .chain(link.webhook_url.iter());
+    for url in urls {
+        if normalize_url(url).is_none() {
+            return Err(invalid(Some(link.short_code), format!("{} is not a valid URL", url)));
+        }
+    }
+    if link.destinations.iter().any(|destination| destination.weight == 0) {
+        return Err(invalid(Some(link.short_code), "destination weights must be at least 1".into()));
+    }
+
+    Ok(ImportLine {
+        line,
+        link,
+        normalized_url,
+    })
+}
+
+// Which of `codes` are already taken.
+async fn existing_codes(pool: &DbPool, codes: &[&str]) -> Result<HashSet<String>, sqlx::Error> {
+    let mut existing = HashSet::new();
+    for batch in codes.chunks(IMPORT_BATCH) {
+        let mut query: QueryBuilder<Sqlite> = QueryBuilder::new("SELECT id FROM urls WHERE id IN (");
+        let mut separated = query.separated(", ");
+        for code in batch {
+            separated.push_bind(*code);
+        }
+        separated.push_unseparated(")");
+        let found: Vec<String> = query.build_query_scalar().fetch_all(pool).traced("find_existing_codes", None).await?;
+        existing.extend(found);
+    }
+    Ok(existing)
+}
+
+async fn import_link(
+    conn: &mut SqliteConnection,
+    import: &ImportLine,
+    conflict: ImportConflict,
+    actor: &str,
+) -> Result<ImportStatus, sqlx::Error> {
+    let link = &import.link;
+    let redirect_type = u16::from(link.redirect_type);
+    let stats_visibility = link.stats_visibility.as_str();
+    let code_strategy = link.code_strategy.map(CodeStrategy::as_str);
+    let rotation = link.rotation.map(Rotation::as_str);
+    let exists = sqlx::query_scalar!(r#"SELECT EXISTS(SELECT 1 FROM urls WHERE id = ?) AS "exists!: bool""#, link.short_code)
+        .fetch_one(&mut *conn)
+        .traced_one("find_import_code", Some(link.short_code.as_str()))
+        .await?;
+
+    let before = match (exists, conflict) {
+        (true, ImportConflict::Overwrite) => audit::snapshot(conn, &link.short_code).await?,
+        _ => None,
+    };
+    let status = match (exists, conflict) {
+        (false, _) => {
+            sqlx::query!(
+                "INSERT INTO urls (id, original_url, normalized_url, created_at, expires_at, starts_at, redirect_type, max_visits, is_active, forward_query, stats_visibility, namespace, code_strategy, visit_count, untracked_visits, total_visits, bot_visits, ios_url, android_url, rotation, webhook_url, webhook_secret, campaign_id, created_by_key, password_hash, delete_token_hash, stats_token_hash) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, (SELECT id FROM campaigns WHERE id = ?), ?, ?, ?, ?)",
+                link.short_code,
+                link.original_url,
+                import.normalized_url,
+                link.created_at,
+                link.expires_at,
+                link.starts_at,
+                redirect_type,
+                link.max_visits,
+                link.active,
+                link.forward_query,
+                stats_visibility,
+                link.namespace,
+                code_strategy,
+                link.visit_count,
+                link.untracked_visits,
+                link.total_visits,
+                link.bot_visits,
+                link.ios_url,
+                link.android_url,
+                rotation,
+                link.webhook_url,
+                link.webhook_secret,
+                link.campaign_id,
+                link.created_by_key,
+                link.password_hash,
+                link.delete_token_hash,
+                link.stats_token_hash
+            )
+            .execute(&mut *conn)
+            .traced("import_link", Some(link.short_code.as_str()))
+            .await?;
+            ImportStatus::Created
+        }
+        (true, ImportConflict::Overwrite) => {
+            sqlx::query!(
+                "UPDATE urls SET original_url = ?, normalized_url = ?, created_at = ?, expires_at = ?, starts_at = ?, redirect_type = ?, max_visits = ?, is_active = ?, forward_query = ?, stats_visibility = ?, namespace = ?, code_strategy = ?, visit_count = ?, untracked_visits = ?, total_visits = ?, bot_visits = ?, ios_url = ?, android_url = ?, rotation = ?, webhook_url = ?, webhook_secret = ?, campaign_id = (SELECT id FROM campaigns WHERE id = ?), created_by_key = ?, password_hash = ?, delete_token_hash = ?, stats_token_hash = ? WHERE id = ?",
+                link.original_url,
+                import.normalized_url,
+                link.created_at,
+                link.expires_at,
+                link.starts_at,
+                redirect_type,
+                link.max_visits,
+                link.active,
+                link.forward_query,
+                stats_visibility,
+                link.namespace,
+                code_strategy,
+                link.visit_count,
+                link.untracked_visits,
+                link.total_visits,
+                link.bot_visits,
+                link.ios_url,
+                link.android_url,
+                rotation,
+                link.webhook_url,
+                link.webhook_secret,
+                link.campaign_id,
+                link.created_by_key,
+                link.password_hash,
+                link.delete_token_hash,
+                link.stats_token_hash,
+                link.short_code
+            )
+            .execute(&mut *conn)
+            .traced("overwrite_link", Some(link.short_code.as_str()))
+            .await?;
+            sqlx::query!("DELETE FROM url_tags WHERE url_id = ?", link.short_code)
+                .execute(&mut *conn)
+                .traced("delete_tags", Some(link.short_code.as_str()))
+                .await?;
+            sqlx::query!("DELETE FROM url_destinations WHERE url_id = ?", link.short_code)
+                .execute(&mut *conn)
+                .traced("delete_destinations", Some(link.short_code.as_str()))
+                .await?;
+            ImportStatus::Overwritten
+        }
+        (true, ImportConflict::Skip) => return Ok(ImportStatus::Skipped),
+        // Only reachable for codes taken since the up-front check, or
+        // repeated within the same import
+        (true, ImportConflict::Fail) => return Ok(ImportStatus::Conflict),
+    };
+    tags::insert_tags(conn, &link.short_code, &tags::normalize(&link.tags)).await?;
+    rotation::insert_destinations(conn, &link.short_code, &link.destinations).await?;
+    let after = audit::snapshot(conn, &link.short_code).await?;
+    audit::record(
+        conn,
+        Entry {
+            actor,
+            action: "import",
+            code: Some(&link.short_code),
+            before,
+            after,
+        },
+    )
+    .await?;
+    Ok(status)
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/import",
+    params(ImportParams),
+    request_body(
+        content = String,
+        content_type = "application/x-ndjson",
+        description = "`LinkExport` objects, one per line, as written by `/admin/export`"
+    ),
+    responses(
+        (status = 200, description = "Per-line results. Lines are written in transactions of 500.", body = ImportResponse),
+        (status = 400, description = "Malformed query parameters or body", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid admin API key", body = ErrorResponse),
+        (status = 409, description = "`conflict=fail` and some short codes already exist; nothing was imported", body = ErrorResponse),
+        (status = 413, description = "Body over 64 MiB", body = ErrorResponse)
+    ),
+    security(
+        ("api_key" = [])
+    )
+)]
+#[tracing::instrument(skip_all)]
+pub async fn import_links(
+    State(state): State<AppState>,
+    AdminKey(actor): AdminKey,
+    params: Result<Query<ImportParams>, QueryRejection>,
+    body: Result<String, StringRejection>,
+) -> Result<Json<ImportResponse>, AppError> {
+    let Query(params) = params.map_err(|rejection| AppError::BadRequest(rejection.body_text()))?;
+    let body = body.map_err(|rejection| AppError::body_rejection(rejection.status(), rejection.body_text()))?;
+    let conflict = params.conflict.unwrap_or_default();
+
+    let mut response = ImportResponse::default();
+    let mut lines = Vec::new();
+    for (index, raw) in body.lines().enumerate() {
+        if raw.trim().is_empty() {
+            continue;
+        }
+        match parse_line(index + 1, raw) {
+            Ok(line) => lines.push(line),
+            Err(result) => response.results.push(result),
+        }
+    }
+
+    if conflict == ImportConflict::Fail {
+        let codes: Vec<&str> = lines.iter().map(|line| line.link.short_code.as_str()).collect();
+        let existing = existing_codes(&state.pool, &codes).await?;
+        if !existing.is_empty() {
+            let conflicts = lines
+                .into_iter()
+                .filter(|line| existing.contains(&line.link.short_code))
+                .map(|line| (line.line, line.link.short_code))
+                .collect();
+            return Err(AppError::ImportConflict(conflicts));
+        }
+    }
+
+    let mut overwritten = Vec::new();
+    for batch in lines.chunks(IMPORT_BATCH) {
+        let mut tx = state.pool.begin().await?;
+        for line in batch {
+            let status = import_link(&mut tx, line, conflict, &actor).await?;
+            if status == ImportStatus::Overwritten {
+                overwritten.push(line.link.short_code.clone());
+            }
+            response.results.push(ImportLineResult {
+                line: line.line,
+                short_code: Some(line.link.short_code.clone()),
+                status,
+                error: (status == ImportStatus::Conflict).then(|| "short code already exists".into()),
+            });
+        }
+        tx.commit().await?;
+    }
+
+    if let Some(cache) = &state.redirect_cache {
+        for code in &overwritten {
+            cache.invalidate(code);
+        }
+    }
+
+    Ok(Json(summarize(response)))
+}
+
+// Sort the results by line and fill in the counts.
+fn summarize(mut response: ImportResponse) -> ImportResponse {
+    response.results.sort_by_key(|result| result.line);
+    for result in &response.results {
+        match result.status {
+            ImportStatus::Created => response.created += 1,
+            ImportStatus::Overwritten => response.overwritten += 1,
+            ImportStatus::Skipped => response.skipped += 1,
+            ImportStatus::Conflict | ImportStatus::Invalid => response.failed += 1,
+        }
+    }
+    response
+}
@@ -0,0 +1,176 @@
+//! Domains the operator has flagged, e.g. for phishing. New links can't
+//! point at them, and existing links that do get an interstitial page
+//! instead of a redirect. A domain also covers its subdomains.
+//!
+//! Entries come from `BLOCKED_DESTINATIONS` and the file at
+//! `BLOCKED_DESTINATIONS_PATH`, which is re-read on `SIGHUP` and every
+//! `BLOCKLIST_RELOAD_SECS`.
+
+use crate::{error::AppError, password::escape_html};
+use axum::response::Html;
+use std::{
+    collections::HashSet,
+    path::PathBuf,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+use url::Url;
+
+pub struct Blocklist {
+    /// Entries from the environment, kept across reloads
+    inline: Vec<String>,
+    path: Option<PathBuf>,
+    domains: RwLock<Arc<HashSet<String>>>,
+}
+
+// Lowercased, without a trailing dot or a leading `*.`, so `*.Evil.com.`
+// and `evil.com` are the same entry.
+fn normalize(entry: &str) -> Option<String> {
+    let entry = entry.trim().trim_start_matches("*.").trim_end_matches('.');
+    (!entry.is_empty()).then(|| entry.to_ascii_lowercase())
+}
+
+// One domain per line; `#` starts a comment.
+fn parse_lines(raw: &str) -> impl Iterator<Item = String> + '_ {
+    raw.lines()
+        .map(|line| line.split('#').next().unwrap_or_default())
+        .filter_map(normalize)
+}
+
+impl Blocklist {
+    /// `inline` is a comma-separated list; `path` is read now and on every
+    /// `reload`. A file that can't be read leaves only the inline entries
+    /// until a reload succeeds.
+    pub fn new(inline: Option<&str>, path: Option<PathBuf>) -> Self {
+        let inline: Vec<String> = inline.unwrap_or_default().split(',').filter_map(normalize).collect();
+        let blocklist = Blocklist {
+            domains: RwLock::new(Arc::new(inline.iter().cloned().collect())),
+            inline,
+            path,
+        };
+        if let Err(e) = blocklist.reload() {
+            tracing::error!("failed to read the destination blocklist: {}", e);
+        }
+        blocklist
+    }
+
+    /// Re-reads the file, returning the number of domains now blocked. On
+    /// error the previous list stays in effect.
+    pub fn reload(&self) -> std::io::Result<usize> {
+        let mut domains: HashSet<String> = self.inline.iter().cloned().collect();
+        if let Some(path) = &self.path {
+            domains.extend(parse_lines(&std::fs::read_to_string(path)?));
+        }
+        let count = domains.len();
+        *self.domains.write().expect("blocklist lock poisoned") = Arc::new(domains);
+        Ok(count)
+    }
+
+    pub fn len(&self) -> usize {
+        self.domains.read().expect("blocklist lock poisoned").len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The blocked domain `url`'s host falls under, if any.
+    pub fn matching(&self, url: &str) -> Option<String> {
+        let domains = self.domains.read().expect("blocklist lock poisoned").clone();
+        if domains.is_empty() {
+            return None;
+        }
+        let url = Url::parse(url.trim()).ok()?;
+        let host = url.host_str()?.trim_end_matches('.').to_ascii_lowercase();
+        // The host itself, then each parent domain: a.b.evil.com, b.evil.com, evil.com, com
+        let mut candidate = host.as_str();
+        loop {
+            if domains.contains(candidate) {
+                return Some(candidate.to_string());
+            }
+            candidate = candidate.split_once('.')?.1;
+        }
+    }
+
+    /// Rejects a new or changed destination under a blocked domain.
+    pub fn check(&self, url: &str) -> Result<(), AppError> {
+        match self.matching(url) {
+            Some(domain) => Err(AppError::BlockedDestination(domain)),
+            None => Ok(()),
+        }
+    }
+}
+
+fn reload_logged(blocklist: &Blocklist, reason: &str) {
+    match blocklist.reload() {
+        Ok(count) => tracing::info!(domains = count, "reloaded destination blocklist ({})", reason),
+        Err(e) => tracing::error!("failed to reload destination blocklist, keeping the old one: {}", e),
+    }
+}
+
+/// Spawns the task re-reading the blocklist file on `SIGHUP` (Unix) and,
+/// when `interval` is set, on a timer. Nothing is spawned without a file.
+pub fn spawn_reloader(blocklist: Arc<Blocklist>, interval: Option<Duration>) {
+    if blocklist.path.is_none() {
+        return;
+    }
+    if let Some(interval) = interval {
+        let blocklist = blocklist.clone();
+        tokio::spawn(async move {
+            let mut ticks = tokio::time::interval(interval);
+            // The first tick fires immediately, and the list is fresh
+            ticks.tick().await;
+            loop {
+                ticks.tick().await;
+                reload_logged(&blocklist, "timer");
+            }
+        });
+    }
+    #[cfg(unix)]
+    tokio::spawn(async move {
+        let mut hangups = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()) {
+            Ok(signal) => signal,
+            Err(e) => {
+                tracing::error!("failed to install SIGHUP handler, blocklist reloads on SIGHUP are off: {}", e);
+                return;
+            }
+        };
+        while hangups.recv().await.is_some() {
+            reload_logged(&blocklist, "SIGHUP");
+        }
+    });
+}
+
+/// Warning shown instead of redirecting to a flagged destination. The
+/// button posts to `/{code}/continue`, which counts the visit and redirects.
+pub fn interstitial(code: &str, domain: &str) -> Html<String> {
+    let code = escape_html(code);
+    let domain = escape_html(domain);
+
+    Html(format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<meta name="robots" content="noindex">
+<title>Destination flagged</title>
+<style>
+body {{ font-family: system-ui, sans-serif; display: flex; justify-content: center; margin-top: 15vh; }}
+main {{ max-width: 32rem; }}
+.domain {{ font-family: monospace; }}
+</style>
+</head>
+<body>
+<main>
+<h1>You are leaving this site</h1>
+<p>This link leads to <span class="domain">{domain}</span>, which has been flagged as a possible phishing or malware site. Only continue if you trust it.</p>
+<form method="post" action="/{code}/continue">
+<button type="submit">Continue anyway</button>
+</form>
+</main>
+</body>
+</html>
+"#
+    ))
+}
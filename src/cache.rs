@@ -0,0 +1,59 @@
+use crate::models::UrlRecord;
+use moka::sync::Cache;
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+/// In-process cache of link records consulted by `redirect_url` before
+/// hitting the database. Entries expire after a fixed TTL and are evicted
+/// explicitly whenever a link is deleted or modified.
+pub struct RedirectCache {
+    entries: Cache<String, UrlRecord>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl RedirectCache {
+    pub fn new(capacity: u64, ttl: Duration) -> Self {
+        Self {
+            entries: Cache::builder()
+                .max_capacity(capacity)
+                .time_to_live(ttl)
+                .build(),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    pub fn get(&self, code: &str) -> Option<UrlRecord> {
+        match self.entries.get(code) {
+            Some(record) => {
+                let hits = self.hits.fetch_add(1, Ordering::Relaxed) + 1;
+                tracing::debug!(code, hits, misses = self.misses.load(Ordering::Relaxed), "redirect cache hit");
+                Some(record)
+            }
+            None => {
+                let misses = self.misses.fetch_add(1, Ordering::Relaxed) + 1;
+                tracing::debug!(code, hits = self.hits.load(Ordering::Relaxed), misses, "redirect cache miss");
+                None
+            }
+        }
+    }
+
+    pub fn insert(&self, record: UrlRecord) {
+        self.entries.insert(record.id.clone(), record);
+    }
+
+    pub fn invalidate(&self, code: &str) {
+        self.entries.invalidate(code);
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+}
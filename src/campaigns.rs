@@ -0,0 +1,342 @@
+//! Campaigns: named groups of links whose stats are read together. Links
+//! join one at creation or through `PATCH /urls/{code}`.
+
+use crate::{
+    audit::{self, Entry},
+    auth::AdminKey,
+    db::Traced,
+    error::AppError,
+    handlers::{fill_daily_gaps, DEFAULT_STATS_DAYS, MAX_STATS_DAYS},
+    models::{
+        Campaign, CampaignLink, CampaignListResponse, CampaignRequest, CampaignStatsParams, CampaignStatsResponse,
+        DailyCount, ErrorResponse, ValidationErrorResponse,
+    },
+    state::AppState,
+};
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use sqlx::SqliteExecutor;
+use validator::{ValidationError, ValidationErrors};
+
+const MAX_NAME_CHARS: usize = 100;
+
+fn invalid(field: &'static str, code: &'static str, message: &'static str) -> AppError {
+    let mut err = ValidationError::new(code);
+    err.message = Some(message.into());
+    let mut errors = ValidationErrors::new();
+    errors.add(field, err);
+    AppError::Validation(errors)
+}
+
+fn campaign_name(payload: CampaignRequest) -> Result<String, AppError> {
+    let name = payload.name.trim();
+    if name.is_empty() || name.chars().count() > MAX_NAME_CHARS {
+        return Err(invalid("name", "length", "name must be between 1 and 100 characters"));
+    }
+    Ok(name.to_string())
+}
+
+// Ids are numbers, so anything else names no campaign
+fn parse_id(raw: &str) -> Result<i64, AppError> {
+    raw.parse().map_err(|_| AppError::CampaignNotFound)
+}
+
+/// Rejects a link's `campaign_id` that doesn't name a campaign.
+pub async fn check_exists<'e>(executor: impl SqliteExecutor<'e>, id: i64) -> Result<(), AppError> {
+    let found = sqlx::query_scalar!("SELECT id FROM campaigns WHERE id = ?", id)
+        .fetch_optional(executor)
+        .traced("find_campaign", None)
+        .await?;
+    match found {
+        Some(_) => Ok(()),
+        None => Err(invalid("campaign_id", "campaign", "campaign_id doesn't match a campaign")),
+    }
+}
+
+async fn load<'e>(executor: impl SqliteExecutor<'e>, id: i64) -> Result<Campaign, AppError> {
+    sqlx::query_as!(
+        Campaign,
+        r#"SELECT c.id AS "id!", c.name, c.created_at AS "created_at: DateTime<Utc>", (SELECT COUNT(*) FROM urls u WHERE u.campaign_id = c.id) AS "links!: i64" FROM campaigns c WHERE c.id = ?"#,
+        id
+    )
+    .fetch_optional(executor)
+    .traced("load_campaign", None)
+    .await?
+    .ok_or(AppError::CampaignNotFound)
+}
+
+fn snapshot(campaign: &Campaign) -> Option<serde_json::Value> {
+    Some(serde_json::to_value(campaign).expect("campaigns serialize"))
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/campaigns",
+    responses(
+        (status = 200, description = "All campaigns, newest first", body = CampaignListResponse),
+        (status = 401, description = "Missing or invalid admin API key", body = ErrorResponse)
+    ),
+    security(
+        ("api_key" = [])
+    )
+)]
+#[tracing::instrument(skip_all)]
+pub async fn list_campaigns(
+    State(state): State<AppState>,
+    _admin: AdminKey,
+) -> Result<Json<CampaignListResponse>, AppError> {
+    let items = sqlx::query_as!(
+        Campaign,
+        r#"SELECT c.id AS "id!", c.name, c.created_at AS "created_at: DateTime<Utc>", (SELECT COUNT(*) FROM urls u WHERE u.campaign_id = c.id) AS "links!: i64" FROM campaigns c ORDER BY c.id DESC"#
+    )
+    .fetch_all(&state.pool)
+    .traced("list_campaigns", None)
+    .await?;
+    Ok(Json(CampaignListResponse { items }))
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/campaigns",
+    request_body = CampaignRequest,
+    responses(
+        (status = 201, description = "Campaign created", body = Campaign),
+        (status = 401, description = "Missing or invalid admin API key", body = ErrorResponse),
+        (status = 422, description = "Name empty or over 100 characters", body = ValidationErrorResponse)
+    ),
+    security(
+        ("api_key" = [])
+    )
+)]
+#[tracing::instrument(skip_all)]
+pub async fn create_campaign(
+    State(state): State<AppState>,
+    AdminKey(actor): AdminKey,
+    Json(payload): Json<CampaignRequest>,
+) -> Result<(StatusCode, Json<Campaign>), AppError> {
+    let name = campaign_name(payload)?;
+
+    let now = Utc::now();
+    let mut tx = state.pool.begin().await?;
+    let id = sqlx::query!("INSERT INTO campaigns (name, created_at) VALUES (?, ?)", name, now)
+        .execute(&mut *tx)
+        .traced("insert_campaign", None)
+        .await?
+        .last_insert_rowid();
+    let campaign = load(&mut *tx, id).await?;
+    audit::record(
+        &mut tx,
+        Entry {
+            actor: &actor,
+            action: "create_campaign",
+            code: None,
+            before: None,
+            after: snapshot(&campaign),
+        },
+    )
+    .await?;
+    tx.commit().await?;
+
+    Ok((StatusCode::CREATED, Json(campaign)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/campaigns/{id}",
+    params(
+        ("id" = i64, Path, description = "Campaign id")
+    ),
+    responses(
+        (status = 200, description = "The campaign", body = Campaign),
+        (status = 401, description = "Missing or invalid admin API key", body = ErrorResponse),
+        (status = 404, description = "Campaign not found", body = ErrorResponse)
+    ),
+    security(
+        ("api_key" = [])
+    )
+)]
+#[tracing::instrument(skip_all, fields(campaign = %id))]
+pub async fn get_campaign(
+    State(state): State<AppState>,
+    _admin: AdminKey,
+    Path(id): Path<String>,
+) -> Result<Json<Campaign>, AppError> {
+    Ok(Json(load(&state.pool, parse_id(&id)?).await?))
+}
+
+#[utoipa::path(
+    patch,
+    path = "/admin/campaigns/{id}",
+    params(
+        ("id" = i64, Path, description = "Campaign id")
+    ),
+    request_body = CampaignRequest,
+    responses(
+        (status = 200, description = "Campaign renamed", body = Campaign),
+        (status = 401, description = "Missing or invalid admin API key", body = ErrorResponse),
+        (status = 404, description = "Campaign not found", body = ErrorResponse),
+        (status = 422, description = "Name empty or over 100 characters", body = ValidationErrorResponse)
+    ),
+    security(
+        ("api_key" = [])
+    )
+)]
+#[tracing::instrument(skip_all, fields(campaign = %id))]
+pub async fn rename_campaign(
+    State(state): State<AppState>,
+    AdminKey(actor): AdminKey,
+    Path(id): Path<String>,
+    Json(payload): Json<CampaignRequest>,
+) -> Result<Json<Campaign>, AppError> {
+    let id = parse_id(&id)?;
+    let name = campaign_name(payload)?;
+
+    let mut tx = state.pool.begin().await?;
+    let before = load(&mut *tx, id).await?;
+    sqlx::query!("UPDATE campaigns SET name = ? WHERE id = ?", name, id)
+        .execute(&mut *tx)
+        .traced("rename_campaign", None)
+        .await?;
+    let after = load(&mut *tx, id).await?;
+    audit::record(
+        &mut tx,
+        Entry {
+            actor: &actor,
+            action: "rename_campaign",
+            code: None,
+            before: snapshot(&before),
+            after: snapshot(&after),
+        },
+    )
+    .await?;
+    tx.commit().await?;
+
+    Ok(Json(after))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/admin/campaigns/{id}",
+    params(
+        ("id" = i64, Path, description = "Campaign id")
+    ),
+    responses(
+        (status = 204, description = "Campaign deleted; its links are kept and leave the campaign"),
+        (status = 401, description = "Missing or invalid admin API key", body = ErrorResponse),
+        (status = 404, description = "Campaign not found", body = ErrorResponse)
+    ),
+    security(
+        ("api_key" = [])
+    )
+)]
+#[tracing::instrument(skip_all, fields(campaign = %id))]
+pub async fn delete_campaign(
+    State(state): State<AppState>,
+    AdminKey(actor): AdminKey,
+    Path(id): Path<String>,
+) -> Result<StatusCode, AppError> {
+    let id = parse_id(&id)?;
+
+    let mut tx = state.pool.begin().await?;
+    let before = load(&mut *tx, id).await?;
+    // Detached here rather than left to the foreign key, which only acts
+    // while SQLITE_FOREIGN_KEYS is on
+    let detached = sqlx::query_scalar!(
+        r#"UPDATE urls SET campaign_id = NULL WHERE campaign_id = ? RETURNING id AS "id!""#,
+        id
+    )
+    .fetch_all(&mut *tx)
+    .traced("detach_campaign_links", None)
+    .await?;
+    sqlx::query!("DELETE FROM campaigns WHERE id = ?", id)
+        .execute(&mut *tx)
+        .traced("delete_campaign", None)
+        .await?;
+    audit::record(
+        &mut tx,
+        Entry {
+            actor: &actor,
+            action: "delete_campaign",
+            code: None,
+            before: snapshot(&before),
+            after: None,
+        },
+    )
+    .await?;
+    tx.commit().await?;
+
+    // Cached records carry the old campaign
+    if let Some(cache) = &state.redirect_cache {
+        for code in &detached {
+            cache.invalidate(code);
+        }
+    }
+    tracing::info!(links = detached.len(), "campaign deleted");
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    get,
+    path = "/admin/campaigns/{id}/stats",
+    params(
+        ("id" = i64, Path, description = "Campaign id"),
+        CampaignStatsParams
+    ),
+    responses(
+        (status = 200, description = "Visits across the campaign's links", body = CampaignStatsResponse),
+        (status = 401, description = "Missing or invalid admin API key", body = ErrorResponse),
+        (status = 404, description = "Campaign not found", body = ErrorResponse)
+    ),
+    security(
+        ("api_key" = [])
+    )
+)]
+#[tracing::instrument(skip_all, fields(campaign = %id))]
+pub async fn campaign_stats(
+    State(state): State<AppState>,
+    _admin: AdminKey,
+    Path(id): Path<String>,
+    Query(params): Query<CampaignStatsParams>,
+) -> Result<Json<CampaignStatsResponse>, AppError> {
+    let pool = &state.pool;
+    let campaign = load(pool, parse_id(&id)?).await?;
+
+    let links = sqlx::query_as!(
+        CampaignLink,
+        r#"SELECT id AS "short_code!", original_url, CASE WHEN ? THEN total_visits ELSE total_visits - bot_visits END AS "total_visits!: i64" FROM urls WHERE campaign_id = ? ORDER BY 3 DESC, id"#,
+        params.include_bots,
+        campaign.id
+    )
+    .fetch_all(pool)
+    .traced("campaign_links", None)
+    .await?;
+    let total_visits = links.iter().map(|link| link.total_visits).sum();
+
+    let days = params.days.unwrap_or(DEFAULT_STATS_DAYS).clamp(1, MAX_STATS_DAYS);
+    let today = Utc::now().date_naive();
+    let first_day = today - Duration::days(i64::from(days) - 1);
+    let window_start = first_day.and_hms_opt(0, 0, 0).unwrap().and_utc();
+    let counts = sqlx::query_as!(
+        DailyCount,
+        r#"SELECT date(v.visited_at) AS "date!: NaiveDate", COUNT(*) AS "count!: i64" FROM visits v JOIN urls u ON u.id = v.url_id WHERE u.campaign_id = ? AND v.visited_at >= ? AND (? OR NOT v.is_bot) GROUP BY date(v.visited_at)"#,
+        campaign.id,
+        window_start,
+        params.include_bots
+    )
+    .fetch_all(pool)
+    .traced("count_campaign_daily_visits", None)
+    .await?;
+
+    Ok(Json(CampaignStatsResponse {
+        id: campaign.id,
+        name: campaign.name,
+        total_visits,
+        links,
+        daily_visits: fill_daily_gaps(counts, first_day, today),
+    }))
+}
@@ -0,0 +1,172 @@
+//! Administration commands the binary runs directly against the database,
+//! for when the server is down: `shorten`, `stats`, `purge-expired` and
+//! `export`. Output is meant for people unless `--json` is given.
+
+use crate::{
+    backup, db,
+    error::AppError,
+    handlers,
+    models::{CreateUrlRequest, StatsSummary, UrlResponse},
+    purge,
+    state::AppState,
+};
+use axum::body::Bytes;
+use chrono::{DateTime, Utc};
+use serde_json::json;
+use std::fmt::Write as _;
+use tokio::{
+    io::{AsyncWrite, AsyncWriteExt},
+    sync::mpsc,
+};
+
+/// Exit status of a command that failed.
+pub const EXIT_FAILURE: u8 = 1;
+/// Exit status when the named link doesn't exist. Clap exits with 2 on
+/// usage errors.
+pub const EXIT_NOT_FOUND: u8 = 3;
+
+/// When a link made with `shorten --expires` stops working.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Expiry {
+    At(DateTime<Utc>),
+    In(u64),
+}
+
+/// Parses `--expires`: an RFC 3339 time, or a duration such as `90s`,
+/// `30m`, `12h` or `7d`.
+pub fn parse_expiry(raw: &str) -> Result<Expiry, String> {
+    if let Ok(at) = DateTime::parse_from_rfc3339(raw) {
+        return Ok(Expiry::At(at.with_timezone(&Utc)));
+    }
+    let unit = match raw.chars().last() {
+        Some('s') => 1,
+        Some('m') => 60,
+        Some('h') => 3600,
+        Some('d') => 86_400,
+        _ => return Err("expected an RFC 3339 time or a duration such as 30m, 12h or 7d".into()),
+    };
+    raw[..raw.len() - 1]
+        .parse::<u64>()
+        .ok()
+        .filter(|n| *n > 0)
+        .and_then(|n| n.checked_mul(unit))
+        .map(Expiry::In)
+        .ok_or_else(|| format!("invalid duration {:?}", raw))
+}
+
+// Short links printed by commands use BASE_URL, or the address the server
+// listens on
+fn base_url(state: &AppState) -> String {
+    let config = &state.config;
+    config.base_url.clone().unwrap_or_else(|| format!("http://{}", config.listen_addr))
+}
+
+/// Creates a link with the same validation as `POST /shorten`.
+pub async fn shorten(
+    state: &AppState,
+    url: String,
+    code: Option<String>,
+    expires: Option<Expiry>,
+) -> Result<UrlResponse, AppError> {
+    let (expires_at, expires_in_secs) = match expires {
+        Some(Expiry::At(at)) => (Some(at), None),
+        Some(Expiry::In(secs)) => (None, Some(secs)),
+        None => (None, None),
+    };
+    let payload: CreateUrlRequest = serde_json::from_value(json!({
+        "url": url,
+        "custom_code": code,
+        "expires_at": expires_at,
+        "expires_in_secs": expires_in_secs,
+    }))
+    .expect("the request fields are well-formed");
+    let (_, link) = handlers::shorten(state, payload, None, &base_url(state)).await?;
+    Ok(link)
+}
+
+/// Visit counts of a link. Unlike `GET /stats/{code}/summary` no stats
+/// token is needed and expired links are shown too.
+pub async fn stats(state: &AppState, code: &str) -> Result<StatsSummary, AppError> {
+    let url = db::find_url(&state.pool, code).await?.ok_or(AppError::UrlNotFound)?;
+    handlers::summary(&state.pool, url, false).await
+}
+
+/// Deletes links expired more than `PURGE_GRACE_SECS` ago, once, and
+/// returns how many were removed. A running server may keep redirecting
+/// them until its cache entries expire.
+pub async fn purge_expired(state: &AppState) -> Result<u64, AppError> {
+    Ok(purge::purge_expired(&state.pool, None, state.config.purge_grace).await?)
+}
+
+/// Writes every link to `out` in the `GET /admin/export` format.
+pub async fn export(state: &AppState, out: &mut (impl AsyncWrite + Unpin)) -> Result<(), AppError> {
+    let (tx, mut rx) = mpsc::channel::<Result<Bytes, sqlx::Error>>(2);
+    let write = async move {
+        while let Some(chunk) = rx.recv().await {
+            out.write_all(&chunk?).await.map_err(|e| AppError::InternalServerError(e.to_string()))?;
+        }
+        out.flush().await.map_err(|e| AppError::InternalServerError(e.to_string()))
+    };
+    let ((), written) = tokio::join!(backup::stream_links(state.pool.clone(), tx), write);
+    written
+}
+
+/// A created link as printed, with the tokens only shown once.
+pub fn render_link(link: &UrlResponse) -> String {
+    let mut out = format!("{} -> {}\n", link.short_url, link.original_url.as_deref().unwrap_or_default());
+    if let Some(expires_at) = link.expires_at {
+        let _ = writeln!(out, "  expires:      {}", expires_at.to_rfc3339());
+    }
+    if let Some(token) = &link.stats_token {
+        let _ = writeln!(out, "  stats token:  {}", token);
+    }
+    if let Some(token) = &link.delete_token {
+        let _ = writeln!(out, "  delete token: {}", token);
+    }
+    out
+}
+
+/// A link's summary as printed.
+pub fn render_summary(summary: &StatsSummary) -> String {
+    let mut out = format!("{} -> {}\n", summary.url, summary.original_url.as_deref().unwrap_or_default());
+    if let Some(title) = &summary.title {
+        let _ = writeln!(out, "  title:           {}", title);
+    }
+    let _ = writeln!(out, "  created:         {}", summary.created_at.to_rfc3339());
+    let expiry = match summary.expires_at {
+        Some(at) if at <= Utc::now() => format!("{} (expired)", at.to_rfc3339()),
+        Some(at) => at.to_rfc3339(),
+        None => "never".into(),
+    };
+    let _ = writeln!(out, "  expires:         {}", expiry);
+    let _ = writeln!(out, "  active:          {}", if summary.active { "yes" } else { "no" });
+    let _ = writeln!(out, "  visits:          {}", summary.total_visits);
+    let _ = writeln!(out, "  unique visitors: {}", summary.unique_visitors);
+    let last = summary.last_visited_at.map(|at| at.to_rfc3339()).unwrap_or_else(|| "never".into());
+    let _ = writeln!(out, "  last visit:      {}", last);
+    out
+}
+
+/// The exit status and message for a failed command, the message in the
+/// API's error shape with `--json`.
+pub fn render_error(e: AppError, json: bool) -> (u8, String) {
+    let status = match e {
+        AppError::UrlNotFound => EXIT_NOT_FOUND,
+        _ => EXIT_FAILURE,
+    };
+    let (code, details) = (e.code(), e.details());
+    let message = e.message();
+    let rendered = if json {
+        let mut body = json!({ "error": message, "code": code });
+        if let Some(details) = details {
+            body["details"] = details;
+        }
+        body.to_string()
+    } else {
+        match details {
+            Some(details) => format!("error: {} ({})", message, details),
+            None => format!("error: {}", message),
+        }
+    };
+    (status, rendered)
+}
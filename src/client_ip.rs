@@ -0,0 +1,227 @@
+use crate::{error::AppError, state::AppState};
+use axum::{
+    extract::{ConnectInfo, FromRequestParts},
+    http::{request::Parts, HeaderMap},
+};
+use std::net::{IpAddr, SocketAddr};
+
+/// An IP network in CIDR notation; a bare address is a /32 (or /128).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpNet {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl IpNet {
+    pub fn parse(raw: &str) -> Option<Self> {
+        let (addr, prefix) = match raw.trim().split_once('/') {
+            Some((addr, prefix)) => (addr.parse::<IpAddr>().ok()?, Some(prefix.parse::<u8>().ok()?)),
+            None => (raw.trim().parse::<IpAddr>().ok()?, None),
+        };
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = prefix.unwrap_or(max);
+        (prefix <= max).then_some(IpNet { addr, prefix })
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, normalize(ip)) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - u32::from(self.prefix)).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - u32::from(self.prefix)).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Parses a comma-separated `TRUSTED_PROXIES` list, returning the first
+/// invalid entry on failure.
+pub fn parse_trusted_proxies(raw: &str) -> Result<Vec<IpNet>, String> {
+    raw.split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|s| IpNet::parse(s).ok_or_else(|| s.to_string()))
+        .collect()
+}
+
+// IPv4-mapped IPv6 peers (::ffff:a.b.c.d) compare as plain IPv4.
+fn normalize(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+        v4 => v4,
+    }
+}
+
+// Parse one X-Forwarded-For hop: `1.2.3.4`, `1.2.3.4:80`, `::1` or `[::1]:80`.
+fn parse_hop(hop: &str) -> Option<IpAddr> {
+    let hop = hop.trim();
+    if let Ok(ip) = hop.parse::<IpAddr>() {
+        return Some(ip);
+    }
+    hop.parse::<SocketAddr>().ok().map(|s| s.ip())
+}
+
+/// Determines the client IP. `X-Forwarded-For` is only honored when the
+/// connecting peer is a trusted proxy (or unknown, e.g. behind a Unix
+/// socket); hops are walked right to left, skipping trusted proxies, so a
+/// client can't spoof its address by prepending entries. A hop that isn't an
+/// address ends the walk, as nothing trusted vouches for what's left of it.
+pub fn resolve_client_ip(peer: Option<IpAddr>, headers: &HeaderMap, trusted: &[IpNet]) -> String {
+    let is_trusted = |ip: IpAddr| trusted.iter().any(|net| net.contains(ip));
+
+    if let Some(peer) = peer
+        && !is_trusted(peer)
+    {
+        return normalize(peer).to_string();
+    }
+
+    let hops: Vec<Option<IpAddr>> = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|h| h.to_str().ok())
+        .flat_map(|h| h.split(','))
+        .filter(|hop| !hop.trim().is_empty())
+        .map(parse_hop)
+        .collect();
+
+    // Ends on the first untrusted hop, or on the leftmost one when the whole
+    // chain is trusted
+    let mut client = None;
+    for hop in hops.iter().rev() {
+        let Some(ip) = *hop else { break };
+        client = Some(ip);
+        if !is_trusted(ip) {
+            break;
+        }
+    }
+
+    client
+        .or(peer)
+        .map(|ip| normalize(ip).to_string())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+fn peer_ip(parts: &Parts) -> Option<IpAddr> {
+    parts
+        .extensions
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip())
+}
+
+/// Extractor yielding the resolved client IP for the request.
+pub struct ClientIp(pub String);
+
+impl FromRequestParts<AppState> for ClientIp {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        Ok(ClientIp(resolve_client_ip(
+            peer_ip(parts),
+            &parts.headers,
+            &state.trusted_proxies,
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn forwarded(values: &[&str]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for value in values {
+            headers.append("x-forwarded-for", HeaderValue::from_str(value).unwrap());
+        }
+        headers
+    }
+
+    fn ip(raw: &str) -> Option<IpAddr> {
+        Some(raw.parse().unwrap())
+    }
+
+    fn proxies(raw: &str) -> Vec<IpNet> {
+        parse_trusted_proxies(raw).unwrap()
+    }
+
+    #[test]
+    fn untrusted_peers_are_recorded_as_they_connect() {
+        let headers = forwarded(&["198.51.100.1"]);
+
+        assert_eq!(resolve_client_ip(ip("203.0.113.7"), &headers, &[]), "203.0.113.7");
+        assert_eq!(resolve_client_ip(ip("203.0.113.7"), &headers, &proxies("10.0.0.0/8")), "203.0.113.7");
+        assert_eq!(resolve_client_ip(ip("::ffff:203.0.113.7"), &headers, &[]), "203.0.113.7");
+    }
+
+    #[test]
+    fn trusted_proxies_are_skipped_right_to_left() {
+        let trusted = proxies("10.0.0.0/8,192.0.2.1");
+        let peer = ip("10.0.0.1");
+
+        let chain = forwarded(&["198.51.100.1, 203.0.113.9, 192.0.2.1, 10.0.0.2"]);
+        assert_eq!(resolve_client_ip(peer, &chain, &trusted), "203.0.113.9");
+        // Repeated headers read as one list, in order
+        let split = forwarded(&["198.51.100.1, 203.0.113.9", "10.0.0.2"]);
+        assert_eq!(resolve_client_ip(peer, &split, &trusted), "203.0.113.9");
+        let internal = forwarded(&["10.1.1.1, 10.0.0.2"]);
+        assert_eq!(resolve_client_ip(peer, &internal, &trusted), "10.1.1.1");
+        assert_eq!(resolve_client_ip(peer, &HeaderMap::new(), &trusted), "10.0.0.1");
+    }
+
+    #[test]
+    fn peers_without_an_address_are_taken_at_their_word() {
+        let headers = forwarded(&["198.51.100.1, 203.0.113.9"]);
+
+        assert_eq!(resolve_client_ip(None, &headers, &[]), "203.0.113.9");
+        assert_eq!(resolve_client_ip(None, &HeaderMap::new(), &[]), "unknown");
+    }
+
+    #[test]
+    fn ipv6_hops_with_and_without_brackets_or_ports() {
+        let trusted = proxies("::1,2001:db8:ffff::/48");
+        let peer = ip("::1");
+
+        for (hop, client) in [
+            ("2001:db8::7", "2001:db8::7"),
+            ("[2001:db8::7]:443", "2001:db8::7"),
+            ("203.0.113.5:8080", "203.0.113.5"),
+            ("::ffff:203.0.113.5", "203.0.113.5"),
+        ] {
+            let headers = forwarded(&[&format!("{}, 2001:db8:ffff::1", hop)]);
+            assert_eq!(resolve_client_ip(peer, &headers, &trusted), client, "{}", hop);
+        }
+        assert_eq!(resolve_client_ip(ip("2001:db8::9"), &forwarded(&["203.0.113.5"]), &trusted), "2001:db8::9");
+    }
+
+    #[test]
+    fn garbage_hops_end_the_walk() {
+        let trusted = proxies("10.0.0.0/8");
+        let peer = ip("10.0.0.1");
+
+        let after = forwarded(&["unknown, 203.0.113.9"]);
+        assert_eq!(resolve_client_ip(peer, &after, &trusted), "203.0.113.9");
+        // Whatever precedes the garbage is the client's own claim
+        let before = forwarded(&["198.51.100.1, unknown, 10.0.0.2"]);
+        assert_eq!(resolve_client_ip(peer, &before, &trusted), "10.0.0.2");
+        let only = forwarded(&["198.51.100.1, not-an-ip"]);
+        assert_eq!(resolve_client_ip(peer, &only, &trusted), "10.0.0.1");
+        let empty = forwarded(&[" , 203.0.113.9,"]);
+        assert_eq!(resolve_client_ip(peer, &empty, &trusted), "203.0.113.9");
+    }
+
+    #[test]
+    fn networks_parse_and_match() {
+        let net = IpNet::parse("10.0.0.0/8").unwrap();
+        assert!(net.contains("10.255.0.1".parse().unwrap()));
+        assert!(net.contains("::ffff:10.0.0.1".parse().unwrap()));
+        assert!(!net.contains("11.0.0.1".parse().unwrap()));
+        assert!(IpNet::parse("0.0.0.0/0").unwrap().contains("203.0.113.7".parse().unwrap()));
+        assert_eq!(IpNet::parse("10.0.0.0/33"), None);
+        assert_eq!(IpNet::parse("::/129"), None);
+        assert_eq!(parse_trusted_proxies("127.0.0.1, nope"), Err("nope".to_string()));
+    }
+}
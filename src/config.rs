@@ -0,0 +1,496 @@
+use crate::{
+    auth::{self, ApiKeyConfig},
+    client_ip::{self, IpNet},
+    cors::{self, AllowedOrigins, CorsPolicy},
+    db, rate_limit,
+    security_headers::{self, SecurityHeaders},
+    telemetry::LogFormat,
+    utils::{self, CodeAlphabet, CodeStyle},
+    visits::AnalyticsMode,
+};
+use axum::http::HeaderValue;
+use governor::Quota;
+use sqlx::sqlite::{SqliteJournalMode, SqliteSynchronous};
+use std::{
+    fmt,
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    str::FromStr,
+    time::Duration,
+};
+
+/// A per-IP rate limit as configured, e.g. `10/60s`.
+#[derive(Debug, Clone)]
+pub struct RateLimit {
+    /// The setting as written, for logs
+    pub spec: String,
+    pub quota: Quota,
+}
+
+/// Settings read from the environment at startup. Everything is validated
+/// up front by `from_env`, so the rest of the service can trust these values.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Throwaway in-memory database, seeded with sample links at startup
+    pub dev_mode: bool,
+    pub database_url: String,
+    pub database_max_connections: u32,
+    pub sqlite_journal_mode: SqliteJournalMode,
+    pub sqlite_synchronous: SqliteSynchronous,
+    /// How long a connection waits on a locked database before giving up
+    pub sqlite_busy_timeout: Duration,
+    pub sqlite_foreign_keys: bool,
+    /// Tries at opening the database and migrating it before giving up
+    pub database_connect_attempts: u32,
+    /// Wait before the first retry, doubled for each one after
+    pub database_retry_delay: Duration,
+    pub listen_addr: SocketAddr,
+    /// Unix socket to listen on instead of `listen_addr`
+    pub listen_socket: Option<PathBuf>,
+    /// Permissions of the socket file, e.g. `0o660`
+    pub listen_socket_mode: u32,
+    /// Public base URL used to build short links, e.g. `https://sho.rt`
+    pub base_url: Option<String>,
+    pub fallback_redirect_url: Option<String>,
+    /// `Referrer-Policy` sent with redirects
+    pub referrer_policy: HeaderValue,
+    /// `Cache-Control` sent with temporary (302, 303, 307) redirects
+    pub temporary_redirect_cache_control: HeaderValue,
+    pub api_keys: Vec<ApiKeyConfig>,
+    pub admin_api_keys: Vec<String>,
+    pub shorten_rate_limit: Option<RateLimit>,
+    pub hit_rate_limit: RateLimit,
+    pub unlock_rate_limit: RateLimit,
+    /// Zero disables the redirect cache
+    pub redirect_cache_capacity: u64,
+    pub redirect_cache_ttl: Duration,
+    pub code_style: CodeStyle,
+    pub code_alphabet: CodeAlphabet,
+    pub code_length: usize,
+    /// Extra words custom codes can't use, comma-separated
+    pub reserved_codes: Option<String>,
+    /// Extra user-agent fragments marking bots, comma-separated
+    pub bot_user_agents: Option<String>,
+    /// Extra words generated codes must not contain, one per line, read from
+    /// `CODE_DENYLIST_PATH`
+    pub code_denylist: Option<String>,
+    /// Private hosts and networks that may still be shortened, comma-separated
+    pub allowed_private_destinations: Option<String>,
+    /// Flagged destination domains, comma-separated
+    pub blocked_destinations: Option<String>,
+    /// File of flagged domains, one per line, re-read on reload
+    pub blocked_destinations_path: Option<PathBuf>,
+    /// How often the blocklist file is re-read besides on `SIGHUP`
+    pub blocklist_reload_interval: Option<Duration>,
+    pub max_link_ttl_days: Option<u32>,
+    pub trusted_proxies: Vec<IpNet>,
+    pub analytics_mode: AnalyticsMode,
+    pub visit_queue_capacity: usize,
+    pub visit_batch_size: usize,
+    pub visit_flush_interval: Duration,
+    pub purge_expired: bool,
+    pub cleanup_interval: Duration,
+    pub purge_grace: Duration,
+    pub visit_retention_days: Option<u32>,
+    /// MaxMind database used to resolve visitor countries
+    pub geoip_db_path: Option<PathBuf>,
+    /// How often destinations are checked for dead links; unset disables the check
+    pub link_check_interval: Option<Duration>,
+    /// Whether stale `Idempotency-Key` records are swept; always on for the server
+    pub sweep_idempotency_keys: bool,
+    pub visitor_hash_salt: Option<String>,
+    pub cookie_secret: Option<String>,
+    pub shutdown_timeout: Duration,
+    /// Largest request body accepted, in bytes (imports have their own limit)
+    pub max_request_body: usize,
+    /// Whether responses are gzip/brotli-compressed when the client accepts it
+    pub compress_responses: bool,
+    /// Origins browsers may call the API from
+    pub cors: CorsPolicy,
+    pub security_headers: SecurityHeaders,
+    /// Requests still running after this long are answered with a 504
+    pub request_timeout: Duration,
+    /// Requests handled at once; more are turned away with a 503
+    pub max_concurrent_requests: usize,
+    /// Read again by `main` before anything else, since logging starts
+    /// first; validated here so a typo is reported with the other problems
+    pub log_format: LogFormat,
+}
+
+/// Every problem found in the environment, reported together.
+#[derive(Debug)]
+pub struct ConfigError(pub Vec<String>);
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid configuration:")?;
+        for problem in &self.0 {
+            write!(f, "\n  - {}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+// Reads variables through `lookup` and collects problems instead of stopping
+// at the first one. Empty values count as unset.
+struct Vars<F> {
+    lookup: F,
+    problems: Vec<String>,
+}
+
+impl<F: Fn(&str) -> Option<String>> Vars<F> {
+    fn get(&self, name: &str) -> Option<String> {
+        (self.lookup)(name).filter(|raw| !raw.trim().is_empty())
+    }
+
+    // Parse with `parse`, recording `expected` as the problem on failure.
+    fn parse_with<T>(&mut self, name: &str, expected: &str, parse: impl FnOnce(&str) -> Option<T>) -> Option<T> {
+        let raw = self.get(name)?;
+        let parsed = parse(raw.trim());
+        if parsed.is_none() {
+            self.problems.push(format!("{} must be {}, got {:?}", name, expected, raw));
+        }
+        parsed
+    }
+
+    fn number<T: FromStr>(&mut self, name: &str, default: T) -> T {
+        self.parse_with(name, "a number", |raw| raw.parse().ok()).unwrap_or(default)
+    }
+
+    fn days(&mut self, name: &str) -> Option<u32> {
+        self.parse_with(name, "a number of days between 1 and 36500", |raw| {
+            raw.parse().ok().filter(|days| (1..=36_500).contains(days))
+        })
+    }
+
+    fn flag(&mut self, name: &str, default: bool) -> bool {
+        self.parse_with(name, "true or false", |raw| match raw.to_ascii_lowercase().as_str() {
+            "true" | "1" | "yes" | "on" => Some(true),
+            "false" | "0" | "no" | "off" => Some(false),
+            _ => None,
+        })
+        .unwrap_or(default)
+    }
+
+    fn rate_limit(&mut self, name: &str) -> Option<RateLimit> {
+        self.parse_with(name, "<count>/<period>, e.g. 10/60s", |raw| {
+            rate_limit::parse_quota(raw).map(|quota| RateLimit { spec: raw.to_string(), quota })
+        })
+    }
+}
+
+impl Config {
+    /// Loads the configuration from the process environment.
+    pub fn from_env() -> Result<Self, ConfigError> {
+        Self::from_lookup(|name| std::env::var(name).ok())
+    }
+
+    /// Loads the configuration from any variable source, reporting every
+    /// missing or malformed value at once.
+    pub fn from_lookup(lookup: impl Fn(&str) -> Option<String>) -> Result<Self, ConfigError> {
+        let mut vars = Vars { lookup, problems: Vec::new() };
+
+        let dev_mode = vars.flag("DEV_MODE", false);
+        let database_url = match vars.get("DATABASE_URL") {
+            _ if dev_mode => db::DEV_DATABASE_URL.to_string(),
+            Some(url) => url,
+            None => {
+                vars.problems.push("DATABASE_URL must be set".to_string());
+                String::new()
+            }
+        };
+        let database_max_connections = vars.number("DATABASE_MAX_CONNECTIONS", 5);
+        if database_max_connections == 0 {
+            vars.problems.push("DATABASE_MAX_CONNECTIONS must be at least 1".to_string());
+        }
+        // WAL lets redirects read while the visit writer holds the write lock
+        let sqlite_journal_mode = vars
+            .parse_with("SQLITE_JOURNAL_MODE", "one of wal, delete, truncate, persist, memory, off", |raw| {
+                raw.parse().ok()
+            })
+            .unwrap_or(SqliteJournalMode::Wal);
+        let sqlite_synchronous = vars
+            .parse_with("SQLITE_SYNCHRONOUS", "one of off, normal, full, extra", |raw| raw.parse().ok())
+            .unwrap_or(SqliteSynchronous::Normal);
+        let sqlite_busy_timeout = Duration::from_millis(vars.number("SQLITE_BUSY_TIMEOUT_MS", 5000));
+        let sqlite_foreign_keys = vars.flag("SQLITE_FOREIGN_KEYS", true);
+        let database_connect_attempts = vars
+            .parse_with("DATABASE_CONNECT_ATTEMPTS", "a number of at least 1", |raw| {
+                raw.parse().ok().filter(|attempts| *attempts >= 1)
+            })
+            .unwrap_or(5);
+        let database_retry_delay = Duration::from_millis(vars.number("DATABASE_RETRY_DELAY_MS", 500));
+
+        let listen_addr = match vars.get("LISTEN_ADDR") {
+            Some(_) => vars.parse_with(
+                "LISTEN_ADDR",
+                "an address with a port such as 0.0.0.0:3000 or [::]:3000",
+                |raw| raw.parse::<SocketAddr>().ok(),
+            ),
+            None => {
+                // Accept IPv6 hosts written with or without brackets
+                let host = vars
+                    .parse_with("HOST", "an IP address such as 0.0.0.0 or ::", |raw| {
+                        raw.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>().ok()
+                    })
+                    .unwrap_or(IpAddr::from([127, 0, 0, 1]));
+                let port = vars
+                    .parse_with("PORT", "a number between 0 and 65535", |raw| raw.parse::<u16>().ok())
+                    .unwrap_or(3000);
+                Some(SocketAddr::new(host, port))
+            }
+        }
+        .unwrap_or_else(|| SocketAddr::from(([127, 0, 0, 1], 3000)));
+        let listen_socket = vars.get("LISTEN_SOCKET").map(PathBuf::from);
+        let listen_socket_mode = vars
+            .parse_with("LISTEN_SOCKET_MODE", "octal permissions such as 660", |raw| {
+                u32::from_str_radix(raw.trim_start_matches("0o"), 8).ok().filter(|mode| *mode <= 0o777)
+            })
+            .unwrap_or(0o660);
+
+        let base_url = vars.parse_with("BASE_URL", "an absolute http(s) URL", utils::normalize_base_url);
+        let fallback_redirect_url = vars.parse_with("FALLBACK_REDIRECT_URL", "an absolute http(s) URL", |raw| {
+            url::Url::parse(raw)
+                .ok()
+                .filter(|url| matches!(url.scheme(), "http" | "https"))
+                .map(String::from)
+        });
+
+        let referrer_policy = vars
+            .parse_with(
+                "REFERRER_POLICY",
+                "a comma-separated list of Referrer-Policy values such as no-referrer",
+                parse_referrer_policy,
+            )
+            .unwrap_or(HeaderValue::from_static("no-referrer"));
+        let temporary_redirect_cache_control = vars
+            .parse_with("TEMPORARY_REDIRECT_CACHE_CONTROL", "a valid header value", |raw| {
+                HeaderValue::from_str(raw).ok()
+            })
+            .unwrap_or(HeaderValue::from_static("private, max-age=90"));
+
+        // HSTS over plain http would only be ignored, or pin a mistake
+        let hsts = vars
+            .parse_with("STRICT_TRANSPORT_SECURITY", "a valid header value", |raw| HeaderValue::from_str(raw).ok())
+            .unwrap_or(HeaderValue::from_static(security_headers::DEFAULT_HSTS));
+        let security_headers = SecurityHeaders {
+            hsts: base_url.as_deref().is_some_and(|base| base.starts_with("https://")).then_some(hsts),
+            frame_options: vars
+                .parse_with("X_FRAME_OPTIONS", "deny, sameorigin or off", security_headers::parse_frame_options)
+                .unwrap_or(Some(HeaderValue::from_static("DENY"))),
+            embeddable_routes: match vars.get("FRAME_EMBEDDABLE_ROUTES") {
+                Some(raw) => raw.split(',').map(str::trim).filter(|route| !route.is_empty()).map(String::from).collect(),
+                None => security_headers::DEFAULT_EMBEDDABLE_ROUTES.iter().map(|route| route.to_string()).collect(),
+            },
+            content_security_policy: vars
+                .parse_with("CONTENT_SECURITY_POLICY", "a valid header value", |raw| HeaderValue::from_str(raw).ok())
+                .unwrap_or(HeaderValue::from_static(security_headers::DEFAULT_CSP)),
+        };
+
+        let api_keys = vars
+            .parse_with(
+                "API_KEYS",
+                "a comma-separated list of keys or namespace:key pairs, with namespaces of up to 20 letters, digits, '-' and '_'",
+                auth::parse_namespaced_api_keys,
+            )
+            .unwrap_or_default();
+        let admin_api_keys = vars
+            .get("ADMIN_API_KEYS")
+            .map(|raw| auth::parse_api_keys(&raw))
+            .unwrap_or_default();
+
+        let shorten_rate_limit = vars.rate_limit("SHORTEN_RATE_LIMIT");
+        let hit_rate_limit = vars.rate_limit("HIT_RATE_LIMIT").unwrap_or_else(|| RateLimit {
+            spec: "60/60s".to_string(),
+            quota: rate_limit::parse_quota("60/60s").expect("default quota is valid"),
+        });
+        let unlock_rate_limit = vars.rate_limit("UNLOCK_RATE_LIMIT").unwrap_or_else(|| RateLimit {
+            spec: "10/60s".to_string(),
+            quota: rate_limit::parse_quota("10/60s").expect("default quota is valid"),
+        });
+
+        let redirect_cache_capacity = vars.number("REDIRECT_CACHE_CAPACITY", 10_000);
+        let redirect_cache_ttl = Duration::from_secs(vars.number("REDIRECT_CACHE_TTL_SECS", 60));
+
+        let code_style = vars
+            .parse_with("CODE_STYLE", "random or words", CodeStyle::parse)
+            .unwrap_or_default();
+        let code_alphabet = vars
+            .parse_with("CODE_ALPHABET", "one of unambiguous, base58, base62", CodeAlphabet::parse)
+            .unwrap_or_default();
+        let code_length = vars.number("CODE_LENGTH", 6);
+        if let Err(problem) = utils::validate_code_length(code_alphabet, code_length) {
+            vars.problems.push(problem);
+        }
+
+        let reserved_codes = vars.get("RESERVED_CODES");
+        let bot_user_agents = vars.get("BOT_USER_AGENTS");
+        let code_denylist = vars.parse_with("CODE_DENYLIST_PATH", "a readable text file", |path| {
+            std::fs::read_to_string(path).ok()
+        });
+        let allowed_private_destinations = vars.get("ALLOWED_PRIVATE_DESTINATIONS");
+        let blocked_destinations = vars.get("BLOCKED_DESTINATIONS");
+        let blocked_destinations_path = vars.parse_with("BLOCKED_DESTINATIONS_PATH", "a readable text file", |path| {
+            std::fs::read_to_string(path).ok().map(|_| PathBuf::from(path))
+        });
+        let blocklist_reload_interval = vars
+            .parse_with("BLOCKLIST_RELOAD_SECS", "a number of seconds, at least 1", |raw| {
+                raw.parse::<u64>().ok().filter(|secs| *secs >= 1)
+            })
+            .map(Duration::from_secs);
+        let max_link_ttl_days = vars.days("MAX_LINK_TTL_DAYS");
+
+        let trusted_proxies = match vars.get("TRUSTED_PROXIES") {
+            Some(raw) => client_ip::parse_trusted_proxies(&raw).unwrap_or_else(|bad| {
+                vars.problems.push(format!("TRUSTED_PROXIES contains an invalid entry: {:?}", bad));
+                Vec::new()
+            }),
+            None => Vec::new(),
+        };
+
+        let analytics_mode = vars
+            .parse_with("ANALYTICS_MODE", "full, anonymized or off", AnalyticsMode::parse)
+            .unwrap_or_default();
+        let visit_queue_capacity = vars.number("VISIT_QUEUE_CAPACITY", 10_000);
+        let visit_batch_size = vars.number("VISIT_BATCH_SIZE", 100);
+        let visit_flush_interval = Duration::from_millis(vars.number("VISIT_FLUSH_INTERVAL_MS", 500));
+
+        let purge_expired = vars.flag("PURGE_EXPIRED", true);
+        let cleanup_interval = Duration::from_secs(vars.number("CLEANUP_INTERVAL_SECS", 3600).max(1));
+        let purge_grace = Duration::from_secs(vars.number("PURGE_GRACE_SECS", 7 * 24 * 3600));
+        let visit_retention_days = vars.days("VISIT_RETENTION_DAYS");
+        let geoip_db_path = vars.get("GEOIP_DB_PATH").map(PathBuf::from);
+        let link_check_interval = vars
+            .parse_with("LINK_CHECK_INTERVAL_SECS", "a number of seconds, at least 1", |raw| {
+                raw.parse::<u64>().ok().filter(|secs| *secs >= 1)
+            })
+            .map(Duration::from_secs);
+
+        let visitor_hash_salt = vars.get("VISITOR_HASH_SALT");
+        let cookie_secret = vars.get("COOKIE_SECRET");
+        if cookie_secret.as_ref().is_some_and(|secret| secret.len() < 32) {
+            vars.problems.push("COOKIE_SECRET must be at least 32 bytes".to_string());
+        }
+
+        let shutdown_timeout = Duration::from_secs(vars.number("SHUTDOWN_TIMEOUT_SECS", 30));
+        let max_request_body = vars.number("MAX_REQUEST_BODY_BYTES", 64 * 1024);
+        if max_request_body == 0 {
+            vars.problems.push("MAX_REQUEST_BODY_BYTES must be at least 1".to_string());
+        }
+        let compress_responses = vars.flag("COMPRESS_RESPONSES", true);
+        let cors = CorsPolicy {
+            permissive: vars.flag("CORS_PERMISSIVE", false),
+            origins: match vars.get("CORS_ALLOWED_ORIGINS") {
+                Some(raw) => cors::parse_origins(&raw).unwrap_or_else(|bad| {
+                    vars.problems.push(format!(
+                        "CORS_ALLOWED_ORIGINS must be * or origins like https://app.example.com, got {:?}",
+                        bad
+                    ));
+                    AllowedOrigins::List(Vec::new())
+                }),
+                None => AllowedOrigins::List(Vec::new()),
+            },
+            methods: match vars.get("CORS_ALLOWED_METHODS") {
+                Some(raw) => cors::parse_methods(&raw).unwrap_or_else(|bad| {
+                    vars.problems.push(format!("CORS_ALLOWED_METHODS contains an invalid method: {:?}", bad));
+                    Vec::new()
+                }),
+                None => cors::DEFAULT_METHODS.to_vec(),
+            },
+            max_age: Duration::from_secs(vars.number("CORS_MAX_AGE_SECS", 600)),
+        };
+        let request_timeout = Duration::from_secs(vars.number("REQUEST_TIMEOUT_SECS", 10).max(1));
+        let max_concurrent_requests = vars.number("MAX_CONCURRENT_REQUESTS", 1024);
+        if max_concurrent_requests == 0 {
+            vars.problems.push("MAX_CONCURRENT_REQUESTS must be at least 1".to_string());
+        }
+        let log_format = vars
+            .parse_with("LOG_FORMAT", "pretty or json", LogFormat::parse)
+            .unwrap_or_default();
+
+        if !vars.problems.is_empty() {
+            return Err(ConfigError(vars.problems));
+        }
+        Ok(Config {
+            dev_mode,
+            database_url,
+            database_max_connections,
+            sqlite_journal_mode,
+            sqlite_synchronous,
+            sqlite_busy_timeout,
+            sqlite_foreign_keys,
+            database_connect_attempts,
+            database_retry_delay,
+            listen_addr,
+            listen_socket,
+            listen_socket_mode,
+            base_url,
+            fallback_redirect_url,
+            referrer_policy,
+            temporary_redirect_cache_control,
+            api_keys,
+            admin_api_keys,
+            shorten_rate_limit,
+            hit_rate_limit,
+            unlock_rate_limit,
+            redirect_cache_capacity,
+            redirect_cache_ttl,
+            code_style,
+            code_alphabet,
+            code_length,
+            reserved_codes,
+            bot_user_agents,
+            code_denylist,
+            allowed_private_destinations,
+            blocked_destinations,
+            blocked_destinations_path,
+            blocklist_reload_interval,
+            max_link_ttl_days,
+            trusted_proxies,
+            analytics_mode,
+            visit_queue_capacity,
+            visit_batch_size,
+            visit_flush_interval,
+            purge_expired,
+            cleanup_interval,
+            purge_grace,
+            visit_retention_days,
+            geoip_db_path,
+            link_check_interval,
+            sweep_idempotency_keys: true,
+            visitor_hash_salt,
+            cookie_secret,
+            shutdown_timeout,
+            max_request_body,
+            compress_responses,
+            cors,
+            security_headers,
+            request_timeout,
+            max_concurrent_requests,
+            log_format,
+        })
+    }
+}
+
+const REFERRER_POLICIES: &[&str] = &[
+    "no-referrer",
+    "no-referrer-when-downgrade",
+    "origin",
+    "origin-when-cross-origin",
+    "same-origin",
+    "strict-origin",
+    "strict-origin-when-cross-origin",
+    "unsafe-url",
+];
+
+// A list of known policy tokens; browsers use the last one they understand.
+fn parse_referrer_policy(raw: &str) -> Option<HeaderValue> {
+    let tokens: Vec<String> = raw.split(',').map(|token| token.trim().to_ascii_lowercase()).collect();
+    tokens
+        .iter()
+        .all(|token| REFERRER_POLICIES.contains(&token.as_str()))
+        .then(|| HeaderValue::from_str(&tokens.join(", ")).ok())
+        .flatten()
+}
@@ -0,0 +1,104 @@
+//! Cross-origin access for browsers, from `CORS_ALLOWED_ORIGINS`,
+//! `CORS_ALLOWED_METHODS` and `CORS_MAX_AGE_SECS`. With no origins
+//! configured no CORS headers are sent, so only pages on the service's own
+//! origin can read responses. `CORS_PERMISSIVE=true` allows everything,
+//! for local development.
+
+use crate::{auth, idempotency, request_id};
+use axum::http::{header, HeaderName, HeaderValue, Method};
+use std::time::Duration;
+use tower_http::cors::{AllowOrigin, CorsLayer};
+use url::Url;
+
+pub const DEFAULT_METHODS: &[Method] = &[Method::GET, Method::HEAD, Method::POST, Method::PATCH, Method::DELETE];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AllowedOrigins {
+    /// `*`: any origin
+    Any,
+    /// Exact origins such as `https://app.example.com`; empty allows none
+    List(Vec<HeaderValue>),
+}
+
+#[derive(Debug, Clone)]
+pub struct CorsPolicy {
+    /// Ignore the rest and allow any origin, method and header
+    pub permissive: bool,
+    pub origins: AllowedOrigins,
+    pub methods: Vec<Method>,
+    /// How long browsers may cache a preflight answer
+    pub max_age: Duration,
+}
+
+// `https://App.Example.com:443/` becomes `https://app.example.com`, the way
+// browsers send it in `Origin`. Paths, queries and credentials aren't part
+// of an origin.
+fn parse_origin(raw: &str) -> Option<HeaderValue> {
+    let url = Url::parse(raw).ok()?;
+    let bare = matches!(url.scheme(), "http" | "https")
+        && url.host().is_some()
+        && url.username().is_empty()
+        && url.password().is_none()
+        && url.path() == "/"
+        && url.query().is_none()
+        && url.fragment().is_none();
+    if !bare {
+        return None;
+    }
+    HeaderValue::from_str(&url.origin().ascii_serialization()).ok()
+}
+
+/// Parses a comma-separated `CORS_ALLOWED_ORIGINS` list, returning the
+/// first invalid entry on failure. `*` must stand alone.
+pub fn parse_origins(raw: &str) -> Result<AllowedOrigins, String> {
+    let entries: Vec<&str> = raw.split(',').map(str::trim).filter(|s| !s.is_empty()).collect();
+    if entries == ["*"] {
+        return Ok(AllowedOrigins::Any);
+    }
+    entries
+        .into_iter()
+        .map(|entry| parse_origin(entry).ok_or_else(|| entry.to_string()))
+        .collect::<Result<_, _>>()
+        .map(AllowedOrigins::List)
+}
+
+/// Parses a comma-separated `CORS_ALLOWED_METHODS` list, e.g. `GET, POST`,
+/// returning the first invalid entry on failure.
+pub fn parse_methods(raw: &str) -> Result<Vec<Method>, String> {
+    raw.split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|s| Method::from_bytes(s.to_ascii_uppercase().as_bytes()).map_err(|_| s.to_string()))
+        .collect()
+}
+
+/// The layer enforcing `policy`. Preflights name the request headers the
+/// API reads; responses expose the ones clients act on.
+pub fn layer(policy: &CorsPolicy) -> CorsLayer {
+    if policy.permissive {
+        return CorsLayer::permissive();
+    }
+    let origins = match &policy.origins {
+        AllowedOrigins::Any => AllowOrigin::any(),
+        AllowedOrigins::List(origins) => AllowOrigin::list(origins.iter().cloned()),
+    };
+    CorsLayer::new()
+        .allow_origin(origins)
+        .allow_methods(policy.methods.clone())
+        .allow_headers([
+            header::CONTENT_TYPE,
+            header::AUTHORIZATION,
+            header::IF_NONE_MATCH,
+            HeaderName::from_static(auth::API_KEY_HEADER),
+            HeaderName::from_static(auth::STATS_TOKEN_HEADER),
+            HeaderName::from_static(idempotency::IDEMPOTENCY_KEY),
+        ])
+        .expose_headers([
+            request_id::X_REQUEST_ID,
+            header::RETRY_AFTER,
+            header::LOCATION,
+            header::ETAG,
+            HeaderName::from_static("idempotent-replayed"),
+        ])
+        .max_age(policy.max_age)
+}
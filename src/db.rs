@@ -1,20 +1,333 @@
-use sqlx::{sqlite::SqlitePoolOptions, Pool, Sqlite};
-use std::env;
+use crate::{
+    auth,
+    config::Config,
+    models::{StatsVisibility, UrlRecord},
+    utils::normalize_url,
+};
+use chrono::{DateTime, Utc};
+use sqlx::{
+    migrate::MigrateError,
+    sqlite::{SqliteConnectOptions, SqlitePoolOptions, SqliteQueryResult},
+    Pool, Sqlite, SqliteExecutor,
+};
+use std::{fmt, future::Future, io, path::PathBuf, str::FromStr, time::Duration};
+use tracing::{field, Instrument, Span};
 
 pub type DbPool = Pool<Sqlite>;
 
-pub async fn init_db() -> Result<DbPool, sqlx::Error> {
-    let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+/// Rows a query returned or changed, as recorded on its span.
+pub trait RowCount {
+    fn row_count(&self) -> u64;
+}
 
-    let pool = SqlitePoolOptions::new()
-        .max_connections(5)
-        .connect(&database_url)
-        .await?;
+impl<T> RowCount for Option<T> {
+    fn row_count(&self) -> u64 {
+        self.is_some() as u64
+    }
+}
+
+impl<T> RowCount for Vec<T> {
+    fn row_count(&self) -> u64 {
+        self.len() as u64
+    }
+}
+
+impl RowCount for SqliteQueryResult {
+    fn row_count(&self) -> u64 {
+        self.rows_affected()
+    }
+}
+
+/// Wraps a database call (`fetch_*` or `execute`) in a `db` span named after
+/// `op` and tagged with the short code it concerns. The span records the row
+/// count, or is marked as failed; it only leaves the process when OTLP export
+/// is on.
+pub trait Traced<T>: Future<Output = Result<T, sqlx::Error>> + Sized {
+    fn traced(self, op: &'static str, code: Option<&str>) -> impl Future<Output = Result<T, sqlx::Error>>
+    where
+        T: RowCount,
+    {
+        record_outcome(query_span(op, code), self, RowCount::row_count)
+    }
+
+    /// `traced` for `fetch_one`, which returns exactly one row when it succeeds.
+    fn traced_one(self, op: &'static str, code: Option<&str>) -> impl Future<Output = Result<T, sqlx::Error>> {
+        record_outcome(query_span(op, code), self, |_| 1)
+    }
+}
+
+impl<T, F: Future<Output = Result<T, sqlx::Error>>> Traced<T> for F {}
+
+/// The span `Traced` uses, for streamed queries that record their outcome
+/// themselves with `record_rows` and `record_failure`.
+pub fn query_span(op: &'static str, code: Option<&str>) -> Span {
+    tracing::info_span!(
+        "db",
+        otel.name = op,
+        code,
+        db.rows = field::Empty,
+        otel.status_code = field::Empty,
+        error = field::Empty,
+    )
+}
+
+async fn record_outcome<T>(
+    span: Span,
+    query: impl Future<Output = Result<T, sqlx::Error>>,
+    rows: impl FnOnce(&T) -> u64,
+) -> Result<T, sqlx::Error> {
+    let result = query.instrument(span.clone()).await;
+    match &result {
+        Ok(value) => record_rows(&span, rows(value)),
+        Err(e) => record_failure(&span, e),
+    }
+    result
+}
+
+pub fn record_rows(span: &Span, rows: u64) {
+    // Recorded signed: the OpenTelemetry layer would stringify a u64
+    span.record("db.rows", rows as i64);
+}
+
+pub fn record_failure(span: &Span, error: &sqlx::Error) {
+    span.record("otel.status_code", "ERROR");
+    span.record("error", field::display(error));
+}
+
+/// What `DEV_MODE` runs on: one in-memory database shared by the pool's
+/// connections, gone when the process exits.
+pub const DEV_DATABASE_URL: &str = "sqlite:file:short-url-dev?mode=memory&cache=shared";
+
+// Backoff between connection attempts stops doubling here
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// Why the database couldn't be opened at startup.
+#[derive(Debug)]
+pub enum InitError {
+    /// `DATABASE_URL` can't be used as given
+    InvalidUrl(sqlx::Error),
+    /// `DATABASE_URL` names another database than SQLite, with this scheme
+    Unsupported(String),
+    /// The directory of the SQLite file couldn't be created
+    CreateDir(PathBuf, io::Error),
+    /// Still failing after the last attempt
+    Connect { attempts: u32, source: sqlx::Error },
+    Migrate(MigrateError),
+}
 
-    sqlx::migrate!("./migrations")
-        .run(&pool)
+impl fmt::Display for InitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InitError::InvalidUrl(e) => write!(f, "invalid DATABASE_URL: {}", e),
+            InitError::Unsupported(scheme) => write!(
+                f,
+                "DATABASE_URL must be a sqlite: URL; only SQLite is supported, not {}",
+                scheme
+            ),
+            InitError::CreateDir(dir, e) => write!(f, "failed to create database directory {}: {}", dir.display(), e),
+            InitError::Connect { attempts, source } => {
+                write!(f, "database unavailable after {} attempt(s): {}", attempts, source)
+            }
+            InitError::Migrate(e) => write!(f, "failed to run migrations: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for InitError {}
+
+// Failures that may clear up by themselves: the server not up yet, a volume
+// not mounted yet, or another instance holding a lock while it migrates.
+// SQLite reports the last two as SQLITE_CANTOPEN (14), SQLITE_BUSY (5) and
+// SQLITE_LOCKED (6).
+fn is_transient(e: &sqlx::Error) -> bool {
+    match e {
+        sqlx::Error::Io(_) | sqlx::Error::PoolTimedOut => true,
+        sqlx::Error::Database(db) => {
+            let primary = db.code().and_then(|code| code.parse::<i32>().ok()).map(|code| code & 0xff);
+            matches!(primary, Some(5 | 6 | 14))
+        }
+        _ => false,
+    }
+}
+
+async fn connect_and_migrate(config: &Config, options: SqliteConnectOptions, attempt: u32) -> Result<DbPool, InitError> {
+    let mut pool_options = SqlitePoolOptions::new().max_connections(config.database_max_connections);
+    // An in-memory database lives only while a connection to it is open
+    let url = &config.database_url;
+    if url.contains(":memory:") || url.contains("mode=memory") {
+        pool_options = pool_options.min_connections(1).idle_timeout(None).max_lifetime(None);
+    }
+    let pool = pool_options
+        .connect_with(options)
         .await
-        .expect("Failed to run migrations");
+        .map_err(|source| InitError::Connect { attempts: attempt, source })?;
 
+    if let Err(e) = sqlx::migrate!("./migrations").run(&pool).await {
+        pool.close().await;
+        return Err(match e {
+            MigrateError::Execute(source) if is_transient(&source) => InitError::Connect { attempts: attempt, source },
+            e => InitError::Migrate(e),
+        });
+    }
+    if let Err(e) = backfill(&pool).await {
+        pool.close().await;
+        return Err(match e {
+            source if is_transient(&source) => InitError::Connect { attempts: attempt, source },
+            e => InitError::Migrate(MigrateError::Execute(e)),
+        });
+    }
     Ok(pool)
 }
+
+// Data migrations SQLite can't do on its own, run after the schema ones.
+async fn backfill(pool: &DbPool) -> Result<(), sqlx::Error> {
+    hash_delete_tokens(pool).await?;
+    normalize_urls(pool).await
+}
+
+// Replaces delete tokens stored in plain text, by databases from before
+// `delete_token_hash`, with their hash. Finds nothing once they're all moved.
+async fn hash_delete_tokens(pool: &DbPool) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let plain = sqlx::query!(r#"SELECT id AS "id!", delete_token AS "delete_token!" FROM urls WHERE delete_token IS NOT NULL"#)
+        .fetch_all(&mut *tx)
+        .traced("find_plain_delete_tokens", None)
+        .await?;
+    for row in &plain {
+        let hash = auth::hash_token(&row.delete_token);
+        sqlx::query!("UPDATE urls SET delete_token_hash = ?, delete_token = NULL WHERE id = ?", hash, row.id)
+            .execute(&mut *tx)
+            .traced("hash_delete_token", Some(row.id.as_str()))
+            .await?;
+    }
+    tx.commit().await?;
+    if !plain.is_empty() {
+        tracing::info!("hashed {} delete tokens stored in plain text", plain.len());
+    }
+    Ok(())
+}
+
+// Fills in `normalized_url` where a migration left it empty, with the
+// normalization new links get. Finds nothing once they're all filled.
+async fn normalize_urls(pool: &DbPool) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let missing = sqlx::query!(r#"SELECT id AS "id!", original_url FROM urls WHERE normalized_url IS NULL"#)
+        .fetch_all(&mut *tx)
+        .traced("find_unnormalized_urls", None)
+        .await?;
+    for row in &missing {
+        // Stored URLs always parse; anything else is kept as it was
+        let normalized = normalize_url(&row.original_url).unwrap_or_else(|| row.original_url.trim_end_matches('/').to_string());
+        sqlx::query!("UPDATE urls SET normalized_url = ? WHERE id = ?", normalized, row.id)
+            .execute(&mut *tx)
+            .traced("normalize_url", Some(row.id.as_str()))
+            .await?;
+    }
+    tx.commit().await?;
+    if !missing.is_empty() {
+        tracing::info!("normalized the destinations of {} links", missing.len());
+    }
+    Ok(())
+}
+
+/// Opens the pool and runs migrations. Transient failures are retried up to
+/// `DATABASE_CONNECT_ATTEMPTS` times, waiting `DATABASE_RETRY_DELAY_MS` and
+/// doubling the wait each time, so the service can start before its
+/// database is reachable.
+pub async fn init_db(config: &Config) -> Result<DbPool, InitError> {
+    // Only the scheme is reported, as other URLs tend to carry a password
+    if !config.database_url.starts_with("sqlite:") {
+        let scheme = match config.database_url.split_once(':') {
+            Some((scheme, _)) => format!("{}:", scheme),
+            None => "a bare path".to_string(),
+        };
+        return Err(InitError::Unsupported(scheme));
+    }
+    // Pragmas are applied to every connection the pool opens
+    let mut options = SqliteConnectOptions::from_str(&config.database_url)
+        .map_err(InitError::InvalidUrl)?
+        .journal_mode(config.sqlite_journal_mode)
+        .synchronous(config.sqlite_synchronous)
+        .busy_timeout(config.sqlite_busy_timeout)
+        .foreign_keys(config.sqlite_foreign_keys);
+    // An explicit `?mode=` (e.g. read-only) is left as given
+    if !config.database_url.contains("mode=") {
+        options = options.create_if_missing(true);
+    }
+    if let Some(dir) = options.get_filename().parent()
+        && !dir.as_os_str().is_empty()
+        && !dir.exists()
+    {
+        std::fs::create_dir_all(dir).map_err(|e| InitError::CreateDir(dir.to_path_buf(), e))?;
+        tracing::info!("created database directory {}", dir.display());
+    }
+
+    let mut delay = config.database_retry_delay;
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        match connect_and_migrate(config, options.clone(), attempt).await {
+            Err(InitError::Connect { source, .. })
+                if attempt < config.database_connect_attempts && is_transient(&source) =>
+            {
+                tracing::warn!(
+                    "database not ready (attempt {} of {}): {}; retrying in {:?}",
+                    attempt,
+                    config.database_connect_attempts,
+                    source,
+                    delay
+                );
+                tokio::time::sleep(delay).await;
+                delay = (delay * 2).min(MAX_RETRY_DELAY);
+            }
+            result => return result,
+        }
+    }
+}
+
+// The column lists below spell out `UrlRecord`; `SELECT *` would also return
+// `normalized_url`, which the record doesn't carry. Timestamps need the
+// `DateTime<Utc>` override because SQLite's DATETIME maps to NaiveDateTime,
+// and the TEXT primary key is nullable as far as SQLite is concerned.
+
+/// Loads a link by short code.
+pub async fn find_url<'e>(executor: impl SqliteExecutor<'e>, code: &str) -> Result<Option<UrlRecord>, sqlx::Error> {
+    sqlx::query_as!(
+        UrlRecord,
+        r#"SELECT id AS "id!", original_url, created_at AS "created_at: DateTime<Utc>", expires_at AS "expires_at: DateTime<Utc>", delete_token_hash, redirect_type, created_by_key, max_visits, visit_count, starts_at AS "starts_at: DateTime<Utc>", password_hash, is_active, expiry_defaulted, forward_query, title, description, metadata_fetched_at AS "metadata_fetched_at: DateTime<Utc>", webhook_url, webhook_secret, rotation, ios_url, android_url, untracked_visits, total_visits, bot_visits, stats_visibility, stats_token_hash, namespace, code_strategy, campaign_id, last_checked_at AS "last_checked_at: DateTime<Utc>", last_status, health FROM urls WHERE id = ?"#,
+        code
+    )
+    .fetch_optional(executor)
+    .traced("find_url", Some(code))
+    .await
+}
+
+// Older links for a URL are rarely still the one to reuse
+const MAX_REUSE_CANDIDATES: i64 = 10;
+
+/// Links for a normalized URL that deduplication may hand out again, newest
+/// first: live, not scheduled, not used up, not password protected, not
+/// rotating, not disabled and with the requested stats visibility. Whether one
+/// behaves like the requested link is left to the caller.
+pub async fn find_reusable_urls<'e>(
+    executor: impl SqliteExecutor<'e>,
+    normalized_url: &str,
+    stats_visibility: StatsVisibility,
+    namespace: Option<&str>,
+    now: DateTime<Utc>,
+) -> Result<Vec<UrlRecord>, sqlx::Error> {
+    let stats_visibility = stats_visibility.as_str();
+    sqlx::query_as!(
+        UrlRecord,
+        r#"SELECT id AS "id!", original_url, created_at AS "created_at: DateTime<Utc>", expires_at AS "expires_at: DateTime<Utc>", delete_token_hash, redirect_type, created_by_key, max_visits, visit_count, starts_at AS "starts_at: DateTime<Utc>", password_hash, is_active, expiry_defaulted, forward_query, title, description, metadata_fetched_at AS "metadata_fetched_at: DateTime<Utc>", webhook_url, webhook_secret, rotation, ios_url, android_url, untracked_visits, total_visits, bot_visits, stats_visibility, stats_token_hash, namespace, code_strategy, campaign_id, last_checked_at AS "last_checked_at: DateTime<Utc>", last_status, health FROM urls WHERE normalized_url = ? AND (expires_at IS NULL OR expires_at > ?) AND (starts_at IS NULL OR starts_at <= ?) AND (max_visits IS NULL OR visit_count < max_visits) AND password_hash IS NULL AND rotation IS NULL AND is_active = 1 AND stats_visibility = ? AND namespace IS ? ORDER BY created_at DESC LIMIT ?"#,
+        normalized_url,
+        now,
+        now,
+        stats_visibility,
+        namespace,
+        MAX_REUSE_CANDIDATES
+    )
+    .fetch_all(executor)
+    .traced("find_reusable_urls", None)
+    .await
+}
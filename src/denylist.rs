@@ -0,0 +1,78 @@
+//! Keeps generated codes from spelling offensive words. Candidates are folded
+//! to lowercase letters, with digits and symbols read as the letters they
+//! resemble, and rejected if they contain a denylisted word.
+
+/// Bundled denylist, embedded at compile time.
+const BUNDLED: &str = include_str!("words/denylist.txt");
+
+/// Candidates the generation loop may discard before giving up. Rejections
+/// have their own budget so they don't use up the retries meant for
+/// collisions.
+pub const MAX_REJECTED_CANDIDATES: usize = 100;
+
+/// Lowercases `raw`, reads look-alikes as letters (`0` → `o`, `1`/`l` → `i`,
+/// `3` → `e`, `4`/`@` → `a`, `5`/`$` → `s`, `7` → `t`, `8` → `b`, `6`/`9` → `g`,
+/// `2` → `z`) and drops everything else, so `5h-1t` and `SHIT` fold alike.
+pub fn fold(raw: &str) -> String {
+    raw.chars()
+        .filter_map(|c| match c.to_ascii_lowercase() {
+            '0' => Some('o'),
+            '1' | 'l' | '|' | '!' => Some('i'),
+            '3' => Some('e'),
+            '4' | '@' => Some('a'),
+            '5' | '$' => Some('s'),
+            '7' | '+' => Some('t'),
+            '8' => Some('b'),
+            '6' | '9' => Some('g'),
+            '2' => Some('z'),
+            c if c.is_ascii_lowercase() => Some(c),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Folded denylist words, checked against every generated code.
+#[derive(Debug, Clone)]
+pub struct CodeFilter {
+    words: Vec<String>,
+}
+
+impl CodeFilter {
+    /// The bundled denylist plus `extra` (one word per line, `#` comments),
+    /// e.g. the contents of `CODE_DENYLIST_PATH`.
+    pub fn new(extra: Option<&str>) -> Self {
+        let mut words: Vec<String> = BUNDLED
+            .lines()
+            .chain(extra.unwrap_or_default().lines())
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(fold)
+            .filter(|word| !word.is_empty())
+            .collect();
+        words.sort();
+        words.dedup();
+        CodeFilter { words }
+    }
+
+    pub fn len(&self) -> usize {
+        self.words.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.words.is_empty()
+    }
+
+    /// Whether `code` contains a denylisted word once folded.
+    pub fn is_blocked(&self, code: &str) -> bool {
+        let folded = fold(code);
+        self.words.iter().any(|word| folded.contains(word.as_str()))
+    }
+
+    /// Draws candidates from `next` until one passes, discarding at most
+    /// `MAX_REJECTED_CANDIDATES` of them.
+    pub fn pick(&self, mut next: impl FnMut() -> String) -> Option<String> {
+        (0..=MAX_REJECTED_CANDIDATES)
+            .map(|_| next())
+            .find(|candidate| !self.is_blocked(candidate))
+    }
+}
@@ -0,0 +1,251 @@
+use crate::{client_ip::IpNet, error::AppError};
+use reqwest::{
+    dns::{Addrs, Name, Resolve, Resolving},
+    redirect, ClientBuilder,
+};
+use std::{
+    error::Error,
+    fmt, io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+use url::{Host, Url};
+
+const RESOLVE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Which destinations may be shortened. Everything on a public address is
+/// allowed; loopback, link-local and private ranges need an explicit
+/// `ALLOWED_PRIVATE_DESTINATIONS` entry.
+#[derive(Debug, Default)]
+pub struct DestinationPolicy {
+    allowed_hosts: Vec<String>,
+    allowed_networks: Vec<IpNet>,
+}
+
+/// Why a name couldn't be resolved to addresses that may be requested.
+#[derive(Debug)]
+pub enum ResolveError {
+    /// The lookup failed or timed out
+    Lookup(io::Error),
+    /// The name has an address the policy doesn't allow
+    Refused(IpAddr),
+}
+
+impl fmt::Display for ResolveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ResolveError::Lookup(e) => write!(f, "lookup failed: {}", e),
+            ResolveError::Refused(ip) => write!(f, "resolves to {}, which is not an allowed destination", ip),
+        }
+    }
+}
+
+impl Error for ResolveError {}
+
+impl DestinationPolicy {
+    /// Parses a comma-separated list of hostnames and IPs/CIDR ranges.
+    pub fn parse(raw: &str) -> Self {
+        let mut policy = Self::default();
+        for entry in raw.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            match IpNet::parse(entry) {
+                Some(net) => policy.allowed_networks.push(net),
+                None => policy.allowed_hosts.push(entry.to_ascii_lowercase()),
+            }
+        }
+        policy
+    }
+
+    fn allows_host(&self, host: &str) -> bool {
+        self.allowed_hosts.iter().any(|allowed| allowed == host)
+    }
+
+    fn allows_ip(&self, ip: IpAddr) -> bool {
+        self.allowed_networks.iter().any(|net| net.contains(ip))
+    }
+
+    /// Checks a destination before it's stored: only `http`/`https`, not this
+    /// service itself, and not a host on a private network.
+    pub async fn check(&self, raw: &str, service_base: &str) -> Result<(), AppError> {
+        let url = Url::parse(raw.trim()).map_err(|_| AppError::InvalidUrl)?;
+        let Some(domain) = self.check_static(&url, service_base)? else {
+            return Ok(());
+        };
+        if is_local_name(domain) {
+            return Err(AppError::PrivateDestination(domain.to_ascii_lowercase()));
+        }
+        // A name we can't resolve right now can't be shown to be private;
+        // let it through rather than fail on DNS hiccups. Requests to it go
+        // through `client_builder`'s resolver, which isn't as forgiving.
+        match self.resolve(domain).await {
+            Err(ResolveError::Refused(_)) => Err(AppError::PrivateDestination(domain.to_ascii_lowercase())),
+            _ => Ok(()),
+        }
+    }
+
+    /// The part of `check` that needs no DNS, for each hop of a request sent
+    /// with a `client_builder` client. Its resolver vets the addresses the
+    /// request actually connects to, so a name can't pass a check here and
+    /// then resolve somewhere private for the request itself.
+    pub fn check_request(&self, raw: &str, service_base: &str) -> Result<(), AppError> {
+        let url = Url::parse(raw.trim()).map_err(|_| AppError::InvalidUrl)?;
+        self.check_static(&url, service_base).map(drop)
+    }
+
+    // Scheme, loop and IP literal checks. Returns the domain that is left to
+    // resolve, unless it's allow-listed.
+    fn check_static<'u>(&self, url: &'u Url, service_base: &str) -> Result<Option<&'u str>, AppError> {
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(AppError::UnsupportedScheme(url.scheme().to_string()));
+        }
+
+        let host = url.host().ok_or(AppError::InvalidUrl)?;
+
+        if points_at(url, service_base) {
+            return Err(AppError::RedirectLoop);
+        }
+
+        let host_name = host.to_string().to_ascii_lowercase();
+        if self.allows_host(&host_name) {
+            return Ok(None);
+        }
+
+        let ip = match host {
+            Host::Ipv4(ip) => IpAddr::V4(ip),
+            Host::Ipv6(ip) => IpAddr::V6(ip),
+            Host::Domain(domain) => return Ok(Some(domain)),
+        };
+        if is_non_public(ip) && !self.allows_ip(ip) {
+            return Err(AppError::PrivateDestination(host_name));
+        }
+        Ok(None)
+    }
+
+    /// Looks `domain` up and returns its addresses, unless one of them is on
+    /// a network the policy doesn't allow. Allow-listed names may resolve
+    /// anywhere.
+    pub async fn resolve(&self, domain: &str) -> Result<Vec<SocketAddr>, ResolveError> {
+        let addrs: Vec<SocketAddr> = tokio::time::timeout(RESOLVE_TIMEOUT, tokio::net::lookup_host((domain, 0)))
+            .await
+            .map_err(|_| ResolveError::Lookup(io::ErrorKind::TimedOut.into()))?
+            .map_err(ResolveError::Lookup)?
+            .collect();
+        if self.allows_host(&domain.to_ascii_lowercase()) {
+            return Ok(addrs);
+        }
+        match addrs.iter().find(|addr| is_non_public(addr.ip()) && !self.allows_ip(addr.ip())) {
+            Some(addr) => Err(ResolveError::Refused(addr.ip())),
+            None => Ok(addrs),
+        }
+    }
+}
+
+// Resolves names for reqwest through the policy, so connections only go to
+// the addresses it vetted.
+struct PolicyResolver(Arc<DestinationPolicy>);
+
+impl Resolve for PolicyResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let policy = self.0.clone();
+        Box::pin(async move {
+            let addrs = policy.resolve(name.as_str()).await?;
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// A client builder for requests the service makes to destinations. Names
+/// are resolved through `policy`, so only allowed addresses are connected to
+/// and names that don't resolve fail the request. Redirects aren't followed,
+/// leaving each hop to `check_request`, and proxies aren't used, as they
+/// would do the resolving themselves.
+pub fn client_builder(policy: Arc<DestinationPolicy>) -> ClientBuilder {
+    reqwest::Client::builder()
+        .redirect(redirect::Policy::none())
+        .no_proxy()
+        .dns_resolver(PolicyResolver(policy))
+}
+
+/// Whether a request failed because its host resolved to an address the
+/// policy refuses.
+pub fn is_refused(err: &reqwest::Error) -> bool {
+    let mut source = err.source();
+    while let Some(e) = source {
+        if matches!(e.downcast_ref(), Some(ResolveError::Refused(_))) {
+            return true;
+        }
+        source = e.source();
+    }
+    false
+}
+
+// Compare scheme-less authority with the base used for short links, so both
+// http and https variants of the service are caught.
+fn points_at(url: &Url, service_base: &str) -> bool {
+    let Ok(base) = Url::parse(service_base) else {
+        return false;
+    };
+    let authority = |u: &Url| {
+        u.host_str()
+            .map(|h| (h.to_ascii_lowercase(), u.port_or_known_default()))
+    };
+    authority(url).is_some() && authority(url) == authority(&base)
+}
+
+fn is_local_name(domain: &str) -> bool {
+    let domain = domain.trim_end_matches('.');
+    domain == "localhost"
+        || domain.ends_with(".localhost")
+        || domain.ends_with(".local")
+        || domain.ends_with(".internal")
+}
+
+fn is_non_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => is_non_public_v4(v4),
+        IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+            Some(v4) => is_non_public_v4(v4),
+            None => is_non_public_v6(v6),
+        },
+    }
+}
+
+fn is_non_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_broadcast()
+        || a == 0
+        // Carrier-grade NAT, 100.64.0.0/10
+        || (a == 100 && (64..128).contains(&b))
+}
+
+fn is_non_public_v6(ip: Ipv6Addr) -> bool {
+    ip.is_loopback() || ip.is_unspecified() || ip.is_unique_local() || ip.is_unicast_link_local()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn loopback_names_are_refused_unless_allowed() {
+        let refused = DestinationPolicy::default().resolve("localhost").await;
+        assert!(matches!(refused, Err(ResolveError::Refused(ip)) if ip.is_loopback()), "{:?}", refused);
+
+        for allowing in ["localhost", "127.0.0.0/8,::1/128"] {
+            let addrs = DestinationPolicy::parse(allowing).resolve("localhost").await.unwrap();
+            assert!(!addrs.is_empty() && addrs.iter().all(|addr| addr.ip().is_loopback()), "{}", allowing);
+        }
+    }
+
+    #[tokio::test]
+    async fn clients_fail_requests_to_refused_addresses() {
+        let client = client_builder(Arc::new(DestinationPolicy::default())).build().unwrap();
+
+        let err = client.get("http://localhost:9/").send().await.unwrap_err();
+        assert!(is_refused(&err), "{:?}", err);
+    }
+}
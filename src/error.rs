@@ -1,16 +1,156 @@
 use axum::{
-    http::StatusCode,
+    http::{header, HeaderValue, StatusCode},
+    BoxError,
     response::{IntoResponse, Response},
     Json,
 };
-use serde_json::json;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use utoipa::ToSchema;
+use validator::{ValidationErrors, ValidationErrorsKind};
+
+/// Flattens validator output into `{"field": ["message", ...]}`, falling back
+/// to the validator code for rules without a message.
+pub fn field_messages(errors: &ValidationErrors) -> BTreeMap<String, Vec<String>> {
+    let mut fields = BTreeMap::new();
+    collect_messages(errors, "", &mut fields);
+    fields
+}
+
+// Nested structs and lists are flattened into paths like `destinations[1].weight`.
+fn collect_messages(errors: &ValidationErrors, prefix: &str, fields: &mut BTreeMap<String, Vec<String>>) {
+    for (field, kind) in errors.errors() {
+        let path = if prefix.is_empty() {
+            field.to_string()
+        } else {
+            format!("{}.{}", prefix, field)
+        };
+        match kind {
+            ValidationErrorsKind::Field(errs) => {
+                let messages = errs.iter().map(|err| match &err.message {
+                    Some(message) => message.to_string(),
+                    None => format!("{} is invalid ({})", field, err.code),
+                });
+                fields.entry(path).or_default().extend(messages);
+            }
+            ValidationErrorsKind::Struct(nested) => collect_messages(nested, &path, fields),
+            ValidationErrorsKind::List(items) => {
+                for (index, nested) in items {
+                    collect_messages(nested, &format!("{}[{}]", path, index), fields);
+                }
+            }
+        }
+    }
+}
+
+/// Stable identifier sent as `code` in every JSON error, so clients can branch
+/// without matching on messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ErrorCode {
+    /// Unknown short code
+    UrlNotFound,
+    /// Unknown campaign id
+    CampaignNotFound,
+    /// `details.expired_at` says when
+    UrlExpired,
+    /// The link's `starts_at` is still in the future
+    NotYetActive,
+    VisitLimitReached,
+    LinkDisabled,
+    InvalidUrl,
+    /// Destination scheme other than `http`/`https`; `details.scheme`
+    UnsupportedScheme,
+    /// Destination is this shortener itself
+    RedirectLoop,
+    /// Destination host is on a private network; `details.host`
+    PrivateDestination,
+    /// Destination is under a blocklisted domain; `details.domain`
+    BlockedDestination,
+    /// `"verify": true` found the destination dead; `details.status` is its
+    /// answer, or null when it couldn't be reached
+    DestinationUnreachable,
+    /// `details.fields` maps each invalid field to its messages
+    ValidationFailed,
+    /// `from` isn't before `to`
+    InvalidTimeRange,
+    /// Malformed body, query or header
+    BadRequest,
+    /// Request body over the configured size limit
+    PayloadTooLarge,
+    /// Custom code in use; `details` has `short_code`, `same_url` and `suggestions`
+    CodeTaken,
+    CodeReserved,
+    IdempotencyKeyReused,
+    IdempotencyKeyInProgress,
+    /// `conflict=fail` import hit existing codes; `details.conflicts` lists `line` and `short_code`
+    ImportConflict,
+    Unauthorized,
+    Forbidden,
+    /// `details.retry_after_secs` matches the `Retry-After` header
+    RateLimited,
+    /// Too many requests in flight; retry after `Retry-After`
+    Overloaded,
+    /// The request took longer than `REQUEST_TIMEOUT_SECS`
+    RequestTimeout,
+    InternalError,
+}
 
 #[derive(Debug)]
 pub enum AppError {
     DatabaseError(sqlx::Error),
     UrlNotFound,
+    CampaignNotFound,
+    UrlExpired(DateTime<Utc>),
+    /// The link is scheduled and its `starts_at` hasn't been reached yet
+    NotYetActive,
+    VisitLimitReached,
+    /// The link was disabled by its owner
+    LinkDisabled,
     InvalidUrl,
-    CodeAlreadyExists,
+    /// The destination's scheme isn't http or https; carries the scheme
+    UnsupportedScheme(String),
+    /// The destination points back at this service
+    RedirectLoop,
+    /// The destination resolves to a private network; carries the host
+    PrivateDestination(String),
+    /// The destination is under this blocklisted domain
+    BlockedDestination(String),
+    /// Verification found the destination dead; carries the status it
+    /// answered, `None` when the name didn't resolve or the connection failed
+    DestinationUnreachable(Option<u16>),
+    /// The request body failed validation; rendered as a field → messages map
+    Validation(ValidationErrors),
+    /// `from` is not before `to` in a visit range
+    InvalidTimeRange,
+    BadRequest(String),
+    /// The request body is over the size limit
+    PayloadTooLarge,
+    /// The custom code is taken; carries details for the 409 body
+    CodeAlreadyExists {
+        short_code: String,
+        /// Whether the existing link points at the same (normalized) URL
+        same_url: bool,
+        /// Free alternatives to the requested code
+        suggestions: Vec<String>,
+    },
+    ReservedCode,
+    /// The `Idempotency-Key` was already used with a different request body
+    IdempotencyKeyReused,
+    /// An earlier request with the same `Idempotency-Key` is still running
+    IdempotencyKeyInProgress,
+    /// An import with `conflict=fail` found these (line, short code) pairs taken
+    ImportConflict(Vec<(usize, String)>),
+    Unauthorized,
+    Forbidden,
+    /// Too many requests; carries the number of seconds until a retry may succeed
+    RateLimited(u64),
+    /// Shed because `MAX_CONCURRENT_REQUESTS` requests are already running
+    Overloaded,
+    /// The handler didn't finish within the request timeout
+    RequestTimeout,
     InternalServerError(String),
 }
 
@@ -20,26 +160,244 @@ impl From<sqlx::Error> for AppError {
     }
 }
 
-impl IntoResponse for AppError {
-    fn into_response(self) -> Response {
-        let (status, error_message) = match self {
+impl AppError {
+    /// Maps a body extractor's rejection, telling oversized bodies apart
+    /// from malformed ones.
+    pub fn body_rejection(status: StatusCode, message: String) -> Self {
+        if status == StatusCode::PAYLOAD_TOO_LARGE {
+            AppError::PayloadTooLarge
+        } else {
+            AppError::BadRequest(message)
+        }
+    }
+
+    /// Stable machine-readable identifier sent as `code` in JSON errors.
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            AppError::DatabaseError(_) | AppError::InternalServerError(_) => ErrorCode::InternalError,
+            AppError::UrlNotFound => ErrorCode::UrlNotFound,
+            AppError::CampaignNotFound => ErrorCode::CampaignNotFound,
+            AppError::UrlExpired(_) => ErrorCode::UrlExpired,
+            AppError::NotYetActive => ErrorCode::NotYetActive,
+            AppError::VisitLimitReached => ErrorCode::VisitLimitReached,
+            AppError::LinkDisabled => ErrorCode::LinkDisabled,
+            AppError::InvalidUrl => ErrorCode::InvalidUrl,
+            AppError::UnsupportedScheme(_) => ErrorCode::UnsupportedScheme,
+            AppError::RedirectLoop => ErrorCode::RedirectLoop,
+            AppError::PrivateDestination(_) => ErrorCode::PrivateDestination,
+            AppError::BlockedDestination(_) => ErrorCode::BlockedDestination,
+            AppError::DestinationUnreachable(_) => ErrorCode::DestinationUnreachable,
+            AppError::Validation(_) => ErrorCode::ValidationFailed,
+            AppError::InvalidTimeRange => ErrorCode::InvalidTimeRange,
+            AppError::BadRequest(_) => ErrorCode::BadRequest,
+            AppError::PayloadTooLarge => ErrorCode::PayloadTooLarge,
+            AppError::CodeAlreadyExists { .. } => ErrorCode::CodeTaken,
+            AppError::ReservedCode => ErrorCode::CodeReserved,
+            AppError::IdempotencyKeyReused => ErrorCode::IdempotencyKeyReused,
+            AppError::IdempotencyKeyInProgress => ErrorCode::IdempotencyKeyInProgress,
+            AppError::ImportConflict(_) => ErrorCode::ImportConflict,
+            AppError::Unauthorized => ErrorCode::Unauthorized,
+            AppError::Forbidden => ErrorCode::Forbidden,
+            AppError::RateLimited(_) => ErrorCode::RateLimited,
+            AppError::Overloaded => ErrorCode::Overloaded,
+            AppError::RequestTimeout => ErrorCode::RequestTimeout,
+        }
+    }
+
+    /// Context sent as `details` alongside the code, for errors that have any.
+    pub fn details(&self) -> Option<Value> {
+        match self {
+            AppError::UrlExpired(expires_at) => Some(json!({ "expired_at": expires_at })),
+            AppError::UnsupportedScheme(scheme) => Some(json!({ "scheme": scheme })),
+            AppError::PrivateDestination(host) => Some(json!({ "host": host })),
+            AppError::BlockedDestination(domain) => Some(json!({ "domain": domain })),
+            AppError::DestinationUnreachable(status) => Some(json!({ "status": status })),
+            AppError::Validation(errors) => Some(json!({ "fields": field_messages(errors) })),
+            AppError::CodeAlreadyExists { short_code, same_url, suggestions } => Some(json!({
+                "short_code": short_code,
+                "same_url": same_url,
+                "suggestions": suggestions,
+            })),
+            AppError::ImportConflict(conflicts) => Some(json!({
+                "conflicts": conflicts
+                    .iter()
+                    .map(|(line, short_code)| json!({ "line": line, "short_code": short_code }))
+                    .collect::<Vec<_>>(),
+            })),
+            AppError::RateLimited(secs) => Some(json!({ "retry_after_secs": secs })),
+            _ => None,
+        }
+    }
+
+    /// The human-readable message, as sent in `error`.
+    pub fn message(self) -> String {
+        self.status_and_message().1
+    }
+
+    fn retry_after(&self) -> Option<u64> {
+        match self {
+            AppError::RateLimited(secs) => Some(*secs),
+            AppError::Overloaded => Some(1),
+            _ => None,
+        }
+    }
+
+    fn status_and_message(self) -> (StatusCode, String) {
+        match self {
             AppError::DatabaseError(e) => {
                 tracing::error!("Database error: {:?}", e);
                 (StatusCode::INTERNAL_SERVER_ERROR, "Database error".to_string())
             }
             AppError::UrlNotFound => (StatusCode::NOT_FOUND, "URL not found".to_string()),
+            AppError::CampaignNotFound => (StatusCode::NOT_FOUND, "Campaign not found".to_string()),
+            AppError::UrlExpired(_) => (StatusCode::GONE, "URL expired".to_string()),
+            AppError::NotYetActive => (StatusCode::NOT_FOUND, "URL not yet active".to_string()),
+            AppError::VisitLimitReached => (StatusCode::GONE, "Visit limit reached".to_string()),
+            AppError::LinkDisabled => (StatusCode::GONE, "Link disabled".to_string()),
             AppError::InvalidUrl => (StatusCode::BAD_REQUEST, "Invalid URL".to_string()),
-            AppError::CodeAlreadyExists => (StatusCode::CONFLICT, "Short code already exists".to_string()),
+            AppError::UnsupportedScheme(scheme) => (
+                StatusCode::BAD_REQUEST,
+                format!("Only http and https URLs can be shortened, got '{}:'", scheme),
+            ),
+            AppError::RedirectLoop => (
+                StatusCode::BAD_REQUEST,
+                "URL points back at this shortener, which would create a redirect loop".to_string(),
+            ),
+            AppError::PrivateDestination(host) => (
+                StatusCode::BAD_REQUEST,
+                format!("URL host '{}' is on a private, loopback or link-local network", host),
+            ),
+            AppError::BlockedDestination(domain) => (
+                StatusCode::BAD_REQUEST,
+                format!("URL domain '{}' is blocklisted", domain),
+            ),
+            AppError::DestinationUnreachable(Some(status)) => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                format!("URL answered {}; check it for typos", status),
+            ),
+            AppError::DestinationUnreachable(None) => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "URL host could not be reached".to_string(),
+            ),
+            AppError::Validation(errors) => {
+                let summary = field_messages(&errors)
+                    .into_iter()
+                    .map(|(field, messages)| format!("{}: {}", field, messages.join(", ")))
+                    .collect::<Vec<_>>()
+                    .join("; ");
+                (StatusCode::UNPROCESSABLE_ENTITY, format!("Validation failed: {}", summary))
+            }
+            AppError::InvalidTimeRange => (StatusCode::BAD_REQUEST, "from must be before to".to_string()),
+            AppError::BadRequest(msg) => (StatusCode::BAD_REQUEST, msg),
+            AppError::PayloadTooLarge => (StatusCode::PAYLOAD_TOO_LARGE, "Request body is too large".to_string()),
+            AppError::CodeAlreadyExists { .. } => (StatusCode::CONFLICT, "Short code already exists".to_string()),
+            AppError::ReservedCode => (StatusCode::UNPROCESSABLE_ENTITY, "Short code is reserved".to_string()),
+            AppError::IdempotencyKeyReused => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "Idempotency-Key was already used with a different request".to_string(),
+            ),
+            AppError::IdempotencyKeyInProgress => (
+                StatusCode::CONFLICT,
+                "A request with this Idempotency-Key is still in progress".to_string(),
+            ),
+            AppError::ImportConflict(_) => (
+                StatusCode::CONFLICT,
+                "Some short codes already exist; nothing was imported".to_string(),
+            ),
+            AppError::Unauthorized => (StatusCode::UNAUTHORIZED, "Missing or invalid API key".to_string()),
+            AppError::Forbidden => (StatusCode::FORBIDDEN, "Forbidden".to_string()),
+            AppError::RateLimited(_) => (StatusCode::TOO_MANY_REQUESTS, "Too many requests".to_string()),
+            AppError::Overloaded => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Server is busy, try again shortly".to_string(),
+            ),
+            AppError::RequestTimeout => (StatusCode::GATEWAY_TIMEOUT, "Request timed out".to_string()),
             AppError::InternalServerError(msg) => {
                 tracing::error!("Internal server error: {}", msg);
                 (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error".to_string())
             }
+        }
+    }
+
+    /// Renders the error as a single `text/plain` line, for clients that
+    /// asked for plain text instead of JSON.
+    pub fn into_plain_response(self) -> Response {
+        let retry_after = self.retry_after();
+        let (status, error_message) = self.status_and_message();
+
+        let line = error_message.replace(['\r', '\n'], " ");
+        let mut response = (status, format!("{}\n", line)).into_response();
+        if let Some(secs) = retry_after {
+            response.headers_mut().insert(header::RETRY_AFTER, HeaderValue::from(secs));
+        }
+        response
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let retry_after = self.retry_after();
+        let code = self.code();
+        let details = self.details();
+
+        // For validation errors the field map carries the details
+        let (status, error_message) = if code == ErrorCode::ValidationFailed {
+            (StatusCode::UNPROCESSABLE_ENTITY, "Validation failed".to_string())
+        } else {
+            self.status_and_message()
         };
 
-        let body = Json(json!({
-            "error": error_message
-        }));
+        let mut body = json!({
+            "error": error_message,
+            "code": code
+        });
+        if let Some(details) = details {
+            // These were top-level fields before `details` existed; keep them
+            // there for existing clients
+            if matches!(code, ErrorCode::ValidationFailed | ErrorCode::CodeTaken | ErrorCode::UrlExpired)
+                && let Some(legacy) = details.as_object()
+            {
+                for (key, value) in legacy {
+                    body[key] = value.clone();
+                }
+            }
+            body["details"] = details;
+        }
+        if let Some(request_id) = crate::request_id::current() {
+            body["request_id"] = request_id.into();
+        }
+        let body = Json(body);
+
+        let mut response = (status, body).into_response();
+        if let Some(secs) = retry_after {
+            response.headers_mut().insert(header::RETRY_AFTER, HeaderValue::from(secs));
+        }
+        response
+    }
+}
+
+/// Swaps the plain-text 413 that `RequestBodyLimitLayer` sends, before any
+/// handler runs, for the JSON error.
+pub async fn json_payload_too_large(response: Response) -> Response {
+    let is_json = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|ct| ct.as_bytes().starts_with(b"application/json"));
+    if response.status() == StatusCode::PAYLOAD_TOO_LARGE && !is_json {
+        AppError::PayloadTooLarge.into_response()
+    } else {
+        response
+    }
+}
 
-        (status, body).into_response()
+/// Maps errors from the `tower` layers of the service stack (timeout, load
+/// shedding) to JSON errors instead of axum's plain-text 500.
+pub async fn middleware_error(err: BoxError) -> AppError {
+    if err.is::<tower::timeout::error::Elapsed>() {
+        AppError::RequestTimeout
+    } else if err.is::<tower::load_shed::error::Overloaded>() {
+        AppError::Overloaded
+    } else {
+        AppError::InternalServerError(format!("middleware error: {}", err))
     }
 }
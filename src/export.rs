@@ -0,0 +1,182 @@
+use crate::{
+    auth::verify_stats_access,
+    db,
+    error::AppError,
+    handlers::hides_destinations,
+    models::{ErrorResponse, StatsParams, VisitStats},
+    state::AppState,
+};
+use axum::{
+    body::{Body, Bytes},
+    extract::{rejection::QueryRejection, Path, Query, State},
+    http::{header, HeaderMap, HeaderValue},
+    response::{IntoResponse, Response},
+};
+use chrono::{DateTime, SecondsFormat, Utc};
+use futures_util::{stream, StreamExt};
+use sqlx::{QueryBuilder, Sqlite};
+use tokio::sync::mpsc;
+use tracing::{Instrument, Span};
+
+const CSV_HEADER: &str = "visited_at,ip_address,user_agent,referrer,source,destination,platform\r\n";
+// Rows are written out in chunks of roughly this many bytes
+const CHUNK_SIZE: usize = 64 * 1024;
+
+// Quote a field when needed (RFC 4180). Values that a spreadsheet would
+// evaluate as a formula get a leading apostrophe so imports stay inert.
+fn csv_field(out: &mut String, value: Option<&str>) {
+    let Some(value) = value else {
+        return;
+    };
+    let formula = value.starts_with(['=', '+', '-', '@', '\t', '\r']);
+    if formula || value.contains([',', '"', '\r', '\n']) {
+        out.push('"');
+        if formula {
+            out.push('\'');
+        }
+        out.push_str(&value.replace('"', "\"\""));
+        out.push('"');
+    } else {
+        out.push_str(value);
+    }
+}
+
+fn csv_row(out: &mut String, visit: &VisitStats) {
+    out.push_str(&visit.visited_at.to_rfc3339_opts(SecondsFormat::Millis, true));
+    out.push(',');
+    csv_field(out, visit.ip_address.as_deref());
+    out.push(',');
+    csv_field(out, visit.user_agent.as_deref());
+    out.push(',');
+    csv_field(out, visit.referrer.as_deref());
+    out.push(',');
+    csv_field(out, Some(&visit.source));
+    out.push(',');
+    csv_field(out, visit.destination.as_deref());
+    out.push(',');
+    csv_field(out, visit.platform.as_deref());
+    out.push_str("\r\n");
+}
+
+#[utoipa::path(
+    get,
+    path = "/stats/{code}/export.csv",
+    params(
+        ("code" = String, Path, description = "Short code"),
+        StatsParams
+    ),
+    responses(
+        (status = 200, description = "All visits, oldest first, as CSV (`visited_at,ip_address,user_agent,referrer,source,destination,platform`). Only `from`/`to` and `stats_token` apply.", content_type = "text/csv"),
+        (status = 400, description = "Malformed query parameters or from not before to", body = ErrorResponse),
+        (status = 403, description = "Private link and missing or wrong stats token", body = ErrorResponse),
+        (status = 404, description = "URL not found", body = ErrorResponse),
+        (status = 410, description = "URL expired", body = ErrorResponse)
+    ),
+    security(
+        (),
+        ("stats_token" = [])
+    )
+)]
+#[tracing::instrument(skip_all, fields(code = %code))]
+pub async fn export_visits(
+    State(state): State<AppState>,
+    Path(code): Path<String>,
+    headers: HeaderMap,
+    params: Result<Query<StatsParams>, QueryRejection>,
+) -> Result<Response, AppError> {
+    let Query(params) = params.map_err(|rejection| AppError::BadRequest(rejection.body_text()))?;
+    if let (Some(from), Some(to)) = (params.from, params.to)
+        && from >= to
+    {
+        return Err(AppError::InvalidTimeRange);
+    }
+
+    let url_record = db::find_url(&state.pool, &code).await?;
+    let url = url_record.ok_or(AppError::UrlNotFound)?;
+    verify_stats_access(&state, &url, &headers, params.stats_token.as_deref())?;
+    if let Some(expires_at) = url.expires_at
+        && Utc::now() >= expires_at
+    {
+        return Err(AppError::UrlExpired(expires_at));
+    }
+    let hidden = hides_destinations(&state, &url, &headers, None);
+
+    // The query runs in its own task and hands over finished chunks, so only
+    // a couple of chunks are ever held in memory
+    let (tx, rx) = mpsc::channel::<Result<Bytes, sqlx::Error>>(2);
+    let pool = state.pool.clone();
+    let span = db::query_span("export_visits", Some(&code));
+    tokio::spawn(stream_rows(pool, code.clone(), params.from, params.to, hidden, tx).instrument(span));
+
+    let body = Body::from_stream(stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|chunk| (chunk, rx))
+    }));
+
+    let disposition = HeaderValue::from_str(&format!("attachment; filename=\"{}-visits.csv\"", code))
+        .map_err(|_| AppError::InternalServerError("Invalid export filename".into()))?;
+    Ok((
+        [
+            (header::CONTENT_TYPE, HeaderValue::from_static("text/csv; charset=utf-8")),
+            (header::CONTENT_DISPOSITION, disposition),
+        ],
+        body,
+    )
+        .into_response())
+}
+
+async fn stream_rows(
+    pool: crate::db::DbPool,
+    code: String,
+    from: Option<DateTime<Utc>>,
+    to: Option<DateTime<Utc>>,
+    hide_destinations: bool,
+    tx: mpsc::Sender<Result<Bytes, sqlx::Error>>,
+) {
+    let mut query: QueryBuilder<Sqlite> =
+        QueryBuilder::new("SELECT ip_address, user_agent, referrer, source, destination, platform, visited_at, is_bot FROM visits WHERE url_id = ");
+    query.push_bind(&code);
+    if let Some(from) = from {
+        query.push(" AND visited_at >= ").push_bind(from);
+    }
+    if let Some(to) = to {
+        query.push(" AND visited_at < ").push_bind(to);
+    }
+    query.push(" ORDER BY visited_at, id");
+
+    let mut rows = query.build_query_as::<VisitStats>().fetch(&pool);
+    let mut chunk = String::with_capacity(CHUNK_SIZE + 1024);
+    chunk.push_str(CSV_HEADER);
+    let mut count = 0;
+
+    while let Some(row) = rows.next().await {
+        match row {
+            Ok(mut visit) => {
+                count += 1;
+                if hide_destinations {
+                    visit.destination = None;
+                }
+                csv_row(&mut chunk, &visit);
+            }
+            Err(e) => {
+                tracing::error!(code, "visit export failed: {:?}", e);
+                db::record_failure(&Span::current(), &e);
+                // Surfacing the error aborts the response instead of
+                // silently truncating the file
+                let _ = tx.send(Err(e)).await;
+                return;
+            }
+        }
+        if chunk.len() >= CHUNK_SIZE {
+            let full = std::mem::replace(&mut chunk, String::with_capacity(CHUNK_SIZE + 1024));
+            if tx.send(Ok(Bytes::from(full))).await.is_err() {
+                // Client went away
+                return;
+            }
+        }
+    }
+
+    db::record_rows(&Span::current(), count);
+    if !chunk.is_empty() {
+        let _ = tx.send(Ok(Bytes::from(chunk))).await;
+    }
+}
@@ -0,0 +1,30 @@
+//! Country lookup for visitor addresses from a local MaxMind database
+//! (GeoLite2 or GeoIP2 Country/City), enabled by `GEOIP_DB_PATH`.
+
+use maxminddb::{geoip2, Mmap, Reader};
+use std::{net::IpAddr, path::Path};
+
+pub struct GeoIp {
+    reader: Reader<Mmap>,
+}
+
+impl GeoIp {
+    pub fn open(path: &Path) -> Result<Self, maxminddb::MaxMindDbError> {
+        // SAFETY: the file must not be truncated or rewritten in place while
+        // mapped. Database updates (e.g. geoipupdate) write a new file and
+        // rename it over the old one, which leaves this mapping intact.
+        let reader = unsafe { Reader::open_mmap(path)? };
+        Ok(GeoIp { reader })
+    }
+
+    /// ISO country code of `ip`, or `None` when it doesn't parse or the
+    /// database has no country for it. Anonymized addresses (`/24`, `/48`)
+    /// still resolve, since countries are assigned to whole networks.
+    pub fn country(&self, ip: &str) -> Option<String> {
+        // IPv4-mapped IPv6 addresses are looked up as IPv4
+        let ip = ip.trim().parse::<IpAddr>().ok()?.to_canonical();
+        let found = self.reader.lookup(ip).ok()?;
+        let record: geoip2::Country = found.decode().ok()??;
+        record.country.iso_code.map(str::to_string)
+    }
+}
@@ -1,214 +1,2164 @@
 use crate::{
-    error::AppError,
-    models::{CreateUrlRequest, StatsResponse, UrlRecord, UrlResponse, VisitStats},
-    utils::generate_short_code,
+    analytics::record_visit,
+    audit::{self, Entry},
+    auth::{self, verify_owner, verify_stats_access, ApiKey},
+    blocklist,
+    campaigns,
+    client_ip::ClientIp,
+    db::{self, Traced},
+    error::{field_messages, AppError, ErrorCode},
+    idempotency::{self, Claim},
+    link_check::{self, DeadDestination},
+    models::{
+        BulkItemResult, CodeStrategy, CountryCount, CreateUrlRequest, DailyCount, DestinationCount, ErrorResponse, HeatmapCell, LinkHealth, QrFormat, QrParams, RedirectType,
+        NamedCount, ReferrerCount, Rotation, StatsParams, StatsResponse, StatsSummary, StatsSummaryParams, StatsSummaryRequest, StatsSummaryResult, StatsVisibility, TokenParams, UnlockForm, UpdateUrlRequest, UrlInfoResponse,
+        UrlRecord, UrlResponse, ValidationErrorResponse, VisitStats, WeightedDestination,
+    },
+    negotiate::{is_prefetch, wants_html, wants_plain_text, JsonOrForm},
+    password::{hash_password, is_unlocked, unlock_cookie, unlock_form, verify_password},
+    reserved::is_reserved,
+    state::AppState,
+    rotation, tags, telemetry, user_agent,
+    utils::{
+        detect_platform, encode_url, generate_short_code, hash_code, is_bot, merge_query, normalize_url, referrer_host, suggest_codes,
+        visitor_hash, CodeAlphabet, CodeGenerator, Platform, HASH_CODE_MAX_LEN,
+    },
+    visits::{NewVisit, VisitSource},
 };
 use axum::{
-    extract::{Path, State},
-    response::{IntoResponse, Redirect, Response},
+    extract::{rejection::QueryRejection, Form, Path, Query, RawQuery, State},
+    http::{header, HeaderMap, HeaderName, HeaderValue, Method, StatusCode},
+    response::{Html, IntoResponse, Response},
     Json,
 };
-use chrono::Utc;
-use qrcode::QrCode;
-use qrcode::render::svg;
-use sqlx::{Pool, Sqlite};
-use validator::Validate;
+use axum_extra::extract::SignedCookieJar;
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveDateTime, Timelike, Utc};
+use chrono_tz::Tz;
+use image::{DynamicImage, ImageBuffer, ImageFormat, Luma, Rgb};
+use qrcode::render::{svg, Renderer};
+use qrcode::{EcLevel, QrCode};
+use sha2::{Digest, Sha256};
+use sqlx::{QueryBuilder, Sqlite, SqliteConnection};
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::Cursor,
+};
+use validator::{Validate, ValidationError, ValidationErrors};
 
-type DbPool = Pool<Sqlite>;
+// Resolve the base URL for short links: the configured BASE_URL wins,
+// otherwise fall back to the request's Host header.
+fn base_url(state: &AppState, headers: &HeaderMap) -> String {
+    if let Some(base) = &state.config.base_url {
+        return base.clone();
+    }
 
-#[utoipa::path(
-    post,
-    path = "/shorten",
-    request_body = CreateUrlRequest,
-    responses(
-        (status = 201, description = "URL shortened successfully", body = UrlResponse),
-        (status = 400, description = "Invalid input"),
-        (status = 409, description = "Custom code already exists")
+    let host = headers
+        .get(axum::http::header::HOST)
+        .and_then(|h| h.to_str().ok())
+        .unwrap_or("localhost:3000");
+    let scheme = headers
+        .get("x-forwarded-proto")
+        .and_then(|h| h.to_str().ok())
+        .filter(|p| *p == "https" || *p == "http")
+        .unwrap_or("http");
+
+    format!("{}://{}", scheme, host)
+}
+
+// Build the public representation of a stored link. Secrets are never included.
+fn link_response(url: UrlRecord, tags: Vec<String>, base: &str) -> UrlResponse {
+    UrlResponse {
+        redirect_type: url.redirect_type(),
+        rotation: url.rotation(),
+        stats_visibility: url.stats_visibility(),
+        short_url: format!("{}/{}", base, url.id),
+        short_code: url.id,
+        original_url: Some(url.original_url),
+        created_at: url.created_at,
+        expires_at: url.expires_at,
+        starts_at: url.starts_at,
+        max_visits: url.max_visits,
+        password_protected: url.password_hash.is_some(),
+        active: url.is_active,
+        tags,
+        forward_query: url.forward_query,
+        ios_url: url.ios_url,
+        android_url: url.android_url,
+        destinations: None,
+        delete_token: None,
+        webhook_secret: None,
+        stats_token: None,
+        campaign_id: url.campaign_id,
+    }
+}
+
+/// Where a password-protected link leads is kept from everyone but its owner,
+/// who proves it the same way as for updates and deletes.
+pub fn hides_destinations(state: &AppState, url: &UrlRecord, headers: &HeaderMap, token: Option<String>) -> bool {
+    url.password_hash.is_some() && verify_owner(state, url, headers, token).is_err()
+}
+
+// A link is expired from the exact second of its `expires_at` onwards.
+fn ensure_not_expired(url: &UrlRecord) -> Result<(), AppError> {
+    match url.expires_at {
+        Some(expires_at) if Utc::now() >= expires_at => Err(AppError::UrlExpired(expires_at)),
+        _ => Ok(()),
+    }
+}
+
+// Scheduled links exist (and have a QR code) before they start redirecting.
+fn is_scheduled(url: &UrlRecord) -> bool {
+    url.starts_at.is_some_and(|starts_at| Utc::now() < starts_at)
+}
+
+fn validate_schedule(
+    starts_at: Option<DateTime<Utc>>,
+    expires_at: Option<DateTime<Utc>>,
+) -> Result<(), AppError> {
+    match (starts_at, expires_at) {
+        (Some(starts_at), Some(expires_at)) if starts_at >= expires_at => Err(
+            AppError::BadRequest("starts_at must be before expires_at".into()),
+        ),
+        _ => Ok(()),
+    }
+}
+
+// Reject expiries beyond MAX_LINK_TTL_DAYS, including "never expires".
+fn check_max_ttl(state: &AppState, expires_at: Option<DateTime<Utc>>) -> Result<(), AppError> {
+    let Some(days) = state.config.max_link_ttl_days else {
+        return Ok(());
+    };
+    let horizon = Utc::now() + Duration::days(days.into());
+    if expires_at.is_some_and(|expires_at| expires_at <= horizon) {
+        return Ok(());
+    }
+
+    let mut err = ValidationError::new("max_ttl");
+    err.message = Some(format!("expires_at must be at most {} days from now", days).into());
+    let mut errors = ValidationErrors::new();
+    errors.add("expires_at", err);
+    Err(AppError::Validation(errors))
+}
+
+// Column values for a new `urls` row.
+struct NewLink<'a> {
+    code: String,
+    original_url: &'a str,
+    normalized_url: &'a str,
+    created_at: DateTime<Utc>,
+    expires_at: Option<DateTime<Utc>>,
+    starts_at: Option<DateTime<Utc>>,
+    delete_token_hash: &'a str,
+    redirect_type: RedirectType,
+    created_by_key: Option<&'a str>,
+    max_visits: Option<i64>,
+    password_hash: Option<&'a str>,
+    expiry_defaulted: bool,
+    forward_query: bool,
+    webhook_url: Option<&'a str>,
+    webhook_secret: Option<&'a str>,
+    rotation: Option<Rotation>,
+    ios_url: Option<&'a str>,
+    android_url: Option<&'a str>,
+    stats_visibility: StatsVisibility,
+    stats_token_hash: Option<&'a str>,
+    namespace: Option<&'a str>,
+    code_strategy: CodeStrategy,
+    campaign_id: Option<i64>,
+}
+
+// Whether an existing link for the URL (the hash link holding its code, or a
+// deduplication candidate) can be returned for a request that would create
+// `link`: it has to still redirect and behave exactly like the new link
+// would. Password-protected, rotating and webhook links are never shared, as
+// their secrets are only handed out once.
+fn matches_existing_link(existing: &UrlRecord, existing_tags: &[String], link: &NewLink<'_>, tags: &[String]) -> bool {
+    let expiry_matches = existing.expires_at == link.expires_at || (existing.expiry_defaulted && link.expiry_defaulted);
+    existing.is_active
+        && existing.expires_at.is_none_or(|at| at > Utc::now())
+        && existing.max_visits.is_none_or(|max| existing.visit_count < max)
+        && existing.password_hash.is_none()
+        && link.password_hash.is_none()
+        && existing.rotation.is_none()
+        && link.rotation.is_none()
+        && expiry_matches
+        && existing.starts_at == link.starts_at
+        && existing.redirect_type() == link.redirect_type
+        && existing.max_visits == link.max_visits
+        && existing.forward_query == link.forward_query
+        && existing.ios_url.as_deref() == link.ios_url
+        && existing.android_url.as_deref() == link.android_url
+        && existing.webhook_url.is_none()
+        && link.webhook_url.is_none()
+        && existing.stats_visibility() == link.stats_visibility
+        && existing.namespace.as_deref() == link.namespace
+        && existing.campaign_id == link.campaign_id
+        && existing_tags == tags
+}
+
+async fn insert_link(conn: &mut SqliteConnection, link: &NewLink<'_>) -> Result<(), sqlx::Error> {
+    let redirect_type = u16::from(link.redirect_type);
+    let rotation = link.rotation.map(Rotation::as_str);
+    let stats_visibility = link.stats_visibility.as_str();
+    let code_strategy = link.code_strategy.as_str();
+    sqlx::query!(
+        "INSERT INTO urls (id, original_url, normalized_url, created_at, expires_at, starts_at, delete_token_hash, redirect_type, created_by_key, max_visits, password_hash, expiry_defaulted, forward_query, webhook_url, webhook_secret, rotation, ios_url, android_url, stats_visibility, stats_token_hash, namespace, code_strategy, campaign_id) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        link.code,
+        link.original_url,
+        link.normalized_url,
+        link.created_at,
+        link.expires_at,
+        link.starts_at,
+        link.delete_token_hash,
+        redirect_type,
+        link.created_by_key,
+        link.max_visits,
+        link.password_hash,
+        link.expiry_defaulted,
+        link.forward_query,
+        link.webhook_url,
+        link.webhook_secret,
+        rotation,
+        link.ios_url,
+        link.android_url,
+        stats_visibility,
+        link.stats_token_hash,
+        link.namespace,
+        code_strategy,
+        link.campaign_id
     )
-)]
-pub async fn shorten_url(
-    State(pool): State<DbPool>,
-    Json(payload): Json<CreateUrlRequest>,
-) -> Result<impl IntoResponse, AppError> {
-    if let Err(_) = payload.validate() {
-        return Err(AppError::InvalidUrl);
-    }
-
-    let code = if let Some(custom) = &payload.custom_code {
-        // Check if exists
-        let exists: bool = sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM urls WHERE id = ?)")
-            .bind(custom)
-            .fetch_one(&pool)
-            .await?;
-        
-        if exists {
-            return Err(AppError::CodeAlreadyExists);
+    .execute(conn)
+    .traced("insert_link", Some(link.code.as_str()))
+    .await?;
+    Ok(())
+}
+
+fn is_unique_violation(err: &sqlx::Error) -> bool {
+    matches!(err, sqlx::Error::Database(db_err) if db_err.is_unique_violation())
+}
+
+const CONFLICT_SUGGESTIONS: usize = 3;
+
+// Describe a custom code collision: whether the existing link is the same
+// destination, plus a few alternatives that are currently free. Suggestions
+// are custom codes, checked under the caller's namespace.
+async fn code_conflict(
+    conn: &mut SqliteConnection,
+    state: &AppState,
+    namespace: Option<&str>,
+    custom: &str,
+    normalized_url: &str,
+) -> Result<AppError, AppError> {
+    let code = auth::namespaced_code(namespace, custom);
+    let existing = sqlx::query_scalar!("SELECT normalized_url FROM urls WHERE id = ?", code)
+        .fetch_optional(&mut *conn)
+        .traced("find_code_holder", Some(code.as_str()))
+        .await?;
+    let same_url = existing.flatten().as_deref() == Some(normalized_url);
+
+    // Over-generate so a couple of taken or reserved candidates don't matter
+    let candidates: Vec<String> = suggest_codes(custom, CONFLICT_SUGGESTIONS * 2)
+        .into_iter()
+        .filter(|candidate| !is_reserved(&state.reserved_codes, candidate) && !state.code_filter.is_blocked(candidate))
+        .collect();
+    let mut query: QueryBuilder<Sqlite> = QueryBuilder::new("SELECT id FROM urls WHERE id IN (");
+    let mut separated = query.separated(", ");
+    for candidate in &candidates {
+        separated.push_bind(auth::namespaced_code(namespace, candidate));
+    }
+    separated.push_unseparated(")");
+    let taken: Vec<String> = query
+        .build_query_scalar()
+        .fetch_all(&mut *conn)
+        .traced("find_taken_suggestions", Some(code.as_str()))
+        .await?;
+
+    let suggestions = candidates
+        .into_iter()
+        .filter(|candidate| !taken.contains(&auth::namespaced_code(namespace, candidate)))
+        .take(CONFLICT_SUGGESTIONS)
+        .collect();
+
+    Ok(AppError::CodeAlreadyExists {
+        short_code: code,
+        same_url,
+        suggestions,
+    })
+}
+
+// A link destination must pass the network policy and stay off the blocklist.
+async fn check_destination(state: &AppState, url: &str, base: &str) -> Result<(), AppError> {
+    state.destinations.check(url, base).await?;
+    state.blocklist.check(url)
+}
+
+// `"verify": true` probes the destination once the request has passed every
+// other check, and before the transaction starts so none is held open on the
+// network. Only the primary destination is probed.
+async fn verify_destination(state: &AppState, prepared: &PreparedLink, base: &str) -> Result<(), AppError> {
+    if !prepared.payload.verify {
+        return Ok(());
+    }
+    state
+        .link_verifier
+        .verify(&prepared.original_url, base)
+        .await
+        .map_err(|dead| match dead {
+            DeadDestination::Status(status) => AppError::DestinationUnreachable(Some(status)),
+            DeadDestination::Unreachable => AppError::DestinationUnreachable(None),
+        })
+}
+
+// A link request that passed validation and the destination checks, ready
+// to be written.
+struct PreparedLink {
+    payload: CreateUrlRequest,
+    destinations: Option<Vec<WeightedDestination>>,
+    original_url: String,
+    normalized_url: String,
+    rotation: Option<Rotation>,
+    expiry_defaulted: bool,
+    code_strategy: CodeStrategy,
+    tags: Vec<String>,
+    password_hash: Option<String>,
+}
+
+// Everything about a new link that needs no database: validation, the DNS
+// checks of each destination and password hashing. Done before a transaction
+// starts so none is held open on the network or the hasher.
+async fn prepare_link(state: &AppState, mut payload: CreateUrlRequest, base: &str) -> Result<PreparedLink, AppError> {
+    payload.validate().map_err(AppError::Validation)?;
+
+    // A link has either a single url or weighted destinations; the first
+    // destination doubles as the stored original_url
+    // Stored percent-encoded, so every destination is a valid Location header
+    let mut destinations = payload.destinations.take();
+    for destination in destinations.iter_mut().flatten() {
+        destination.url = encode_url(&destination.url).ok_or(AppError::InvalidUrl)?;
+    }
+    for app_url in [&mut payload.ios_url, &mut payload.android_url].into_iter().flatten() {
+        *app_url = encode_url(app_url).ok_or(AppError::InvalidUrl)?;
+    }
+    let original_url = match (payload.url.take(), &destinations) {
+        (Some(_), Some(_)) => {
+            return Err(AppError::BadRequest("url and destinations can't both be set".into()));
+        }
+        (Some(url), None) => encode_url(&url).ok_or(AppError::InvalidUrl)?,
+        (None, Some(destinations)) => destinations[0].url.clone(),
+        (None, None) => return Err(AppError::BadRequest("url or destinations is required".into())),
+    };
+    let rotation = destinations
+        .as_ref()
+        .map(|_| payload.rotation.unwrap_or_default());
+
+    if let Some(secs) = payload.expires_in_secs {
+        if payload.expires_at.is_some() {
+            return Err(AppError::BadRequest(
+                "expires_at and expires_in_secs can't both be set".into(),
+            ));
+        }
+        // Bounded by validation, so this can't overflow
+        payload.expires_at = Some(Utc::now() + Duration::seconds(secs));
+    }
+    // Links without an expiry get the maximum lifetime when one is configured
+    let expiry_defaulted = match (payload.expires_at, state.config.max_link_ttl_days) {
+        (None, Some(days)) => {
+            payload.expires_at = Some(Utc::now() + Duration::days(days.into()));
+            true
+        }
+        _ => false,
+    };
+    check_max_ttl(state, payload.expires_at)?;
+    validate_schedule(payload.starts_at, payload.expires_at)?;
+    check_destination(state, &original_url, base).await?;
+    let alternates = destinations
+        .iter()
+        .flatten()
+        .skip(1)
+        .map(|destination| &destination.url)
+        .chain(payload.ios_url.iter())
+        .chain(payload.android_url.iter());
+    for alternate in alternates {
+        check_destination(state, alternate, base).await?;
+        normalize_url(alternate).ok_or(AppError::InvalidUrl)?;
+    }
+    if let Some(webhook_url) = &payload.webhook_url {
+        state.destinations.check(webhook_url, base).await?;
+    }
+
+    let normalized_url = normalize_url(&original_url).ok_or(AppError::InvalidUrl)?;
+
+    let code_strategy = match (&payload.custom_code, payload.code_strategy) {
+        (Some(_), None | Some(CodeStrategy::Custom)) => CodeStrategy::Custom,
+        (Some(_), Some(_)) => {
+            return Err(AppError::BadRequest("custom_code and code_strategy can't both be set".into()));
+        }
+        (None, Some(CodeStrategy::Custom)) => {
+            return Err(AppError::BadRequest("code_strategy custom needs a custom_code".into()));
+        }
+        (None, strategy) => strategy.unwrap_or_default(),
+    };
+    if payload.code_style.is_some() && code_strategy != CodeStrategy::Random {
+        return Err(AppError::BadRequest("code_style only applies to random codes".into()));
+    }
+    let tags = tags::normalize(payload.tags.as_deref().unwrap_or_default());
+
+    let password_hash = match payload.password.clone() {
+        Some(password) => Some(
+            tokio::task::spawn_blocking(move || hash_password(&password))
+                .await
+                .map_err(|_| AppError::InternalServerError("Password hashing task failed".into()))??,
+        ),
+        None => None,
+    };
+
+    Ok(PreparedLink {
+        payload,
+        destinations,
+        original_url,
+        normalized_url,
+        rotation,
+        expiry_defaulted,
+        code_strategy,
+        tags,
+        password_hash,
+    })
+}
+
+// Insert a prepared link on the given connection, which may be a
+// transaction. Returns 200 with an existing link when deduplication matched,
+// otherwise 201 with the new one.
+async fn create_link(
+    conn: &mut SqliteConnection,
+    state: &AppState,
+    prepared: PreparedLink,
+    api_key: Option<&str>,
+    base: &str,
+) -> Result<(StatusCode, UrlResponse), AppError> {
+    let PreparedLink {
+        payload,
+        destinations,
+        original_url,
+        normalized_url,
+        rotation,
+        expiry_defaulted,
+        code_strategy,
+        tags,
+        password_hash,
+    } = prepared;
+    let generator = CodeGenerator::new(
+        payload.code_style.unwrap_or(state.config.code_style),
+        state.config.code_alphabet,
+        state.config.code_length,
+    );
+    let stats_visibility = payload.stats_visibility.unwrap_or_default();
+    let namespace = api_key
+        .and_then(|key| state.api_keys.as_ref()?.get(key))
+        .and_then(|namespace| namespace.as_deref());
+
+    if let Some(campaign_id) = payload.campaign_id {
+        campaigns::check_exists(&mut *conn, campaign_id).await?;
+    }
+
+    let delete_token = generate_short_code(32, CodeAlphabet::Base62);
+    let delete_token_hash = auth::hash_token(&delete_token);
+    let webhook_secret = payload
+        .webhook_url
+        .as_ref()
+        .map(|_| generate_short_code(32, CodeAlphabet::Base62));
+    let stats_token = (stats_visibility == StatsVisibility::Private)
+        .then(|| generate_short_code(32, CodeAlphabet::Base62));
+    let stats_token_hash = stats_token.as_deref().map(auth::hash_token);
+    let redirect_type = payload.redirect_type.unwrap_or_default();
+    let mut link = NewLink {
+        code: String::new(),
+        original_url: &original_url,
+        normalized_url: &normalized_url,
+        created_at: Utc::now(),
+        expires_at: payload.expires_at,
+        starts_at: payload.starts_at,
+        delete_token_hash: &delete_token_hash,
+        redirect_type,
+        created_by_key: api_key,
+        max_visits: payload.max_visits,
+        password_hash: password_hash.as_deref(),
+        expiry_defaulted,
+        forward_query: payload.forward_query.unwrap_or(true),
+        webhook_url: payload.webhook_url.as_deref(),
+        webhook_secret: webhook_secret.as_deref(),
+        rotation,
+        ios_url: payload.ios_url.as_deref(),
+        android_url: payload.android_url.as_deref(),
+        stats_visibility,
+        stats_token_hash: stats_token_hash.as_deref(),
+        namespace,
+        code_strategy,
+        campaign_id: payload.campaign_id,
+    };
+
+    if payload.deduplicate
+        && code_strategy == CodeStrategy::Random
+        && payload.password.is_none()
+        && tags.is_empty()
+        && destinations.is_none()
+        && payload.campaign_id.is_none()
+    {
+        for existing in db::find_reusable_urls(&mut *conn, &normalized_url, stats_visibility, namespace, Utc::now()).await? {
+            let existing_tags = tags::load_tags(&mut *conn, &existing.id).await?;
+            if matches_existing_link(&existing, &existing_tags, &link, &tags) {
+                return Ok((StatusCode::OK, link_response(existing, existing_tags, base)));
+            }
+        }
+    }
+
+    // The primary key is the source of truth for uniqueness: insert directly
+    // and treat a constraint violation as "taken" instead of checking first.
+    if let Some(custom) = &payload.custom_code {
+        if is_reserved(&state.reserved_codes, custom) {
+            return Err(AppError::ReservedCode);
+        }
+
+        link.code = auth::namespaced_code(namespace, custom);
+        match insert_link(conn, &link).await {
+            Err(e) if is_unique_violation(&e) => {
+                return Err(code_conflict(conn, state, namespace, custom, &normalized_url).await?);
+            }
+            result => result?,
+        }
+    } else if code_strategy == CodeStrategy::Hash {
+        // Lengthen the hash past codes held by other URLs, or by this URL with
+        // other settings; a hash code holding this URL as requested is the
+        // link to return
+        let mut length = state.config.code_length;
+        loop {
+            if length > HASH_CODE_MAX_LEN {
+                return Err(AppError::InternalServerError("Failed to find a free hash code".into()));
+            }
+            link.code = hash_code(&normalized_url, length);
+            length += 1;
+            // The filter is deterministic too, so the same URL always skips
+            // the same lengths
+            if is_reserved(&state.reserved_codes, &link.code) || state.code_filter.is_blocked(&link.code) {
+                continue;
+            }
+            match insert_link(conn, &link).await {
+                Ok(()) => break,
+                Err(e) if is_unique_violation(&e) => {
+                    let holder = sqlx::query_scalar!(
+                        "SELECT normalized_url FROM urls WHERE id = ? AND code_strategy = 'hash'",
+                        link.code
+                    )
+                    .fetch_optional(&mut *conn)
+                    .traced("find_hash_holder", Some(link.code.as_str()))
+                    .await?
+                    .flatten();
+                    if holder.as_deref() == Some(normalized_url.as_str())
+                        && let Some(existing) = db::find_url(&mut *conn, &link.code).await?
+                    {
+                        let existing_tags = tags::load_tags(&mut *conn, &existing.id).await?;
+                        if matches_existing_link(&existing, &existing_tags, &link, &tags) {
+                            return Ok((StatusCode::OK, link_response(existing, existing_tags, base)));
+                        }
+                    }
+                }
+                Err(e) => return Err(e.into()),
+            }
         }
-        custom.clone()
     } else {
-        // Generate random unique code
+        // Generate random unique code, retrying on collisions
         let mut attempts = 0;
         loop {
-            let candidate = generate_short_code(6);
-            let exists: bool = sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM urls WHERE id = ?)")
-                .bind(&candidate)
-                .fetch_one(&pool)
-                .await?;
-            if !exists {
-                break candidate;
-            }
             attempts += 1;
             if attempts > 10 {
                 return Err(AppError::InternalServerError("Failed to generate unique code".into()));
             }
-        }
-    };
-
-    let now = Utc::now();
-    sqlx::query("INSERT INTO urls (id, original_url, created_at, expires_at) VALUES (?, ?, ?, ?)")
-        .bind(&code)
-        .bind(&payload.url)
-        .bind(now)
-        .bind(payload.expires_at)
-        .execute(&pool)
-        .await?;
 
-    // Construct full short URL (assuming localhost for now, can be configured)
-    let short_url = format!("http://localhost:3000/{}", code);
+            link.code = state.code_filter.pick(|| generator.generate()).ok_or_else(|| {
+                AppError::InternalServerError("Every generated code matched the denylist".into())
+            })?;
+            if is_reserved(&state.reserved_codes, &link.code) {
+                continue;
+            }
+            match insert_link(conn, &link).await {
+                Ok(()) => break,
+                Err(e) if is_unique_violation(&e) => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+    let code = link.code;
+    let created_at = link.created_at;
+    let forward_query = link.forward_query;
+    tags::insert_tags(conn, &code, &tags).await?;
+    rotation::insert_destinations(conn, &code, destinations.as_deref().unwrap_or_default()).await?;
+    let after = audit::snapshot(conn, &code).await?;
+    audit::record(
+        conn,
+        Entry {
+            actor: &audit::actor(api_key),
+            action: "create",
+            code: Some(&code),
+            before: None,
+            after,
+        },
+    )
+    .await?;
+    if payload.fetch_metadata {
+        state.metadata.fetch(&code, &original_url, base);
+    }
+    metrics::counter!(telemetry::LINKS_CREATED_TOTAL).increment(1);
 
     Ok((
-        axum::http::StatusCode::CREATED,
-        Json(UrlResponse {
+        StatusCode::CREATED,
+        UrlResponse {
+            short_url: format!("{}/{}", base, code),
             short_code: code,
-            original_url: payload.url,
-            short_url,
+            original_url: Some(original_url),
+            created_at,
             expires_at: payload.expires_at,
-        }),
+            starts_at: payload.starts_at,
+            redirect_type,
+            max_visits: payload.max_visits,
+            password_protected: password_hash.is_some(),
+            active: true,
+            tags,
+            forward_query,
+            ios_url: payload.ios_url,
+            android_url: payload.android_url,
+            stats_visibility,
+            rotation,
+            destinations,
+            delete_token: Some(delete_token),
+            webhook_secret,
+            stats_token,
+            campaign_id: payload.campaign_id,
+        },
     ))
 }
 
+/// Creates one link the way `POST /shorten` does, minus authentication and
+/// idempotency: the request is validated and its destinations checked, the
+/// primary destination is probed when `verify` is set, and only then are the
+/// link and its audit entry written in one transaction.
+pub async fn shorten(
+    state: &AppState,
+    payload: CreateUrlRequest,
+    api_key: Option<&str>,
+    base: &str,
+) -> Result<(StatusCode, UrlResponse), AppError> {
+    let prepared = prepare_link(state, payload, base).await?;
+    verify_destination(state, &prepared, base).await?;
+    let mut tx = state.pool.begin().await?;
+    let created = create_link(&mut tx, state, prepared, api_key, base).await?;
+    tx.commit().await?;
+    Ok(created)
+}
+
 #[utoipa::path(
-    get,
+    post,
+    path = "/shorten",
+    request_body(content(
+        (CreateUrlRequest = "application/json"),
+        (CreateUrlRequest = "application/x-www-form-urlencoded")
+    )),
+    responses(
+        (status = 200, description = "Existing link returned (deduplicate)", content(
+            (UrlResponse = "application/json"),
+            (String = "text/plain", example = "http://127.0.0.1:3000/rust")
+        )),
+        (status = 201, description = "URL shortened successfully; `Accept: text/plain` returns only the short URL", content(
+            (UrlResponse = "application/json"),
+            (String = "text/plain", example = "http://127.0.0.1:3000/rust")
+        )),
+        (status = 400, description = "Invalid input", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid API key", body = ErrorResponse),
+        (status = 409, description = "Custom code already exists (`CODE_TAKEN`, with `short_code`, `same_url` and free `suggestions` in `details`), or a request with the same `Idempotency-Key` is still in progress", body = ErrorResponse),
+        (status = 413, description = "Body over `MAX_REQUEST_BODY_BYTES`", body = ErrorResponse),
+        (status = 422, description = "Validation failed (field → messages map), custom code is reserved, `\"verify\": true` found the destination dead (`DESTINATION_UNREACHABLE`), or `Idempotency-Key` reused with a different body", body = ValidationErrorResponse),
+        (status = 429, description = "Rate limit exceeded", body = ErrorResponse)
+    ),
+    params(
+        ("Idempotency-Key" = Option<String>, Header, description = "Retrying with the same key within 24 hours returns the original response (with `Idempotent-Replayed: true`) instead of creating another link")
+    ),
+    security(
+        (),
+        ("api_key" = [])
+    )
+)]
+// The code is recorded once the link exists
+#[tracing::instrument(skip_all, fields(code = tracing::field::Empty))]
+pub async fn shorten_url(
+    State(state): State<AppState>,
+    api_key: Result<ApiKey, AppError>,
+    headers: HeaderMap,
+    payload: Result<JsonOrForm<CreateUrlRequest>, AppError>,
+) -> Response {
+    // Extractor failures are taken as values so they can be rendered in the
+    // format the client asked for
+    let result = async {
+        let ApiKey(api_key) = api_key?;
+        let JsonOrForm(payload) = payload?;
+        let base = base_url(&state, &headers);
+        // The link and its audit entry are written in one transaction
+        let Some(key) = idempotency::key_from_headers(&headers)? else {
+            let (status, link) = shorten(&state, payload, api_key.as_deref(), &base).await?;
+            return Ok((status, link, false));
+        };
+
+        let scope = api_key.as_deref().unwrap_or_default();
+        let fingerprint = idempotency::fingerprint(&payload);
+        if let Claim::Replay(status, link) = idempotency::claim(&state.pool, scope, &key, &fingerprint).await? {
+            return Ok((status, *link, true));
+        }
+
+        match shorten(&state, payload, api_key.as_deref(), &base).await {
+            Ok((status, link)) => {
+                // The link exists either way; a lost key only means a retry
+                // would create a second one
+                if let Err(e) = idempotency::complete(&state.pool, scope, &key, status, &link).await {
+                    tracing::error!("failed to store idempotent response: {:?}", e);
+                }
+                Ok((status, link, false))
+            }
+            Err(e) => {
+                idempotency::release(&state.pool, scope, &key).await;
+                Err(e)
+            }
+        }
+    }
+    .await;
+
+    match (result, wants_plain_text(&headers)) {
+        (Ok((status, link, replayed)), plain) => {
+            tracing::Span::current().record("code", link.short_code.as_str());
+            let mut response = if plain {
+                (status, format!("{}\n", link.short_url)).into_response()
+            } else {
+                (status, Json(link)).into_response()
+            };
+            if replayed {
+                response
+                    .headers_mut()
+                    .insert("idempotent-replayed", HeaderValue::from_static("true"));
+            }
+            response
+        }
+        (Err(e), true) => e.into_plain_response(),
+        (Err(e), false) => e.into_response(),
+    }
+}
+
+const MAX_BULK_ITEMS: usize = 500;
+
+#[utoipa::path(
+    post,
+    path = "/shorten/bulk",
+    request_body = Vec<CreateUrlRequest>,
+    responses(
+        (status = 200, description = "Per-item results, in request order", body = [BulkItemResult]),
+        (status = 400, description = "Empty batch or more than 500 items", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid API key", body = ErrorResponse),
+        (status = 413, description = "Body over `MAX_REQUEST_BODY_BYTES`", body = ErrorResponse),
+        (status = 429, description = "Rate limit exceeded", body = ErrorResponse)
+    ),
+    security(
+        (),
+        ("api_key" = [])
+    )
+)]
+#[tracing::instrument(skip_all)]
+pub async fn shorten_bulk(
+    State(state): State<AppState>,
+    ApiKey(api_key): ApiKey,
+    headers: HeaderMap,
+    Json(payloads): Json<Vec<CreateUrlRequest>>,
+) -> Result<Json<Vec<BulkItemResult>>, AppError> {
+    if payloads.is_empty() || payloads.len() > MAX_BULK_ITEMS {
+        return Err(AppError::BadRequest(format!(
+            "A batch must contain between 1 and {} items",
+            MAX_BULK_ITEMS
+        )));
+    }
+
+    let base = base_url(&state, &headers);
+    // Every item is validated and its destinations resolved before the write
+    // transaction starts; only the inserts run inside it
+    let mut prepared = Vec::with_capacity(payloads.len());
+    for payload in payloads {
+        prepared.push(prepare_link(&state, payload, &base).await);
+    }
+    let mut tx = state.pool.begin().await?;
+    let mut results = Vec::with_capacity(prepared.len());
+
+    for (index, prepared) in prepared.into_iter().enumerate() {
+        let created = match prepared {
+            Ok(prepared) => create_link(&mut tx, &state, prepared, api_key.as_deref(), &base).await,
+            Err(e) => Err(e),
+        };
+        let result = match created {
+            Ok((StatusCode::OK, link)) => BulkItemResult::Existing { index, link },
+            Ok((_, link)) => BulkItemResult::Created { index, link },
+            Err(AppError::CodeAlreadyExists { short_code, .. }) => BulkItemResult::Conflict { index, short_code },
+            Err(AppError::Validation(errors)) => BulkItemResult::Invalid {
+                index,
+                error: "Validation failed".to_string(),
+                code: ErrorCode::ValidationFailed,
+                fields: Some(field_messages(&errors)),
+            },
+            // Database and internal errors abort the whole batch
+            Err(e @ (AppError::DatabaseError(_) | AppError::InternalServerError(_))) => return Err(e),
+            Err(e) => BulkItemResult::Invalid {
+                index,
+                code: e.code(),
+                error: e.message(),
+                fields: None,
+            },
+        };
+        results.push(result);
+    }
+
+    tx.commit().await?;
+
+    Ok(Json(results))
+}
+
+/// `HEAD` requests and prefetches (`Sec-Purpose: prefetch`, `Purpose: prefetch`,
+/// `X-Purpose: preview`) get the same answer but aren't counted as visits.
+#[utoipa::path(
+    method(get, head),
     path = "/{code}",
     params(
         ("code" = String, Path, description = "Short code")
     ),
     responses(
-        (status = 307, description = "Redirect to original URL"),
-        (status = 404, description = "URL not found"),
-        (status = 410, description = "URL expired")
+        (status = 301, description = "Permanent redirect to original URL (per-link setting)"),
+        (status = 302, description = "Temporary redirect to original URL (per-link setting), or to FALLBACK_REDIRECT_URL for unknown codes requested by a browser"),
+        (status = 307, description = "Temporary redirect to original URL (default)"),
+        (status = 308, description = "Permanent redirect to original URL (per-link setting)"),
+        (status = 200, description = "Password form for protected links without a valid unlock cookie, or a warning page with a continue button when a destination is blocklisted (not counted as a visit)"),
+        (status = 404, description = "URL not found or not yet active (starts_at in the future). Clients that prefer `text/html` get an HTML page for unknown codes.", body = ErrorResponse),
+        (status = 410, description = "URL expired, visit limit reached or link disabled", body = ErrorResponse)
     )
 )]
+#[tracing::instrument(skip_all, fields(code = %code))]
 pub async fn redirect_url(
-    State(pool): State<DbPool>,
+    State(state): State<AppState>,
     Path(code): Path<String>,
-    headers: axum::http::HeaderMap,
+    ClientIp(ip): ClientIp,
+    RawQuery(query): RawQuery,
+    method: Method,
+    jar: SignedCookieJar,
+    headers: HeaderMap,
 ) -> Result<Response, AppError> {
-    let url_record: Option<UrlRecord> = sqlx::query_as("SELECT * FROM urls WHERE id = ?")
-        .bind(&code)
-        .fetch_optional(&pool)
-        .await?;
+    let counted = method != Method::HEAD && !is_prefetch(&headers);
+    let url = match load_active_link(&state, &code).await {
+        Err(AppError::UrlNotFound) if wants_html(&headers) => return Ok(not_found_page(&state)),
+        result => result?,
+    };
 
-    let url = match url_record {
+    if url.password_hash.is_some() && !is_unlocked(&jar, &code) {
+        return Ok(protected_page(StatusCode::OK, &code, None));
+    }
+    // Not counted until the visitor chooses to continue
+    if let Some(domain) = flagged_domain(&state, &url).await? {
+        return Ok(interstitial_page(&code, &domain));
+    }
+
+    let status = match url.password_hash {
+        // Never let the browser cache a permanent redirect past the password
+        Some(_) => StatusCode::TEMPORARY_REDIRECT,
+        None => url.redirect_type().status_code(),
+    };
+    follow_link(&state, url, ip, &headers, query.as_deref(), status, counted).await
+}
+
+const NOT_FOUND_PAGE: &str = include_str!("../static/404.html");
+
+// What a browser gets for an unknown code: the fallback redirect when one is
+// configured, otherwise the HTML 404 page. API clients keep the JSON error.
+fn not_found_page(state: &AppState) -> Response {
+    match &state.fallback_url {
+        Some(fallback) => (
+            StatusCode::FOUND,
+            [
+                (header::LOCATION, fallback.clone()),
+                (HeaderName::from_static("x-robots-tag"), HeaderValue::from_static("noindex")),
+            ],
+        )
+            .into_response(),
+        None => (StatusCode::NOT_FOUND, Html(NOT_FOUND_PAGE)).into_response(),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/{code}/unlock",
+    params(
+        ("code" = String, Path, description = "Short code")
+    ),
+    request_body(content = UnlockForm, content_type = "application/x-www-form-urlencoded"),
+    responses(
+        (status = 303, description = "Password accepted; redirects and sets a short-lived unlock cookie"),
+        (status = 200, description = "Password accepted, but a destination is blocklisted; the warning page is shown", body = String, content_type = "text/html"),
+        (status = 401, description = "Wrong password; the form is shown again", body = String, content_type = "text/html"),
+        (status = 404, description = "URL not found or not yet active", body = ErrorResponse),
+        (status = 410, description = "URL expired, visit limit reached or link disabled", body = ErrorResponse),
+        (status = 429, description = "Too many password attempts from this IP (`UNLOCK_RATE_LIMIT`)", body = ErrorResponse)
+    )
+)]
+#[tracing::instrument(skip_all, fields(code = %code))]
+pub async fn unlock_url(
+    State(state): State<AppState>,
+    Path(code): Path<String>,
+    ClientIp(ip): ClientIp,
+    jar: SignedCookieJar,
+    headers: HeaderMap,
+    Form(form): Form<UnlockForm>,
+) -> Result<Response, AppError> {
+    let url = load_active_link(&state, &code).await?;
+
+    if let Some(hash) = url.password_hash.clone() {
+        let valid = tokio::task::spawn_blocking(move || verify_password(&form.password, &hash))
+            .await
+            .map_err(|_| AppError::InternalServerError("Password check task failed".into()))?;
+        if !valid {
+            return Ok(protected_page(StatusCode::UNAUTHORIZED, &code, Some("Incorrect password")));
+        }
+    }
+
+    let jar = jar.add(unlock_cookie(&code));
+    if let Some(domain) = flagged_domain(&state, &url).await? {
+        return Ok((jar, interstitial_page(&code, &domain)).into_response());
+    }
+    let response = follow_link(&state, url, ip, &headers, None, StatusCode::SEE_OTHER, true).await?;
+    Ok((jar, response).into_response())
+}
+
+#[utoipa::path(
+    post,
+    path = "/{code}/continue",
+    params(
+        ("code" = String, Path, description = "Short code")
+    ),
+    responses(
+        (status = 303, description = "Visitor chose to continue past the blocklist warning; the visit is counted and they're redirected"),
+        (status = 200, description = "Password form for protected links without a valid unlock cookie", body = String, content_type = "text/html"),
+        (status = 404, description = "URL not found or not yet active", body = ErrorResponse),
+        (status = 410, description = "URL expired, visit limit reached or link disabled", body = ErrorResponse)
+    )
+)]
+#[tracing::instrument(skip_all, fields(code = %code))]
+pub async fn continue_url(
+    State(state): State<AppState>,
+    Path(code): Path<String>,
+    ClientIp(ip): ClientIp,
+    jar: SignedCookieJar,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let url = load_active_link(&state, &code).await?;
+    // The warning doesn't stand in for the password
+    if url.password_hash.is_some() && !is_unlocked(&jar, &code) {
+        return Ok(protected_page(StatusCode::OK, &code, None));
+    }
+    follow_link(&state, url, ip, &headers, None, StatusCode::SEE_OTHER, true).await
+}
+
+// The blocklisted domain any of the link's destinations falls under. The
+// list can change after a link is created, so this runs on every redirect.
+async fn flagged_domain(state: &AppState, url: &UrlRecord) -> Result<Option<String>, AppError> {
+    if state.blocklist.is_empty() {
+        return Ok(None);
+    }
+    let mut destinations = vec![url.original_url.clone()];
+    destinations.extend(url.ios_url.iter().chain(url.android_url.iter()).cloned());
+    if url.rotation().is_some() {
+        let rotating = rotation::load_destinations(&state.pool, &url.id).await?;
+        destinations.extend(rotating.into_iter().map(|destination| destination.url));
+    }
+    Ok(destinations.iter().find_map(|destination| state.blocklist.matching(destination)))
+}
+
+// The blocklist warning; never cached, so a later unblock takes effect.
+fn interstitial_page(code: &str, domain: &str) -> Response {
+    (
+        StatusCode::OK,
+        [
+            (header::CACHE_CONTROL, HeaderValue::from_static("no-store")),
+            (HeaderName::from_static("x-robots-tag"), HeaderValue::from_static("noindex")),
+        ],
+        blocklist::interstitial(code, domain),
+    )
+        .into_response()
+}
+
+// The password form; never cached so a later unlock isn't shadowed by it.
+fn protected_page(status: StatusCode, code: &str, error: Option<&str>) -> Response {
+    (status, [(header::CACHE_CONTROL, "no-store")], unlock_form(code, error)).into_response()
+}
+
+// Look up a link for redirecting, preferring the cache, and reject links
+// that are expired or not yet active.
+async fn load_active_link(state: &AppState, code: &str) -> Result<UrlRecord, AppError> {
+    let cached = state.redirect_cache.as_ref().and_then(|cache| cache.get(code));
+
+    let url = match cached {
         Some(u) => u,
-        None => return Err(AppError::UrlNotFound),
+        None => {
+            let url_record = db::find_url(&state.pool, code).await?;
+
+            match url_record {
+                Some(u) => {
+                    if let Some(cache) = &state.redirect_cache {
+                        cache.insert(u.clone());
+                    }
+                    u
+                }
+                None => {
+                    metrics::counter!(telemetry::REDIRECT_NOT_FOUND_TOTAL).increment(1);
+                    return Err(AppError::UrlNotFound);
+                }
+            }
+        }
     };
 
-    if let Some(expires_at) = url.expires_at {
-        if Utc::now() > expires_at {
-            return Err(AppError::UrlNotFound); // Or 410 Gone
+    if !url.is_active {
+        return Err(AppError::LinkDisabled);
+    }
+    if let Err(e) = ensure_not_expired(&url) {
+        if let Some(cache) = &state.redirect_cache {
+            cache.invalidate(code);
         }
+        return Err(e);
     }
+    if is_scheduled(&url) {
+        return Err(AppError::NotYetActive);
+    }
+
+    Ok(url)
+}
 
-    // Record visit asynchronously (spawn task)
-    let pool_clone = pool.clone();
+// Collect the visit details recorded for both redirects and beacon hits.
+fn new_visit(
+    state: &AppState,
+    url_id: String,
+    ip: String,
+    headers: &HeaderMap,
+    source: VisitSource,
+) -> NewVisit {
     let user_agent = headers
-        .get(axum::http::header::USER_AGENT)
+        .get(header::USER_AGENT)
         .and_then(|h| h.to_str().ok())
         .map(|s| s.to_string());
-    
-    let ip = headers
-        .get("x-forwarded-for")
+    let referrer = headers
+        .get(header::REFERER)
         .and_then(|h| h.to_str().ok())
-        .map(|s| s.split(',').next().unwrap_or(s).trim().to_string())
-        .unwrap_or_else(|| "unknown".to_string());
+        .filter(|s| !s.is_empty())
+        .map(|s| s.to_string());
+    let visitor_hash = visitor_hash(&state.visitor_salt, &ip, user_agent.as_deref());
+    let is_bot = is_bot(user_agent.as_deref(), &state.bot_patterns);
+    let platform = detect_platform(user_agent.as_deref());
+    let do_not_track = headers
+        .get("dnt")
+        .is_some_and(|h| h.as_bytes().trim_ascii() == b"1");
+
+    let mut visit = NewVisit {
+        url_id,
+        ip_address: ip,
+        user_agent,
+        referrer,
+        visitor_hash,
+        source,
+        destination: None,
+        platform,
+        visited_at: Utc::now(),
+        country: None,
+        is_bot,
+        detailed: !do_not_track,
+    };
+    state.config.analytics_mode.apply(&mut visit);
+    visit
+}
 
-    tokio::spawn(async move {
-        let _ = sqlx::query("INSERT INTO visits (url_id, ip_address, user_agent, visited_at) VALUES (?, ?, ?, ?)")
-            .bind(code)
-            .bind(ip)
-            .bind(user_agent)
-            .bind(Utc::now())
-            .execute(&pool_clone)
-            .await;
-    });
+// Count the visit and answer with a redirect to the link's destination,
+// carrying over the short link's query string unless the link opted out.
+async fn follow_link(
+    state: &AppState,
+    url: UrlRecord,
+    ip: String,
+    headers: &HeaderMap,
+    query: Option<&str>,
+    status: StatusCode,
+    counted: bool,
+) -> Result<Response, AppError> {
+    let mut visit = new_visit(state, url.id.clone(), ip, headers, VisitSource::Redirect);
+    // App-store links win over the web destination; otherwise rotating links
+    // pick one of their destinations per visit
+    let platform_url = match visit.platform {
+        Platform::Ios => url.ios_url.as_deref(),
+        Platform::Android => url.android_url.as_deref(),
+        Platform::Other => None,
+    };
+    if platform_url.is_none()
+        && let Some(rotation) = url.rotation()
+    {
+        let destinations = rotation::load_destinations(&state.pool, &url.id).await?;
+        visit.destination = rotation::pick(&destinations, rotation, &url.id, &visit.visitor_hash)
+            .map(|destination| destination.url.clone());
+    }
+    let destination = platform_url
+        .or(visit.destination.as_deref())
+        .unwrap_or(&url.original_url);
+
+    let location = match query.filter(|_| url.forward_query) {
+        Some(query) => HeaderValue::from_str(&merge_query(destination, query)),
+        None => HeaderValue::from_str(destination),
+    }
+    .map_err(|_| AppError::InternalServerError("Stored URL is not a valid header value".into()))?;
+
+    // Claim one of the remaining visits atomically so concurrent clicks can't
+    // both pass the limit. Done last, so a redirect that fails above doesn't
+    // use one up; uncounted requests don't either.
+    if counted && url.max_visits.is_some() {
+        let claimed = sqlx::query!(
+            "UPDATE urls SET visit_count = visit_count + 1 WHERE id = ? AND visit_count < max_visits",
+            url.id
+        )
+        .execute(&state.pool)
+        .traced("claim_visit", Some(url.id.as_str()))
+        .await?;
+        if claimed.rows_affected() == 0 {
+            return Err(AppError::VisitLimitReached);
+        }
+    }
 
-    Ok(Redirect::temporary(&url.original_url).into_response())
+    if counted {
+        record_visit(state, &url, visit);
+        metrics::counter!(telemetry::REDIRECTS_TOTAL).increment(1);
+    } else {
+        metrics::counter!(telemetry::REDIRECT_PREFETCHES_TOTAL).increment(1);
+    }
+    let mut response = (
+        status,
+        [
+            (header::LOCATION, location),
+            (HeaderName::from_static("x-robots-tag"), HeaderValue::from_static("noindex")),
+            (header::REFERRER_POLICY, state.config.referrer_policy.clone()),
+        ],
+    )
+        .into_response();
+    // Permanent redirects are left to the browser's own caching rules
+    if matches!(status, StatusCode::FOUND | StatusCode::SEE_OTHER | StatusCode::TEMPORARY_REDIRECT) {
+        response.headers_mut().insert(
+            header::CACHE_CONTROL,
+            state.config.temporary_redirect_cache_control.clone(),
+        );
+    }
+    Ok(response)
+}
+
+/// Keeps crawlers on the home page and out of short links, which are
+/// redirects rather than content.
+pub async fn robots_txt(State(state): State<AppState>, headers: HeaderMap) -> impl IntoResponse {
+    let body = format!(
+        "# Short links on {} only redirect; there is nothing to index behind them\nUser-agent: *\nAllow: /$\nDisallow: /\n",
+        base_url(&state, &headers)
+    );
+    ([(header::CONTENT_TYPE, "text/plain; charset=utf-8")], body)
+}
+
+/// Records a visit without redirecting, for clients that cached a permanent
+/// redirect or report clicks server-to-server. Also accepts `POST`, which is
+/// what `navigator.sendBeacon` sends.
+#[utoipa::path(
+    method(get, post),
+    path = "/hit/{code}",
+    params(
+        ("code" = String, Path, description = "Short code")
+    ),
+    responses(
+        (status = 204, description = "Visit recorded with source `beacon`"),
+        (status = 404, description = "URL not found or not yet active", body = ErrorResponse),
+        (status = 410, description = "URL expired or link disabled", body = ErrorResponse),
+        (status = 429, description = "Too many hits from this IP", body = ErrorResponse)
+    )
+)]
+#[tracing::instrument(skip_all, fields(code = %code))]
+pub async fn record_hit(
+    State(state): State<AppState>,
+    Path(code): Path<String>,
+    ClientIp(ip): ClientIp,
+    headers: HeaderMap,
+) -> Result<StatusCode, AppError> {
+    let url = load_active_link(&state, &code).await?;
+
+    let visit = new_visit(&state, url.id.clone(), ip, &headers, VisitSource::Beacon);
+    record_visit(&state, &url, visit);
+    metrics::counter!(telemetry::BEACON_HITS_TOTAL).increment(1);
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    get,
+    path = "/info/{code}",
+    params(
+        ("code" = String, Path, description = "Short code"),
+        TokenParams
+    ),
+    responses(
+        (status = 200, description = "Link details, without recording a visit. Destinations of password-protected links are null unless the delete token or creating API key is sent.", body = UrlInfoResponse),
+        (status = 404, description = "URL not found", body = ErrorResponse)
+    ),
+    security(
+        (),
+        ("api_key" = [])
+    )
+)]
+#[tracing::instrument(skip_all, fields(code = %code))]
+pub async fn get_info(
+    State(state): State<AppState>,
+    Path(code): Path<String>,
+    Query(params): Query<TokenParams>,
+    headers: HeaderMap,
+) -> Result<Json<UrlInfoResponse>, AppError> {
+    let url_record = db::find_url(&state.pool, &code).await?;
+
+    let url = match url_record {
+        Some(u) => u,
+        None => return Err(AppError::UrlNotFound),
+    };
+
+    let expired = ensure_not_expired(&url).is_err();
+    let scheduled = is_scheduled(&url);
+    let expiry_defaulted = url.expiry_defaulted;
+    let hidden = hides_destinations(&state, &url, &headers, params.token);
+    // Titles and descriptions come from the destination page
+    let (title, description, metadata_fetched_at) = match hidden {
+        true => (None, None, None),
+        false => (url.title.clone(), url.description.clone(), url.metadata_fetched_at),
+    };
+    let (health, last_checked_at, last_status) = (url.health(), url.last_checked_at, url.last_status);
+    let tags = tags::load_tags(&state.pool, &url.id).await?;
+    let remaining_visits = url.max_visits.map(|max| (max - url.visit_count).max(0));
+    let destinations = match url.rotation {
+        Some(_) if !hidden => Some(rotation::load_destinations(&state.pool, &url.id).await?),
+        _ => None,
+    };
+    // Each names its destination, so they're hidden along with them
+    let destination_checks = match hidden {
+        true => Vec::new(),
+        false => {
+            let checked = link_check::destinations_of(
+                &url.original_url,
+                url.ios_url.as_deref(),
+                url.android_url.as_deref(),
+                destinations.as_deref().unwrap_or_default(),
+            );
+            link_check::load_checks(&state.pool, &url.id, &checked).await?
+        }
+    };
+    let mut link = link_response(url, tags, &base_url(&state, &headers));
+    link.destinations = destinations;
+    if hidden {
+        link.original_url = None;
+        link.ios_url = None;
+        link.android_url = None;
+    }
+
+    Ok(Json(UrlInfoResponse {
+        link,
+        expired,
+        expiry_defaulted,
+        scheduled,
+        remaining_visits,
+        title,
+        description,
+        metadata_fetched_at,
+        health,
+        last_checked_at,
+        last_status,
+        destination_checks,
+    }))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/{code}",
+    params(
+        ("code" = String, Path, description = "Short code"),
+        TokenParams
+    ),
+    responses(
+        (status = 204, description = "URL deleted"),
+        (status = 403, description = "Missing or invalid delete token or API key", body = ErrorResponse),
+        (status = 404, description = "URL not found", body = ErrorResponse)
+    ),
+    security(
+        (),
+        ("api_key" = [])
+    )
+)]
+#[tracing::instrument(skip_all, fields(code = %code))]
+pub async fn delete_url(
+    State(state): State<AppState>,
+    Path(code): Path<String>,
+    Query(params): Query<TokenParams>,
+    headers: HeaderMap,
+) -> Result<StatusCode, AppError> {
+    let pool = &state.pool;
+    let url_record = db::find_url(pool, &code).await?;
+
+    let url = match url_record {
+        Some(u) => u,
+        None => return Err(AppError::UrlNotFound),
+    };
+
+    let actor = verify_owner(&state, &url, &headers, params.token)?;
+
+    let mut tx = pool.begin().await?;
+    let before = audit::snapshot(&mut tx, &code).await?;
+    sqlx::query!("DELETE FROM visits WHERE url_id = ?", code)
+        .execute(&mut *tx)
+        .traced("delete_visits", Some(code.as_str()))
+        .await?;
+    sqlx::query!("DELETE FROM url_tags WHERE url_id = ?", code)
+        .execute(&mut *tx)
+        .traced("delete_tags", Some(code.as_str()))
+        .await?;
+    sqlx::query!("DELETE FROM url_destinations WHERE url_id = ?", code)
+        .execute(&mut *tx)
+        .traced("delete_destinations", Some(code.as_str()))
+        .await?;
+    sqlx::query!("DELETE FROM destination_checks WHERE url_id = ?", code)
+        .execute(&mut *tx)
+        .traced("delete_destination_checks", Some(code.as_str()))
+        .await?;
+    sqlx::query!("DELETE FROM urls WHERE id = ?", code)
+        .execute(&mut *tx)
+        .traced("delete_url", Some(code.as_str()))
+        .await?;
+    audit::record(
+        &mut tx,
+        Entry {
+            actor: &actor,
+            action: "delete",
+            code: Some(&code),
+            before,
+            after: None,
+        },
+    )
+    .await?;
+    tx.commit().await?;
+
+    if let Some(cache) = &state.redirect_cache {
+        cache.invalidate(&code);
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    patch,
+    path = "/urls/{code}",
+    params(
+        ("code" = String, Path, description = "Short code"),
+        TokenParams
+    ),
+    request_body = UpdateUrlRequest,
+    responses(
+        (status = 200, description = "URL updated", body = UrlResponse),
+        (status = 400, description = "Invalid input", body = ErrorResponse),
+        (status = 403, description = "Missing or invalid delete token or API key", body = ErrorResponse),
+        (status = 404, description = "URL not found", body = ErrorResponse),
+        (status = 413, description = "Body over `MAX_REQUEST_BODY_BYTES`", body = ErrorResponse),
+        (status = 422, description = "Validation failed", body = ValidationErrorResponse)
+    ),
+    security(
+        (),
+        ("api_key" = [])
+    )
+)]
+#[tracing::instrument(skip_all, fields(code = %code))]
+pub async fn update_url(
+    State(state): State<AppState>,
+    Path(code): Path<String>,
+    Query(params): Query<TokenParams>,
+    headers: HeaderMap,
+    Json(payload): Json<UpdateUrlRequest>,
+) -> Result<Json<UrlResponse>, AppError> {
+    let pool = &state.pool;
+    payload.validate().map_err(AppError::Validation)?;
+
+    let url_record = db::find_url(pool, &code).await?;
+
+    let mut url = match url_record {
+        Some(u) => u,
+        None => return Err(AppError::UrlNotFound),
+    };
+
+    let actor = verify_owner(&state, &url, &headers, params.token)?;
+    // Pausing is recorded as its own action so it's easy to find
+    let action = if payload.active == Some(false) && url.is_active { "disable" } else { "update" };
+    let checked = (url.original_url.clone(), url.ios_url.clone(), url.android_url.clone());
+
+    if let Some(new_url) = payload.url {
+        let new_url = encode_url(&new_url).ok_or(AppError::InvalidUrl)?;
+        if url.rotation.is_some() {
+            return Err(AppError::BadRequest(
+                "url can't be changed on a link with multiple destinations".into(),
+            ));
+        }
+        // The code was derived from the URL, so another URL would break the
+        // promise that hashing it gives this code
+        if url.code_strategy() == Some(CodeStrategy::Hash) && normalize_url(&new_url) != normalize_url(&url.original_url) {
+            return Err(AppError::BadRequest(
+                "url can't be changed on a hash link, whose code is derived from it; create a new link instead".into(),
+            ));
+        }
+        check_destination(&state, &new_url, &base_url(&state, &headers)).await?;
+        url.original_url = new_url;
+    }
+    if let Some(expires_at) = payload.expires_at {
+        check_max_ttl(&state, expires_at)?;
+        url.expires_at = expires_at;
+        url.expiry_defaulted = false;
+    }
+    if let Some(active) = payload.active {
+        url.is_active = active;
+    }
+    if let Some(forward_query) = payload.forward_query {
+        url.forward_query = forward_query;
+    }
+    if let Some(ios_url) = payload.ios_url {
+        let ios_url = ios_url.map(|ios_url| encode_url(&ios_url).ok_or(AppError::InvalidUrl)).transpose()?;
+        if let Some(ios_url) = &ios_url {
+            check_destination(&state, ios_url, &base_url(&state, &headers)).await?;
+        }
+        url.ios_url = ios_url;
+    }
+    if let Some(android_url) = payload.android_url {
+        let android_url = android_url.map(|android_url| encode_url(&android_url).ok_or(AppError::InvalidUrl)).transpose()?;
+        if let Some(android_url) = &android_url {
+            check_destination(&state, android_url, &base_url(&state, &headers)).await?;
+        }
+        url.android_url = android_url;
+    }
+    if let Some(campaign_id) = payload.campaign_id {
+        if let Some(campaign_id) = campaign_id {
+            campaigns::check_exists(pool, campaign_id).await?;
+        }
+        url.campaign_id = campaign_id;
+    }
+    validate_schedule(url.starts_at, url.expires_at)?;
+    let normalized_url = normalize_url(&url.original_url).ok_or(AppError::InvalidUrl)?;
+    // The old destinations' check results say nothing about new ones
+    if checked != (url.original_url.clone(), url.ios_url.clone(), url.android_url.clone()) {
+        url.health = LinkHealth::Unknown.as_str().to_string();
+        url.last_checked_at = None;
+        url.last_status = None;
+    }
+    // Nor does the old page's title describe the new one; links that had
+    // metadata get it fetched again once the change is stored
+    let url_changed = url.original_url != checked.0;
+    let refetch_metadata = url_changed && url.metadata_fetched_at.is_some();
+    if url_changed {
+        url.title = None;
+        url.description = None;
+        url.metadata_fetched_at = None;
+    }
+
+    let mut tx = pool.begin().await?;
+    let before = audit::snapshot(&mut tx, &code).await?;
+    sqlx::query!(
+        "UPDATE urls SET original_url = ?, normalized_url = ?, expires_at = ?, expiry_defaulted = ?, is_active = ?, forward_query = ?, ios_url = ?, android_url = ?, campaign_id = ?, health = ?, last_checked_at = ?, last_status = ?, title = ?, description = ?, metadata_fetched_at = ? WHERE id = ?",
+        url.original_url,
+        normalized_url,
+        url.expires_at,
+        url.expiry_defaulted,
+        url.is_active,
+        url.forward_query,
+        url.ios_url,
+        url.android_url,
+        url.campaign_id,
+        url.health,
+        url.last_checked_at,
+        url.last_status,
+        url.title,
+        url.description,
+        url.metadata_fetched_at,
+        code
+    )
+    .execute(&mut *tx)
+    .traced("update_url", Some(code.as_str()))
+    .await?;
+    let after = audit::snapshot(&mut tx, &code).await?;
+    audit::record(
+        &mut tx,
+        Entry {
+            actor: &actor,
+            action,
+            code: Some(&code),
+            before,
+            after,
+        },
+    )
+    .await?;
+    tx.commit().await?;
+
+    if let Some(cache) = &state.redirect_cache {
+        cache.invalidate(&code);
+    }
+    if refetch_metadata {
+        state.metadata.fetch(&code, &url.original_url, &base_url(&state, &headers));
+    }
+
+    let tags = tags::load_tags(pool, &code).await?;
+    Ok(Json(link_response(url, tags, &base_url(&state, &headers))))
+}
+
+pub const DEFAULT_STATS_DAYS: u32 = 30;
+const DEFAULT_VISITS_PAGE: i64 = 100;
+const MAX_VISITS_PAGE: i64 = 1000;
+pub const MAX_STATS_DAYS: u32 = 365;
+
+/// Expands sparse per-day counts into a continuous series from `first` to `last`.
+pub fn fill_daily_gaps(counts: Vec<DailyCount>, first: NaiveDate, last: NaiveDate) -> Vec<DailyCount> {
+    let by_date: HashMap<NaiveDate, i64> =
+        counts.into_iter().map(|c| (c.date, c.count)).collect();
+
+    first
+        .iter_days()
+        .take_while(|date| *date <= last)
+        .map(|date| DailyCount {
+            date,
+            count: by_date.get(&date).copied().unwrap_or(0),
+        })
+        .collect()
+}
+
+// Visits per weekday and hour as the full 7×24 grid, Sunday first. SQLite
+// buckets UTC itself; for other zones visits are grouped by UTC quarter hour,
+// the finest step real offsets use, and each slot is shifted here so DST
+// changes land in the right hour.
+async fn visit_heatmap(
+    pool: &crate::db::DbPool,
+    code: &str,
+    tz: Option<Tz>,
+    include_bots: bool,
+) -> Result<Vec<HeatmapCell>, AppError> {
+    let mut grid = [0i64; 7 * 24];
+    let mut add = |weekday: u32, hour: u32, count: i64| {
+        if let Some(cell) = grid.get_mut((weekday * 24 + hour) as usize) {
+            *cell += count;
+        }
+    };
+    match tz {
+        None => {
+            let rows = sqlx::query!(
+                r#"SELECT CAST(strftime('%w', visited_at) AS INTEGER) AS "weekday!: u32", CAST(strftime('%H', visited_at) AS INTEGER) AS "hour!: u32", COUNT(*) AS "count!: i64" FROM visits WHERE url_id = ? AND (? OR NOT is_bot) GROUP BY 1, 2"#,
+                code,
+                include_bots
+            )
+            .fetch_all(pool)
+            .traced("count_visit_heatmap", Some(code))
+            .await?;
+            for row in rows {
+                add(row.weekday, row.hour, row.count);
+            }
+        }
+        Some(tz) => {
+            let rows = sqlx::query!(
+                r#"SELECT strftime('%Y-%m-%d %H:', visited_at) || printf('%02d', CAST(strftime('%M', visited_at) AS INTEGER) / 15 * 15) AS "slot!: String", COUNT(*) AS "count!: i64" FROM visits WHERE url_id = ? AND (? OR NOT is_bot) GROUP BY 1"#,
+                code,
+                include_bots
+            )
+            .fetch_all(pool)
+            .traced("count_visit_heatmap", Some(code))
+            .await?;
+            for row in rows {
+                let Ok(slot) = NaiveDateTime::parse_from_str(&row.slot, "%Y-%m-%d %H:%M") else {
+                    continue;
+                };
+                let local = slot.and_utc().with_timezone(&tz);
+                add(local.weekday().num_days_from_sunday(), local.hour(), row.count);
+            }
+        }
+    }
+    Ok(grid
+        .into_iter()
+        .enumerate()
+        .map(|(index, count)| HeatmapCell {
+            weekday: (index / 24) as u8,
+            hour: (index % 24) as u8,
+            count,
+        })
+        .collect())
+}
+
+// Visits per configured destination of a rotating link, in configuration order.
+async fn destination_counts(
+    pool: &crate::db::DbPool,
+    code: &str,
+    include_bots: bool,
+) -> Result<Vec<DestinationCount>, AppError> {
+    let visits: HashMap<String, i64> = sqlx::query!(
+        r#"SELECT destination AS "destination!", COUNT(*) AS "visits!: i64" FROM visits WHERE url_id = ? AND destination IS NOT NULL AND (? OR NOT is_bot) GROUP BY destination"#,
+        code,
+        include_bots
+    )
+    .fetch_all(pool)
+    .traced("count_destination_visits", Some(code))
+    .await?
+    .into_iter()
+    .map(|row| (row.destination, row.visits))
+    .collect();
+
+    Ok(rotation::load_destinations(pool, code)
+        .await?
+        .into_iter()
+        .map(|destination| DestinationCount {
+            visits: visits.get(&destination.url).copied().unwrap_or(0),
+            url: destination.url,
+            weight: destination.weight,
+        })
+        .collect())
+}
+
+const TOP_REFERRERS: usize = 10;
+
+// Group raw referrer counts by host and keep the most frequent ones.
+fn top_referrers(referrers: Vec<(Option<String>, i64)>) -> Vec<ReferrerCount> {
+    let mut by_host: HashMap<String, i64> = HashMap::new();
+    for (referrer, count) in referrers {
+        *by_host.entry(referrer_host(referrer.as_deref())).or_default() += count;
+    }
+
+    let mut counts: Vec<ReferrerCount> = by_host
+        .into_iter()
+        .map(|(referrer, count)| ReferrerCount { referrer, count })
+        .collect();
+    counts.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.referrer.cmp(&b.referrer)));
+    counts.truncate(TOP_REFERRERS);
+    counts
 }
 
 #[utoipa::path(
     get,
     path = "/stats/{code}",
     params(
-        ("code" = String, Path, description = "Short code")
+        ("code" = String, Path, description = "Short code"),
+        StatsParams
     ),
     responses(
         (status = 200, description = "Statistics", body = StatsResponse),
-        (status = 404, description = "URL not found")
+        (status = 400, description = "Malformed query parameters, from not before to, an unknown `include` or an invalid `tz`", body = ErrorResponse),
+        (status = 403, description = "Private link and missing or wrong stats token", body = ErrorResponse),
+        (status = 404, description = "URL not found", body = ErrorResponse),
+        (status = 410, description = "URL expired", body = ErrorResponse)
+    ),
+    security(
+        (),
+        ("stats_token" = [])
     )
 )]
+#[tracing::instrument(skip_all, fields(code = %code))]
 pub async fn get_stats(
-    State(pool): State<DbPool>,
+    State(state): State<AppState>,
     Path(code): Path<String>,
+    headers: HeaderMap,
+    params: Result<Query<StatsParams>, QueryRejection>,
 ) -> Result<Json<StatsResponse>, AppError> {
+    let pool = &state.pool;
+    // Report malformed dates or numbers in the usual JSON error shape
+    let Query(params) = params.map_err(|rejection| AppError::BadRequest(rejection.body_text()))?;
+    if let (Some(from), Some(to)) = (params.from, params.to)
+        && from >= to
+    {
+        return Err(AppError::InvalidTimeRange);
+    }
+    let mut include_heatmap = false;
+    for extra in params.include.iter().flat_map(|raw| raw.split(',')).map(str::trim) {
+        match extra {
+            "heatmap" => include_heatmap = true,
+            "" => {}
+            other => return Err(AppError::BadRequest(format!("include can only list heatmap, got {:?}", other))),
+        }
+    }
+    let tz = params
+        .tz
+        .as_deref()
+        .map(|name| {
+            name.trim()
+                .parse::<Tz>()
+                .map_err(|_| AppError::BadRequest(format!("tz must be an IANA time zone name like Europe/Berlin, got {:?}", name)))
+        })
+        .transpose()?;
+    let limit = params.limit.unwrap_or(DEFAULT_VISITS_PAGE).clamp(1, MAX_VISITS_PAGE);
+    let offset = params.offset.unwrap_or(0).max(0);
     // Check if URL exists first
-    let url_record: Option<UrlRecord> = sqlx::query_as("SELECT * FROM urls WHERE id = ?")
-        .bind(&code)
-        .fetch_optional(&pool)
-        .await?;
+    let url_record = db::find_url(pool, &code).await?;
 
     let url = match url_record {
         Some(u) => u,
         None => return Err(AppError::UrlNotFound),
     };
 
-    let visits: Vec<VisitStats> = sqlx::query_as("SELECT ip_address, user_agent, visited_at FROM visits WHERE url_id = ? ORDER BY visited_at DESC LIMIT 100")
-        .bind(&code)
-        .fetch_all(&pool)
-        .await?;
-    
-    let total: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM visits WHERE url_id = ?")
-        .bind(&code)
-        .fetch_one(&pool)
+    verify_stats_access(&state, &url, &headers, params.stats_token.as_deref())?;
+    ensure_not_expired(&url)?;
+    let hidden = hides_destinations(&state, &url, &headers, None);
+
+    // Fetch one extra row to learn whether another page exists
+    let mut visits_query: QueryBuilder<Sqlite> =
+        QueryBuilder::new("SELECT ip_address, user_agent, referrer, source, destination, platform, visited_at, is_bot FROM visits WHERE url_id = ");
+    visits_query.push_bind(&code);
+    if let Some(from) = params.from {
+        visits_query.push(" AND visited_at >= ").push_bind(from);
+    }
+    if let Some(to) = params.to {
+        visits_query.push(" AND visited_at < ").push_bind(to);
+    }
+    visits_query
+        .push(" ORDER BY visited_at DESC, id DESC LIMIT ")
+        .push_bind(limit + 1)
+        .push(" OFFSET ")
+        .push_bind(offset);
+    let mut visits: Vec<VisitStats> = visits_query
+        .build_query_as()
+        .fetch_all(pool)
+        .traced("list_visits", Some(code.as_str()))
         .await?;
+    let has_more = visits.len() as i64 > limit;
+    visits.truncate(limit as usize);
+    if hidden {
+        visits.iter_mut().for_each(|visit| visit.destination = None);
+    }
 
+    // Bots are left out of every count below unless include_bots is set
+    let include_bots = params.include_bots;
+    let sources = sqlx::query!(
+        r#"SELECT COALESCE(SUM(source = 'redirect'), 0) AS "redirect_visits!: i64", COALESCE(SUM(source = 'beacon'), 0) AS "beacon_visits!: i64" FROM visits WHERE url_id = ? AND (? OR NOT is_bot)"#,
+        code,
+        include_bots
+    )
+    .fetch_one(pool)
+    .traced_one("count_visit_sources", Some(code.as_str()))
+    .await?;
+
+    let unique_visitors = sqlx::query_scalar!(
+        r#"SELECT COUNT(DISTINCT visitor_hash) AS "unique_visitors!: i64" FROM visits WHERE url_id = ? AND (? OR NOT is_bot)"#,
+        code,
+        include_bots
+    )
+    .fetch_one(pool)
+    .traced_one("count_unique_visitors", Some(code.as_str()))
+    .await?;
+
+    let span = sqlx::query!(
+        r#"SELECT MIN(visited_at) AS "first_visit_at?: DateTime<Utc>", MAX(visited_at) AS "last_visit_at?: DateTime<Utc>" FROM visits WHERE url_id = ? AND (? OR NOT is_bot)"#,
+        code,
+        include_bots
+    )
+    .fetch_one(pool)
+    .traced_one("visit_span", Some(code.as_str()))
+    .await?;
+    let total_visits = if include_bots {
+        url.total_visits
+    } else {
+        url.total_visits - url.bot_visits
+    };
+    // A link younger than a day counts as a day old, so the average never
+    // exceeds the total
+    let days_old = ((Utc::now() - url.created_at).num_seconds() as f64 / 86_400.0).max(1.0);
+    let avg_visits_per_day = (total_visits as f64 / days_old * 100.0).round() / 100.0;
+
+    let days = params.days.unwrap_or(DEFAULT_STATS_DAYS).clamp(1, MAX_STATS_DAYS);
+    let today = Utc::now().date_naive();
+    let first_day = today - Duration::days(i64::from(days) - 1);
+    let window_start = first_day.and_hms_opt(0, 0, 0).unwrap().and_utc();
+
+    let counts = sqlx::query_as!(
+        DailyCount,
+        r#"SELECT date(visited_at) AS "date!: NaiveDate", COUNT(*) AS "count!: i64" FROM visits WHERE url_id = ? AND visited_at >= ? AND (? OR NOT is_bot) GROUP BY date(visited_at)"#,
+        code,
+        window_start,
+        include_bots
+    )
+    .fetch_all(pool)
+    .traced("count_daily_visits", Some(code.as_str()))
+    .await?;
+
+    let referrers: Vec<(Option<String>, i64)> = sqlx::query!(
+        r#"SELECT referrer, COUNT(*) AS "count!: i64" FROM visits WHERE url_id = ? AND (? OR NOT is_bot) GROUP BY referrer"#,
+        code,
+        include_bots
+    )
+    .fetch_all(pool)
+    .traced("count_referrers", Some(code.as_str()))
+    .await?
+    .into_iter()
+    .map(|row| (row.referrer, row.count))
+    .collect();
+
+    let user_agents: Vec<(Option<String>, i64)> = sqlx::query!(
+        r#"SELECT user_agent, COUNT(*) AS "count!: i64" FROM visits WHERE url_id = ? AND (? OR NOT is_bot) GROUP BY user_agent"#,
+        code,
+        include_bots
+    )
+    .fetch_all(pool)
+    .traced("count_user_agents", Some(code.as_str()))
+    .await?
+    .into_iter()
+    .map(|row| (row.user_agent, row.count))
+    .collect();
+    let agents = user_agent::breakdown(user_agents);
+
+    let destinations = match url.rotation {
+        Some(_) if !hidden => destination_counts(pool, &code, include_bots).await?,
+        _ => Vec::new(),
+    };
+    let platforms: Vec<(String, i64)> = sqlx::query!(
+        r#"SELECT platform AS "platform!", COUNT(*) AS "count!: i64" FROM visits WHERE url_id = ? AND platform IS NOT NULL AND (? OR NOT is_bot) GROUP BY platform ORDER BY COUNT(*) DESC, platform"#,
+        code,
+        include_bots
+    )
+    .fetch_all(pool)
+    .traced("count_platforms", Some(code.as_str()))
+    .await?
+    .into_iter()
+    .map(|row| (row.platform, row.count))
+    .collect();
+
+    let countries = sqlx::query_as!(
+        CountryCount,
+        r#"SELECT country AS "code!", COUNT(*) AS "count!: i64" FROM visits WHERE url_id = ? AND country IS NOT NULL AND (? OR NOT is_bot) GROUP BY country ORDER BY COUNT(*) DESC, country"#,
+        code,
+        include_bots
+    )
+    .fetch_all(pool)
+    .traced("count_countries", Some(code.as_str()))
+    .await?;
+
+    let heatmap = if include_heatmap {
+        Some(visit_heatmap(pool, &code, tz, include_bots).await?)
+    } else {
+        None
+    };
+
+    let code_strategy = url.code_strategy();
     Ok(Json(StatsResponse {
         url: code,
-        original_url: url.original_url,
-        total_visits: total,
+        redirect_type: url.redirect_type(),
+        scheduled: is_scheduled(&url),
+        active: url.is_active,
+        created_at: url.created_at,
+        expires_at: url.expires_at,
+        // Expired links answer 410 above, but the clock may tick past in between
+        expires_in_secs: url.expires_at.map(|at| (at - Utc::now()).num_seconds().max(0)),
+        original_url: (!hidden).then_some(url.original_url),
+        total_visits,
+        bot_visits: url.bot_visits,
+        untracked_visits: url.untracked_visits,
+        redirect_visits: sources.redirect_visits,
+        beacon_visits: sources.beacon_visits,
+        unique_visitors,
+        first_visit_at: span.first_visit_at,
+        last_visit_at: span.last_visit_at,
+        avg_visits_per_day,
         visits,
+        has_more,
+        next_offset: has_more.then_some(offset + limit),
+        daily_visits: fill_daily_gaps(counts, first_day, today),
+        top_referrers: top_referrers(referrers),
+        browsers: agents.browsers,
+        operating_systems: agents.operating_systems,
+        device_types: agents.device_types,
+        destinations,
+        platforms: platforms
+            .into_iter()
+            .map(|(name, count)| NamedCount { name, count })
+            .collect(),
+        countries,
+        code_strategy,
+        heatmap,
     }))
 }
 
+// Looks up one link for the summary endpoints, with the same access and
+// expiry checks as the full stats.
+async fn summarize(
+    state: &AppState,
+    code: &str,
+    headers: &HeaderMap,
+    stats_token: Option<&str>,
+    include_bots: bool,
+) -> Result<StatsSummary, AppError> {
+    let url = db::find_url(&state.pool, code).await?.ok_or(AppError::UrlNotFound)?;
+    verify_stats_access(state, &url, headers, stats_token)?;
+    ensure_not_expired(&url)?;
+    let hidden = hides_destinations(state, &url, headers, None);
+    let mut summary = summary(&state.pool, url, include_bots).await?;
+    if hidden {
+        summary.original_url = None;
+        summary.title = None;
+    }
+    Ok(summary)
+}
+
+/// The summary of a link already looked up, expired or not, with no access
+/// check. Bot visits are only counted with `include_bots`.
+pub async fn summary(pool: &crate::db::DbPool, url: UrlRecord, include_bots: bool) -> Result<StatsSummary, AppError> {
+    let code = url.id.as_str();
+    let visits = sqlx::query!(
+        r#"SELECT COUNT(DISTINCT visitor_hash) AS "unique_visitors!: i64", MAX(visited_at) AS "last_visited_at?: DateTime<Utc>" FROM visits WHERE url_id = ? AND (? OR NOT is_bot)"#,
+        code,
+        include_bots
+    )
+    .fetch_one(pool)
+    .traced_one("summarize_visits", Some(code))
+    .await?;
+
+    Ok(StatsSummary {
+        url: url.id,
+        original_url: Some(url.original_url),
+        title: url.title,
+        active: url.is_active,
+        created_at: url.created_at,
+        expires_at: url.expires_at,
+        total_visits: if include_bots { url.total_visits } else { url.total_visits - url.bot_visits },
+        unique_visitors: visits.unique_visitors,
+        last_visited_at: visits.last_visited_at,
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/stats/{code}/summary",
+    params(
+        ("code" = String, Path, description = "Short code"),
+        StatsSummaryParams
+    ),
+    responses(
+        (status = 200, description = "Visit counts and link details, without the visit list", body = StatsSummary),
+        (status = 403, description = "Private link and missing or wrong stats token", body = ErrorResponse),
+        (status = 404, description = "URL not found", body = ErrorResponse),
+        (status = 410, description = "URL expired", body = ErrorResponse)
+    ),
+    security(
+        (),
+        ("stats_token" = [])
+    )
+)]
+#[tracing::instrument(skip_all, fields(code = %code))]
+pub async fn get_stats_summary(
+    State(state): State<AppState>,
+    Path(code): Path<String>,
+    headers: HeaderMap,
+    params: Result<Query<StatsSummaryParams>, QueryRejection>,
+) -> Result<Json<StatsSummary>, AppError> {
+    let Query(params) = params.map_err(|rejection| AppError::BadRequest(rejection.body_text()))?;
+    Ok(Json(summarize(&state, &code, &headers, params.stats_token.as_deref(), params.include_bots).await?))
+}
+
+const MAX_SUMMARY_CODES: usize = 100;
+
+#[utoipa::path(
+    post,
+    path = "/stats/summary",
+    request_body = StatsSummaryRequest,
+    responses(
+        (status = 200, description = "Summary or error for each requested code, keyed by code", body = BTreeMap<String, StatsSummaryResult>),
+        (status = 400, description = "No codes or more than 100", body = ErrorResponse)
+    ),
+    security(
+        (),
+        ("stats_token" = [])
+    )
+)]
+#[tracing::instrument(skip_all)]
+pub async fn bulk_stats_summary(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(payload): Json<StatsSummaryRequest>,
+) -> Result<Json<BTreeMap<String, StatsSummaryResult>>, AppError> {
+    if payload.codes.is_empty() || payload.codes.len() > MAX_SUMMARY_CODES {
+        return Err(AppError::BadRequest(format!(
+            "codes must list between 1 and {} short codes",
+            MAX_SUMMARY_CODES
+        )));
+    }
+
+    // The header would win over a code's own token, so it's left out for those
+    let mut without_header = headers.clone();
+    without_header.remove(auth::STATS_TOKEN_HEADER);
+    let mut results = BTreeMap::new();
+    for code in payload.codes {
+        if results.contains_key(&code) {
+            continue;
+        }
+        let token = payload.stats_tokens.get(&code).map(String::as_str);
+        let headers = if token.is_some() { &without_header } else { &headers };
+        let result = match summarize(&state, &code, headers, token, payload.include_bots).await {
+            Ok(summary) => StatsSummaryResult::Found(summary),
+            Err(e @ (AppError::DatabaseError(_) | AppError::InternalServerError(_))) => return Err(e),
+            Err(e) => StatsSummaryResult::Failed {
+                code: e.code(),
+                error: e.message(),
+            },
+        };
+        results.insert(code, result);
+    }
+    Ok(Json(results))
+}
+
+const QR_MIN_SIZE: u32 = 64;
+const QR_MAX_SIZE: u32 = 2048;
+const QR_DEFAULT_SIZE: u32 = 256;
+const QR_DEFAULT_MARGIN: u32 = 4;
+const QR_MAX_MARGIN: u32 = 16;
+const QR_CACHE_CONTROL: &str = "public, max-age=86400";
+// Bump when rendering changes so cached images are refetched
+const QR_RENDER_VERSION: &str = "1";
+
+// Strong validator over everything that determines the image bytes.
+fn qr_etag(
+    short_url: &str,
+    format: QrFormat,
+    size: Option<u32>,
+    ec_level: EcLevel,
+    margin: u32,
+    fg: Option<[u8; 3]>,
+    bg: Option<[u8; 3]>,
+) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(format!(
+        "{}\0{}\0{:?}\0{:?}\0{:?}\0{}\0{:?}\0{:?}",
+        QR_RENDER_VERSION, short_url, format, size, ec_level, margin, fg, bg
+    ));
+    let digest = hasher.finalize();
+    let hex: String = digest[..16].iter().map(|b| format!("{:02x}", b)).collect();
+    format!("\"{}\"", hex)
+}
+
+// Whether `If-None-Match` lists the given tag (or `*`). Weak comparison, as
+// RFC 9110 prescribes for this header.
+fn etag_matches(headers: &HeaderMap, etag: &str) -> bool {
+    headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|tag| tag.trim())
+        .any(|tag| tag == "*" || tag.strip_prefix("W/").unwrap_or(tag) == etag)
+}
+
+// Parse a `RRGGBB` color, tolerating a leading '#'.
+fn parse_hex_color(name: &str, raw: &str) -> Result<[u8; 3], AppError> {
+    let hex = raw.strip_prefix('#').unwrap_or(raw);
+    let invalid = || {
+        AppError::BadRequest(format!(
+            "{} must be a 6-digit hex color such as 112233, got '{}'",
+            name, raw
+        ))
+    };
+    if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(invalid());
+    }
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| invalid());
+    Ok([channel(0)?, channel(2)?, channel(4)?])
+}
+
+fn parse_ec_level(raw: &str) -> Result<EcLevel, AppError> {
+    match raw.to_ascii_uppercase().as_str() {
+        "L" => Ok(EcLevel::L),
+        "M" => Ok(EcLevel::M),
+        "Q" => Ok(EcLevel::Q),
+        "H" => Ok(EcLevel::H),
+        _ => Err(AppError::BadRequest(format!(
+            "ec must be one of L, M, Q or H, got '{}'",
+            raw
+        ))),
+    }
+}
+
+// Draw the modules with the given quiet zone and colors, then center them on
+// a background-colored square canvas of exactly `size` pixels.
+fn qr_canvas<P>(qr: &QrCode, size: u32, margin: u32, dark: P, light: P) -> ImageBuffer<P, Vec<u8>>
+where
+    P: qrcode::render::Pixel<Image = ImageBuffer<P, Vec<u8>>> + image::Pixel<Subpixel = u8>,
+{
+    let colors = qr.to_colors();
+    let rendered = Renderer::<P>::new(&colors, qr.width(), margin)
+        .dark_color(dark)
+        .light_color(light)
+        .max_dimensions(size, size)
+        .build();
+
+    let mut canvas = ImageBuffer::from_pixel(size, size, light);
+    let x = (size.saturating_sub(rendered.width()) / 2) as i64;
+    let y = (size.saturating_sub(rendered.height()) / 2) as i64;
+    image::imageops::overlay(&mut canvas, &rendered, x, y);
+    canvas
+}
+
+// Render a QR code as PNG. Without custom colors this stays the grayscale
+// black-on-white image served before colors were configurable.
+fn render_qr_png(qr: &QrCode, size: u32, margin: u32, colors: Option<([u8; 3], [u8; 3])>) -> Result<Vec<u8>, AppError> {
+    let image = match colors {
+        None => DynamicImage::ImageLuma8(qr_canvas(qr, size, margin, Luma([0u8]), Luma([255u8]))),
+        Some((fg, bg)) => DynamicImage::ImageRgb8(qr_canvas(qr, size, margin, Rgb(fg), Rgb(bg))),
+    };
+
+    let mut bytes = Vec::new();
+    image
+        .write_to(&mut Cursor::new(&mut bytes), ImageFormat::Png)
+        .map_err(|e| AppError::InternalServerError(format!("PNG encoding failed: {}", e)))?;
+    Ok(bytes)
+}
+
 #[utoipa::path(
     get,
     path = "/qr/{code}",
     params(
-        ("code" = String, Path, description = "Short code")
+        ("code" = String, Path, description = "Short code"),
+        QrParams
     ),
     responses(
-        (status = 200, description = "QR Code SVG image"),
-        (status = 404, description = "URL not found")
+        (status = 200, description = "QR Code image (SVG by default, PNG with format=png), with an `ETag` and `Cache-Control: public, max-age=86400`"),
+        (status = 304, description = "`If-None-Match` matched; the image isn't rendered"),
+        (status = 400, description = "Invalid format, color, error correction level or margin", body = ErrorResponse),
+        (status = 404, description = "URL not found", body = ErrorResponse)
     )
 )]
+#[tracing::instrument(skip_all, fields(code = %code))]
 pub async fn generate_qr(
-    State(pool): State<DbPool>,
+    State(state): State<AppState>,
     Path(code): Path<String>,
-) -> Result<impl IntoResponse, AppError> {
-     let url_record: Option<UrlRecord> = sqlx::query_as("SELECT * FROM urls WHERE id = ?")
-        .bind(&code)
-        .fetch_optional(&pool)
-        .await?;
+    Query(params): Query<QrParams>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let pool = &state.pool;
+    let format = match params.format.as_deref().unwrap_or("svg") {
+        f if f.eq_ignore_ascii_case("svg") => QrFormat::Svg,
+        f if f.eq_ignore_ascii_case("png") => QrFormat::Png,
+        other => {
+            return Err(AppError::BadRequest(format!(
+                "Unsupported format '{}', expected 'svg' or 'png'",
+                other
+            )))
+        }
+    };
+
+    let fg = params.fg.as_deref().map(|raw| parse_hex_color("fg", raw)).transpose()?;
+    let bg = params.bg.as_deref().map(|raw| parse_hex_color("bg", raw)).transpose()?;
+    let ec_level = params.ec.as_deref().map(parse_ec_level).transpose()?.unwrap_or(EcLevel::M);
+    let margin = params.margin.unwrap_or(QR_DEFAULT_MARGIN);
+    if margin > QR_MAX_MARGIN {
+        return Err(AppError::BadRequest(format!(
+            "margin must be between 0 and {}",
+            QR_MAX_MARGIN
+        )));
+    }
+
+    let url_record = db::find_url(pool, &code).await?;
 
     let _url = match url_record {
         Some(u) => u,
@@ -216,13 +2166,45 @@ pub async fn generate_qr(
     };
 
     // Construct full short URL
-    let short_url = format!("http://localhost:3000/{}", code);
-    
-    let code = QrCode::new(short_url).map_err(|_| AppError::InternalServerError("QR generation failed".into()))?;
-    let image = code.render::<svg::Color>().build();
+    let short_url = format!("{}/{}", base_url(&state, &headers), code);
+    let size = (format == QrFormat::Png)
+        .then(|| params.size.unwrap_or(QR_DEFAULT_SIZE).clamp(QR_MIN_SIZE, QR_MAX_SIZE));
 
-    Ok((
-        [(axum::http::header::CONTENT_TYPE, "image/svg+xml")],
-        image,
-    ))
+    let etag = qr_etag(&short_url, format, size, ec_level, margin, fg, bg);
+    let cache_headers = [
+        (header::ETAG, HeaderValue::from_str(&etag).map_err(|_| AppError::InternalServerError("Invalid ETag".into()))?),
+        (header::CACHE_CONTROL, HeaderValue::from_static(QR_CACHE_CONTROL)),
+    ];
+    if etag_matches(&headers, &etag) {
+        return Ok((StatusCode::NOT_MODIFIED, cache_headers).into_response());
+    }
+
+    let code = QrCode::with_error_correction_level(short_url, ec_level)
+        .map_err(|_| AppError::InternalServerError("QR generation failed".into()))?;
+
+    match format {
+        QrFormat::Svg => {
+            let hex = |rgb: [u8; 3]| format!("#{:02x}{:02x}{:02x}", rgb[0], rgb[1], rgb[2]);
+            let (dark, light) = (fg.map(hex), bg.map(hex));
+            let colors = code.to_colors();
+            let mut renderer = Renderer::<svg::Color>::new(&colors, code.width(), margin);
+            if let Some(dark) = &dark {
+                renderer.dark_color(svg::Color(dark));
+            }
+            if let Some(light) = &light {
+                renderer.light_color(svg::Color(light));
+            }
+            let image = renderer.build();
+            metrics::counter!(telemetry::QR_CODES_RENDERED_TOTAL, "format" => "svg").increment(1);
+            Ok((cache_headers, [(header::CONTENT_TYPE, "image/svg+xml")], image).into_response())
+        }
+        QrFormat::Png => {
+            let size = size.unwrap_or(QR_DEFAULT_SIZE);
+            let colors = (fg.is_some() || bg.is_some())
+                .then(|| (fg.unwrap_or([0, 0, 0]), bg.unwrap_or([255, 255, 255])));
+            let image = render_qr_png(&code, size, margin, colors)?;
+            metrics::counter!(telemetry::QR_CODES_RENDERED_TOTAL, "format" => "png").increment(1);
+            Ok((cache_headers, [(header::CONTENT_TYPE, "image/png")], image).into_response())
+        }
+    }
 }
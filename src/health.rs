@@ -0,0 +1,46 @@
+use crate::db::DbPool;
+use axum::{extract::State, http::StatusCode, Json};
+use serde_json::{json, Value};
+use std::time::Duration;
+
+const READINESS_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[utoipa::path(
+    get,
+    path = "/healthz",
+    responses(
+        (status = 200, description = "Process is up")
+    )
+)]
+pub async fn healthz() -> Json<Value> {
+    Json(json!({ "status": "ok" }))
+}
+
+#[utoipa::path(
+    get,
+    path = "/readyz",
+    responses(
+        (status = 200, description = "Every check passed"),
+        (status = 503, description = "A check failed or timed out; `checks` names it")
+    )
+)]
+pub async fn readyz(State(pool): State<DbPool>) -> (StatusCode, Json<Value>) {
+    let check = sqlx::query_scalar!("SELECT 1").fetch_one(&pool);
+
+    let database = match tokio::time::timeout(READINESS_TIMEOUT, check).await {
+        Ok(Ok(_)) => "ok",
+        Ok(Err(e)) => {
+            tracing::warn!("readiness check failed: {:?}", e);
+            "failed"
+        }
+        Err(_) => {
+            tracing::warn!("readiness check timed out after {:?}", READINESS_TIMEOUT);
+            "timed_out"
+        }
+    };
+    let (status, summary) = match database {
+        "ok" => (StatusCode::OK, "ready"),
+        _ => (StatusCode::SERVICE_UNAVAILABLE, "unavailable"),
+    };
+    (status, Json(json!({ "status": summary, "checks": { "database": database } })))
+}
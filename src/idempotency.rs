@@ -0,0 +1,175 @@
+use crate::{
+    db::{DbPool, Traced},
+    error::AppError,
+    models::{CreateUrlRequest, UrlResponse},
+};
+use axum::http::{HeaderMap, StatusCode};
+use chrono::{DateTime, Utc};
+use sha2::{Digest, Sha256};
+use std::time::Duration;
+
+pub const IDEMPOTENCY_KEY: &str = "idempotency-key";
+/// Keys are remembered this long; a retry after that creates a new link.
+const KEY_TTL_HOURS: i64 = 24;
+const SWEEP_INTERVAL: Duration = Duration::from_secs(3600);
+const MAX_KEY_LENGTH: usize = 255;
+
+fn cutoff() -> DateTime<Utc> {
+    Utc::now() - chrono::Duration::hours(KEY_TTL_HOURS)
+}
+
+/// Outcome of claiming a key for a request.
+pub enum Claim {
+    /// First use: the caller creates the link and then calls `complete`
+    New,
+    /// Same key and payload as an earlier success; answer with its response
+    Replay(StatusCode, Box<UrlResponse>),
+}
+
+/// Reads the optional `Idempotency-Key` header, rejecting empty or oversized
+/// values and anything other than visible ASCII.
+pub fn key_from_headers(headers: &HeaderMap) -> Result<Option<String>, AppError> {
+    let Some(value) = headers.get(IDEMPOTENCY_KEY) else {
+        return Ok(None);
+    };
+    match value.to_str().map(str::trim) {
+        Ok(key)
+            if !key.is_empty()
+                && key.len() <= MAX_KEY_LENGTH
+                && key.bytes().all(|b| b.is_ascii_graphic()) =>
+        {
+            Ok(Some(key.to_string()))
+        }
+        _ => Err(AppError::BadRequest(format!(
+            "Idempotency-Key must be 1-{} visible ASCII characters",
+            MAX_KEY_LENGTH
+        ))),
+    }
+}
+
+/// Hash of everything in the request that affects the created link. The
+/// password isn't serialized, so it's hashed separately.
+pub fn fingerprint(payload: &CreateUrlRequest) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(serde_json::to_vec(payload).unwrap_or_default());
+    hasher.update([0]);
+    hasher.update(payload.password.as_deref().unwrap_or("").as_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+/// Records the key as in progress, or reports what an earlier request with
+/// the same key did. Keys are scoped to the API key that sent them.
+pub async fn claim(pool: &DbPool, scope: &str, key: &str, fingerprint: &str) -> Result<Claim, AppError> {
+    let now = Utc::now();
+
+    // An expired key is free again even before the sweeper gets to it
+    let cutoff = cutoff();
+    sqlx::query!(
+        "DELETE FROM idempotency_keys WHERE scope = ? AND key = ? AND created_at < ?",
+        scope,
+        key,
+        cutoff
+    )
+    .execute(pool)
+    .traced("expire_idempotency_key", None)
+    .await?;
+
+    let inserted = sqlx::query!(
+        "INSERT INTO idempotency_keys (scope, key, fingerprint, created_at) VALUES (?, ?, ?, ?) ON CONFLICT DO NOTHING",
+        scope,
+        key,
+        fingerprint,
+        now
+    )
+    .execute(pool)
+    .traced("claim_idempotency_key", None)
+    .await?;
+    if inserted.rows_affected() == 1 {
+        return Ok(Claim::New);
+    }
+
+    let existing = sqlx::query!(
+        "SELECT fingerprint, status, response FROM idempotency_keys WHERE scope = ? AND key = ?",
+        scope,
+        key
+    )
+    .fetch_optional(pool)
+    .traced("find_idempotency_key", None)
+    .await?
+    .map(|row| (row.fingerprint, row.status, row.response));
+
+    match existing {
+        Some((stored, _, _)) if stored != fingerprint => Err(AppError::IdempotencyKeyReused),
+        Some((_, Some(status), Some(response))) => {
+            let status = u16::try_from(status)
+                .ok()
+                .and_then(|status| StatusCode::from_u16(status).ok())
+                .ok_or_else(|| AppError::InternalServerError("Stored idempotent status is invalid".into()))?;
+            let link = serde_json::from_str(&response)
+                .map_err(|e| AppError::InternalServerError(format!("Stored idempotent response is invalid: {}", e)))?;
+            Ok(Claim::Replay(status, Box::new(link)))
+        }
+        // The first request hasn't finished, or the row vanished in between
+        _ => Err(AppError::IdempotencyKeyInProgress),
+    }
+}
+
+/// Stores the response of a successful request under its key.
+pub async fn complete(
+    pool: &DbPool,
+    scope: &str,
+    key: &str,
+    status: StatusCode,
+    link: &UrlResponse,
+) -> Result<(), sqlx::Error> {
+    let response = serde_json::to_string(link).unwrap_or_default();
+    let status = status.as_u16();
+    sqlx::query!(
+        "UPDATE idempotency_keys SET status = ?, response = ? WHERE scope = ? AND key = ?",
+        status,
+        response,
+        scope,
+        key
+    )
+    .execute(pool)
+    .traced("store_idempotent_response", None)
+    .await?;
+    Ok(())
+}
+
+/// Forgets a key whose request failed, so the client can retry with it.
+pub async fn release(pool: &DbPool, scope: &str, key: &str) {
+    let result = sqlx::query!(
+        "DELETE FROM idempotency_keys WHERE scope = ? AND key = ? AND status IS NULL",
+        scope,
+        key
+    )
+    .execute(pool)
+    .traced("release_idempotency_key", None)
+    .await;
+    if let Err(e) = result {
+        tracing::error!("failed to release idempotency key: {:?}", e);
+    }
+}
+
+/// Spawns the task that deletes keys older than a day.
+pub fn spawn_sweeper(pool: DbPool) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+        loop {
+            interval.tick().await;
+            let cutoff = cutoff();
+            match sqlx::query!("DELETE FROM idempotency_keys WHERE created_at < ?", cutoff)
+                .execute(&pool)
+                .traced("sweep_idempotency_keys", None)
+                .await
+            {
+                Ok(done) if done.rows_affected() > 0 => {
+                    tracing::debug!(removed = done.rows_affected(), "swept idempotency keys")
+                }
+                Ok(_) => {}
+                Err(e) => tracing::error!("failed to sweep idempotency keys: {:?}", e),
+            }
+        }
+    });
+}
@@ -0,0 +1,453 @@
+//! SwiftLink URL shortener. The binary in `main.rs` only parses the command
+//! line, loads `Config` and serves the router built here; integration tests
+//! drive the same router with an in-memory database.
+
+use axum::{error_handling::HandleErrorLayer, extract::DefaultBodyLimit, middleware, routing::get, Router};
+use axum_extra::extract::cookie::Key;
+use sha2::{Digest, Sha512};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+    time::Duration,
+};
+use tower::{limit::GlobalConcurrencyLimitLayer, ServiceBuilder};
+use tower_http::{
+    compression::CompressionLayer,
+    limit::RequestBodyLimitLayer,
+    request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer},
+    services::ServeDir,
+    trace::TraceLayer,
+};
+use utoipa::{
+    openapi::security::{ApiKey, ApiKeyValue, SecurityScheme},
+    Modify, OpenApi,
+};
+use utoipa_axum::{
+    router::{OpenApiRouter, UtoipaMethodRouterExt},
+    routes,
+};
+use utoipa_swagger_ui::SwaggerUi;
+
+use crate::{config::Config, state::AppState};
+
+pub mod admin;
+pub mod analytics;
+pub mod audit;
+pub mod auth;
+pub mod backup;
+pub mod blocklist;
+pub mod cache;
+pub mod campaigns;
+pub mod cli;
+pub mod client_ip;
+pub mod config;
+pub mod cors;
+pub mod db;
+pub mod denylist;
+pub mod destination;
+pub mod error;
+pub mod export;
+pub mod geoip;
+pub mod handlers;
+pub mod health;
+pub mod idempotency;
+pub mod link_check;
+pub mod live;
+pub mod metadata;
+pub mod models;
+pub mod negotiate;
+pub mod otel;
+pub mod password;
+pub mod privacy;
+pub mod purge;
+pub mod rate_limit;
+pub mod request_id;
+pub mod reserved;
+pub mod retention;
+pub mod rotation;
+pub mod security_headers;
+pub mod seed;
+pub mod state;
+pub mod tags;
+pub mod telemetry;
+#[cfg(unix)]
+pub mod unix_socket;
+pub mod user_agent;
+pub mod utils;
+pub mod visits;
+pub mod webhooks;
+pub mod words;
+
+#[derive(OpenApi)]
+#[openapi(
+    // Paths are collected from the routes registered in `build_app`
+    components(
+        schemas(models::ErrorResponse, error::ErrorCode, models::CreateUrlRequest, models::UpdateUrlRequest, models::UnlockForm, models::UrlResponse, models::UrlInfoResponse, models::ValidationErrorResponse, models::BulkItemResult, models::StatsResponse, models::VisitStats, models::DailyCount, models::HeatmapCell, models::ReferrerCount, models::NamedCount, models::CountryCount, models::StatsSummary, models::StatsSummaryRequest, models::StatsSummaryResult, models::DestinationCount, models::WeightedDestination, models::Rotation, models::StatsVisibility, models::CodeStrategy, utils::CodeStyle, models::LinkHealth, models::DestinationCheck, models::AdminUrlItem, models::UrlListResponse, models::SummaryResponse, models::TopLink, models::ResetVisitsResponse, models::EraseRequest, models::EraseResponse, models::AuditEntry, models::AuditLogResponse, models::CampaignRequest, models::Campaign, models::CampaignListResponse, models::CampaignLink, models::CampaignStatsResponse, models::LinkExport, models::ImportConflict, models::ImportStatus, models::ImportLineResult, models::ImportResponse)
+    ),
+    modifiers(&SecurityAddon),
+    tags(
+        (name = "url-shortener", description = "URL Shortener API")
+    )
+)]
+struct ApiDoc;
+
+struct SecurityAddon;
+
+impl Modify for SecurityAddon {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        if let Some(components) = openapi.components.as_mut() {
+            components.add_security_scheme(
+                "api_key",
+                SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new("X-Api-Key"))),
+            );
+            components.add_security_scheme(
+                "stats_token",
+                SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new("X-Stats-Token"))),
+            );
+        }
+    }
+}
+
+const STATIC_DIR: &str = "static";
+
+/// Connects to the database, runs migrations and starts the background
+/// workers, returning the shared state plus the visit writer, which the
+/// caller should shut down last so queued visits are written.
+pub async fn build_state(config: Arc<Config>) -> Result<(AppState, visits::RecorderHandle), db::InitError> {
+    // Prometheus recorder backing /metrics
+    let metrics = telemetry::install_recorder();
+
+    let pool = db::init_db(&config).await?;
+
+    match &config.base_url {
+        Some(base) => tracing::info!("using base URL {}", base),
+        None => tracing::info!("BASE_URL not set, deriving short links from the Host header"),
+    }
+
+    // Optional redirect target for browsers hitting unknown codes
+    let fallback_url = config.fallback_redirect_url.as_deref().map(|url| {
+        tracing::info!("redirecting unknown codes to {}", url);
+        axum::http::HeaderValue::from_str(url).expect("parsed URLs are valid header values")
+    });
+
+    // Optional API keys guarding mutating endpoints
+    let api_keys = (!config.api_keys.is_empty()).then(|| {
+        Arc::new(
+            config
+                .api_keys
+                .iter()
+                .map(|entry| (entry.key.clone(), entry.namespace.clone()))
+                .collect::<HashMap<_, _>>(),
+        )
+    });
+    match &api_keys {
+        Some(keys) => tracing::info!("API key authentication enabled ({} keys)", keys.len()),
+        None => tracing::info!("API_KEYS not set, link creation is open to anyone"),
+    }
+
+    // Keys for /admin endpoints; admin routes reject everything when unset
+    let admin_keys: HashSet<String> = config.admin_api_keys.iter().cloned().collect();
+    if admin_keys.is_empty() {
+        tracing::info!("ADMIN_API_KEYS not set, admin endpoints are disabled");
+    }
+    let admin_keys = Arc::new(admin_keys);
+
+    // Optional per-IP rate limit on link creation, e.g. SHORTEN_RATE_LIMIT=10/60s
+    let shorten_limiter = config.shorten_rate_limit.as_ref().map(|limit| {
+        tracing::info!("rate limiting /shorten to {} per IP", limit.spec);
+        rate_limit::build_limiter(limit.quota)
+    });
+
+    // Per-IP limit on beacon hits, which unlike redirects cost the client nothing to replay
+    let hit_limiter = rate_limit::build_limiter(config.hit_rate_limit.quota);
+    tracing::info!("rate limiting /hit to {} per IP", config.hit_rate_limit.spec);
+
+    // Per-IP limit on password attempts, each of which costs an Argon2 verify
+    let unlock_limiter = rate_limit::build_limiter(config.unlock_rate_limit.quota);
+    tracing::info!("rate limiting /unlock to {} per IP", config.unlock_rate_limit.spec);
+
+    // Redirect cache, disabled with REDIRECT_CACHE_CAPACITY=0
+    let redirect_cache = (config.redirect_cache_capacity > 0).then(|| {
+        tracing::info!(
+            "redirect cache enabled (capacity {}, ttl {:?})",
+            config.redirect_cache_capacity,
+            config.redirect_cache_ttl
+        );
+        let cache = Arc::new(cache::RedirectCache::new(
+            config.redirect_cache_capacity,
+            config.redirect_cache_ttl,
+        ));
+
+        // Periodic hit/miss summary for load testing
+        let stats = cache.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(60));
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                tracing::info!(hits = stats.hits(), misses = stats.misses(), "redirect cache stats");
+            }
+        });
+
+        cache
+    });
+
+    // Words that custom codes may not use (routes, static files, RESERVED_CODES)
+    let reserved_codes = Arc::new(reserved::reserved_codes(
+        std::path::Path::new(STATIC_DIR),
+        config.reserved_codes.as_deref(),
+    ));
+
+    // User-agent fragments marking bots besides the bundled ones
+    let bot_patterns = Arc::new(
+        config
+            .bot_user_agents
+            .as_deref()
+            .unwrap_or_default()
+            .split(',')
+            .map(|pattern| pattern.trim().to_ascii_lowercase())
+            .filter(|pattern| !pattern.is_empty())
+            .collect(),
+    );
+
+    // Words generated codes must not spell: the bundled list plus CODE_DENYLIST_PATH
+    let code_filter = Arc::new(denylist::CodeFilter::new(config.code_denylist.as_deref()));
+    tracing::info!("filtering generated codes against {} denylisted words", code_filter.len());
+
+    // Private-network destinations allowed despite the SSRF/loop checks
+    let destinations = Arc::new(
+        config
+            .allowed_private_destinations
+            .as_deref()
+            .map(destination::DestinationPolicy::parse)
+            .unwrap_or_default(),
+    );
+
+    if let Some(days) = config.max_link_ttl_days {
+        tracing::info!("links expire after at most {} days", days);
+    }
+
+    // Background scraper for fetch_metadata, bound by the same destination rules
+    let metadata = metadata::spawn_fetcher(pool.clone(), destinations.clone());
+    // Visit webhooks are delivered off the request path, with retries
+    let webhooks = webhooks::spawn_dispatcher(pool.clone(), destinations.clone());
+
+    // Flagged domains, reloaded from BLOCKED_DESTINATIONS_PATH on SIGHUP
+    let blocklist = Arc::new(blocklist::Blocklist::new(
+        config.blocked_destinations.as_deref(),
+        config.blocked_destinations_path.clone(),
+    ));
+    if !blocklist.is_empty() {
+        tracing::info!("blocking destinations under {} domains", blocklist.len());
+    }
+    blocklist::spawn_reloader(blocklist.clone(), config.blocklist_reload_interval);
+
+    // One client for every `"verify": true` request
+    let link_verifier = Arc::new(link_check::LinkChecker::verifier(
+        destinations.clone(),
+        config.base_url.as_deref().unwrap_or_default(),
+    ));
+
+    // Only these peers may set X-Forwarded-For
+    let trusted_proxies = Arc::new(config.trusted_proxies.clone());
+
+    // How much visitor detail is stored; DNT: 1 always means count-only
+    tracing::info!("analytics mode: {:?}", config.analytics_mode);
+
+    // Visitor countries; without a readable database they're left null
+    let geoip = config.geoip_db_path.as_deref().and_then(|path| match geoip::GeoIp::open(path) {
+        Ok(geoip) => {
+            tracing::info!("resolving visitor countries with {}", path.display());
+            Some(Arc::new(geoip))
+        }
+        Err(e) => {
+            tracing::error!("failed to open GEOIP_DB_PATH, countries won't be recorded: {}", e);
+            None
+        }
+    });
+
+    // Background writer batching visit inserts
+    let (visit_recorder, visit_worker) = visits::spawn_recorder(
+        pool.clone(),
+        visits::RecorderConfig {
+            queue_capacity: config.visit_queue_capacity,
+            batch_size: config.visit_batch_size,
+            flush_interval: config.visit_flush_interval,
+            geoip,
+        },
+    );
+
+    // Periodic deletion of long-expired links, disabled with PURGE_EXPIRED=false
+    if config.purge_expired {
+        let purge_config = purge::PurgeConfig {
+            interval: config.cleanup_interval,
+            grace: config.purge_grace,
+        };
+        tracing::info!(
+            "purging links expired for more than {:?} every {:?}",
+            purge_config.grace,
+            purge_config.interval
+        );
+        purge::spawn_purger(pool.clone(), redirect_cache.clone(), purge_config);
+    } else {
+        tracing::info!("PURGE_EXPIRED=false, expired links are kept");
+    }
+
+    // Scrubbing of IPs and user agents from visits older than VISIT_RETENTION_DAYS
+    if let Some(days) = config.visit_retention_days {
+        let retention_config = retention::RetentionConfig {
+            interval: config.cleanup_interval,
+            retention: Duration::from_secs(u64::from(days) * 24 * 3600),
+        };
+        tracing::info!(
+            "scrubbing visitor details older than {} days every {:?}",
+            days,
+            retention_config.interval
+        );
+        retention::spawn_scrubber(pool.clone(), retention_config);
+    }
+
+    // Dead-link detection, opt-in with LINK_CHECK_INTERVAL_SECS
+    if let Some(interval) = config.link_check_interval {
+        tracing::info!("checking link destinations every {:?}", interval);
+        let checker = link_check::LinkChecker::new(
+            destinations.clone(),
+            config.base_url.as_deref().unwrap_or_default(),
+            link_check::RETRY_BACKOFF,
+        );
+        link_check::spawn_checker(pool.clone(), checker, interval);
+    }
+
+    // Hourly removal of Idempotency-Key records older than a day
+    if config.sweep_idempotency_keys {
+        idempotency::spawn_sweeper(pool.clone());
+    }
+
+    // Salt for visitor hashes; a random one makes unique counts restart-local
+    let visitor_salt: Arc<str> = match &config.visitor_hash_salt {
+        Some(salt) => salt.as_str().into(),
+        None => {
+            tracing::warn!("VISITOR_HASH_SALT not set, unique visitors will be recounted after a restart");
+            utils::generate_short_code(32, utils::CodeAlphabet::Base62).into()
+        }
+    };
+
+    // Key signing unlock cookies; a random one logs everyone out on restart
+    let cookie_key = match &config.cookie_secret {
+        // Key wants 64 bytes of key material; stretch the secret to fit
+        Some(secret) => Key::from(&Sha512::digest(secret.as_bytes())),
+        None => Key::generate(),
+    };
+
+    let state = AppState {
+        pool: pool.clone(),
+        config: config.clone(),
+        api_keys,
+        admin_keys,
+        shorten_limiter,
+        hit_limiter,
+        unlock_limiter,
+        fallback_url,
+        redirect_cache,
+        reserved_codes,
+        code_filter,
+        bot_patterns,
+        destinations,
+        blocklist,
+        link_verifier,
+        trusted_proxies,
+        metadata,
+        webhooks,
+        visits: visit_recorder,
+        visitor_salt,
+        metrics,
+        cookie_key,
+    };
+
+    Ok((state, visit_worker))
+}
+
+/// The full HTTP surface: API routes, Swagger UI, static files and probes.
+pub fn build_app(state: AppState) -> Router {
+    let static_files = ServeDir::new(STATIC_DIR);
+    let limit_shorten = middleware::from_fn_with_state(state.clone(), rate_limit::limit_shorten);
+    let limit_hits = middleware::from_fn_with_state(state.clone(), rate_limit::limit_hits);
+    let limit_unlocks = middleware::from_fn_with_state(state.clone(), rate_limit::limit_unlocks);
+    let max_body = state.config.max_request_body;
+    let compress = state.config.compress_responses;
+    let cors = cors::layer(&state.config.cors);
+    let security_headers = Arc::new(state.config.security_headers.clone());
+    let request_timeout = state.config.request_timeout;
+    let max_concurrent = state.config.max_concurrent_requests;
+
+    // `routes!` registers each handler together with its `#[utoipa::path]`
+    // operation, so an endpoint can't be served without being documented
+    let (api, mut openapi) = OpenApiRouter::with_openapi(ApiDoc::openapi())
+        .routes(routes!(handlers::shorten_url).layer(limit_shorten.clone()))
+        .routes(routes!(handlers::shorten_bulk).layer(limit_shorten))
+        .routes(routes!(handlers::redirect_url, handlers::delete_url))
+        .routes(routes!(handlers::unlock_url).layer(limit_unlocks))
+        .routes(routes!(handlers::continue_url))
+        .routes(routes!(handlers::record_hit).layer(limit_hits))
+        .routes(routes!(handlers::get_info))
+        .routes(routes!(handlers::update_url))
+        .routes(routes!(handlers::get_stats))
+        .routes(routes!(handlers::get_stats_summary))
+        .routes(routes!(handlers::bulk_stats_summary))
+        .routes(routes!(export::export_visits))
+        .routes(routes!(live::live_visits))
+        .routes(routes!(handlers::generate_qr))
+        .routes(routes!(admin::list_urls))
+        .routes(routes!(admin::summary))
+        .routes(routes!(admin::reset_visits))
+        .routes(routes!(privacy::erase))
+        .routes(routes!(admin::audit_log))
+        .routes(routes!(campaigns::list_campaigns, campaigns::create_campaign))
+        .routes(routes!(campaigns::get_campaign, campaigns::rename_campaign, campaigns::delete_campaign))
+        .routes(routes!(campaigns::campaign_stats))
+        .routes(routes!(backup::export_links))
+        // Bodies over the limit are refused from `Content-Length` where there
+        // is one, and cut off while streaming otherwise
+        .layer(RequestBodyLimitLayer::new(max_body))
+        .layer(DefaultBodyLimit::max(max_body))
+        // Added after the limit so dumps can be far larger
+        .routes(routes!(backup::import_links).layer(DefaultBodyLimit::max(backup::MAX_IMPORT_BYTES)))
+        .split_for_parts();
+    // Probes and scrapes are registered after the layers so they stay out
+    // of the trace logs and latency histograms, and still answer when the
+    // concurrency limit is reached
+    let (probes, probe_docs) = OpenApiRouter::new()
+        .routes(routes!(health::healthz))
+        .routes(routes!(health::readyz))
+        .split_for_parts();
+    openapi.merge(probe_docs);
+
+    api.route("/robots.txt", get(handlers::robots_txt))
+        .merge(SwaggerUi::new("/swagger-ui").url("/api-docs/openapi.json", openapi))
+        .fallback_service(static_files)
+        .layer(
+            ServiceBuilder::new()
+                .layer(SetRequestIdLayer::new(request_id::X_REQUEST_ID, MakeRequestUuid))
+                .layer(
+                    TraceLayer::new_for_http()
+                        .make_span_with(request_id::make_span)
+                        .on_response(telemetry::log_response),
+                )
+                .layer(PropagateRequestIdLayer::new(request_id::X_REQUEST_ID))
+                .layer(middleware::from_fn(request_id::scope))
+                .layer(cors)
+                .layer(CompressionLayer::new().gzip(compress).br(compress))
+                .layer(middleware::from_fn(telemetry::track_latency))
+                .layer(middleware::map_response(error::json_payload_too_large))
+                .layer(HandleErrorLayer::new(error::middleware_error))
+                // Shed instead of queueing once every slot is taken. The
+                // limit is global: `Router::layer` wraps each route separately
+                .load_shed()
+                .layer(GlobalConcurrencyLimitLayer::new(max_concurrent))
+                .timeout(request_timeout),
+        )
+        .merge(probes)
+        .route("/metrics", get(telemetry::render_metrics))
+        .layer(middleware::from_fn_with_state(security_headers, security_headers::apply))
+        .with_state(state)
+}
@@ -0,0 +1,407 @@
+//! Background dead-link detection, enabled by `LINK_CHECK_INTERVAL_SECS`.
+//! Active links are walked in batches and every destination (the URL, app
+//! store URLs and rotation destinations) probed with `HEAD`, or a one-byte
+//! ranged `GET` for servers that refuse `HEAD`. Each outcome is kept in
+//! `destination_checks`, and the link's overall one as `health`,
+//! `last_status` and `last_checked_at`. The same probe, with tighter limits, backs the
+//! `"verify": true` check on new links.
+
+use crate::{
+    db::{DbPool, Traced},
+    destination::{self, DestinationPolicy},
+    models::{DestinationCheck, LinkHealth, WeightedDestination},
+    rotation,
+};
+use chrono::{DateTime, Utc};
+use futures_util::{stream, StreamExt};
+use reqwest::{header, Client, Method, Response, StatusCode, Url};
+use sqlx::{QueryBuilder, Sqlite};
+use std::{collections::HashMap, sync::Arc, time::Duration};
+use tokio::{sync::Semaphore, time::MissedTickBehavior};
+
+/// Links loaded and checked at a time.
+const BATCH_SIZE: i64 = 100;
+const MAX_CONCURRENT_CHECKS: usize = 16;
+/// Checks in flight against any one host, so a domain with many links
+/// doesn't get a burst of requests.
+const MAX_CHECKS_PER_HOST: usize = 2;
+const CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_REDIRECTS: usize = 5;
+/// Limits for verifying a destination while its link is being created,
+/// which happens on the request path.
+const VERIFY_TIMEOUT: Duration = Duration::from_secs(2);
+const VERIFY_MAX_REDIRECTS: usize = 3;
+/// Waits before each retry of a failing destination. A link is only marked
+/// broken once the last retry fails too.
+pub const RETRY_BACKOFF: &[Duration] = &[Duration::from_secs(10), Duration::from_secs(60)];
+const USER_AGENT: &str = concat!("short-url-service/", env!("CARGO_PKG_VERSION"), " (link check)");
+
+// What one attempt at a destination found.
+enum Probe {
+    /// Final status after redirects
+    Answered(StatusCode),
+    /// The name didn't resolve or the connection was refused
+    Unreachable,
+    /// Timed out, stopped by the destination policy or too many redirects
+    Failed,
+}
+
+impl Probe {
+    fn status(&self) -> Option<u16> {
+        match self {
+            Probe::Answered(status) => Some(status.as_u16()),
+            _ => None,
+        }
+    }
+
+    fn healthy(&self) -> bool {
+        matches!(self, Probe::Answered(status) if status.is_success())
+    }
+}
+
+/// Why a destination failed verification.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeadDestination {
+    /// It answered `404` or `410`, after redirects
+    Status(u16),
+    /// The name didn't resolve or the connection was refused
+    Unreachable,
+}
+
+/// Counts from one pass over the links.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct CheckSummary {
+    pub checked: u64,
+    pub broken: u64,
+}
+
+pub struct LinkChecker {
+    client: Client,
+    destinations: Arc<DestinationPolicy>,
+    service_base: String,
+    retry_backoff: Vec<Duration>,
+    max_redirects: usize,
+}
+
+impl LinkChecker {
+    /// Every hop of a check passes the same destination policy as new links,
+    /// and connects only to addresses it allows, so the checker can't be
+    /// pointed at private networks.
+    pub fn new(destinations: Arc<DestinationPolicy>, service_base: &str, retry_backoff: &[Duration]) -> Self {
+        Self::build(destinations, service_base, retry_backoff, CHECK_TIMEOUT, MAX_REDIRECTS)
+    }
+
+    /// A checker for `verify`: a 2 second timeout, at most 3 redirects and
+    /// no retries. Built once and shared, so requests reuse its connections.
+    pub fn verifier(destinations: Arc<DestinationPolicy>, service_base: &str) -> Self {
+        Self::build(destinations, service_base, &[], VERIFY_TIMEOUT, VERIFY_MAX_REDIRECTS)
+    }
+
+    fn build(
+        destinations: Arc<DestinationPolicy>,
+        service_base: &str,
+        retry_backoff: &[Duration],
+        timeout: Duration,
+        max_redirects: usize,
+    ) -> Self {
+        // Redirects are followed by hand so every hop passes the destination policy
+        let client = destination::client_builder(destinations.clone())
+            .user_agent(USER_AGENT)
+            .timeout(timeout)
+            .build()
+            .expect("HTTP client configuration is valid");
+        LinkChecker {
+            client,
+            destinations,
+            service_base: service_base.to_string(),
+            retry_backoff: retry_backoff.to_vec(),
+            max_redirects,
+        }
+    }
+
+    /// Probes `url` once and reports it only when it's clearly dead. Slow or
+    /// failing (`5xx`) destinations pass, since that may be temporary; so
+    /// does a URL the destination policy stops, which creation reports itself.
+    pub async fn verify(&self, url: &str, service_base: &str) -> Result<(), DeadDestination> {
+        match self.probe(url, service_base).await {
+            Probe::Answered(status) if matches!(status, StatusCode::NOT_FOUND | StatusCode::GONE) => {
+                Err(DeadDestination::Status(status.as_u16()))
+            }
+            Probe::Unreachable => Err(DeadDestination::Unreachable),
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks every destination of each active, unexpired link once and
+    /// stores the results, per destination and for the link as a whole.
+    pub async fn check_all(&self, pool: &DbPool) -> Result<CheckSummary, sqlx::Error> {
+        let mut summary = CheckSummary::default();
+        // Shared by every batch of the pass, so the per-host limit holds
+        // across batch boundaries
+        let mut hosts: HashMap<String, Arc<Semaphore>> = HashMap::new();
+        let mut after = String::new();
+
+        loop {
+            let now = Utc::now();
+            let batch = sqlx::query!(
+                r#"SELECT id AS "id!", original_url, ios_url, android_url, rotation FROM urls WHERE is_active = 1 AND (expires_at IS NULL OR expires_at > ?) AND id > ? ORDER BY id LIMIT ?"#,
+                now,
+                after,
+                BATCH_SIZE
+            )
+            .fetch_all(pool)
+            .traced("find_links_to_check", None)
+            .await?;
+            let Some(last) = batch.last() else {
+                break;
+            };
+            after = last.id.clone();
+            let more = batch.len() as i64 == BATCH_SIZE;
+
+            let mut links = Vec::with_capacity(batch.len());
+            for row in batch {
+                let rotating = match row.rotation {
+                    Some(_) => rotation::load_destinations(pool, &row.id).await?,
+                    None => Vec::new(),
+                };
+                let destinations =
+                    destinations_of(&row.original_url, row.ios_url.as_deref(), row.android_url.as_deref(), &rotating);
+                links.push(Candidate {
+                    id: row.id,
+                    original_url: row.original_url,
+                    ios_url: row.ios_url,
+                    android_url: row.android_url,
+                    destinations,
+                });
+            }
+            let jobs: Vec<_> = links
+                .iter()
+                .enumerate()
+                .flat_map(|(index, link)| link.destinations.iter().enumerate().map(move |(position, url)| (index, position, url)))
+                .map(|(link, position, url)| {
+                    let host = Url::parse(url)
+                        .ok()
+                        .and_then(|url| url.host_str().map(str::to_ascii_lowercase))
+                        .unwrap_or_default();
+                    let permits = hosts
+                        .entry(host)
+                        .or_insert_with(|| Arc::new(Semaphore::new(MAX_CHECKS_PER_HOST)))
+                        .clone();
+                    (link, position, url.clone(), permits)
+                })
+                .collect();
+            let mut results: Vec<_> = stream::iter(jobs)
+                .map(|(link, position, url, permits)| async move {
+                    let probe = self.check_with_retries(&url, &permits).await;
+                    (link, position, probe)
+                })
+                .buffer_unordered(MAX_CONCURRENT_CHECKS)
+                .collect()
+                .await;
+            results.sort_by_key(|(link, position, _)| (*link, *position));
+            let mut results = results.into_iter().map(|(_, _, probe)| probe);
+
+            for link in &links {
+                let probes: Vec<Probe> = results.by_ref().take(link.destinations.len()).collect();
+                store_results(pool, link, &probes).await?;
+                if !probes.iter().all(Probe::healthy) {
+                    summary.broken += 1;
+                }
+                summary.checked += 1;
+            }
+
+            if !more {
+                break;
+            }
+        }
+
+        Ok(summary)
+    }
+
+    async fn check_with_retries(&self, url: &str, permits: &Semaphore) -> Probe {
+        let mut delays = self.retry_backoff.iter();
+        loop {
+            // Held per attempt, not while waiting to retry
+            let probe = {
+                let _permit = permits.acquire().await.expect("host semaphores are never closed");
+                self.probe(url, &self.service_base).await
+            };
+            match delays.next() {
+                Some(delay) if !probe.healthy() => tokio::time::sleep(*delay).await,
+                _ => return probe,
+            }
+        }
+    }
+
+    async fn probe(&self, destination: &str, service_base: &str) -> Probe {
+        let mut url = destination.to_string();
+        for _ in 0..=self.max_redirects {
+            if self.destinations.check_request(&url, service_base).is_err() {
+                return Probe::Failed;
+            }
+            let response = match self.request(Method::HEAD, &url).await {
+                Ok(response) if matches!(response.status(), StatusCode::METHOD_NOT_ALLOWED | StatusCode::NOT_IMPLEMENTED) => {
+                    self.request(Method::GET, &url).await
+                }
+                other => other,
+            };
+            let response = match response {
+                Ok(response) => response,
+                Err(e) if destination::is_refused(&e) => return Probe::Failed,
+                // DNS failures surface as connect errors too
+                Err(e) if e.is_connect() && !e.is_timeout() => return Probe::Unreachable,
+                Err(_) => return Probe::Failed,
+            };
+
+            let status = response.status();
+            if status.is_redirection()
+                && let Some(next) = location(&url, &response)
+            {
+                url = next;
+                continue;
+            }
+            return Probe::Answered(status);
+        }
+        // Too many redirects
+        Probe::Failed
+    }
+
+    async fn request(&self, method: Method, url: &str) -> reqwest::Result<Response> {
+        let mut request = self.client.request(method.clone(), url);
+        if method == Method::GET {
+            // Only the status matters; ask for as little of the body as possible
+            request = request.header(header::RANGE, "bytes=0-0");
+        }
+        request.send().await
+    }
+}
+
+// One link of a batch and the destinations being checked for it.
+struct Candidate {
+    id: String,
+    original_url: String,
+    ios_url: Option<String>,
+    android_url: Option<String>,
+    destinations: Vec<String>,
+}
+
+async fn store_results(pool: &DbPool, link: &Candidate, probes: &[Probe]) -> Result<(), sqlx::Error> {
+    let id = link.id.as_str();
+    let checked_at = Utc::now();
+    for (position, probe) in probes.iter().enumerate().filter(|(_, probe)| !probe.healthy()) {
+        tracing::info!(code = id, destination = position, status = probe.status(), "link destination is broken");
+    }
+    let health = match probes.iter().all(Probe::healthy) {
+        true => LinkHealth::Healthy,
+        false => LinkHealth::Broken,
+    };
+    // The first broken destination's status, or else the main one's
+    let reported = probes.iter().find(|probe| !probe.healthy()).or(probes.first());
+    let (health, status) = (health.as_str(), reported.and_then(Probe::status).map(i64::from));
+
+    let mut tx = pool.begin().await?;
+    // Destinations changed mid-check keep their fresh `unknown`
+    let updated = sqlx::query!(
+        "UPDATE urls SET health = ?, last_status = ?, last_checked_at = ? WHERE id = ? AND original_url = ? AND ios_url IS ? AND android_url IS ?",
+        health,
+        status,
+        checked_at,
+        id,
+        link.original_url,
+        link.ios_url,
+        link.android_url
+    )
+    .execute(&mut *tx)
+    .traced("store_link_health", Some(id))
+    .await?
+    .rows_affected();
+    if updated > 0 {
+        sqlx::query!("DELETE FROM destination_checks WHERE url_id = ?", id)
+            .execute(&mut *tx)
+            .traced("clear_destination_checks", Some(id))
+            .await?;
+        let mut query: QueryBuilder<Sqlite> =
+            QueryBuilder::new("INSERT INTO destination_checks (url_id, url, health, last_status, checked_at) ");
+        query.push_values(link.destinations.iter().zip(probes), |mut row, (url, probe)| {
+            let health = if probe.healthy() { LinkHealth::Healthy } else { LinkHealth::Broken };
+            row.push_bind(id)
+                .push_bind(url)
+                .push_bind(health.as_str())
+                .push_bind(probe.status().map(i64::from))
+                .push_bind(checked_at);
+        });
+        query.build().execute(&mut *tx).traced("store_destination_checks", Some(id)).await?;
+    }
+    tx.commit().await
+}
+
+/// Every destination a link can send visitors to, main URL first and each
+/// only once.
+pub fn destinations_of(
+    original_url: &str,
+    ios_url: Option<&str>,
+    android_url: Option<&str>,
+    rotating: &[WeightedDestination],
+) -> Vec<String> {
+    let mut destinations = vec![original_url.to_string()];
+    let others = [ios_url, android_url]
+        .into_iter()
+        .flatten()
+        .chain(rotating.iter().map(|destination| destination.url.as_str()));
+    for url in others {
+        if !destinations.iter().any(|known| known == url) {
+            destinations.push(url.to_string());
+        }
+    }
+    destinations
+}
+
+/// The last check of each of `destinations` that has been checked, in the
+/// same order. Results for destinations the link no longer has are skipped.
+pub async fn load_checks(
+    pool: &DbPool,
+    url_id: &str,
+    destinations: &[String],
+) -> Result<Vec<DestinationCheck>, sqlx::Error> {
+    let rows = sqlx::query!(
+        r#"SELECT url, health, last_status, checked_at AS "checked_at: DateTime<Utc>" FROM destination_checks WHERE url_id = ?"#,
+        url_id
+    )
+    .fetch_all(pool)
+    .traced("load_destination_checks", Some(url_id))
+    .await?;
+    let mut checks: HashMap<String, DestinationCheck> = rows
+        .into_iter()
+        .map(|row| {
+            let check = DestinationCheck {
+                url: row.url.clone(),
+                health: LinkHealth::parse(&row.health).unwrap_or_default(),
+                last_status: row.last_status,
+                checked_at: row.checked_at,
+            };
+            (row.url, check)
+        })
+        .collect();
+    Ok(destinations.iter().filter_map(|url| checks.remove(url)).collect())
+}
+
+fn location(base: &str, response: &Response) -> Option<String> {
+    let location = response.headers().get(header::LOCATION)?.to_str().ok()?;
+    Some(Url::parse(base).ok()?.join(location).ok()?.to_string())
+}
+
+/// Spawns the task that checks every link each `interval`.
+pub fn spawn_checker(pool: DbPool, checker: LinkChecker, interval: Duration) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(interval);
+        // A pass slower than the interval starts the next one late, not twice
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            match checker.check_all(&pool).await {
+                Ok(summary) => tracing::info!(checked = summary.checked, broken = summary.broken, "checked link destinations"),
+                Err(e) => tracing::error!("failed to check link destinations: {:?}", e),
+            }
+        }
+    });
+}
@@ -0,0 +1,87 @@
+use crate::{
+    auth::verify_stats_access,
+    db,
+    error::AppError,
+    handlers::hides_destinations,
+    models::{ErrorResponse, StatsTokenParams},
+    state::AppState,
+};
+use axum::{
+    extract::{rejection::QueryRejection, Path, Query, State},
+    http::HeaderMap,
+    response::sse::{Event, KeepAlive, Sse},
+};
+use chrono::Utc;
+use futures_util::{stream, Stream};
+use std::{convert::Infallible, time::Duration};
+use tokio::sync::broadcast::error::RecvError;
+
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+#[utoipa::path(
+    get,
+    path = "/stats/{code}/live",
+    params(
+        ("code" = String, Path, description = "Short code"),
+        StatsTokenParams
+    ),
+    responses(
+        (status = 200, description = "Server-Sent Events stream with one `visit` event (a `VisitStats` JSON object) per visit as it's recorded. A `lagged` event with the number of missed visits is sent when the client falls behind.", content_type = "text/event-stream"),
+        (status = 403, description = "Private link and missing or wrong stats token", body = ErrorResponse),
+        (status = 404, description = "URL not found", body = ErrorResponse),
+        (status = 410, description = "URL expired", body = ErrorResponse)
+    ),
+    security(
+        (),
+        ("stats_token" = [])
+    )
+)]
+#[tracing::instrument(skip_all, fields(code = %code))]
+pub async fn live_visits(
+    State(state): State<AppState>,
+    Path(code): Path<String>,
+    headers: HeaderMap,
+    params: Result<Query<StatsTokenParams>, QueryRejection>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, AppError> {
+    let Query(params) = params.map_err(|rejection| AppError::BadRequest(rejection.body_text()))?;
+    let url_record = db::find_url(&state.pool, &code).await?;
+    let url = url_record.ok_or(AppError::UrlNotFound)?;
+    verify_stats_access(&state, &url, &headers, params.stats_token.as_deref())?;
+    if let Some(expires_at) = url.expires_at
+        && Utc::now() >= expires_at
+    {
+        return Err(AppError::UrlExpired(expires_at));
+    }
+    let hidden = hides_destinations(&state, &url, &headers, None);
+
+    // The receiver is dropped with the stream when the client disconnects,
+    // which is all the cleanup a subscription needs
+    let rx = state.visits.subscribe();
+    let events = stream::unfold((rx, code), move |(mut rx, code)| async move {
+        loop {
+            let event = match rx.recv().await {
+                Ok(mut live) if live.url_id == code => {
+                    if hidden {
+                        live.visit.destination = None;
+                    }
+                    match Event::default().event("visit").json_data(&live.visit) {
+                        Ok(event) => event,
+                        Err(e) => {
+                            tracing::error!(code, "failed to encode live visit: {:?}", e);
+                            continue;
+                        }
+                    }
+                }
+                Ok(_) => continue,
+                Err(RecvError::Lagged(missed)) => {
+                    Event::default().event("lagged").data(missed.to_string())
+                }
+                // Recorder shut down
+                Err(RecvError::Closed) => return None,
+            };
+            return Some((Ok(event), (rx, code)));
+        }
+    });
+
+    Ok(Sse::new(events).keep_alive(KeepAlive::new().interval(KEEP_ALIVE_INTERVAL)))
+}
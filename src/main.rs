@@ -1,78 +1,283 @@
-use axum::{
-    routing::{get, post},
-    Router,
+use clap::{Parser, Subcommand, ValueEnum};
+#[cfg(unix)]
+use short_url_service::unix_socket;
+use short_url_service::{
+    build_app, build_state,
+    cli::{self, Expiry},
+    config, otel, seed,
+    state::AppState,
+    telemetry::LogFormat,
 };
-use std::net::SocketAddr;
-use tower::ServiceBuilder;
-use tower_http::{
-    cors::CorsLayer,
-    services::ServeDir,
-    trace::TraceLayer,
-};
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
-use utoipa::OpenApi;
-use utoipa_swagger_ui::SwaggerUi;
-
-mod db;
-mod error;
-mod handlers;
-mod models;
-mod utils;
-
-#[derive(OpenApi)]
-#[openapi(
-    paths(
-        handlers::shorten_url,
-        handlers::redirect_url,
-        handlers::get_stats,
-        handlers::generate_qr
-    ),
-    components(
-        schemas(models::CreateUrlRequest, models::UrlResponse, models::StatsResponse, models::VisitStats)
-    ),
-    tags(
-        (name = "url-shortener", description = "URL Shortener API")
-    )
-)]
-struct ApiDoc;
+use std::{future::IntoFuture, net::SocketAddr, pin::Pin, sync::Arc};
+use tracing_subscriber::{fmt::writer::BoxMakeWriter, layer::SubscriberExt, util::SubscriberInitExt};
+
+/// URL shortener service. Configuration is read from the environment (and `.env`).
+#[derive(Parser)]
+#[command(version)]
+struct Cli {
+    /// Port to listen on, overriding PORT and the port of LISTEN_ADDR
+    #[arg(long, global = true)]
+    port: Option<u16>,
+    /// Run on a throwaway in-memory database seeded with sample links (same as DEV_MODE=true)
+    #[arg(long, global = true)]
+    dev: bool,
+    /// Print command results (and errors) as JSON, for scripts
+    #[arg(long, global = true)]
+    json: bool,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+/// Commands other than `serve` work on the database directly and exit with
+/// 0 on success, 3 when the link doesn't exist and 1 on any other error.
+#[derive(Subcommand)]
+enum Command {
+    /// Run the HTTP server (the default)
+    Serve,
+    /// Create a short link
+    Shorten {
+        url: String,
+        /// Custom short code
+        #[arg(long)]
+        code: Option<String>,
+        /// Expiry as an RFC 3339 time or a duration such as 30m, 12h or 7d
+        #[arg(long, value_parser = cli::parse_expiry)]
+        expires: Option<Expiry>,
+    },
+    /// Show the visit summary of a link, expired or not
+    Stats { code: String },
+    /// Delete links expired more than PURGE_GRACE_SECS ago, once
+    PurgeExpired,
+    /// Write every link to stdout, in the /admin/export format
+    Export {
+        #[arg(long, value_enum, default_value_t = ExportFormat::Jsonl)]
+        format: ExportFormat,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ExportFormat {
+    /// One JSON object per line
+    Jsonl,
+}
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let cli = Cli::parse();
     dotenvy::dotenv().ok();
+    let command = cli.command.unwrap_or(Command::Serve);
+    let serving = matches!(command, Command::Serve);
 
-    // Initialize logging
+    // Initialize logging. An invalid LOG_FORMAT falls back to pretty here
+    // and is reported by the config check below. Commands only log errors, to
+    // stderr, so their output can be piped.
+    let log_format = std::env::var("LOG_FORMAT")
+        .ok()
+        .and_then(|raw| LogFormat::parse(&raw))
+        .unwrap_or_default();
+    let json = log_format == LogFormat::Json;
+    let writer = || match serving {
+        true => BoxMakeWriter::new(std::io::stdout),
+        false => BoxMakeWriter::new(std::io::stderr),
+    };
+    let default_filter = if serving { "info" } else { "error" };
+    let tracer_provider = otel::provider_from_env()?;
     tracing_subscriber::registry()
         .with(tracing_subscriber::EnvFilter::new(
-            std::env::var("RUST_LOG").unwrap_or_else(|_| "info".into()),
+            std::env::var("RUST_LOG").unwrap_or_else(|_| default_filter.into()),
         ))
-        .with(tracing_subscriber::fmt::layer())
+        .with((!json).then(|| tracing_subscriber::fmt::layer().with_writer(writer())))
+        .with(json.then(|| tracing_subscriber::fmt::layer().json().with_writer(writer())))
+        .with(tracer_provider.as_ref().map(otel::layer))
         .init();
+    if tracer_provider.is_some() {
+        tracing::info!("exporting traces over OTLP");
+    }
+
+    // Validated before anything else starts so bad values fail fast, all at once
+    let mut config = match config::Config::from_lookup(|name| match name {
+        "DEV_MODE" if cli.dev => Some("true".into()),
+        _ => std::env::var(name).ok(),
+    }) {
+        Ok(config) => config,
+        Err(e) => {
+            tracing::error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    if let Some(port) = cli.port {
+        config.listen_addr.set_port(port);
+    }
+    if !serving {
+        // Background jobs are the server's; a command only does what it says
+        config.purge_expired = false;
+        config.visit_retention_days = None;
+        config.link_check_interval = None;
+        config.sweep_idempotency_keys = false;
+    }
+    let config = Arc::new(config);
+
+    let (state, visit_worker) = match build_state(config.clone()).await {
+        Ok(built) => built,
+        Err(e) => {
+            tracing::error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    if config.dev_mode {
+        let visits = match seed::run(&state).await {
+            Ok(visits) => visits,
+            Err(e) => {
+                tracing::error!("failed to seed the dev database: {}", e);
+                std::process::exit(1);
+            }
+        };
+        let base = config.base_url.clone().unwrap_or_else(|| format!("http://{}", config.listen_addr));
+        tracing::warn!(
+            "dev mode: in-memory database with {} sample links and {} visits, discarded on exit",
+            seed::LINKS.len(),
+            visits
+        );
+        for (code, url, _) in seed::LINKS {
+            tracing::info!("  {}/{} -> {}", base, code, url);
+        }
+    }
+    let pool = state.pool.clone();
+    if !serving {
+        let status = run_command(command, &state, cli.json).await;
+        visit_worker.shutdown().await;
+        pool.close().await;
+        std::process::exit(i32::from(status));
+    }
+    let app = build_app(state);
+
+    // Stop accepting on SIGINT/SIGTERM and give in-flight requests a grace period
+    let shutdown_timeout = config.shutdown_timeout;
+    let (shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(false);
+    tokio::spawn(async move {
+        shutdown_signal().await;
+        tracing::info!("shutdown signal received, draining in-flight requests");
+        let _ = shutdown_tx.send(true);
+    });
 
-    // Initialize DB
-    let pool = db::init_db().await?;
-
-    // Static files
-    let static_files = ServeDir::new("static");
-
-    // Router
-    let app = Router::new()
-        .merge(SwaggerUi::new("/swagger-ui").url("/api-docs/openapi.json", ApiDoc::openapi()))
-        .route("/shorten", post(handlers::shorten_url))
-        .route("/{code}", get(handlers::redirect_url))
-        .route("/stats/{code}", get(handlers::get_stats))
-        .route("/qr/{code}", get(handlers::generate_qr))
-        .fallback_service(static_files)
-        .layer(
-            ServiceBuilder::new()
-                .layer(TraceLayer::new_for_http())
-                .layer(CorsLayer::permissive())
-        )
-        .with_state(pool);
-
-    let addr = SocketAddr::from(([127, 0, 0, 1], 3000));
-    tracing::info!("listening on {}", addr);
-    let listener = tokio::net::TcpListener::bind(addr).await?;
-    axum::serve(listener, app.into_make_service()).await?;
+    let mut server_shutdown = shutdown_rx.clone();
+    let stopped = async move {
+        let _ = server_shutdown.wait_for(|stop| *stop).await;
+    };
+    // Held until the server is done, then removes the socket file
+    let mut _socket_file = None;
+    let server: Pin<Box<dyn Future<Output = std::io::Result<()>> + Send>> = match &config.listen_socket {
+        #[cfg(unix)]
+        Some(path) => {
+            let (listener, file) = unix_socket::bind(path, config.listen_socket_mode)
+                .await
+                .map_err(|e| format!("failed to bind {}: {}", path.display(), e))?;
+            tracing::info!("listening on {}", path.display());
+            _socket_file = Some(file);
+            // No peer address here, so client IPs come from X-Forwarded-For
+            Box::pin(axum::serve(listener, app.into_make_service()).with_graceful_shutdown(stopped).into_future())
+        }
+        #[cfg(not(unix))]
+        Some(_) => return Err("LISTEN_SOCKET needs a Unix platform".into()),
+        None => {
+            let addr = config.listen_addr;
+            let listener = tokio::net::TcpListener::bind(addr)
+                .await
+                .map_err(|e| format!("failed to bind {}: {}", addr, e))?;
+            tracing::info!("listening on {}", listener.local_addr()?);
+            Box::pin(
+                axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
+                    .with_graceful_shutdown(stopped)
+                    .into_future(),
+            )
+        }
+    };
+    let mut deadline = shutdown_rx;
+    tokio::select! {
+        result = server => result?,
+        _ = async {
+            let _ = deadline.wait_for(|stop| *stop).await;
+            tokio::time::sleep(shutdown_timeout).await;
+        } => {
+            tracing::warn!(
+                "in-flight requests still running after {:?}, shutting down anyway",
+                shutdown_timeout
+            );
+        }
+    }
+
+    // No more requests can queue visits; write out whatever is still pending
+    let flushed = visit_worker.shutdown().await;
+    tracing::info!("flushed {} pending visits", flushed);
+
+    pool.close().await;
+    // Sends whatever spans are still buffered
+    if let Some(provider) = tracer_provider
+        && let Err(e) = provider.shutdown()
+    {
+        tracing::warn!("failed to flush traces: {}", e);
+    }
+    tracing::info!("shutdown complete");
 
     Ok(())
 }
+
+// Runs an administration command, printing its result to stdout or its
+// error to stderr, and returns the exit status.
+async fn run_command(command: Command, state: &AppState, json: bool) -> u8 {
+    let to_json = |value: serde_json::Value| format!("{}\n", value);
+    let output = match command {
+        Command::Serve => unreachable!("serve is handled by main"),
+        Command::Shorten { url, code, expires } => cli::shorten(state, url, code, expires).await.map(|link| match json {
+            true => to_json(serde_json::to_value(&link).expect("links serialize")),
+            false => cli::render_link(&link),
+        }),
+        Command::Stats { code } => cli::stats(state, &code).await.map(|summary| match json {
+            true => to_json(serde_json::to_value(&summary).expect("summaries serialize")),
+            false => cli::render_summary(&summary),
+        }),
+        Command::PurgeExpired => cli::purge_expired(state).await.map(|purged| match json {
+            true => to_json(serde_json::json!({ "purged": purged })),
+            false => format!("purged {} expired links\n", purged),
+        }),
+        Command::Export { format: ExportFormat::Jsonl } => {
+            cli::export(state, &mut tokio::io::stdout()).await.map(|()| String::new())
+        }
+    };
+    match output {
+        Ok(output) => {
+            print!("{}", output);
+            0
+        }
+        Err(e) => {
+            let (status, message) = cli::render_error(e, json);
+            eprintln!("{}", message);
+            status
+        }
+    }
+}
+
+// Resolves on Ctrl+C, or SIGTERM on Unix (what container runtimes send).
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("failed to install Ctrl+C handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to install SIGTERM handler")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
@@ -0,0 +1,204 @@
+use crate::{
+    db::{DbPool, Traced},
+    destination::{self, DestinationPolicy},
+};
+use chrono::Utc;
+use reqwest::{header, Client, StatusCode, Url};
+use scraper::{Html, Selector};
+use std::{sync::Arc, time::Duration};
+use tokio::sync::{mpsc, Semaphore};
+
+const QUEUE_CAPACITY: usize = 1024;
+const MAX_CONCURRENT_FETCHES: usize = 4;
+const FETCH_TIMEOUT: Duration = Duration::from_secs(5);
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+const MAX_REDIRECTS: usize = 3;
+/// Only the start of the page is read; `<head>` is nearly always within it.
+const MAX_BODY_BYTES: usize = 512 * 1024;
+const MAX_FIELD_CHARS: usize = 300;
+const USER_AGENT: &str = concat!("short-url-service/", env!("CARGO_PKG_VERSION"), " (link preview)");
+
+struct FetchJob {
+    code: String,
+    url: String,
+    service_base: String,
+}
+
+/// Handle used by handlers to request a metadata fetch for a new link.
+#[derive(Clone)]
+pub struct MetadataFetcher {
+    tx: mpsc::Sender<FetchJob>,
+}
+
+impl MetadataFetcher {
+    /// Queues a fetch without waiting. When the queue is full the link simply
+    /// goes without a title.
+    pub fn fetch(&self, code: &str, url: &str, service_base: &str) {
+        let job = FetchJob {
+            code: code.to_string(),
+            url: url.to_string(),
+            service_base: service_base.to_string(),
+        };
+        if self.tx.try_send(job).is_err() {
+            tracing::warn!(code, "metadata queue full, skipping fetch");
+        }
+    }
+}
+
+/// Spawns the worker that fetches destination pages, at most a few at a
+/// time, and stores their title and description.
+pub fn spawn_fetcher(pool: DbPool, destinations: Arc<DestinationPolicy>) -> MetadataFetcher {
+    let (tx, mut rx) = mpsc::channel::<FetchJob>(QUEUE_CAPACITY);
+    // Redirects are followed by hand so every hop passes the destination
+    // policy, and each connects only to addresses the policy allows
+    let client = destination::client_builder(destinations.clone())
+        .user_agent(USER_AGENT)
+        .timeout(FETCH_TIMEOUT)
+        .build()
+        .expect("HTTP client configuration is valid");
+    let permits = Arc::new(Semaphore::new(MAX_CONCURRENT_FETCHES));
+
+    tokio::spawn(async move {
+        while let Some(job) = rx.recv().await {
+            let Ok(permit) = permits.clone().acquire_owned().await else {
+                break;
+            };
+            let (pool, client, destinations) = (pool.clone(), client.clone(), destinations.clone());
+            tokio::spawn(async move {
+                run_job(&pool, &client, &destinations, job).await;
+                drop(permit);
+            });
+        }
+    });
+
+    MetadataFetcher { tx }
+}
+
+async fn run_job(pool: &DbPool, client: &Client, destinations: &DestinationPolicy, job: FetchJob) {
+    // One retry for transient failures, then give up quietly
+    let mut result = fetch_page(client, destinations, &job).await;
+    if let Err(e) = &result {
+        tracing::debug!(code = job.code, "metadata fetch failed, retrying: {}", e);
+        tokio::time::sleep(RETRY_DELAY).await;
+        result = fetch_page(client, destinations, &job).await;
+    }
+
+    let page = match result {
+        Ok(page) => page,
+        Err(e) => {
+            // Errors are built without the destination, so private links
+            // stay out of the logs; the code finds the link
+            tracing::info!(code = job.code, "giving up on metadata: {}", e);
+            return;
+        }
+    };
+    let (title, description) = parse_metadata(&page);
+
+    let now = Utc::now();
+    // Skipped when the link was pointed elsewhere while this page loaded
+    let stored = sqlx::query!(
+        "UPDATE urls SET title = ?, description = ?, metadata_fetched_at = ? WHERE id = ? AND original_url = ?",
+        title,
+        description,
+        now,
+        job.code,
+        job.url
+    )
+    .execute(pool)
+    .traced("store_metadata", Some(job.code.as_str()))
+    .await;
+    if let Err(e) = stored {
+        tracing::error!(code = job.code, "failed to store link metadata: {:?}", e);
+    }
+}
+
+// Fetch the page body, following a few redirects and reading at most
+// MAX_BODY_BYTES.
+async fn fetch_page(client: &Client, destinations: &DestinationPolicy, job: &FetchJob) -> Result<String, String> {
+    let mut url = job.url.clone();
+    for _ in 0..=MAX_REDIRECTS {
+        destinations
+            .check_request(&url, &job.service_base)
+            .map_err(|_| "not an allowed destination")?;
+
+        let mut response = client
+            .get(&url)
+            .header(header::ACCEPT, "text/html")
+            .send()
+            .await
+            .map_err(without_url)?;
+
+        if response.status().is_redirection() {
+            let location = response
+                .headers()
+                .get(header::LOCATION)
+                .and_then(|h| h.to_str().ok())
+                .ok_or("redirect without a Location header")?;
+            url = Url::parse(&url)
+                .and_then(|base| base.join(location))
+                .map_err(|e| e.to_string())?
+                .to_string();
+            continue;
+        }
+        if response.status() != StatusCode::OK {
+            return Err(format!("status {}", response.status()));
+        }
+        let is_html = response
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|h| h.to_str().ok())
+            .is_none_or(|ct| ct.contains("html"));
+        if !is_html {
+            return Err("not an HTML page".into());
+        }
+
+        let mut body = Vec::new();
+        while let Some(chunk) = response.chunk().await.map_err(without_url)? {
+            body.extend_from_slice(&chunk);
+            if body.len() >= MAX_BODY_BYTES {
+                body.truncate(MAX_BODY_BYTES);
+                break;
+            }
+        }
+        return Ok(String::from_utf8_lossy(&body).into_owned());
+    }
+    Err("too many redirects".into())
+}
+
+// reqwest errors name the URL they failed on, which may be a private link's
+// destination or one of its redirect hops.
+fn without_url(e: reqwest::Error) -> String {
+    e.without_url().to_string()
+}
+
+// Collapse whitespace and cap the length of a scraped value.
+fn clean(raw: &str) -> Option<String> {
+    let text = raw.split_whitespace().collect::<Vec<_>>().join(" ");
+    if text.is_empty() {
+        return None;
+    }
+    Some(text.chars().take(MAX_FIELD_CHARS).collect())
+}
+
+/// Title (`<title>`, else `og:title`) and description (`og:description`,
+/// else `<meta name="description">`) of an HTML page.
+fn parse_metadata(page: &str) -> (Option<String>, Option<String>) {
+    let document = Html::parse_document(page);
+    let meta = |selector: &str| {
+        let selector = Selector::parse(selector).ok()?;
+        document
+            .select(&selector)
+            .find_map(|el| el.value().attr("content").and_then(clean))
+    };
+    let title = Selector::parse("title")
+        .ok()
+        .and_then(|selector| {
+            document
+                .select(&selector)
+                .find_map(|el| clean(&el.text().collect::<String>()))
+        })
+        .or_else(|| meta(r#"meta[property="og:title"]"#));
+    let description = meta(r#"meta[property="og:description"]"#)
+        .or_else(|| meta(r#"meta[name="description"]"#));
+    (title, description)
+}
@@ -1,48 +1,1206 @@
-use chrono::{DateTime, Utc};
+use crate::{error::ErrorCode, utils::CodeStyle};
+use axum::http::StatusCode;
+use chrono::{DateTime, NaiveDate, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
-use utoipa::ToSchema;
-use validator::Validate;
+use std::collections::BTreeMap;
+use utoipa::{IntoParams, ToSchema};
+use validator::{Validate, ValidationError};
+
+/// Characters allowed in short codes, both custom and generated.
+pub fn is_code_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '-' || c == '_'
+}
+
+fn validate_code_charset(code: &str) -> Result<(), ValidationError> {
+    if code.chars().all(is_code_char) {
+        Ok(())
+    } else {
+        let mut err = ValidationError::new("charset");
+        err.message = Some("custom_code may only contain letters, digits, '-' and '_'".into());
+        Err(err)
+    }
+}
+
+/// Tags are 1–32 characters from `a-z`, `0-9` and `-`.
+fn validate_tags(tags: &[String]) -> Result<(), ValidationError> {
+    let valid = |tag: &String| {
+        (1..=32).contains(&tag.len())
+            && tag.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+    };
+    match tags.iter().find(|tag| !valid(tag)) {
+        None => Ok(()),
+        Some(tag) => {
+            let mut err = ValidationError::new("tag");
+            err.message = Some(
+                format!("tag '{}' must be 1-32 lowercase letters, digits or '-'", tag).into(),
+            );
+            Err(err)
+        }
+    }
+}
+
+fn validate_https(url: &str) -> Result<(), ValidationError> {
+    if url.trim_start().get(..8).is_some_and(|scheme| scheme.eq_ignore_ascii_case("https://")) {
+        Ok(())
+    } else {
+        let mut err = ValidationError::new("https");
+        err.message = Some("webhook_url must use https".into());
+        Err(err)
+    }
+}
+
+/// HTTP status used when redirecting a short code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(try_from = "u16", into = "u16")]
+pub enum RedirectType {
+    MovedPermanently,
+    Found,
+    #[default]
+    Temporary,
+    Permanent,
+}
+
+impl RedirectType {
+    pub fn status_code(self) -> StatusCode {
+        match self {
+            RedirectType::MovedPermanently => StatusCode::MOVED_PERMANENTLY,
+            RedirectType::Found => StatusCode::FOUND,
+            RedirectType::Temporary => StatusCode::TEMPORARY_REDIRECT,
+            RedirectType::Permanent => StatusCode::PERMANENT_REDIRECT,
+        }
+    }
+}
+
+impl From<RedirectType> for u16 {
+    fn from(value: RedirectType) -> Self {
+        value.status_code().as_u16()
+    }
+}
+
+impl TryFrom<u16> for RedirectType {
+    type Error = String;
+
+    fn try_from(value: u16) -> Result<Self, Self::Error> {
+        match value {
+            301 => Ok(RedirectType::MovedPermanently),
+            302 => Ok(RedirectType::Found),
+            307 => Ok(RedirectType::Temporary),
+            308 => Ok(RedirectType::Permanent),
+            other => Err(format!("unsupported redirect status {}, expected 301, 302, 307 or 308", other)),
+        }
+    }
+}
+
+/// How a link with several destinations chooses one per visit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Rotation {
+    /// Independent weighted choice on every visit
+    #[default]
+    Random,
+    /// Weighted choice derived from the visitor hash, so repeat visitors see
+    /// the same destination
+    Sticky,
+}
+
+impl Rotation {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Rotation::Random => "random",
+            Rotation::Sticky => "sticky",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "random" => Some(Rotation::Random),
+            "sticky" => Some(Rotation::Sticky),
+            _ => None,
+        }
+    }
+}
+
+/// Who may read a link's stats, export and live stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum StatsVisibility {
+    /// Anyone who knows the short code
+    Public,
+    /// Only callers presenting the link's `stats_token`
+    #[default]
+    Private,
+}
+
+impl StatsVisibility {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            StatsVisibility::Public => "public",
+            StatsVisibility::Private => "private",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "public" => Some(StatsVisibility::Public),
+            "private" => Some(StatsVisibility::Private),
+            _ => None,
+        }
+    }
+}
+
+/// Whether a link's destination still answers, as last seen by the
+/// background link check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum LinkHealth {
+    /// Not checked yet, or the destination changed since
+    #[default]
+    Unknown,
+    /// Answered with a 2xx, possibly after redirects
+    Healthy,
+    /// Kept failing (error status, timeout, refused) through every retry
+    Broken,
+}
+
+impl LinkHealth {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LinkHealth::Unknown => "unknown",
+            LinkHealth::Healthy => "healthy",
+            LinkHealth::Broken => "broken",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "unknown" => Some(LinkHealth::Unknown),
+            "healthy" => Some(LinkHealth::Healthy),
+            "broken" => Some(LinkHealth::Broken),
+            _ => None,
+        }
+    }
+}
+
+/// How a link's short code was chosen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum CodeStrategy {
+    /// Random characters from the configured alphabet
+    #[default]
+    Random,
+    /// Base62 hash of the normalized URL, so the same URL always gets the same code
+    Hash,
+    /// Picked by the creator as `custom_code`
+    Custom,
+}
+
+impl CodeStrategy {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CodeStrategy::Random => "random",
+            CodeStrategy::Hash => "hash",
+            CodeStrategy::Custom => "custom",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "random" => Some(CodeStrategy::Random),
+            "hash" => Some(CodeStrategy::Hash),
+            "custom" => Some(CodeStrategy::Custom),
+            _ => None,
+        }
+    }
+}
+
+/// One destination of a rotating link.
+#[derive(Debug, Clone, Serialize, Deserialize, Validate, FromRow, ToSchema)]
+pub struct WeightedDestination {
+    #[validate(url(message = "destination url must be an absolute URL such as https://example.com"))]
+    #[schema(example = "https://example.com/landing-a")]
+    pub url: String,
+    /// Relative share of traffic, e.g. 3 and 1 for a 75/25 split
+    #[validate(range(min = 1, max = 1_000_000, message = "weight must be between 1 and 1000000"))]
+    #[schema(example = 1)]
+    pub weight: u32,
+}
 
 #[derive(Debug, Serialize, Deserialize, Validate, ToSchema)]
 pub struct CreateUrlRequest {
-    #[validate(url)]
-    #[schema(example = "https://www.google.com")]
-    pub url: String,
-    #[schema(example = "custom123", nullable)]
-    #[validate(length(min = 3, max = 20))]
+    /// Destination of the link. Required unless `destinations` is given.
+    #[validate(url(message = "url must be an absolute URL such as https://example.com"))]
+    #[schema(example = "https://www.google.com", nullable)]
+    pub url: Option<String>,
+    /// Split traffic between up to 10 weighted destinations instead of a
+    /// single `url`. The first one is reported as `original_url`.
+    #[validate(
+        length(min = 1, max = 10, message = "destinations must list between 1 and 10 URLs"),
+        nested
+    )]
+    #[schema(nullable)]
+    pub destinations: Option<Vec<WeightedDestination>>,
+    /// How rotating links pick a destination; `random` by default
+    #[schema(nullable)]
+    pub rotation: Option<Rotation>,
+    /// Where iPhone, iPad and iPod visitors are sent instead, e.g. an App Store page
+    #[validate(url(message = "ios_url must be an absolute URL"))]
+    #[schema(example = "https://apps.apple.com/app/id000000000", nullable)]
+    pub ios_url: Option<String>,
+    /// Where Android visitors are sent instead, e.g. a Google Play page
+    #[validate(url(message = "android_url must be an absolute URL"))]
+    #[schema(example = "https://play.google.com/store/apps/details?id=com.example", nullable)]
+    pub android_url: Option<String>,
+    /// 3–32 characters from `A-Z`, `a-z`, `0-9`, `-` and `_`, e.g. `spring-sale-2024`
+    #[schema(example = "custom123", nullable, pattern = "^[A-Za-z0-9_-]{3,32}$")]
+    #[validate(
+        length(min = 3, max = 32, message = "custom_code must be between 3 and 32 characters"),
+        custom(function = "validate_code_charset")
+    )]
     pub custom_code: Option<String>,
     #[schema(example = "2025-12-31T23:59:59Z", nullable)]
     pub expires_at: Option<DateTime<Utc>>,
+    /// Expire the link this many seconds after creation instead of at a fixed
+    /// `expires_at`; the two can't be combined. At most ten years.
+    #[validate(range(min = 1, max = 315_360_000, message = "expires_in_secs must be between 1 and 315360000 (ten years)"))]
+    #[schema(example = 604800, nullable)]
+    pub expires_in_secs: Option<i64>,
+    /// Keep the link inactive until this time. Must be before `expires_at`.
+    #[schema(example = "2025-06-01T09:00:00Z", nullable)]
+    pub starts_at: Option<DateTime<Utc>>,
+    /// Redirect status: 301, 302, 307 (default) or 308.
+    #[schema(value_type = Option<u16>, example = 307, nullable)]
+    pub redirect_type: Option<RedirectType>,
+    /// Disable the link after this many redirects
+    #[validate(range(min = 1, message = "max_visits must be at least 1"))]
+    #[schema(example = 1, nullable)]
+    pub max_visits: Option<i64>,
+    /// Require this password on an interstitial page before redirecting
+    #[validate(length(min = 1, max = 256, message = "password must be between 1 and 256 characters"))]
+    #[serde(skip_serializing)]
+    #[schema(nullable, write_only)]
+    pub password: Option<String>,
+    /// Up to 10 labels for grouping links, each 1–32 characters from `a-z`, `0-9` and `-`
+    #[validate(
+        length(max = 10, message = "at most 10 tags are allowed"),
+        custom(function = "validate_tags")
+    )]
+    #[schema(example = json!(["campaign-x"]), nullable)]
+    pub tags: Option<Vec<String>>,
+    /// Pass query parameters on the short link (e.g. `?utm_source=x`) on to the
+    /// destination, overriding parameters with the same key. Defaults to true.
+    #[schema(example = true, nullable)]
+    pub forward_query: Option<bool>,
+    /// HTTPS endpoint that receives a signed `POST` for every visit. The signing
+    /// secret is returned once, as `webhook_secret`.
+    #[validate(
+        url(message = "webhook_url must be an absolute URL"),
+        custom(function = "validate_https")
+    )]
+    #[schema(example = "https://example.com/hooks/visits", nullable)]
+    pub webhook_url: Option<String>,
+    /// Return an existing link for the same URL instead of creating a new one,
+    /// if it still redirects and was made with the same settings (expiry,
+    /// start, redirect type, visit limit, query forwarding and platform
+    /// URLs). Ignored when `custom_code`, `password`, `tags`, `destinations`,
+    /// `webhook_url` or `campaign_id` is set.
+    #[serde(default)]
+    pub deduplicate: bool,
+    /// Fetch the destination's title and description in the background; they
+    /// show up in `/info` once retrieved
+    #[serde(default)]
+    pub fetch_metadata: bool,
+    /// Probe the destination first (2 s timeout, up to 3 redirects) and refuse
+    /// it with `422 DESTINATION_UNREACHABLE` when it answers `404`/`410` or
+    /// can't be reached. Only `POST /shorten` checks; bulk items ignore it.
+    #[serde(default)]
+    pub verify: bool,
+    /// `private` (default) requires the returned `stats_token` to read stats;
+    /// `public` leaves them open to anyone with the short code
+    #[schema(nullable)]
+    pub stats_visibility: Option<StatsVisibility>,
+    /// `random` (default) or `hash`, which derives the code from the URL so
+    /// the same URL always gets the same code; creating it again returns the
+    /// existing link with `200`. Can't be combined with `custom_code`.
+    #[schema(nullable)]
+    pub code_strategy: Option<CodeStrategy>,
+    /// Look of a generated random code: `random` characters or `words` such
+    /// as `brave-otter-4217`. Defaults to `CODE_STYLE`.
+    #[schema(nullable)]
+    pub code_style: Option<CodeStyle>,
+    /// Campaign from `/admin/campaigns` to count the link's visits under
+    #[schema(example = 1, nullable)]
+    pub campaign_id: Option<i64>,
 }
 
-#[derive(Debug, Serialize, ToSchema)]
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
 pub struct UrlResponse {
     pub short_code: String,
-    pub original_url: String,
+    /// Null in `/info/{code}` for a password-protected link, along with its
+    /// other destinations, unless the delete token or creating API key is sent
+    pub original_url: Option<String>,
     pub short_url: String,
+    pub created_at: DateTime<Utc>,
     pub expires_at: Option<DateTime<Utc>>,
+    /// Time from which the link redirects, if it was scheduled
+    pub starts_at: Option<DateTime<Utc>>,
+    /// Redirect status served for this link. Permanent redirects (301/308) are
+    /// cached by browsers, so repeat clicks from the same client may never
+    /// reach the server and will not show up in stats.
+    #[schema(value_type = u16, example = 307)]
+    pub redirect_type: RedirectType,
+    /// Number of redirects after which the link stops working
+    pub max_visits: Option<i64>,
+    /// Whether visitors must enter a password before being redirected
+    pub password_protected: bool,
+    /// False while the link is disabled and answers redirects with 410
+    pub active: bool,
+    /// Labels attached at creation, sorted
+    pub tags: Vec<String>,
+    /// Whether the short link's query string is appended to the destination
+    pub forward_query: bool,
+    /// Destination for iOS visitors, if it differs from the default
+    pub ios_url: Option<String>,
+    /// Destination for Android visitors, if it differs from the default
+    pub android_url: Option<String>,
+    /// Whether stats need the link's `stats_token`
+    pub stats_visibility: StatsVisibility,
+    /// Set for links that split traffic between several destinations
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rotation: Option<Rotation>,
+    /// Weighted destinations of a rotating link. Included at creation and by
+    /// `/info/{code}`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub destinations: Option<Vec<WeightedDestination>>,
+    /// Secret required to update or delete the link. Only returned once, at creation.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delete_token: Option<String>,
+    /// Key for verifying `X-Webhook-Signature` on webhook deliveries. Only
+    /// returned once, at creation, when `webhook_url` was set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub webhook_secret: Option<String>,
+    /// Secret for reading the stats of a private link, sent as `X-Stats-Token`
+    /// or the `stats_token` query param. Only returned once, at creation.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stats_token: Option<String>,
+    /// Campaign the link belongs to, if any
+    pub campaign_id: Option<i64>,
+}
+
+/// Link details returned by `/info/{code}` without recording a visit.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct UrlInfoResponse {
+    #[serde(flatten)]
+    pub link: UrlResponse,
+    pub expired: bool,
+    /// True when `expires_at` wasn't requested but set to the server's maximum
+    /// link lifetime (`MAX_LINK_TTL_DAYS`)
+    pub expiry_defaulted: bool,
+    /// True while `starts_at` is still in the future
+    pub scheduled: bool,
+    /// Redirects left before `max_visits` is reached, if the link has a limit
+    pub remaining_visits: Option<i64>,
+    /// Destination page title, when metadata was fetched
+    pub title: Option<String>,
+    /// Destination page description (`og:description` or meta description)
+    pub description: Option<String>,
+    /// When the metadata was fetched; null if it wasn't requested or failed
+    pub metadata_fetched_at: Option<DateTime<Utc>>,
+    /// Whether the destination still answers, when `LINK_CHECK_INTERVAL_SECS` is set
+    pub health: LinkHealth,
+    /// When the destination was last checked
+    pub last_checked_at: Option<DateTime<Utc>>,
+    /// Status the destination last answered with (after redirects); null on
+    /// timeouts and connection errors. With several destinations, that of the
+    /// first broken one.
+    pub last_status: Option<i64>,
+    /// Last check of each destination (`url`, `ios_url`, `android_url` and
+    /// rotation destinations); `health` is broken when any of them is. Left
+    /// out before the first check and while destinations are hidden.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub destination_checks: Vec<DestinationCheck>,
+}
+
+/// What the link checker last found at one destination.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DestinationCheck {
+    pub url: String,
+    pub health: LinkHealth,
+    /// Status it answered with (after redirects); null on timeouts and
+    /// connection errors
+    pub last_status: Option<i64>,
+    pub checked_at: DateTime<Utc>,
 }
 
-#[derive(Debug, FromRow, Serialize)]
+/// Body of every JSON error response. For compatibility, `CODE_TAKEN`,
+/// `URL_EXPIRED` and `VALIDATION_FAILED` also repeat their `details` at the
+/// top level.
+#[derive(Serialize, ToSchema)]
+pub struct ErrorResponse {
+    /// Human-readable message; may change between releases
+    #[schema(example = "URL not found")]
+    pub error: String,
+    /// Stable identifier of the error, for branching in clients
+    pub code: ErrorCode,
+    /// Context for the error, e.g. the conflicting code or seconds until a retry
+    #[schema(value_type = Option<Object>, example = json!({"retry_after_secs": 12}))]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<serde_json::Value>,
+    /// Same as the `x-request-id` response header; quote it when reporting a problem
+    #[schema(example = "4f0c2a1e-8d3b-4c7a-9e55-2b1f6d0a9c13")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+/// Body of a `422` validation failure.
+#[derive(Serialize, ToSchema)]
+pub struct ValidationErrorResponse {
+    #[schema(example = "Validation failed")]
+    pub error: String,
+    pub code: ErrorCode,
+    /// Messages for each invalid field
+    #[schema(example = json!({"custom_code": ["custom_code must be between 3 and 32 characters"]}))]
+    pub fields: BTreeMap<String, Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+/// Outcome of one item in a bulk shorten request, tagged by `status`.
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum BulkItemResult {
+    Created { index: usize, link: UrlResponse },
+    Existing { index: usize, link: UrlResponse },
+    Invalid {
+        index: usize,
+        error: String,
+        code: ErrorCode,
+        /// Per-field messages when the item failed validation
+        #[serde(skip_serializing_if = "Option::is_none")]
+        fields: Option<BTreeMap<String, Vec<String>>>,
+    },
+    Conflict { index: usize, short_code: String },
+}
+
+// No Debug: the record carries the delete and stats token hashes, password
+// hash and webhook secret.
+#[derive(Clone, FromRow, Serialize)]
 pub struct UrlRecord {
     pub id: String,
     pub original_url: String,
     pub created_at: DateTime<Utc>,
     pub expires_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing)]
+    pub delete_token_hash: Option<String>,
+    pub redirect_type: i64,
+    #[serde(skip_serializing)]
+    pub created_by_key: Option<String>,
+    pub max_visits: Option<i64>,
+    pub visit_count: i64,
+    pub starts_at: Option<DateTime<Utc>>,
+    #[serde(skip_serializing)]
+    pub password_hash: Option<String>,
+    pub is_active: bool,
+    pub expiry_defaulted: bool,
+    pub forward_query: bool,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub metadata_fetched_at: Option<DateTime<Utc>>,
+    pub webhook_url: Option<String>,
+    #[serde(skip_serializing)]
+    pub webhook_secret: Option<String>,
+    pub rotation: Option<String>,
+    pub ios_url: Option<String>,
+    pub android_url: Option<String>,
+    pub untracked_visits: i64,
+    /// Recorded plus untracked visits
+    pub total_visits: i64,
+    /// Share of `total_visits` made by bots
+    pub bot_visits: i64,
+    pub stats_visibility: String,
+    #[serde(skip_serializing)]
+    pub stats_token_hash: Option<String>,
+    /// Namespace of the API key the link was created with
+    pub namespace: Option<String>,
+    pub code_strategy: Option<String>,
+    pub campaign_id: Option<i64>,
+    pub last_checked_at: Option<DateTime<Utc>>,
+    /// HTTP status of the last check; null when no response came back
+    pub last_status: Option<i64>,
+    pub health: String,
 }
 
-#[derive(Debug, FromRow, Serialize, ToSchema)]
+impl UrlRecord {
+    /// Rotation mode, for links with weighted destinations.
+    pub fn rotation(&self) -> Option<Rotation> {
+        self.rotation.as_deref().and_then(Rotation::parse)
+    }
+
+    /// Stats visibility; anything unrecognized is treated as private.
+    pub fn stats_visibility(&self) -> StatsVisibility {
+        StatsVisibility::parse(&self.stats_visibility).unwrap_or(StatsVisibility::Private)
+    }
+
+    /// Result of the link check; anything unrecognized counts as unknown.
+    pub fn health(&self) -> LinkHealth {
+        LinkHealth::parse(&self.health).unwrap_or_default()
+    }
+
+    /// How the code was chosen; `None` for links from before it was recorded.
+    pub fn code_strategy(&self) -> Option<CodeStrategy> {
+        self.code_strategy.as_deref().and_then(CodeStrategy::parse)
+    }
+
+    pub fn redirect_type(&self) -> RedirectType {
+        u16::try_from(self.redirect_type)
+            .ok()
+            .and_then(|code| RedirectType::try_from(code).ok())
+            .unwrap_or_default()
+    }
+}
+
+/// Form posted by the password interstitial.
+#[derive(Deserialize, ToSchema)]
+pub struct UnlockForm {
+    pub password: String,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct TokenParams {
+    /// Delete token returned at creation, as an alternative to the `Authorization` header
+    pub token: Option<String>,
+}
+
+// Distinguishes an absent field (`None`) from an explicit `null` (`Some(None)`).
+fn double_option<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+#[derive(Debug, Deserialize, Validate, ToSchema)]
+pub struct UpdateUrlRequest {
+    /// New destination URL
+    #[validate(url(message = "url must be an absolute URL such as https://example.com"))]
+    #[schema(example = "https://www.rust-lang.org", nullable)]
+    pub url: Option<String>,
+    /// New expiry. Send `null` to remove the expiry, omit to keep it unchanged.
+    #[serde(default, deserialize_with = "double_option")]
+    #[schema(value_type = Option<DateTime<Utc>>, example = "2026-12-31T23:59:59Z", nullable)]
+    pub expires_at: Option<Option<DateTime<Utc>>>,
+    /// Disable (`false`) or re-enable (`true`) the link. Disabled links keep
+    /// their stats and QR code but redirects answer 410.
+    pub active: Option<bool>,
+    /// Turn query forwarding to the destination on or off
+    pub forward_query: Option<bool>,
+    /// New iOS destination. Send `null` to remove it, omit to keep it unchanged.
+    #[serde(default, deserialize_with = "double_option")]
+    #[validate(url(message = "ios_url must be an absolute URL"))]
+    #[schema(value_type = Option<String>, nullable)]
+    pub ios_url: Option<Option<String>>,
+    /// New Android destination. Send `null` to remove it, omit to keep it unchanged.
+    #[serde(default, deserialize_with = "double_option")]
+    #[validate(url(message = "android_url must be an absolute URL"))]
+    #[schema(value_type = Option<String>, nullable)]
+    pub android_url: Option<Option<String>>,
+    /// Move the link to this campaign. Send `null` to take it out of its
+    /// campaign, omit to keep it unchanged.
+    #[serde(default, deserialize_with = "double_option")]
+    #[schema(value_type = Option<i64>, nullable)]
+    pub campaign_id: Option<Option<i64>>,
+}
+
+#[derive(Debug, Clone, FromRow, Serialize, ToSchema)]
 pub struct VisitStats {
     pub ip_address: Option<String>,
     pub user_agent: Option<String>,
+    pub referrer: Option<String>,
+    /// `redirect` or `beacon`
+    #[schema(example = "redirect")]
+    pub source: String,
+    /// Destination the visitor was sent to, for rotating links
+    pub destination: Option<String>,
+    /// `ios`, `android` or `other`, detected from the user agent
+    #[schema(example = "ios")]
+    pub platform: Option<String>,
     pub visited_at: DateTime<Utc>,
+    /// Made by a crawler or link preview fetcher, judged by the user agent
+    pub is_bot: bool,
 }
 
 #[derive(Debug, Serialize, ToSchema)]
 pub struct StatsResponse {
     pub url: String,
-    pub original_url: String,
+    /// Null for a password-protected link, along with the visits'
+    /// destinations, unless the delete token (as `Authorization: Bearer`) or
+    /// creating API key is sent
+    pub original_url: Option<String>,
+    /// Redirect status served for this link. For permanent redirects (301/308)
+    /// browsers cache the destination after the first click, so visits are
+    /// undercounted compared to temporary redirects.
+    #[schema(value_type = u16, example = 307)]
+    pub redirect_type: RedirectType,
+    /// True while the link's `starts_at` is still in the future
+    pub scheduled: bool,
+    /// False while the link is disabled
+    pub active: bool,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    /// Seconds until `expires_at`; null for links that don't expire
+    pub expires_in_secs: Option<i64>,
+    /// Recorded visits plus `untracked_visits`, without `bot_visits` unless
+    /// `include_bots=true`
     pub total_visits: i64,
+    /// Visits from crawlers and link preview fetchers. They stay in the visit
+    /// list, flagged `is_bot`, but are left out of the counts and breakdowns
+    /// below unless `include_bots=true`.
+    pub bot_visits: i64,
+    /// Visits that were only counted, with no row kept: made while
+    /// `ANALYTICS_MODE=off` or with `DNT: 1`. They have no details, so they
+    /// aren't part of any breakdown.
+    pub untracked_visits: i64,
+    /// Recorded visits counted while redirecting
+    pub redirect_visits: i64,
+    /// Recorded visits reported through `/hit/{code}`
+    pub beacon_visits: i64,
+    /// Distinct visitors by salted IP + user-agent hash. Visits recorded
+    /// before hashing was introduced aren't counted.
+    pub unique_visitors: i64,
+    /// Earliest and latest recorded visit, bots included only with
+    /// `include_bots=true`; null without any
+    pub first_visit_at: Option<DateTime<Utc>>,
+    pub last_visit_at: Option<DateTime<Utc>>,
+    /// `total_visits` spread over the days since the link was created,
+    /// counting at least one day, to two decimals
+    pub avg_visits_per_day: f64,
+    /// Most recent visits first, paged with `limit`/`offset` and filtered by `from`/`to`
     pub visits: Vec<VisitStats>,
+    /// Whether more visits match beyond this page
+    pub has_more: bool,
+    /// Offset of the next page, when there is one
+    pub next_offset: Option<i64>,
+    /// Visits per day over the requested window, oldest first, with zero-count days included
+    pub daily_visits: Vec<DailyCount>,
+    /// Most common referrer hosts, with missing referrers bucketed as "direct"
+    pub top_referrers: Vec<ReferrerCount>,
+    /// Visits per browser, parsed from user agents at query time
+    pub browsers: Vec<NamedCount>,
+    pub operating_systems: Vec<NamedCount>,
+    /// `Desktop`, `Mobile`, `Bot`, `Appliance`, `Other` or `Unknown`
+    pub device_types: Vec<NamedCount>,
+    /// Per-destination visit counts for rotating links; empty otherwise
+    pub destinations: Vec<DestinationCount>,
+    /// Visits by platform (`ios`, `android`, `other`), most first
+    pub platforms: Vec<NamedCount>,
+    /// Visits by visitor country, most first. Only filled while
+    /// `GEOIP_DB_PATH` is set; visits with no known country are left out.
+    pub countries: Vec<CountryCount>,
+    /// How the short code was chosen; null for links created before this was recorded
+    pub code_strategy: Option<CodeStrategy>,
+    /// Visits by weekday and hour, all 168 cells, with `?include=heatmap`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub heatmap: Option<Vec<HeatmapCell>>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct NamedCount {
+    #[schema(example = "Chrome")]
+    pub name: String,
+    pub count: i64,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct CountryCount {
+    /// ISO 3166-1 alpha-2 code, e.g. `DE`
+    pub code: String,
+    pub count: i64,
+}
+
+/// Visits sent to one destination of a rotating link.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct DestinationCount {
+    #[schema(example = "https://example.com/landing-a")]
+    pub url: String,
+    pub weight: u32,
+    pub visits: i64,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ReferrerCount {
+    #[schema(example = "news.ycombinator.com")]
+    pub referrer: String,
+    pub count: i64,
+}
+
+#[derive(Debug, FromRow, Serialize, ToSchema)]
+pub struct DailyCount {
+    #[schema(value_type = String, format = Date, example = "2025-01-31")]
+    pub date: NaiveDate,
+    pub count: i64,
+}
+
+/// Visits in one hour of one weekday, over all of a link's visits.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct HeatmapCell {
+    /// 0 = Sunday through 6 = Saturday
+    pub weekday: u8,
+    /// 0–23
+    pub hour: u8,
+    pub count: i64,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct StatsParams {
+    /// Number of days covered by `daily_visits`, 1–365 (default 30)
+    pub days: Option<u32>,
+    /// Page size of the `visits` list, 1–1000 (default 100)
+    pub limit: Option<i64>,
+    /// Number of visits to skip (default 0)
+    pub offset: Option<i64>,
+    /// Only list visits at or after this time
+    pub from: Option<DateTime<Utc>>,
+    /// Only list visits before this time
+    pub to: Option<DateTime<Utc>>,
+    /// Token of a private link, instead of the `X-Stats-Token` header
+    pub stats_token: Option<String>,
+    /// Count bot visits in the totals and breakdowns (default false)
+    #[serde(default)]
+    pub include_bots: bool,
+    /// Comma-separated extras to compute: `heatmap`
+    pub include: Option<String>,
+    /// IANA time zone the heatmap is bucketed in, e.g. `Europe/Berlin`
+    /// (default UTC)
+    pub tz: Option<String>,
+}
+
+/// Headline numbers of a link, without the visit list or breakdowns.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct StatsSummary {
+    pub url: String,
+    /// Null for a password-protected link, as in the full stats, and so is
+    /// the title
+    pub original_url: Option<String>,
+    pub title: Option<String>,
+    /// False while the link is disabled
+    pub active: bool,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    /// Recorded visits plus untracked ones, as in the full stats. Bots are
+    /// left out of this and the fields below unless `include_bots=true`.
+    pub total_visits: i64,
+    pub unique_visitors: i64,
+    /// Most recent recorded visit; untracked visits leave no time behind
+    pub last_visited_at: Option<DateTime<Utc>>,
+}
+
+/// Body of `POST /stats/summary`.
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct StatsSummaryRequest {
+    /// 1–100 short codes; repeats are answered once
+    pub codes: Vec<String>,
+    /// Stats tokens of private links, by code. An `X-Stats-Token` header
+    /// applies to every code without one here.
+    #[serde(default)]
+    pub stats_tokens: BTreeMap<String, String>,
+    /// Count bot visits, for every code (default false)
+    #[serde(default)]
+    pub include_bots: bool,
+}
+
+/// One code's entry in a bulk summary: the summary, or the error the
+/// single-link endpoint would have answered with.
+#[derive(Debug, Serialize, ToSchema)]
+#[serde(untagged)]
+pub enum StatsSummaryResult {
+    Found(StatsSummary),
+    Failed { error: String, code: ErrorCode },
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct StatsTokenParams {
+    /// Token of a private link, instead of the `X-Stats-Token` header
+    pub stats_token: Option<String>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct StatsSummaryParams {
+    /// Token of a private link, instead of the `X-Stats-Token` header
+    pub stats_token: Option<String>,
+    /// Count bot visits (default false)
+    #[serde(default)]
+    pub include_bots: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QrFormat {
+    Svg,
+    Png,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct QrParams {
+    /// Output format: `svg` (default) or `png`
+    pub format: Option<String>,
+    /// PNG width and height in pixels, clamped to 64–2048 (default 256)
+    pub size: Option<u32>,
+    /// Dark module color as 6-digit hex, e.g. `112233` (default black)
+    pub fg: Option<String>,
+    /// Background color as 6-digit hex (default white)
+    pub bg: Option<String>,
+    /// Error correction level: `L`, `M` (default), `Q` or `H`
+    pub ec: Option<String>,
+    /// Quiet zone width in modules, 0–16 (default 4)
+    pub margin: Option<u32>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct ListUrlsParams {
+    /// Page size, 1–500 (default 50)
+    pub limit: Option<i64>,
+    /// Number of rows to skip (default 0)
+    pub offset: Option<i64>,
+    /// `next_cursor` of the previous page. Unlike `offset`, stays correct
+    /// while links are being created; the two can't be combined.
+    pub cursor: Option<String>,
+    /// Only links created at or after this time
+    pub created_after: Option<DateTime<Utc>>,
+    /// Only links created before this time
+    pub created_before: Option<DateTime<Utc>>,
+    /// `true` for expired links only, `false` for live links only
+    pub expired: Option<bool>,
+    /// Substring of the destination URL
+    pub url_contains: Option<String>,
+    /// Search term matched case-insensitively against the destination URL
+    /// and the short code; see `matched` on each item
+    pub q: Option<String>,
+    /// Only codes starting with this (case-sensitive, like codes)
+    pub code_prefix: Option<String>,
+    /// Only links carrying this tag
+    pub tag: Option<String>,
+    /// Only links created with this API key
+    pub owner_key: Option<String>,
+    /// Only links created by keys in this namespace
+    pub namespace: Option<String>,
+    /// Only links whose last check found them `healthy`, `broken` or not
+    /// yet checked (`unknown`)
+    pub health: Option<String>,
+}
+
+#[derive(Debug, FromRow, Serialize, ToSchema)]
+pub struct AdminUrlItem {
+    pub id: String,
+    pub original_url: String,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub redirect_type: i64,
+    pub max_visits: Option<i64>,
+    pub total_visits: i64,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    /// Namespace of the API key the link was created with
+    pub namespace: Option<String>,
+    /// `unknown`, `healthy` or `broken`
+    pub health: String,
+    #[sqlx(skip)]
+    pub tags: Vec<String>,
+    /// Fields that matched `q` or `code_prefix` (`original_url`, `id`), for
+    /// highlighting. Left out when neither was given.
+    #[sqlx(skip)]
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub matched: Vec<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct UrlListResponse {
+    pub items: Vec<AdminUrlItem>,
+    /// Number of links matching the filters, across all pages
+    pub total: i64,
+    pub limit: i64,
+    pub offset: i64,
+    /// Offset of the next page, or null on the last page or when paging by cursor
+    pub next_offset: Option<i64>,
+    /// Cursor for the next page, or null on the last page
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct SummaryParams {
+    /// Period the top links are ranked over: `<n>h` or `<n>d`, up to `365d` (default `7d`)
+    pub window: Option<String>,
+    /// Count bot visits in the totals and top links (default false)
+    #[serde(default)]
+    pub include_bots: bool,
+}
+
+#[derive(Debug, FromRow, Serialize, ToSchema)]
+pub struct TopLink {
+    pub short_code: String,
+    pub original_url: String,
+    /// Recorded visits within the window
+    pub visits: i64,
+}
+
+/// Service-wide overview for `/admin/summary`.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct SummaryResponse {
+    pub total_links: i64,
+    /// Visits across all links, untracked ones included and bots left out
+    /// unless `include_bots` is set
+    pub total_visits: i64,
+    /// Links created in the last 24 hours
+    pub links_created_24h: i64,
+    /// Links created in the last 7 days
+    pub links_created_7d: i64,
+    /// Window the top links are ranked over
+    #[schema(example = "7d")]
+    pub window: String,
+    /// Up to 10 links with the most recorded visits in the window, most first.
+    /// Untracked visits have no timestamp and don't count here.
+    pub top_links: Vec<TopLink>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ResetVisitsResponse {
+    /// Visit rows removed
+    pub deleted: u64,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct AuditParams {
+    /// Only entries about this short code
+    pub code: Option<String>,
+    /// Entries to return, newest first (default 50, max 500)
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct AuditEntry {
+    pub id: i64,
+    pub created_at: DateTime<Utc>,
+    /// `key:` and a hash of the API key used, `delete_token` or `anonymous`
+    #[schema(example = "key:3f79bb7b435b")]
+    pub actor: String,
+    /// `create`, `update`, `disable`, `delete`, `import`, `reset_visits`,
+    /// `erase`, `create_campaign`, `rename_campaign` or `delete_campaign`
+    pub action: String,
+    pub code: Option<String>,
+    /// The link before the change, in `/admin/export` form
+    pub before: Option<serde_json::Value>,
+    /// The link after the change, or the outcome of an erasure
+    pub after: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct AuditLogResponse {
+    pub items: Vec<AuditEntry>,
+}
+
+/// Body of `POST /admin/campaigns` and `PATCH /admin/campaigns/{id}`.
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct CampaignRequest {
+    /// 1–100 characters, e.g. `Q3 launch`
+    #[schema(example = "Q3 launch")]
+    pub name: String,
+}
+
+#[derive(Debug, Clone, FromRow, Serialize, ToSchema)]
+pub struct Campaign {
+    pub id: i64,
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+    /// Links currently in the campaign
+    pub links: i64,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct CampaignListResponse {
+    /// Newest first
+    pub items: Vec<Campaign>,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct CampaignStatsParams {
+    /// Number of days covered by `daily_visits`, 1–365 (default 30)
+    pub days: Option<u32>,
+    /// Count bot visits in the totals and daily counts (default false)
+    #[serde(default)]
+    pub include_bots: bool,
+}
+
+#[derive(Debug, FromRow, Serialize, ToSchema)]
+pub struct CampaignLink {
+    pub short_code: String,
+    pub original_url: String,
+    /// Recorded plus untracked visits, without bots unless `include_bots` is set
+    pub total_visits: i64,
+}
+
+/// Combined performance of a campaign's links.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct CampaignStatsResponse {
+    pub id: i64,
+    pub name: String,
+    /// Sum of the links' `total_visits`
+    pub total_visits: i64,
+    /// Member links, most visited first
+    pub links: Vec<CampaignLink>,
+    /// Recorded visits per day across the links, oldest first, with zero-count days included
+    pub daily_visits: Vec<DailyCount>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct EraseRequest {
+    /// IPv4 or IPv6 address of the data subject
+    #[schema(example = "203.0.113.7")]
+    pub ip_address: String,
+}
+
+/// Rows changed per table by `/admin/privacy/erase`.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct EraseResponse {
+    /// Visits whose IP address, user agent and visitor hash were cleared
+    pub visits: u64,
+    /// Failed webhook deliveries deleted because their payload carried a
+    /// matching visitor hash
+    pub webhook_failures: u64,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// One line of `/admin/export` and `/admin/import`. The password, delete
+/// token and stats token travel as the hashes the service stores and the
+/// webhook secret as is, so an imported link behaves like the original; an
+/// export is as sensitive as the database. Visit rows are not carried over.
+#[derive(Debug, Clone, Serialize, Deserialize, ToSchema)]
+pub struct LinkExport {
+    /// Custom codes prefixed with their namespace and `~` are kept as they are
+    #[schema(pattern = "^[A-Za-z0-9_~-]{1,64}$")]
+    pub short_code: String,
+    pub original_url: String,
+    pub created_at: DateTime<Utc>,
+    #[serde(default)]
+    pub expires_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub starts_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    #[schema(value_type = u16, example = 307)]
+    pub redirect_type: RedirectType,
+    #[serde(default)]
+    pub max_visits: Option<i64>,
+    #[serde(default = "default_true")]
+    pub active: bool,
+    #[serde(default = "default_true")]
+    pub forward_query: bool,
+    #[serde(default)]
+    pub stats_visibility: StatsVisibility,
+    #[serde(default)]
+    pub namespace: Option<String>,
+    #[serde(default)]
+    pub code_strategy: Option<CodeStrategy>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Redirects counted against `max_visits`
+    #[serde(default)]
+    pub visit_count: i64,
+    #[serde(default)]
+    pub untracked_visits: i64,
+    #[serde(default)]
+    pub total_visits: i64,
+    /// Share of `total_visits` made by bots
+    #[serde(default)]
+    pub bot_visits: i64,
+    #[serde(default)]
+    pub ios_url: Option<String>,
+    #[serde(default)]
+    pub android_url: Option<String>,
+    /// Set for rotating links, which also have `destinations`
+    #[serde(default)]
+    pub rotation: Option<Rotation>,
+    #[serde(default)]
+    pub destinations: Vec<WeightedDestination>,
+    #[serde(default)]
+    pub webhook_url: Option<String>,
+    #[serde(default)]
+    pub webhook_secret: Option<String>,
+    /// Dropped on import when no campaign has this id
+    #[serde(default)]
+    pub campaign_id: Option<i64>,
+    /// API key the link was created with
+    #[serde(default)]
+    pub created_by_key: Option<String>,
+    #[serde(default)]
+    pub password_hash: Option<String>,
+    #[serde(default)]
+    pub delete_token_hash: Option<String>,
+    #[serde(default)]
+    pub stats_token_hash: Option<String>,
+}
+
+impl LinkExport {
+    /// The link without its hashes, webhook secret and creating key, as the
+    /// audit log records it.
+    pub fn without_secrets(self) -> Self {
+        LinkExport {
+            webhook_secret: None,
+            created_by_key: None,
+            password_hash: None,
+            delete_token_hash: None,
+            stats_token_hash: None,
+            ..self
+        }
+    }
+}
+
+/// What `/admin/import` does with a line whose short code already exists.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportConflict {
+    /// Keep the existing link
+    Skip,
+    /// Replace the exported fields of the existing link
+    Overwrite,
+    /// Import nothing if any short code exists
+    #[default]
+    Fail,
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+pub struct ImportParams {
+    /// `skip`, `overwrite` or `fail` (default)
+    pub conflict: Option<ImportConflict>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportStatus {
+    Created,
+    Overwritten,
+    Skipped,
+    Conflict,
+    Invalid,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ImportLineResult {
+    /// 1-based line number in the request body
+    pub line: usize,
+    pub short_code: Option<String>,
+    pub status: ImportStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize, ToSchema)]
+pub struct ImportResponse {
+    pub created: usize,
+    pub overwritten: usize,
+    pub skipped: usize,
+    /// Lines that were invalid or conflicted
+    pub failed: usize,
+    /// One entry per non-empty line, in order
+    pub results: Vec<ImportLineResult>,
 }
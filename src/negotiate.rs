@@ -0,0 +1,98 @@
+use crate::error::AppError;
+use axum::{
+    extract::{FromRequest, Request},
+    http::{header, HeaderMap},
+    Form, Json,
+};
+use serde::de::DeserializeOwned;
+
+/// Request body accepted as JSON (the default) or, when the `Content-Type`
+/// says so, as `application/x-www-form-urlencoded` for `curl -d url=...`.
+pub struct JsonOrForm<T>(pub T);
+
+impl<S, T> FromRequest<S> for JsonOrForm<T>
+where
+    S: Send + Sync,
+    T: DeserializeOwned,
+{
+    type Rejection = AppError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let is_form = req
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|h| h.to_str().ok())
+            .is_some_and(|ct| ct.starts_with("application/x-www-form-urlencoded"));
+
+        if is_form {
+            let Form(value) = Form::<T>::from_request(req, state)
+                .await
+                .map_err(|rejection| AppError::body_rejection(rejection.status(), rejection.body_text()))?;
+            Ok(JsonOrForm(value))
+        } else {
+            let Json(value) = Json::<T>::from_request(req, state)
+                .await
+                .map_err(|rejection| AppError::body_rejection(rejection.status(), rejection.body_text()))?;
+            Ok(JsonOrForm(value))
+        }
+    }
+}
+
+/// Whether the `Accept` header ranks `text/plain` above JSON. A missing
+/// header or `*/*` (curl's default) keeps JSON.
+pub fn wants_plain_text(headers: &HeaderMap) -> bool {
+    prefers_over_json(headers, "text/plain")
+}
+
+/// Whether the `Accept` header ranks `text/html` above JSON, as browsers'
+/// navigation requests do.
+pub fn wants_html(headers: &HeaderMap) -> bool {
+    prefers_over_json(headers, "text/html")
+}
+
+/// Whether the request is a speculative fetch rather than a click: browser
+/// prefetch/prerender (`Sec-Purpose`, `Purpose`, `X-Moz`) or a link preview
+/// (`X-Purpose: preview`).
+pub fn is_prefetch(headers: &HeaderMap) -> bool {
+    ["sec-purpose", "purpose", "x-purpose", "x-moz"].iter().any(|name| {
+        headers
+            .get(*name)
+            .and_then(|h| h.to_str().ok())
+            .is_some_and(|value| {
+                // Sec-Purpose is a structured list such as `prefetch;prerender`
+                value
+                    .split([';', ','])
+                    .map(str::trim)
+                    .any(|token| ["prefetch", "prerender", "preview"].iter().any(|p| token.eq_ignore_ascii_case(p)))
+            })
+    })
+}
+
+// Compare the best q-value of a `text/...` type against JSON's, with wildcards
+// other than `text/*` counting for JSON.
+fn prefers_over_json(headers: &HeaderMap, text_media: &str) -> bool {
+    let Some(accept) = headers.get(header::ACCEPT).and_then(|h| h.to_str().ok()) else {
+        return false;
+    };
+
+    let mut text = 0.0_f32;
+    let mut json = 0.0_f32;
+    for range in accept.split(',') {
+        let mut parts = range.split(';').map(str::trim);
+        let media = parts.next().unwrap_or("").to_ascii_lowercase();
+        let q = parts
+            .find_map(|p| p.strip_prefix("q="))
+            .and_then(|q| q.parse::<f32>().ok())
+            .unwrap_or(1.0);
+
+        match media.as_str() {
+            m if m == text_media => text = text.max(q),
+            "application/json" => json = json.max(q),
+            "application/*" | "*/*" => json = json.max(q),
+            "text/*" => text = text.max(q),
+            _ => {}
+        }
+    }
+
+    text > json
+}
@@ -0,0 +1,47 @@
+//! Optional OpenTelemetry export of the `tracing` spans: requests, handlers
+//! and database calls. Nothing is set up unless `OTEL_EXPORTER_OTLP_ENDPOINT`
+//! is set, in which case spans are batched and sent over OTLP/HTTP. The
+//! exporter reads the other standard `OTEL_*` variables itself.
+
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry_otlp::ExporterBuildError;
+use opentelemetry_sdk::{
+    trace::{SdkTracer, SdkTracerProvider, SpanExporter},
+    Resource,
+};
+use tracing::Subscriber;
+use tracing_opentelemetry::OpenTelemetryLayer;
+use tracing_subscriber::registry::LookupSpan;
+
+const SERVICE_NAME: &str = env!("CARGO_PKG_NAME");
+
+/// The OTLP pipeline, or `None` when `OTEL_EXPORTER_OTLP_ENDPOINT` isn't set.
+pub fn provider_from_env() -> Result<Option<SdkTracerProvider>, ExporterBuildError> {
+    if std::env::var("OTEL_EXPORTER_OTLP_ENDPOINT").unwrap_or_default().trim().is_empty() {
+        return Ok(None);
+    }
+    let exporter = opentelemetry_otlp::SpanExporter::builder().with_http().build()?;
+    Ok(Some(provider(exporter)))
+}
+
+/// A provider batching spans into `exporter`. Call `shutdown` on it before
+/// exiting so buffered spans are flushed.
+pub fn provider(exporter: impl SpanExporter + 'static) -> SdkTracerProvider {
+    let mut resource = Resource::builder();
+    // OTEL_SERVICE_NAME, when set, is picked up by the builder and wins
+    if std::env::var_os("OTEL_SERVICE_NAME").is_none() {
+        resource = resource.with_service_name(SERVICE_NAME);
+    }
+    SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(resource.build())
+        .build()
+}
+
+/// Subscriber layer that turns `tracing` spans into OpenTelemetry spans.
+pub fn layer<S>(provider: &SdkTracerProvider) -> OpenTelemetryLayer<S, SdkTracer>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    tracing_opentelemetry::layer().with_tracer(provider.tracer(SERVICE_NAME))
+}
@@ -0,0 +1,96 @@
+use crate::error::AppError;
+use argon2::{
+    password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use axum::response::Html;
+use axum_extra::extract::{
+    cookie::{Cookie, SameSite},
+    SignedCookieJar,
+};
+use chrono::Utc;
+
+/// How long a successful unlock lets the same browser through without the form.
+const UNLOCK_TTL_SECS: i64 = 10 * 60;
+
+/// Hashes a link password with Argon2id and a random salt. CPU-heavy, so
+/// callers run it on the blocking pool.
+pub fn hash_password(password: &str) -> Result<String, AppError> {
+    let salt = SaltString::generate(&mut rand::rngs::OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|_| AppError::InternalServerError("Password hashing failed".into()))
+}
+
+pub fn verify_password(password: &str, hash: &str) -> bool {
+    PasswordHash::new(hash)
+        .map(|parsed| {
+            Argon2::default()
+                .verify_password(password.as_bytes(), &parsed)
+                .is_ok()
+        })
+        .unwrap_or(false)
+}
+
+fn cookie_name(code: &str) -> String {
+    format!("unlock_{}", code)
+}
+
+/// Signed session cookie remembering an unlock for this code. The value is the
+/// expiry timestamp; the signature stops clients from forging or extending it.
+pub fn unlock_cookie(code: &str) -> Cookie<'static> {
+    let expires = Utc::now().timestamp() + UNLOCK_TTL_SECS;
+    Cookie::build((cookie_name(code), expires.to_string()))
+        .path(format!("/{}", code))
+        .http_only(true)
+        .same_site(SameSite::Lax)
+        .build()
+}
+
+pub fn is_unlocked(jar: &SignedCookieJar, code: &str) -> bool {
+    jar.get(&cookie_name(code))
+        .and_then(|cookie| cookie.value().parse::<i64>().ok())
+        .is_some_and(|expires| Utc::now().timestamp() < expires)
+}
+
+pub fn escape_html(raw: &str) -> String {
+    raw.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+/// Minimal page asking for the link password; posts to `/{code}/unlock`.
+pub fn unlock_form(code: &str, error: Option<&str>) -> Html<String> {
+    let code = escape_html(code);
+    let error = error
+        .map(|msg| format!("<p class=\"error\">{}</p>", escape_html(msg)))
+        .unwrap_or_default();
+
+    Html(format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>Protected link</title>
+<style>
+body {{ font-family: system-ui, sans-serif; display: flex; justify-content: center; margin-top: 15vh; }}
+form {{ display: flex; flex-direction: column; gap: 0.75rem; width: 18rem; }}
+.error {{ color: #b00020; margin: 0; }}
+</style>
+</head>
+<body>
+<form method="post" action="/{code}/unlock">
+<h1>This link is password protected</h1>
+{error}
+<input type="password" name="password" placeholder="Password" required autofocus>
+<button type="submit">Continue</button>
+</form>
+</body>
+</html>
+"#
+    ))
+}
@@ -0,0 +1,137 @@
+use crate::{
+    audit::{self, Entry},
+    auth::AdminKey,
+    db::Traced,
+    error::AppError,
+    models::{EraseRequest, EraseResponse, ErrorResponse},
+    state::AppState,
+    utils::{anonymize_ip, visitor_hash},
+};
+use axum::{extract::State, Json};
+use serde_json::json;
+use sqlx::{QueryBuilder, Sqlite};
+use std::net::IpAddr;
+
+// The address as it may have been stored: as given and in its IPv4-mapped
+// IPv6 (or plain IPv4) spelling, depending on how the socket reported it.
+fn spellings(ip: IpAddr) -> Vec<String> {
+    let other = match ip {
+        IpAddr::V4(v4) => Some(IpAddr::V6(v4.to_ipv6_mapped())),
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4),
+    };
+    std::iter::once(ip).chain(other).map(|ip| ip.to_string()).collect()
+}
+
+#[utoipa::path(
+    post,
+    path = "/admin/privacy/erase",
+    request_body = EraseRequest,
+    responses(
+        (status = 200, description = "Visit details tied to the address cleared; counts per table", body = EraseResponse),
+        (status = 400, description = "Not an IP address", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid admin API key", body = ErrorResponse),
+        (status = 413, description = "Body over `MAX_REQUEST_BODY_BYTES`", body = ErrorResponse)
+    ),
+    security(
+        ("api_key" = [])
+    )
+)]
+#[tracing::instrument(skip_all)]
+pub async fn erase(
+    State(state): State<AppState>,
+    AdminKey(actor): AdminKey,
+    Json(payload): Json<EraseRequest>,
+) -> Result<Json<EraseResponse>, AppError> {
+    let ip: IpAddr = payload
+        .ip_address
+        .trim()
+        .parse()
+        .map_err(|_| AppError::BadRequest("ip_address must be an IPv4 or IPv6 address".into()))?;
+    let exact = spellings(ip);
+    // Anonymized mode stored the /24 or /48 network instead. Other visitors
+    // in it can't be told apart, so their rows are cleared as well.
+    let mut stored: Vec<String> = exact.iter().map(|ip| anonymize_ip(ip)).collect();
+    stored.extend(exact.iter().cloned());
+
+    let mut tx = state.pool.begin().await?;
+
+    // Visitor hashes mix in the user agent, so recompute them for every
+    // agent seen with this address. That also finds rows retention already
+    // stripped of their IP, and webhook payloads, which only carry the hash.
+    let mut agents_query: QueryBuilder<Sqlite> =
+        QueryBuilder::new("SELECT DISTINCT user_agent FROM visits WHERE ip_address IN (");
+    let mut list = agents_query.separated(", ");
+    for ip in &exact {
+        list.push_bind(ip);
+    }
+    agents_query.push(")");
+    let mut agents: Vec<Option<String>> = agents_query
+        .build_query_scalar()
+        .fetch_all(&mut *tx)
+        .traced("erase_find_agents", None)
+        .await?;
+    if !agents.contains(&None) {
+        agents.push(None);
+    }
+    let hashes: Vec<String> = exact
+        .iter()
+        .flat_map(|ip| {
+            agents
+                .iter()
+                .map(|agent| visitor_hash(&state.visitor_salt, ip, agent.as_deref()))
+        })
+        .collect();
+
+    let mut scrub: QueryBuilder<Sqlite> = QueryBuilder::new(
+        "UPDATE visits SET ip_address = NULL, user_agent = NULL, visitor_hash = NULL WHERE ip_address IN (",
+    );
+    let mut list = scrub.separated(", ");
+    for ip in &stored {
+        list.push_bind(ip);
+    }
+    scrub.push(") OR visitor_hash IN (");
+    let mut list = scrub.separated(", ");
+    for hash in &hashes {
+        list.push_bind(hash);
+    }
+    scrub.push(")");
+    let visits = scrub
+        .build()
+        .execute(&mut *tx)
+        .traced("erase_visits", None)
+        .await?
+        .rows_affected();
+
+    let mut failures: QueryBuilder<Sqlite> = QueryBuilder::new("DELETE FROM webhook_failures WHERE 0");
+    for hash in &hashes {
+        failures
+            .push(" OR payload LIKE ")
+            .push_bind(format!("%\"visitor_hash\":\"{}\"%", hash));
+    }
+    let webhook_failures = failures
+        .build()
+        .execute(&mut *tx)
+        .traced("erase_webhook_failures", None)
+        .await?
+        .rows_affected();
+
+    // The entry records that an erasure happened, not whose
+    audit::record(
+        &mut tx,
+        Entry {
+            actor: &actor,
+            action: "erase",
+            code: None,
+            before: None,
+            after: Some(json!({ "visits": visits, "webhook_failures": webhook_failures })),
+        },
+    )
+    .await?;
+    tx.commit().await?;
+
+    tracing::info!(actor, visits, webhook_failures, "erased visitor data");
+    Ok(Json(EraseResponse {
+        visits,
+        webhook_failures,
+    }))
+}
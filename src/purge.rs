@@ -0,0 +1,92 @@
+use crate::{cache::RedirectCache, db::{DbPool, Traced}, telemetry};
+use chrono::Utc;
+use sqlx::{QueryBuilder, Sqlite};
+use std::{sync::Arc, time::Duration};
+
+/// Links deleted per transaction, so a large backlog never holds the SQLite
+/// write lock for long.
+const PURGE_BATCH_SIZE: i64 = 500;
+
+pub struct PurgeConfig {
+    pub interval: Duration,
+    /// How long after `expires_at` a link is kept before it's deleted.
+    pub grace: Duration,
+}
+
+/// Spawns the task that periodically deletes expired links and their visits.
+pub fn spawn_purger(pool: DbPool, cache: Option<Arc<RedirectCache>>, config: PurgeConfig) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(config.interval);
+        loop {
+            interval.tick().await;
+            match purge_expired(&pool, cache.as_deref(), config.grace).await {
+                Ok(0) => tracing::debug!("no expired links to purge"),
+                Ok(purged) => tracing::info!(purged, "purged expired links"),
+                Err(e) => tracing::error!("failed to purge expired links: {:?}", e),
+            }
+        }
+    });
+}
+
+/// Deletes links that expired more than `grace` ago, in batches, and returns
+/// how many were removed.
+pub async fn purge_expired(
+    pool: &DbPool,
+    cache: Option<&RedirectCache>,
+    grace: Duration,
+) -> Result<u64, sqlx::Error> {
+    let grace = chrono::Duration::from_std(grace).unwrap_or(chrono::Duration::MAX);
+    let cutoff = Utc::now().checked_sub_signed(grace).unwrap_or(chrono::DateTime::<Utc>::MIN_UTC);
+    let mut purged = 0;
+
+    loop {
+        let mut tx = pool.begin().await?;
+
+        let ids: Vec<String> = sqlx::query_scalar!(
+            r#"SELECT id AS "id!" FROM urls WHERE expires_at IS NOT NULL AND expires_at < ? LIMIT ?"#,
+            cutoff,
+            PURGE_BATCH_SIZE
+        )
+        .fetch_all(&mut *tx)
+        .traced("find_expired_links", None)
+        .await?;
+        if ids.is_empty() {
+            break;
+        }
+
+        for table_and_column in [
+            "visits WHERE url_id",
+            "url_tags WHERE url_id",
+            "url_destinations WHERE url_id",
+            "destination_checks WHERE url_id",
+            "urls WHERE id",
+        ] {
+            let mut query: QueryBuilder<Sqlite> =
+                QueryBuilder::new(format!("DELETE FROM {} IN (", table_and_column));
+            let mut separated = query.separated(", ");
+            for id in &ids {
+                separated.push_bind(id);
+            }
+            separated.push_unseparated(")");
+            query.build().execute(&mut *tx).traced("purge_expired_links", None).await?;
+        }
+
+        tx.commit().await?;
+
+        if let Some(cache) = cache {
+            for id in &ids {
+                cache.invalidate(id);
+            }
+        }
+        purged += ids.len() as u64;
+        metrics::counter!(telemetry::EXPIRED_LINKS_PURGED_TOTAL).increment(ids.len() as u64);
+
+        if (ids.len() as i64) < PURGE_BATCH_SIZE {
+            break;
+        }
+        // Let queued writers (visits, new links) in between batches
+        tokio::task::yield_now().await;
+    }
+
+    Ok(purged)
+}
@@ -0,0 +1,100 @@
+use crate::{client_ip::ClientIp, error::AppError, negotiate::wants_plain_text, state::AppState};
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::Response,
+};
+use governor::{clock::Clock, DefaultKeyedRateLimiter, Quota};
+use std::{num::NonZeroU32, sync::Arc, time::Duration};
+
+pub type IpRateLimiter = DefaultKeyedRateLimiter<String>;
+
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Parses a limit such as `10/60s`, `100/1m` or `1000/1h` into a quota that
+/// allows a burst of N requests, refilled evenly over the period.
+pub fn parse_quota(raw: &str) -> Option<Quota> {
+    let (count, period) = raw.trim().split_once('/')?;
+    let count: NonZeroU32 = count.trim().parse().ok()?;
+
+    let period = period.trim();
+    let (value, unit_secs) = match period.chars().last()? {
+        's' => (&period[..period.len() - 1], 1),
+        'm' => (&period[..period.len() - 1], 60),
+        'h' => (&period[..period.len() - 1], 3600),
+        _ => (period, 1),
+    };
+    let value: u64 = if value.is_empty() { 1 } else { value.parse().ok()? };
+    let period = Duration::from_secs(value.checked_mul(unit_secs)?);
+
+    Quota::with_period(period / count.get()).map(|q| q.allow_burst(count))
+}
+
+/// Builds the limiter and spawns a task that periodically drops buckets that
+/// have fully refilled, so idle clients don't accumulate in memory.
+pub fn build_limiter(quota: Quota) -> Arc<IpRateLimiter> {
+    let limiter = Arc::new(IpRateLimiter::keyed(quota));
+
+    let cleanup = limiter.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(CLEANUP_INTERVAL);
+        loop {
+            interval.tick().await;
+            cleanup.retain_recent();
+            cleanup.shrink_to_fit();
+        }
+    });
+
+    limiter
+}
+
+fn check(limiter: &IpRateLimiter, ip: &String) -> Result<(), AppError> {
+    limiter.check_key(ip).map_err(|not_until| {
+        let wait = not_until.wait_time_from(governor::clock::DefaultClock::default().now());
+        AppError::RateLimited(wait.as_secs().max(1))
+    })
+}
+
+/// Middleware enforcing the per-IP limit on link creation.
+pub async fn limit_shorten(
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    if let Some(limiter) = &state.shorten_limiter
+        && let Err(err) = check(limiter, &ip)
+    {
+        if wants_plain_text(request.headers()) {
+            return Ok(err.into_plain_response());
+        }
+        return Err(err);
+    }
+
+    Ok(next.run(request).await)
+}
+
+/// Middleware enforcing the per-IP limit on `/hit/{code}`, so stats can't be
+/// stuffed by replaying beacons.
+pub async fn limit_hits(
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    check(&state.hit_limiter, &ip)?;
+    Ok(next.run(request).await)
+}
+
+/// Middleware enforcing the per-IP limit on password attempts, so passwords
+/// can't be guessed at speed and guesses can't tie up the blocking threads
+/// that verify them.
+pub async fn limit_unlocks(
+    State(state): State<AppState>,
+    ClientIp(ip): ClientIp,
+    request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    check(&state.unlock_limiter, &ip)?;
+    Ok(next.run(request).await)
+}
@@ -0,0 +1,59 @@
+//! Per-request ids, so a failure a user reports can be found in the logs.
+//! `SetRequestIdLayer` assigns one (keeping an incoming `x-request-id`),
+//! the trace span records it, and JSON errors repeat it as `request_id`.
+
+use axum::{
+    extract::{MatchedPath, Request},
+    http::HeaderName,
+    middleware::Next,
+    response::Response,
+};
+use tracing::{field, Span};
+
+pub const X_REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+tokio::task_local! {
+    static REQUEST_ID: String;
+}
+
+/// Id of the request being handled, if it came through the service stack.
+pub fn current() -> Option<String> {
+    REQUEST_ID.try_with(String::clone).ok()
+}
+
+fn header_value(request: &Request) -> &str {
+    request
+        .headers()
+        .get(X_REQUEST_ID)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+}
+
+/// Span for `TraceLayer`, at `info` so the id tags every log line of the
+/// request under the default `RUST_LOG`. Only the path is recorded: query
+/// strings carry stats and delete tokens, and client IPs are left out so
+/// logs hold no more than `ANALYTICS_MODE` allows to be stored.
+/// Exported spans are named after the route template, e.g. `GET /{code}`.
+pub fn make_span(request: &Request) -> Span {
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or("unmatched", MatchedPath::as_str);
+    tracing::info_span!(
+        "request",
+        method = %request.method(),
+        path = request.uri().path(),
+        request_id = header_value(request),
+        otel.name = format!("{} {}", request.method(), route),
+        otel.kind = "server",
+        http.route = route,
+        http.response.status_code = field::Empty,
+        otel.status_code = field::Empty,
+    )
+}
+
+/// Makes the id available to `current` while the rest of the stack runs.
+pub async fn scope(request: Request, next: Next) -> Response {
+    let id = header_value(&request).to_string();
+    REQUEST_ID.scope(id, next.run(request)).await
+}
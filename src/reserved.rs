@@ -0,0 +1,49 @@
+use std::{collections::HashSet, path::Path};
+
+/// First path segments of every route registered in `main.rs`. A custom code
+/// equal to one of these would be shadowed by (or shadow) the real route.
+pub const ROUTE_SEGMENTS: &[&str] = &[
+    "shorten",
+    "urls",
+    "info",
+    "stats",
+    "qr",
+    "hit",
+    "admin",
+    "swagger-ui",
+    "api-docs",
+    "healthz",
+    "readyz",
+    "metrics",
+    "robots.txt",
+];
+
+/// Builds the case-insensitive reserved-code set from the registered routes,
+/// the files served from `static_dir` and an optional comma-separated list of
+/// extra words (the `RESERVED_CODES` env var).
+pub fn reserved_codes(static_dir: &Path, extra: Option<&str>) -> HashSet<String> {
+    let mut codes: HashSet<String> = ROUTE_SEGMENTS.iter().map(|s| s.to_string()).collect();
+
+    if let Ok(entries) = std::fs::read_dir(static_dir) {
+        for entry in entries.flatten() {
+            if let Some(name) = entry.file_name().to_str() {
+                codes.insert(name.to_lowercase());
+            }
+        }
+    }
+
+    if let Some(extra) = extra {
+        codes.extend(
+            extra
+                .split(',')
+                .map(|s| s.trim().to_lowercase())
+                .filter(|s| !s.is_empty()),
+        );
+    }
+
+    codes
+}
+
+pub fn is_reserved(codes: &HashSet<String>, code: &str) -> bool {
+    codes.contains(&code.to_lowercase())
+}
@@ -0,0 +1,58 @@
+use crate::{db::{DbPool, Traced}, telemetry};
+use chrono::Utc;
+use std::time::Duration;
+
+/// Visits scrubbed per statement, so the SQLite write lock is released often.
+const SCRUB_BATCH_SIZE: i64 = 1000;
+
+pub struct RetentionConfig {
+    pub interval: Duration,
+    /// Age after which a visit loses its IP address and user agent.
+    pub retention: Duration,
+}
+
+/// Spawns the task that periodically scrubs personal data from old visits.
+pub fn spawn_scrubber(pool: DbPool, config: RetentionConfig) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(config.interval);
+        loop {
+            interval.tick().await;
+            match scrub_visits(&pool, config.retention).await {
+                Ok(0) => tracing::debug!("no visits to scrub"),
+                Ok(scrubbed) => tracing::info!(scrubbed, "scrubbed IP addresses and user agents from old visits"),
+                Err(e) => tracing::error!("failed to scrub old visits: {:?}", e),
+            }
+        }
+    });
+}
+
+/// Clears `ip_address` and `user_agent` on visits older than `retention`,
+/// in batches, and returns how many were scrubbed. The rows stay, so visit
+/// counts are unaffected.
+pub async fn scrub_visits(pool: &DbPool, retention: Duration) -> Result<u64, sqlx::Error> {
+    let retention = chrono::Duration::from_std(retention).unwrap_or(chrono::Duration::MAX);
+    let cutoff = Utc::now().checked_sub_signed(retention).unwrap_or(chrono::DateTime::<Utc>::MIN_UTC);
+    let mut scrubbed = 0;
+
+    loop {
+        let result = sqlx::query!(
+            "UPDATE visits SET ip_address = NULL, user_agent = NULL WHERE id IN (SELECT id FROM visits WHERE (ip_address IS NOT NULL OR user_agent IS NOT NULL) AND visited_at < ? LIMIT ?)",
+            cutoff,
+            SCRUB_BATCH_SIZE
+        )
+        .execute(pool)
+        .traced("scrub_visits", None)
+        .await?;
+        let rows = result.rows_affected();
+        scrubbed += rows;
+        metrics::counter!(telemetry::VISITS_SCRUBBED_TOTAL).increment(rows);
+
+        if (rows as i64) < SCRUB_BATCH_SIZE {
+            break;
+        }
+        // Let queued writers in between batches
+        tokio::task::yield_now().await;
+    }
+
+    Ok(scrubbed)
+}
@@ -0,0 +1,81 @@
+use crate::{
+    db::Traced,
+    models::{Rotation, WeightedDestination},
+};
+use rand::Rng;
+use sha2::{Digest, Sha256};
+use sqlx::{QueryBuilder, Sqlite, SqliteConnection, SqliteExecutor};
+
+pub async fn insert_destinations(
+    conn: &mut SqliteConnection,
+    url_id: &str,
+    destinations: &[WeightedDestination],
+) -> Result<(), sqlx::Error> {
+    if destinations.is_empty() {
+        return Ok(());
+    }
+
+    let mut query: QueryBuilder<Sqlite> =
+        QueryBuilder::new("INSERT INTO url_destinations (url_id, position, url, weight) ");
+    query.push_values(destinations.iter().enumerate(), |mut row, (position, destination)| {
+        row.push_bind(url_id)
+            .push_bind(position as i64)
+            .push_bind(&destination.url)
+            .push_bind(i64::from(destination.weight));
+    });
+    query.build().execute(conn).traced("insert_destinations", Some(url_id)).await?;
+    Ok(())
+}
+
+/// Destinations of a rotating link, in the order they were given.
+pub async fn load_destinations<'e>(
+    executor: impl SqliteExecutor<'e>,
+    url_id: &str,
+) -> Result<Vec<WeightedDestination>, sqlx::Error> {
+    sqlx::query_as!(
+        WeightedDestination,
+        r#"SELECT url, weight AS "weight: u32" FROM url_destinations WHERE url_id = ? ORDER BY position"#,
+        url_id
+    )
+    .fetch_all(executor)
+    .traced("load_destinations", Some(url_id))
+    .await
+}
+
+/// Picks a destination with probability proportional to its weight. Sticky
+/// links derive the choice from the visitor hash, so a returning visitor
+/// lands on the same destination as long as the weights don't change.
+pub fn pick<'a>(
+    destinations: &'a [WeightedDestination],
+    rotation: Rotation,
+    url_id: &str,
+    visitor_hash: &str,
+) -> Option<&'a WeightedDestination> {
+    let total: u64 = destinations.iter().map(|d| u64::from(d.weight)).sum();
+    if total == 0 {
+        return None;
+    }
+
+    let mut point = match rotation {
+        Rotation::Random => rand::thread_rng().gen_range(0..total),
+        // Mixing in the code keeps a visitor's bucket independent across links
+        Rotation::Sticky => {
+            let digest = Sha256::new()
+                .chain_update(url_id)
+                .chain_update([0])
+                .chain_update(visitor_hash)
+                .finalize();
+            let mut bytes = [0u8; 8];
+            bytes.copy_from_slice(&digest[..8]);
+            u64::from_be_bytes(bytes) % total
+        }
+    };
+    for destination in destinations {
+        let weight = u64::from(destination.weight);
+        if point < weight {
+            return Some(destination);
+        }
+        point -= weight;
+    }
+    None
+}
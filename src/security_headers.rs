@@ -0,0 +1,76 @@
+//! Security headers added to every response: `X-Content-Type-Options`,
+//! `X-Frame-Options` (except on routes meant to be embedded, like QR codes),
+//! `Strict-Transport-Security` when `BASE_URL` is https, and a
+//! `Content-Security-Policy` on HTML pages.
+
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::{header, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+use std::sync::Arc;
+
+/// Allows the inline scripts and styles of the pages in `static/`, and
+/// nothing from other origins.
+pub const DEFAULT_CSP: &str = "default-src 'self'; script-src 'self' 'unsafe-inline'; style-src 'self' 'unsafe-inline'; img-src 'self' data:; object-src 'none'; base-uri 'self'; frame-ancestors 'none'";
+pub const DEFAULT_HSTS: &str = "max-age=31536000; includeSubDomains";
+/// Route templates that may be framed by default
+pub const DEFAULT_EMBEDDABLE_ROUTES: &[&str] = &["/qr/{code}"];
+
+// Swagger UI ships its own scripts and styles
+const CSP_EXEMPT_PREFIX: &str = "/swagger-ui";
+
+#[derive(Debug, Clone)]
+pub struct SecurityHeaders {
+    /// `Strict-Transport-Security`; `None` unless the service is served over https
+    pub hsts: Option<HeaderValue>,
+    /// `X-Frame-Options`; `None` sends none at all
+    pub frame_options: Option<HeaderValue>,
+    /// Route templates, e.g. `/qr/{code}`, sent without `X-Frame-Options`
+    pub embeddable_routes: Vec<String>,
+    /// `Content-Security-Policy` for `text/html` responses
+    pub content_security_policy: HeaderValue,
+}
+
+/// Parses `X_FRAME_OPTIONS`: `deny`, `sameorigin` or `off`.
+pub fn parse_frame_options(raw: &str) -> Option<Option<HeaderValue>> {
+    match raw.to_ascii_lowercase().as_str() {
+        "deny" => Some(Some(HeaderValue::from_static("DENY"))),
+        "sameorigin" => Some(Some(HeaderValue::from_static("SAMEORIGIN"))),
+        "off" => Some(None),
+        _ => None,
+    }
+}
+
+/// Middleware setting the headers. Values a handler already set are kept.
+pub async fn apply(State(policy): State<Arc<SecurityHeaders>>, request: Request, next: Next) -> Response {
+    let embeddable = request
+        .extensions()
+        .get::<MatchedPath>()
+        .is_some_and(|path| policy.embeddable_routes.iter().any(|route| route == path.as_str()));
+    let csp_exempt = request.uri().path().starts_with(CSP_EXEMPT_PREFIX);
+
+    let mut response = next.run(request).await;
+    let is_html = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("text/html"));
+    let headers = response.headers_mut();
+    headers
+        .entry(header::X_CONTENT_TYPE_OPTIONS)
+        .or_insert(HeaderValue::from_static("nosniff"));
+    if let (Some(frame_options), false) = (&policy.frame_options, embeddable) {
+        headers.entry(header::X_FRAME_OPTIONS).or_insert(frame_options.clone());
+    }
+    if let Some(hsts) = &policy.hsts {
+        headers.entry(header::STRICT_TRANSPORT_SECURITY).or_insert(hsts.clone());
+    }
+    if is_html && !csp_exempt {
+        headers
+            .entry(header::CONTENT_SECURITY_POLICY)
+            .or_insert(policy.content_security_policy.clone());
+    }
+    response
+}
@@ -0,0 +1,93 @@
+//! Sample links and visits for `--dev` mode, so the API and the stats pages
+//! have something to show with zero setup. The test harness seeds with it too.
+
+use crate::{
+    analytics,
+    db::Traced,
+    state::AppState,
+    utils::{detect_platform, is_bot, normalize_url, visitor_hash},
+    visits::{NewVisit, VisitSource},
+};
+use chrono::{Duration, Utc};
+
+/// Short code, destination and title of each sample link. Their stats are
+/// public, so no stats token is needed.
+pub const LINKS: &[(&str, &str, &str)] = &[
+    ("rust", "https://www.rust-lang.org/", "Rust Programming Language"),
+    ("docs", "https://docs.rs/", "Docs.rs"),
+    ("crates", "https://crates.io/", "crates.io: Rust Package Registry"),
+    ("axum", "https://github.com/tokio-rs/axum", "tokio-rs/axum"),
+    ("sqlite", "https://www.sqlite.org/", "SQLite Home Page"),
+];
+
+/// Visits on the first link; each one after gets 12 fewer.
+pub const MAX_VISITS: usize = 60;
+
+const USER_AGENTS: &[&str] = &[
+    "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/119.0.0.0 Safari/537.36",
+    "Mozilla/5.0 (iPhone; CPU iPhone OS 17_1 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.1 Mobile/15E148 Safari/604.1",
+    "Mozilla/5.0 (Linux; Android 14; Pixel 8) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/119.0.0.0 Mobile Safari/537.36",
+    "Mozilla/5.0 (X11; Linux x86_64; rv:120.0) Gecko/20100101 Firefox/120.0",
+    "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.1 Safari/605.1.15",
+    "Twitterbot/1.0",
+];
+
+const REFERRERS: &[Option<&str>] = &[
+    None,
+    Some("https://news.ycombinator.com/"),
+    Some("https://www.reddit.com/r/rust/"),
+    Some("https://www.google.com/"),
+];
+
+const COUNTRIES: &[&str] = &["US", "DE", "JP", "BR", "IN", "GB", "US"];
+
+/// Inserts the sample links, created a month ago, with visits spread over
+/// the last two weeks. The visits are written like real ones, so the links'
+/// counters match. Returns how many visits were stored.
+pub async fn run(state: &AppState) -> Result<u64, sqlx::Error> {
+    let now = Utc::now();
+    let created_at = now - Duration::days(30);
+    let mut tx = state.pool.begin().await?;
+    for (code, url, title) in LINKS {
+        let normalized = normalize_url(url);
+        sqlx::query!(
+            "INSERT INTO urls (id, original_url, normalized_url, created_at, title, stats_visibility) VALUES (?, ?, ?, ?, ?, 'public')",
+            code,
+            url,
+            normalized,
+            created_at,
+            title
+        )
+        .execute(&mut *tx)
+        .traced("seed_url", Some(code))
+        .await?;
+    }
+    tx.commit().await?;
+
+    // Spread deterministically, so every run (and test) sees the same stats
+    let mut pending = Vec::new();
+    for (link, (code, _, _)) in LINKS.iter().enumerate() {
+        for n in 0..MAX_VISITS - link * 12 {
+            let user_agent = USER_AGENTS[(n + link) % USER_AGENTS.len()];
+            let ip = format!("198.51.100.{}", (n * 7 + link) % 50 + 1);
+            let age = Duration::days(((n * 5 + link) % 14) as i64) + Duration::minutes((n * 97 % 1440) as i64);
+            pending.push(NewVisit {
+                url_id: code.to_string(),
+                visitor_hash: visitor_hash(&state.visitor_salt, &ip, Some(user_agent)),
+                ip_address: ip,
+                user_agent: Some(user_agent.to_string()),
+                referrer: REFERRERS[n % REFERRERS.len()].map(String::from),
+                source: VisitSource::Redirect,
+                destination: None,
+                platform: detect_platform(Some(user_agent)),
+                visited_at: now - age,
+                country: Some(COUNTRIES[(n + link * 3) % COUNTRIES.len()].to_string()),
+                is_bot: is_bot(Some(user_agent), &state.bot_patterns),
+                detailed: true,
+            });
+        }
+    }
+    let total = pending.len() as u64;
+    let dropped = analytics::write_visits(&state.pool, &mut pending).await;
+    Ok(total - dropped)
+}
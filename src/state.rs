@@ -0,0 +1,76 @@
+use crate::{
+    blocklist::Blocklist, cache::RedirectCache, client_ip::IpNet, config::Config, db::DbPool, denylist::CodeFilter,
+    destination::DestinationPolicy, link_check::LinkChecker, metadata::MetadataFetcher, rate_limit::IpRateLimiter,
+    visits::VisitRecorder, webhooks::WebhookDispatcher,
+};
+use axum::{extract::FromRef, http::HeaderValue};
+use axum_extra::extract::cookie::Key;
+use metrics_exporter_prometheus::PrometheusHandle;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
+/// Shared state handed to every handler. Each field is a handle (`Arc`,
+/// pool, channel sender), so cloning it per request is cheap.
+#[derive(Clone)]
+pub struct AppState {
+    pub pool: DbPool,
+    /// Settings validated at startup. Values derived from it (key sets,
+    /// limiters, parsed policies) have their own fields below.
+    pub config: Arc<Config>,
+    /// Accepted `X-Api-Key` values, each with its namespace if it has one.
+    /// `None` disables API-key authentication.
+    pub api_keys: Option<Arc<HashMap<String, Option<String>>>>,
+    /// `X-Api-Key` values accepted by `/admin` endpoints. Empty disables them.
+    pub admin_keys: Arc<HashSet<String>>,
+    /// Per-IP limiter for `POST /shorten`. `None` disables rate limiting.
+    pub shorten_limiter: Option<Arc<IpRateLimiter>>,
+    /// Per-IP limiter for `/hit/{code}` beacons. Always on.
+    pub hit_limiter: Arc<IpRateLimiter>,
+    /// Per-IP limiter for password attempts on `/{code}/unlock`. Always on.
+    pub unlock_limiter: Arc<IpRateLimiter>,
+    /// Where browsers are sent for unknown codes instead of the HTML 404 page.
+    pub fallback_url: Option<HeaderValue>,
+    /// Cache of link records used by redirects. `None` disables caching.
+    pub redirect_cache: Option<Arc<RedirectCache>>,
+    /// Lowercased words that can't be used as custom codes.
+    pub reserved_codes: Arc<HashSet<String>>,
+    /// Denylist that generated codes must not match.
+    pub code_filter: Arc<CodeFilter>,
+    /// Lowercased `BOT_USER_AGENTS` patterns, on top of the bundled ones.
+    pub bot_patterns: Arc<Vec<String>>,
+    /// Private-network destinations that may still be shortened.
+    pub destinations: Arc<DestinationPolicy>,
+    /// Flagged domains: refused for new links, behind an interstitial for
+    /// existing ones.
+    pub blocklist: Arc<Blocklist>,
+    /// Probes destinations of links created with `"verify": true`.
+    pub link_verifier: Arc<LinkChecker>,
+    /// Proxies whose `X-Forwarded-For` header is trusted.
+    pub trusted_proxies: Arc<Vec<IpNet>>,
+    /// Queue of destination pages to scrape for titles.
+    pub metadata: MetadataFetcher,
+    /// Queue feeding the background visit writer.
+    pub visits: VisitRecorder,
+    /// Queue of signed visit notifications for links with a `webhook_url`.
+    pub webhooks: WebhookDispatcher,
+    /// Secret mixed into visitor hashes so they can't be reversed to an IP.
+    pub visitor_salt: Arc<str>,
+    /// Renders the Prometheus scrape payload for `/metrics`.
+    pub metrics: PrometheusHandle,
+    /// Signs the cookies that remember unlocked password-protected links.
+    pub cookie_key: Key,
+}
+
+impl FromRef<AppState> for DbPool {
+    fn from_ref(state: &AppState) -> Self {
+        state.pool.clone()
+    }
+}
+
+impl FromRef<AppState> for Key {
+    fn from_ref(state: &AppState) -> Self {
+        state.cookie_key.clone()
+    }
+}
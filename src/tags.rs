@@ -0,0 +1,57 @@
+use crate::db::Traced;
+use sqlx::{QueryBuilder, Sqlite, SqliteConnection, SqliteExecutor};
+use std::collections::{BTreeSet, HashMap};
+
+/// Sorted, de-duplicated copy of the requested tags.
+pub fn normalize(tags: &[String]) -> Vec<String> {
+    tags.iter().cloned().collect::<BTreeSet<_>>().into_iter().collect()
+}
+
+pub async fn insert_tags(
+    conn: &mut SqliteConnection,
+    url_id: &str,
+    tags: &[String],
+) -> Result<(), sqlx::Error> {
+    if tags.is_empty() {
+        return Ok(());
+    }
+
+    let mut query: QueryBuilder<Sqlite> = QueryBuilder::new("INSERT OR IGNORE INTO url_tags (url_id, tag) ");
+    query.push_values(tags, |mut row, tag| {
+        row.push_bind(url_id).push_bind(tag);
+    });
+    query.build().execute(conn).traced("insert_tags", Some(url_id)).await?;
+    Ok(())
+}
+
+/// Tags of one link, sorted.
+pub async fn load_tags<'e>(executor: impl SqliteExecutor<'e>, url_id: &str) -> Result<Vec<String>, sqlx::Error> {
+    sqlx::query_scalar!("SELECT tag FROM url_tags WHERE url_id = ? ORDER BY tag", url_id)
+        .fetch_all(executor)
+        .traced("load_tags", Some(url_id))
+        .await
+}
+
+/// Tags of several links at once, keyed by link id. Links without tags are absent.
+pub async fn load_tags_for<'e>(
+    executor: impl SqliteExecutor<'e>,
+    url_ids: &[String],
+) -> Result<HashMap<String, Vec<String>>, sqlx::Error> {
+    if url_ids.is_empty() {
+        return Ok(HashMap::new());
+    }
+
+    let mut query: QueryBuilder<Sqlite> = QueryBuilder::new("SELECT url_id, tag FROM url_tags WHERE url_id IN (");
+    let mut separated = query.separated(", ");
+    for id in url_ids {
+        separated.push_bind(id);
+    }
+    separated.push_unseparated(") ORDER BY url_id, tag");
+
+    let rows: Vec<(String, String)> = query.build_query_as().fetch_all(executor).traced("load_tags_for_urls", None).await?;
+    let mut by_url: HashMap<String, Vec<String>> = HashMap::new();
+    for (url_id, tag) in rows {
+        by_url.entry(url_id).or_default().push(tag);
+    }
+    Ok(by_url)
+}
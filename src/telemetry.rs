@@ -0,0 +1,136 @@
+use crate::state::AppState;
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::header,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+use std::{
+    sync::OnceLock,
+    time::{Duration, Instant},
+};
+use tracing::Span;
+
+pub const REDIRECTS_TOTAL: &str = "redirects_total";
+pub const REDIRECT_PREFETCHES_TOTAL: &str = "redirect_prefetches_total";
+pub const REDIRECT_NOT_FOUND_TOTAL: &str = "redirect_not_found_total";
+pub const BEACON_HITS_TOTAL: &str = "beacon_hits_total";
+pub const LINKS_CREATED_TOTAL: &str = "links_created_total";
+pub const QR_CODES_RENDERED_TOTAL: &str = "qr_codes_rendered_total";
+pub const EXPIRED_LINKS_PURGED_TOTAL: &str = "expired_links_purged_total";
+pub const VISITS_SCRUBBED_TOTAL: &str = "visits_scrubbed_total";
+pub const VISITS_DROPPED_TOTAL: &str = "visits_dropped_total";
+const REQUEST_DURATION: &str = "http_request_duration_seconds";
+
+// Tuned for a service whose redirects usually answer from cache in well under 10ms
+const LATENCY_BUCKETS: &[f64] = &[
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5,
+];
+const UPKEEP_INTERVAL: Duration = Duration::from_secs(5);
+
+static RECORDER: OnceLock<PrometheusHandle> = OnceLock::new();
+
+/// How log lines are written, from `LOG_FORMAT`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Human-readable lines
+    #[default]
+    Pretty,
+    /// One JSON object per line, for log aggregators
+    Json,
+}
+
+impl LogFormat {
+    pub fn parse(raw: &str) -> Option<Self> {
+        match raw.to_ascii_lowercase().as_str() {
+            "pretty" => Some(LogFormat::Pretty),
+            "json" => Some(LogFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+/// `TraceLayer` response log, which also fills in the request span's status.
+/// Redirects are the bulk of the traffic and log at `debug`, client errors at
+/// `warn`, everything else at `info`. Server errors are left to `on_failure`,
+/// which logs them at `error`.
+pub fn log_response(response: &Response, latency: Duration, span: &Span) {
+    let status = response.status().as_u16();
+    span.record("http.response.status_code", i64::from(status));
+    if status >= 500 {
+        span.record("otel.status_code", "ERROR");
+    }
+    let latency_ms = latency.as_millis() as u64;
+    match status {
+        300..=399 => tracing::debug!(status, latency_ms, "redirected"),
+        400..=499 => tracing::warn!(status, latency_ms, "client error"),
+        500..=599 => {}
+        _ => tracing::info!(status, latency_ms, "finished"),
+    }
+}
+
+/// Installs the global Prometheus recorder and spawns the task that drains
+/// histogram samples between scrapes. The recorder is process-wide, so later
+/// calls (each test builds its own app) get the same handle back.
+pub fn install_recorder() -> PrometheusHandle {
+    RECORDER
+        .get_or_init(|| {
+            let handle = PrometheusBuilder::new()
+                .set_buckets_for_metric(Matcher::Full(REQUEST_DURATION.to_string()), LATENCY_BUCKETS)
+                .expect("latency buckets are non-empty")
+                .install_recorder()
+                .expect("metrics recorder can only be installed once");
+
+            let upkeep = handle.clone();
+            tokio::spawn(async move {
+                let mut interval = tokio::time::interval(UPKEEP_INTERVAL);
+                loop {
+                    interval.tick().await;
+                    upkeep.run_upkeep();
+                }
+            });
+
+            handle
+        })
+        .clone()
+}
+
+/// Middleware recording request latency per route. The label is the route
+/// template (`/{code}`, not `/abc123`) so short codes can't blow up cardinality.
+pub async fn track_latency(request: Request, next: Next) -> Response {
+    let method = request.method().to_string();
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| "static".to_string());
+
+    let start = Instant::now();
+    let response = next.run(request).await;
+
+    metrics::histogram!(
+        REQUEST_DURATION,
+        "method" => method,
+        "route" => route,
+        "status" => response.status().as_u16().to_string(),
+    )
+    .record(start.elapsed().as_secs_f64());
+
+    response
+}
+
+/// Prometheus scrape endpoint.
+pub async fn render_metrics(State(state): State<AppState>) -> impl IntoResponse {
+    // Pool figures are sampled at scrape time rather than tracked per query
+    let size = state.pool.size();
+    let idle = state.pool.num_idle() as u32;
+    metrics::gauge!("db_pool_connections").set(size);
+    metrics::gauge!("db_pool_idle_connections").set(idle);
+    metrics::gauge!("db_pool_in_use_connections").set(size.saturating_sub(idle));
+
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        state.metrics.render(),
+    )
+}
@@ -0,0 +1,44 @@
+//! Listening on a Unix domain socket (`LISTEN_SOCKET`) instead of TCP, for a
+//! reverse proxy on the same host. Connections carry no peer address, so
+//! client IPs come from `X-Forwarded-For`; `LISTEN_SOCKET_MODE` should keep
+//! the socket to the proxy.
+
+use std::{
+    fs, io,
+    os::unix::fs::{FileTypeExt, PermissionsExt},
+    path::{Path, PathBuf},
+};
+use tokio::net::{UnixListener, UnixStream};
+
+/// The socket file, removed when dropped.
+#[derive(Debug)]
+pub struct SocketFile(PathBuf);
+
+impl Drop for SocketFile {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.0) {
+            tracing::warn!("failed to remove {}: {}", self.0.display(), e);
+        }
+    }
+}
+
+/// Binds `path` with permissions `mode`. A socket left behind by a run that
+/// didn't shut down cleanly is replaced; one something still listens on, or
+/// a file that isn't a socket, is an error.
+pub async fn bind(path: &Path, mode: u32) -> io::Result<(UnixListener, SocketFile)> {
+    match fs::symlink_metadata(path) {
+        Ok(meta) if meta.file_type().is_socket() => {
+            if UnixStream::connect(path).await.is_ok() {
+                return Err(io::Error::new(io::ErrorKind::AddrInUse, "another process is listening on it"));
+            }
+            fs::remove_file(path)?;
+        }
+        Ok(_) => return Err(io::Error::new(io::ErrorKind::AlreadyExists, "the path exists and isn't a socket")),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+    let listener = UnixListener::bind(path)?;
+    let file = SocketFile(path.to_path_buf());
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+    Ok((listener, file))
+}
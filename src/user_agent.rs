@@ -0,0 +1,86 @@
+use crate::models::NamedCount;
+use std::collections::HashMap;
+use woothee::parser::Parser;
+
+const UNKNOWN: &str = "Unknown";
+
+/// Visit counts grouped by browser, operating system and device type.
+pub struct UserAgentBreakdown {
+    pub browsers: Vec<NamedCount>,
+    pub operating_systems: Vec<NamedCount>,
+    pub device_types: Vec<NamedCount>,
+}
+
+// woothee reports "UNKNOWN" for anything it can't classify
+fn label(value: &str) -> String {
+    if value.is_empty() || value == woothee::woothee::VALUE_UNKNOWN {
+        UNKNOWN.to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+fn device_type(category: &str) -> &'static str {
+    match category {
+        "pc" => "Desktop",
+        "smartphone" | "mobilephone" => "Mobile",
+        "crawler" => "Bot",
+        "appliance" => "Appliance",
+        "misc" => "Other",
+        _ => UNKNOWN,
+    }
+}
+
+fn sorted(counts: HashMap<String, i64>) -> Vec<NamedCount> {
+    let mut counts: Vec<NamedCount> = counts
+        .into_iter()
+        .map(|(name, count)| NamedCount { name, count })
+        .collect();
+    counts.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.name.cmp(&b.name)));
+    counts
+}
+
+/// Parses user agents at query time. Input is one row per distinct user
+/// agent with its visit count, so each string is parsed once per request
+/// rather than once per visit.
+pub fn breakdown(user_agents: Vec<(Option<String>, i64)>) -> UserAgentBreakdown {
+    let parser = Parser::new();
+    let mut browsers: HashMap<String, i64> = HashMap::new();
+    let mut operating_systems: HashMap<String, i64> = HashMap::new();
+    let mut device_types: HashMap<String, i64> = HashMap::new();
+
+    for (user_agent, count) in user_agents {
+        let parsed = user_agent
+            .as_deref()
+            .map(str::trim)
+            .filter(|ua| !ua.is_empty())
+            .and_then(|ua| parser.parse(ua));
+        let (browser, os, device) = match (parsed, user_agent.as_deref()) {
+            (Some(result), _) => (label(result.name), label(result.os), device_type(result.category)),
+            // Anonymized visits only keep the browser family, which has no spaces
+            (None, Some(family)) if !family.is_empty() && !family.contains([' ', '/']) => {
+                (family.to_string(), UNKNOWN.to_string(), UNKNOWN)
+            }
+            (None, _) => (UNKNOWN.to_string(), UNKNOWN.to_string(), UNKNOWN),
+        };
+        *browsers.entry(browser).or_default() += count;
+        *operating_systems.entry(os).or_default() += count;
+        *device_types.entry(device.to_string()).or_default() += count;
+    }
+
+    UserAgentBreakdown {
+        browsers: sorted(browsers),
+        operating_systems: sorted(operating_systems),
+        device_types: sorted(device_types),
+    }
+}
+
+/// Browser family ("Chrome", "Firefox", ...) of a user agent, without
+/// versions or OS details. `None` when woothee can't tell.
+pub fn family(user_agent: &str) -> Option<String> {
+    Parser::new()
+        .parse(user_agent)
+        .map(|result| result.name)
+        .filter(|name| !name.is_empty() && *name != woothee::woothee::VALUE_UNKNOWN)
+        .map(str::to_string)
+}
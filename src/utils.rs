@@ -1,9 +1,360 @@
-use rand::{distributions::Alphanumeric, Rng};
+use crate::words;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use utoipa::ToSchema;
 
-pub fn generate_short_code(length: usize) -> String {
-    rand::thread_rng()
-        .sample_iter(&Alphanumeric)
-        .take(length)
-        .map(char::from)
+/// Character sets available for generated short codes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CodeAlphabet {
+    /// Base58 without `0 O o 1 l I`, safe to read aloud or off paper
+    #[default]
+    Unambiguous,
+    /// Bitcoin-style base58 (no `0 O I l`)
+    Base58,
+    /// `0-9`, `A-Z`, `a-z`
+    Base62,
+}
+
+impl CodeAlphabet {
+    pub fn parse(raw: &str) -> Option<Self> {
+        match raw.trim().to_ascii_lowercase().as_str() {
+            "unambiguous" => Some(CodeAlphabet::Unambiguous),
+            "base58" => Some(CodeAlphabet::Base58),
+            "base62" => Some(CodeAlphabet::Base62),
+            _ => None,
+        }
+    }
+
+    pub fn chars(self) -> &'static [u8] {
+        match self {
+            CodeAlphabet::Unambiguous => b"23456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnpqrstuvwxyz",
+            CodeAlphabet::Base58 => b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz",
+            CodeAlphabet::Base62 => b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz",
+        }
+    }
+}
+
+/// What generated short codes look like.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum CodeStyle {
+    /// `CODE_LENGTH` characters from `CODE_ALPHABET`, e.g. `x7Qk2p`
+    #[default]
+    Random,
+    /// Adjective, noun and number, e.g. `brave-otter-4217`, easy to read out loud
+    Words,
+}
+
+impl CodeStyle {
+    pub fn parse(raw: &str) -> Option<Self> {
+        match raw.trim().to_ascii_lowercase().as_str() {
+            "random" => Some(CodeStyle::Random),
+            "words" => Some(CodeStyle::Words),
+            _ => None,
+        }
+    }
+}
+
+/// Generator for short codes of one style. Both styles only produce
+/// characters accepted for custom codes, so generated and custom codes route
+/// the same way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CodeGenerator {
+    Random { alphabet: CodeAlphabet, length: usize },
+    Words,
+}
+
+impl CodeGenerator {
+    pub fn new(style: CodeStyle, alphabet: CodeAlphabet, length: usize) -> Self {
+        match style {
+            CodeStyle::Random => CodeGenerator::Random { alphabet, length },
+            CodeStyle::Words => CodeGenerator::Words,
+        }
+    }
+
+    pub fn generate(self) -> String {
+        match self {
+            CodeGenerator::Random { alphabet, length } => generate_short_code(length, alphabet),
+            CodeGenerator::Words => words::generate_word_code(),
+        }
+    }
+}
+
+// Lowercase, without look-alikes, so suggestions are easy to read aloud
+const SUGGESTION_SUFFIX_CHARS: &[u8] = b"23456789abcdefghjkmnpqrstuvwxyz";
+const SUGGESTION_SUFFIX_LEN: usize = 3;
+const MAX_CUSTOM_CODE_LEN: usize = 32;
+
+/// Candidate alternatives for a taken custom code, formed by appending a
+/// random `-xyz` suffix and truncated to stay a valid custom code. Callers
+/// still have to check the candidates against the database.
+pub fn suggest_codes(code: &str, count: usize) -> Vec<String> {
+    let max_base = MAX_CUSTOM_CODE_LEN - SUGGESTION_SUFFIX_LEN - 1;
+    let base: String = code.chars().take(max_base).collect();
+    let mut rng = rand::thread_rng();
+
+    let mut suggestions: Vec<String> = Vec::with_capacity(count);
+    while suggestions.len() < count {
+        let suffix: String = (0..SUGGESTION_SUFFIX_LEN)
+            .map(|_| SUGGESTION_SUFFIX_CHARS[rng.gen_range(0..SUGGESTION_SUFFIX_CHARS.len())] as char)
+            .collect();
+        let candidate = format!("{}-{}", base, suffix);
+        if !suggestions.contains(&candidate) {
+            suggestions.push(candidate);
+        }
+    }
+    suggestions
+}
+
+/// Smallest number of distinct codes we accept for a generator configuration,
+/// keeping random collisions (and retries) rare for realistic table sizes.
+pub const MIN_CODE_SPACE: f64 = 1e9;
+
+/// Checks a configured code length against the alphabet size.
+pub fn validate_code_length(alphabet: CodeAlphabet, length: usize) -> Result<(), String> {
+    if !(4..=20).contains(&length) {
+        return Err(format!("CODE_LENGTH must be between 4 and 20, got {}", length));
+    }
+    let space = (alphabet.chars().len() as f64).powi(length as i32);
+    if space < MIN_CODE_SPACE {
+        return Err(format!(
+            "CODE_LENGTH {} with {:?} gives only {:.0} codes; use a longer length",
+            length, alphabet, space
+        ));
+    }
+    Ok(())
+}
+
+/// Generates a random string from `alphabet`, for `CodeGenerator::Random`
+/// and for tokens. Every alphabet is a subset of the characters accepted for
+/// custom codes (see `models::is_code_char`).
+pub fn generate_short_code(length: usize, alphabet: CodeAlphabet) -> String {
+    let chars = alphabet.chars();
+    let mut rng = rand::thread_rng();
+    (0..length)
+        .map(|_| chars[rng.gen_range(0..chars.len())] as char)
         .collect()
 }
+
+/// Longest code `hash_code` produces: 43 base62 digits hold all 256 bits.
+pub const HASH_CODE_MAX_LEN: usize = 43;
+
+/// Deterministic code for `url`: its SHA-256 written in base62, least
+/// significant digit first, cut to `len` characters (at most
+/// `HASH_CODE_MAX_LEN`). A shorter code is always a prefix of a longer one.
+/// Callers pass the normalized URL so equivalent spellings share a code.
+pub fn hash_code(url: &str, len: usize) -> String {
+    let chars = CodeAlphabet::Base62.chars();
+    let mut digest = Sha256::digest(url.as_bytes());
+    (0..len.min(HASH_CODE_MAX_LEN))
+        .map(|_| {
+            // Divide the big-endian digest by 62 in place, keeping the remainder
+            let mut remainder = 0u32;
+            for byte in digest.iter_mut() {
+                let value = (remainder << 8) | u32::from(*byte);
+                *byte = (value / 62) as u8;
+                remainder = value % 62;
+            }
+            chars[remainder as usize] as char
+        })
+        .collect()
+}
+
+/// Normalizes a configured base URL: trims whitespace and trailing slashes and
+/// requires an explicit `http` or `https` scheme.
+pub fn normalize_base_url(raw: &str) -> Option<String> {
+    let trimmed = raw.trim().trim_end_matches('/');
+    let rest = trimmed
+        .strip_prefix("https://")
+        .or_else(|| trimmed.strip_prefix("http://"))?;
+    if rest.is_empty() {
+        return None;
+    }
+    Some(trimmed.to_string())
+}
+
+/// Serializes a destination URL the way it's stored and sent in `Location`:
+/// parsed by `Url`, so non-ASCII paths and queries come out percent-encoded
+/// and hosts punycoded.
+pub fn encode_url(raw: &str) -> Option<String> {
+    url::Url::parse(raw.trim()).ok().map(String::from)
+}
+
+/// Normalizes a destination URL for duplicate detection: the scheme and host
+/// are lowercased (by `Url` parsing) and a trailing slash on the path is
+/// dropped when there is no query or fragment.
+pub fn normalize_url(raw: &str) -> Option<String> {
+    let parsed = url::Url::parse(raw.trim()).ok()?;
+    let serialized = parsed.to_string();
+    if parsed.query().is_none() && parsed.fragment().is_none() {
+        Some(serialized.trim_end_matches('/').to_string())
+    } else {
+        Some(serialized)
+    }
+}
+
+// Decoded key of a raw `key=value` query pair, for comparing keys however
+// they were encoded.
+fn query_key(pair: &str) -> String {
+    url::form_urlencoded::parse(pair.as_bytes())
+        .next()
+        .map(|(key, _)| key.into_owned())
+        .unwrap_or_default()
+}
+
+/// Appends a short link's query string to its destination. Destination
+/// parameters whose key also appears in `query` are dropped, everything else
+/// keeps its order (destination first) and its original percent-encoding, and
+/// the destination's fragment stays at the end.
+pub fn merge_query(destination: &str, query: &str) -> String {
+    let query = query.trim_start_matches('?');
+    if query.is_empty() {
+        return destination.to_string();
+    }
+
+    let (without_fragment, fragment) = match destination.split_once('#') {
+        Some((rest, fragment)) => (rest, Some(fragment)),
+        None => (destination, None),
+    };
+    let (base, existing) = match without_fragment.split_once('?') {
+        Some((base, existing)) => (base, existing),
+        None => (without_fragment, ""),
+    };
+
+    let incoming: Vec<&str> = query.split('&').filter(|pair| !pair.is_empty()).collect();
+    let overridden: Vec<String> = incoming.iter().map(|pair| query_key(pair)).collect();
+    let pairs: Vec<&str> = existing
+        .split('&')
+        .filter(|pair| !pair.is_empty() && !overridden.contains(&query_key(pair)))
+        .chain(incoming.iter().copied())
+        .collect();
+
+    let mut merged = base.to_string();
+    if !pairs.is_empty() {
+        merged.push('?');
+        merged.push_str(&pairs.join("&"));
+    }
+    if let Some(fragment) = fragment {
+        merged.push('#');
+        merged.push_str(fragment);
+    }
+    merged
+}
+
+/// Reduces a `Referer` header value to its host for aggregation. Missing,
+/// empty or unparseable referrers are reported as "direct".
+pub fn referrer_host(referrer: Option<&str>) -> String {
+    referrer
+        .map(str::trim)
+        .filter(|r| !r.is_empty())
+        .and_then(|r| url::Url::parse(r).ok())
+        .and_then(|u| u.host_str().map(|h| h.to_lowercase()))
+        .unwrap_or_else(|| "direct".to_string())
+}
+
+/// Fingerprints a visitor as `sha256(salt, ip, user agent)` in hex. The salt
+/// keeps the hash from being reversed by enumerating the IPv4 space.
+pub fn visitor_hash(salt: &str, ip: &str, user_agent: Option<&str>) -> String {
+    let mut hasher = Sha256::new();
+    for part in [salt, ip, user_agent.unwrap_or("")] {
+        hasher.update(part.as_bytes());
+        hasher.update([0]);
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+/// Mobile platform a visitor is on, as far as device-aware redirects care.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Platform {
+    Ios,
+    Android,
+    /// Desktops and every other device
+    Other,
+}
+
+impl Platform {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Platform::Ios => "ios",
+            Platform::Android => "android",
+            Platform::Other => "other",
+        }
+    }
+}
+
+/// Detects iOS and Android from a `User-Agent`. Windows Phone claims to be
+/// Android and iPhone at once, so it's ruled out first; iPads that request
+/// the desktop site report macOS and count as `Other`.
+pub fn detect_platform(user_agent: Option<&str>) -> Platform {
+    let Some(ua) = user_agent else {
+        return Platform::Other;
+    };
+    if ua.contains("Windows Phone") {
+        Platform::Other
+    } else if ua.contains("iPhone") || ua.contains("iPad") || ua.contains("iPod") {
+        Platform::Ios
+    } else if ua.contains("Android") {
+        Platform::Android
+    } else {
+        Platform::Other
+    }
+}
+
+/// User-agent fragments of crawlers and link preview fetchers, lowercase.
+/// Most bots name themselves `SomethingBot/1.0` or link to a page about the
+/// bot as `+https://...`, which browsers never do; the rest are listed.
+const BOT_PATTERNS: &[&str] = &[
+    "bot/",
+    "bot-",
+    "bot;",
+    "+http",
+    "crawler",
+    "spider",
+    "slurp",
+    "googleother",
+    "google-inspectiontool",
+    "bingpreview",
+    "facebookexternalhit",
+    "facebookcatalog",
+    "meta-externalagent",
+    "twitterbot",
+    "slackbot",
+    "linkedinbot",
+    "discordbot",
+    "telegrambot",
+    "whatsapp/",
+    "skypeuripreview",
+    "embedly",
+    "iframely",
+    "quora link preview",
+    "headlesschrome",
+];
+
+/// Whether a `User-Agent` belongs to a known bot: one of the bundled
+/// patterns or `extra_patterns` (lowercase) appears in it, ignoring case. A
+/// missing user agent isn't taken as a bot.
+pub fn is_bot(user_agent: Option<&str>, extra_patterns: &[String]) -> bool {
+    let Some(ua) = user_agent else {
+        return false;
+    };
+    let ua = ua.to_ascii_lowercase();
+    BOT_PATTERNS.iter().any(|pattern| ua.contains(pattern))
+        || extra_patterns.iter().any(|pattern| ua.contains(pattern.as_str()))
+}
+
+/// Zeroes the host part of an IP, keeping the IPv4 /24 or IPv6 /48 network.
+/// Strings that aren't an IP (e.g. "unknown") are returned unchanged.
+pub fn anonymize_ip(ip: &str) -> String {
+    match ip.parse::<std::net::IpAddr>() {
+        Ok(std::net::IpAddr::V4(v4)) => {
+            let [a, b, c, _] = v4.octets();
+            std::net::Ipv4Addr::new(a, b, c, 0).to_string()
+        }
+        Ok(std::net::IpAddr::V6(v6)) => {
+            let [a, b, c, ..] = v6.segments();
+            std::net::Ipv6Addr::new(a, b, c, 0, 0, 0, 0, 0).to_string()
+        }
+        Err(_) => ip.to_string(),
+    }
+}
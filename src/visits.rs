@@ -0,0 +1,264 @@
+use crate::{
+    analytics::write_visits,
+    db::DbPool,
+    geoip::GeoIp,
+    models::VisitStats,
+    telemetry, user_agent,
+    utils::{anonymize_ip, Platform},
+};
+use chrono::{DateTime, Utc};
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::{
+    sync::{broadcast, mpsc, oneshot},
+    task::JoinHandle,
+};
+
+/// How a visit reached the service.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VisitSource {
+    /// Counted while serving a redirect
+    Redirect,
+    /// Reported through `/hit/{code}`, e.g. for clients that cached a permanent redirect
+    Beacon,
+}
+
+impl VisitSource {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            VisitSource::Redirect => "redirect",
+            VisitSource::Beacon => "beacon",
+        }
+    }
+}
+
+/// How much of each visit is stored, set by `ANALYTICS_MODE`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AnalyticsMode {
+    /// Full IP and user agent
+    #[default]
+    Full,
+    /// IPv4 truncated to /24, IPv6 to /48, and the user agent reduced to its
+    /// browser family
+    Anonymized,
+    /// No per-visit rows; only the link's aggregate counter moves
+    Off,
+}
+
+impl AnalyticsMode {
+    pub fn parse(raw: &str) -> Option<Self> {
+        match raw.trim().to_ascii_lowercase().as_str() {
+            "full" => Some(AnalyticsMode::Full),
+            "anonymized" | "anonymised" => Some(AnalyticsMode::Anonymized),
+            "off" => Some(AnalyticsMode::Off),
+            _ => None,
+        }
+    }
+
+    /// Strips what this mode doesn't allow storing. `Off` leaves the details
+    /// in place but marks the visit as count-only.
+    pub fn apply(self, visit: &mut NewVisit) {
+        match self {
+            AnalyticsMode::Full => {}
+            AnalyticsMode::Anonymized => {
+                visit.ip_address = anonymize_ip(&visit.ip_address);
+                visit.user_agent = visit.user_agent.as_deref().and_then(user_agent::family);
+            }
+            AnalyticsMode::Off => visit.detailed = false,
+        }
+    }
+}
+
+/// A visit waiting to be written to the `visits` table.
+#[derive(Debug)]
+pub struct NewVisit {
+    pub url_id: String,
+    pub ip_address: String,
+    pub user_agent: Option<String>,
+    pub referrer: Option<String>,
+    pub visitor_hash: String,
+    pub source: VisitSource,
+    /// Destination chosen for rotating links
+    pub destination: Option<String>,
+    pub platform: Platform,
+    pub visited_at: DateTime<Utc>,
+    /// ISO country code, filled in by the recorder when `GEOIP_DB_PATH` is set
+    pub country: Option<String>,
+    /// Classified from the user agent before any of it is stripped
+    pub is_bot: bool,
+    /// False when only the link's counter may be bumped, because analytics
+    /// are off or the visitor sent `DNT: 1`
+    pub detailed: bool,
+}
+
+/// Events buffered per live subscriber before it starts missing visits.
+const LIVE_BUFFER: usize = 256;
+
+/// A queued visit as published to live subscribers.
+#[derive(Debug, Clone)]
+pub struct LiveVisit {
+    pub url_id: String,
+    pub visit: VisitStats,
+}
+
+pub struct RecorderConfig {
+    pub queue_capacity: usize,
+    pub batch_size: usize,
+    pub flush_interval: Duration,
+    /// Resolves each stored visit's country; `None` leaves it null
+    pub geoip: Option<Arc<GeoIp>>,
+}
+
+/// Handle used by handlers to queue visits for the background writer.
+#[derive(Clone)]
+pub struct VisitRecorder {
+    tx: mpsc::Sender<NewVisit>,
+    dropped: Arc<AtomicU64>,
+    live: broadcast::Sender<LiveVisit>,
+}
+
+impl VisitRecorder {
+    /// Queues a visit without waiting. When the queue is full the visit is
+    /// dropped rather than slowing down the redirect.
+    pub fn record(&self, visit: NewVisit) {
+        // Only pay for the copy when someone is watching
+        let live = (visit.detailed && self.live.receiver_count() > 0).then(|| LiveVisit {
+            url_id: visit.url_id.clone(),
+            visit: VisitStats {
+                ip_address: Some(visit.ip_address.clone()),
+                user_agent: visit.user_agent.clone(),
+                referrer: visit.referrer.clone(),
+                source: visit.source.as_str().to_string(),
+                destination: visit.destination.clone(),
+                platform: Some(visit.platform.as_str().to_string()),
+                visited_at: visit.visited_at,
+                is_bot: visit.is_bot,
+            },
+        });
+
+        if let Err(err) = self.tx.try_send(visit) {
+            let dropped = self.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+            let reason = match err {
+                mpsc::error::TrySendError::Full(_) => {
+                    tracing::warn!(dropped, "visit queue full, dropping visit");
+                    "queue_full"
+                }
+                mpsc::error::TrySendError::Closed(_) => {
+                    tracing::warn!(dropped, "visit recorder stopped, dropping visit");
+                    "recorder_stopped"
+                }
+            };
+            metrics::counter!(telemetry::VISITS_DROPPED_TOTAL, "reason" => reason).increment(1);
+        } else if let Some(live) = live {
+            // Errors only mean every subscriber left in the meantime
+            let _ = self.live.send(live);
+        }
+    }
+
+    /// Subscribes to every visit queued from now on, across all links. Slow
+    /// subscribers miss events instead of holding up redirects.
+    pub fn subscribe(&self) -> broadcast::Receiver<LiveVisit> {
+        self.live.subscribe()
+    }
+}
+
+/// Owner-side handle of the background writer, used to stop it on shutdown.
+pub struct RecorderHandle {
+    stop: oneshot::Sender<()>,
+    task: JoinHandle<u64>,
+}
+
+impl RecorderHandle {
+    /// Stops accepting visits, writes everything still queued and returns how
+    /// many visits were flushed during the drain.
+    pub async fn shutdown(self) -> u64 {
+        let _ = self.stop.send(());
+        match self.task.await {
+            Ok(flushed) => flushed,
+            Err(e) => {
+                tracing::error!("visit recorder task failed: {:?}", e);
+                0
+            }
+        }
+    }
+}
+
+/// Spawns the background writer. It flushes pending visits in one multi-row
+/// insert whenever `batch_size` visits are buffered or `flush_interval`
+/// elapses, and drains the queue when stopped through the `RecorderHandle`
+/// or once every `VisitRecorder` has been dropped.
+pub fn spawn_recorder(pool: DbPool, config: RecorderConfig) -> (VisitRecorder, RecorderHandle) {
+    let (tx, rx) = mpsc::channel(config.queue_capacity.max(1));
+    let (stop, stop_rx) = oneshot::channel();
+    let recorder = VisitRecorder {
+        tx,
+        dropped: Arc::new(AtomicU64::new(0)),
+        live: broadcast::channel(LIVE_BUFFER).0,
+    };
+    let flush_interval = config.flush_interval.max(Duration::from_millis(1));
+    let task = tokio::spawn(run(pool, rx, stop_rx, config.batch_size.max(1), flush_interval, config.geoip));
+    (recorder, RecorderHandle { stop, task })
+}
+
+async fn run(
+    pool: DbPool,
+    mut rx: mpsc::Receiver<NewVisit>,
+    mut stop: oneshot::Receiver<()>,
+    batch_size: usize,
+    flush_interval: Duration,
+    geoip: Option<Arc<GeoIp>>,
+) -> u64 {
+    // Looked up here rather than in the request, which only queues the visit
+    let locate = |mut visit: NewVisit| {
+        if let Some(geoip) = &geoip
+            && visit.detailed
+        {
+            visit.country = geoip.country(&visit.ip_address);
+        }
+        visit
+    };
+    let mut pending = Vec::with_capacity(batch_size);
+    let mut interval = tokio::time::interval(flush_interval);
+
+    loop {
+        tokio::select! {
+            visit = rx.recv() => match visit {
+                Some(visit) => {
+                    pending.push(locate(visit));
+                    if pending.len() >= batch_size {
+                        write_visits(&pool, &mut pending).await;
+                    }
+                }
+                None => break,
+            },
+            _ = interval.tick() => {
+                if !pending.is_empty() {
+                    write_visits(&pool, &mut pending).await;
+                }
+            }
+            _ = &mut stop => {
+                // Late senders now get `Closed`; anything already queued is kept
+                rx.close();
+                break;
+            }
+        }
+    }
+
+    let mut drained = pending.len() as u64;
+    while let Some(visit) = rx.recv().await {
+        pending.push(locate(visit));
+        drained += 1;
+        if pending.len() >= batch_size {
+            write_visits(&pool, &mut pending).await;
+        }
+    }
+    write_visits(&pool, &mut pending).await;
+    tracing::debug!(flushed = drained, "visit recorder drained");
+
+    drained
+}
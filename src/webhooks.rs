@@ -0,0 +1,202 @@
+use crate::{
+    db::{DbPool, Traced},
+    destination::{self, DestinationPolicy},
+    models::UrlRecord,
+    visits::NewVisit,
+};
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use reqwest::Client;
+use serde::Serialize;
+use sha2::Sha256;
+use std::{sync::Arc, time::Duration};
+use tokio::sync::{mpsc, OwnedSemaphorePermit, Semaphore};
+
+pub const SIGNATURE_HEADER: &str = "x-webhook-signature";
+pub const TIMESTAMP_HEADER: &str = "x-webhook-timestamp";
+const QUEUE_CAPACITY: usize = 10_000;
+const MAX_CONCURRENT_DELIVERIES: usize = 8;
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(5);
+/// Waits before each retry; the number of attempts is one more than this.
+const RETRY_BACKOFF: &[Duration] = &[
+    Duration::from_secs(1),
+    Duration::from_secs(5),
+    Duration::from_secs(25),
+];
+
+/// Body POSTed to a link's webhook for every visit.
+#[derive(Serialize)]
+struct VisitEvent<'a> {
+    event: &'static str,
+    code: &'a str,
+    visited_at: DateTime<Utc>,
+    referrer: Option<&'a str>,
+    user_agent: Option<&'a str>,
+    /// Salted hash of IP and user agent, the same value used for unique visitors
+    visitor_hash: &'a str,
+    source: &'static str,
+}
+
+struct Delivery {
+    url_id: String,
+    webhook_url: String,
+    secret: String,
+    payload: String,
+}
+
+/// Handle used by handlers to queue webhook deliveries.
+#[derive(Clone)]
+pub struct WebhookDispatcher {
+    tx: mpsc::Sender<Delivery>,
+}
+
+impl WebhookDispatcher {
+    /// Queues a delivery for the visit if the link has a webhook. Never waits:
+    /// when the queue is full the event is dropped and logged. Count-only
+    /// visits (analytics off, Do Not Track) aren't sent.
+    pub fn visit(&self, url: &UrlRecord, visit: &NewVisit) {
+        let (Some(webhook_url), Some(secret)) = (&url.webhook_url, &url.webhook_secret) else {
+            return;
+        };
+        if !visit.detailed {
+            return;
+        }
+        let event = VisitEvent {
+            event: "visit",
+            code: &visit.url_id,
+            visited_at: visit.visited_at,
+            referrer: visit.referrer.as_deref(),
+            user_agent: visit.user_agent.as_deref(),
+            visitor_hash: &visit.visitor_hash,
+            source: visit.source.as_str(),
+        };
+        let delivery = Delivery {
+            url_id: visit.url_id.clone(),
+            webhook_url: webhook_url.clone(),
+            secret: secret.clone(),
+            payload: serde_json::to_string(&event).unwrap_or_default(),
+        };
+        if self.tx.try_send(delivery).is_err() {
+            tracing::warn!(code = visit.url_id, "webhook queue full, dropping visit event");
+        }
+    }
+}
+
+/// `sha256=<hex>` HMAC of `"{timestamp}.{body}"` under the link's secret.
+/// Receivers recompute it and should reject stale timestamps.
+pub fn signature(secret: &str, timestamp: i64, body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body.as_bytes());
+    format!("sha256={:x}", mac.finalize().into_bytes())
+}
+
+/// Spawns the worker delivering webhooks, a bounded number of attempts at a
+/// time. Deliveries waiting to retry don't hold a slot.
+pub fn spawn_dispatcher(pool: DbPool, destinations: Arc<DestinationPolicy>) -> WebhookDispatcher {
+    let (tx, mut rx) = mpsc::channel::<Delivery>(QUEUE_CAPACITY);
+    // Connects only to addresses the destination policy allows, looked up
+    // again for every connection
+    let client = destination::client_builder(destinations.clone())
+        .timeout(DELIVERY_TIMEOUT)
+        .build()
+        .expect("HTTP client configuration is valid");
+    let permits = Arc::new(Semaphore::new(MAX_CONCURRENT_DELIVERIES));
+
+    tokio::spawn(async move {
+        while let Some(delivery) = rx.recv().await {
+            // Taken here so the queue only drains as fast as first attempts go out
+            let Ok(permit) = permits.clone().acquire_owned().await else {
+                break;
+            };
+            let (pool, client, destinations, permits) = (pool.clone(), client.clone(), destinations.clone(), permits.clone());
+            tokio::spawn(async move {
+                deliver(&pool, &client, &destinations, &permits, permit, delivery).await;
+            });
+        }
+    });
+
+    WebhookDispatcher { tx }
+}
+
+async fn attempt(client: &Client, destinations: &DestinationPolicy, delivery: &Delivery) -> Result<(), String> {
+    destinations
+        .check_request(&delivery.webhook_url, "")
+        .map_err(|_| "webhook URL is not an allowed destination".to_string())?;
+
+    let timestamp = Utc::now().timestamp();
+    let response = client
+        .post(&delivery.webhook_url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .header(TIMESTAMP_HEADER, timestamp)
+        .header(SIGNATURE_HEADER, signature(&delivery.secret, timestamp, &delivery.payload))
+        .body(delivery.payload.clone())
+        .send()
+        .await
+        .map_err(|e| match destination::is_refused(&e) {
+            true => "webhook host resolves to an address that is not an allowed destination".to_string(),
+            false => e.to_string(),
+        })?;
+
+    if response.status().is_success() {
+        Ok(())
+    } else {
+        Err(format!("status {}", response.status()))
+    }
+}
+
+async fn deliver(
+    pool: &DbPool,
+    client: &Client,
+    destinations: &DestinationPolicy,
+    permits: &Semaphore,
+    first_permit: OwnedSemaphorePermit,
+    delivery: Delivery,
+) {
+    let mut attempts = 1;
+    let mut result = attempt(client, destinations, &delivery).await;
+    drop(first_permit);
+    for backoff in RETRY_BACKOFF {
+        let Err(e) = &result else {
+            break;
+        };
+        tracing::debug!(code = delivery.url_id, attempts, "webhook delivery failed, retrying: {}", e);
+        tokio::time::sleep(*backoff).await;
+        attempts += 1;
+        // Held per attempt, not while waiting to retry
+        result = {
+            let Ok(_permit) = permits.acquire().await else {
+                return;
+            };
+            attempt(client, destinations, &delivery).await
+        };
+    }
+
+    let Err(last_error) = result else {
+        return;
+    };
+    tracing::warn!(
+        code = delivery.url_id,
+        attempts,
+        "webhook delivery to {} failed permanently: {}",
+        delivery.webhook_url,
+        last_error
+    );
+    let failed_at = Utc::now();
+    let stored = sqlx::query!(
+        "INSERT INTO webhook_failures (url_id, webhook_url, payload, attempts, last_error, failed_at) VALUES (?, ?, ?, ?, ?, ?)",
+        delivery.url_id,
+        delivery.webhook_url,
+        delivery.payload,
+        attempts,
+        last_error,
+        failed_at
+    )
+    .execute(pool)
+    .traced("record_webhook_failure", Some(delivery.url_id.as_str()))
+    .await;
+    if let Err(e) = stored {
+        tracing::error!(code = delivery.url_id, "failed to record webhook failure: {:?}", e);
+    }
+}
@@ -0,0 +1,42 @@
+//! Word lists behind `CodeStyle::Words`, embedded at compile time. Both lists
+//! are hand-picked to be inoffensive, lowercase and at most 7 letters, so
+//! `adjective-noun-1234` stays within the 20 characters of a custom code.
+
+use crate::utils::MIN_CODE_SPACE;
+use rand::{seq::SliceRandom, Rng};
+use std::sync::LazyLock;
+
+static ADJECTIVES: LazyLock<Vec<&'static str>> = LazyLock::new(|| parse(include_str!("words/adjectives.txt")));
+static NOUNS: LazyLock<Vec<&'static str>> = LazyLock::new(|| parse(include_str!("words/nouns.txt")));
+
+fn parse(list: &'static str) -> Vec<&'static str> {
+    list.lines().map(str::trim).filter(|word| !word.is_empty()).collect()
+}
+
+pub fn adjectives() -> &'static [&'static str] {
+    &ADJECTIVES
+}
+
+pub fn nouns() -> &'static [&'static str] {
+    &NOUNS
+}
+
+/// Digits in the number ending a word code: the fewest, and at least two,
+/// that give `MIN_CODE_SPACE` possible codes.
+pub fn number_digits() -> u32 {
+    let pairs = (adjectives().len() * nouns().len()) as f64;
+    (2..).find(|&digits| pairs * 10f64.powi(digits as i32) >= MIN_CODE_SPACE).unwrap_or(2)
+}
+
+/// A random `adjective-noun-number` code such as `brave-otter-4217`.
+pub fn generate_word_code() -> String {
+    let mut rng = rand::thread_rng();
+    let digits = number_digits();
+    format!(
+        "{}-{}-{:0width$}",
+        adjectives().choose(&mut rng).expect("adjective list is not empty"),
+        nouns().choose(&mut rng).expect("noun list is not empty"),
+        rng.gen_range(0..10u64.pow(digits)),
+        width = digits as usize
+    )
+}
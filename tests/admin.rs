@@ -0,0 +1,570 @@
+mod common;
+
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+};
+use chrono::{DateTime, Duration, Utc};
+use common::{body_bytes, json_body, spawn_app_with, TestApp};
+use serde_json::{json, Value};
+
+const ADMIN_KEY: &str = "admin-key";
+
+async fn admin_app() -> TestApp {
+    spawn_app_with(&[("ADMIN_API_KEYS", ADMIN_KEY)]).await
+}
+
+async fn get_summary(app: &TestApp, query: &str) -> (StatusCode, Value) {
+    let request = Request::get(format!("/admin/summary{}", query))
+        .header("x-api-key", ADMIN_KEY)
+        .body(Body::empty())
+        .unwrap();
+    let response = app.send(request).await;
+    (response.status(), json_body(response).await)
+}
+
+// Creates a link and stores visits for it directly, at the given times, so a
+// summary sees a known history without waiting on the visit writer.
+async fn seed_link(app: &TestApp, code: &str, visited_at: &[DateTime<Utc>]) {
+    app.shorten(json!({ "url": format!("https://example.com/{}", code), "custom_code": code }))
+        .await;
+    for at in visited_at {
+        sqlx::query("INSERT INTO visits (url_id, visited_at) VALUES (?, ?)")
+            .bind(code)
+            .bind(at)
+            .execute(&app.state.pool)
+            .await
+            .unwrap();
+    }
+    sqlx::query("UPDATE urls SET total_visits = total_visits + ? WHERE id = ?")
+        .bind(visited_at.len() as i64)
+        .bind(code)
+        .execute(&app.state.pool)
+        .await
+        .unwrap();
+}
+
+fn times(count: usize, ago: Duration) -> Vec<DateTime<Utc>> {
+    vec![Utc::now() - ago; count]
+}
+
+#[tokio::test]
+async fn summary_ranks_links_by_visits_in_the_window() {
+    let app = admin_app().await;
+    seed_link(&app, "third", &times(2, Duration::hours(1))).await;
+    seed_link(&app, "first", &times(5, Duration::days(2))).await;
+    // Most visits overall, but nearly all of them before the window
+    let mut old = times(10, Duration::days(30));
+    old.extend(times(3, Duration::hours(3)));
+    seed_link(&app, "second", &old).await;
+    seed_link(&app, "unvisited", &[]).await;
+    for i in 0..12 {
+        seed_link(&app, &format!("tail{:02}", i), &times(1, Duration::hours(2))).await;
+    }
+
+    let (status, summary) = get_summary(&app, "?window=7d").await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(summary["window"], "7d");
+    assert_eq!(summary["total_links"], 16);
+    assert_eq!(summary["total_visits"], 2 + 5 + 13 + 12);
+    assert_eq!(summary["links_created_24h"], 16);
+    assert_eq!(summary["links_created_7d"], 16);
+    let top = summary["top_links"].as_array().unwrap();
+    let codes: Vec<_> = top.iter().map(|link| link["short_code"].as_str().unwrap()).collect();
+    // Ties are broken by short code
+    assert_eq!(
+        codes,
+        ["first", "second", "third", "tail00", "tail01", "tail02", "tail03", "tail04", "tail05", "tail06"]
+    );
+    assert_eq!(top[0]["visits"], 5);
+    assert_eq!(top[0]["original_url"], "https://example.com/first");
+    assert_eq!(top[1]["visits"], 3);
+
+    let (_, summary) = get_summary(&app, "?window=24h").await;
+    let codes: Vec<_> = summary["top_links"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .take(2)
+        .map(|link| link["short_code"].as_str().unwrap())
+        .collect();
+    assert_eq!(codes, ["second", "third"]);
+}
+
+#[tokio::test]
+async fn summary_counts_links_by_creation_time() {
+    let app = admin_app().await;
+    seed_link(&app, "today", &[]).await;
+    seed_link(&app, "lastweek", &[]).await;
+    seed_link(&app, "lastmonth", &[]).await;
+    for (code, age) in [("lastweek", Duration::days(3)), ("lastmonth", Duration::days(40))] {
+        sqlx::query("UPDATE urls SET created_at = ? WHERE id = ?")
+            .bind(Utc::now() - age)
+            .bind(code)
+            .execute(&app.state.pool)
+            .await
+            .unwrap();
+    }
+
+    let (status, summary) = get_summary(&app, "").await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(summary["window"], "7d");
+    assert_eq!(summary["total_links"], 3);
+    assert_eq!(summary["links_created_24h"], 1);
+    assert_eq!(summary["links_created_7d"], 2);
+    assert_eq!(summary["top_links"], json!([]));
+}
+
+#[tokio::test]
+async fn summary_rejects_bad_windows_and_missing_keys() {
+    let app = admin_app().await;
+
+    for window in ["7", "0d", "1w", "366d", "-1h"] {
+        let (status, body) = get_summary(&app, &format!("?window={}", window)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", window);
+        assert_eq!(body["code"], "BAD_REQUEST");
+    }
+
+    let response = app.get("/admin/summary").await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn admin_listing_filters_by_owner() {
+    let app = spawn_app_with(&[("ADMIN_API_KEYS", ADMIN_KEY), ("API_KEYS", "team-a:key-a,key-b")]).await;
+    for (key, code) in [("key-a", "one"), ("key-a", "two"), ("key-b", "three")] {
+        let request = Request::post("/shorten")
+            .header("content-type", "application/json")
+            .header("x-api-key", key)
+            .body(Body::from(json!({ "url": "https://example.com/", "custom_code": code }).to_string()))
+            .unwrap();
+        assert_eq!(app.send(request).await.status(), StatusCode::CREATED);
+    }
+    let app = &app;
+    let list = |query: &'static str| async move {
+        let request = Request::get(format!("/admin/urls?{}", query))
+            .header("x-api-key", ADMIN_KEY)
+            .body(Body::empty())
+            .unwrap();
+        let body = json_body(app.send(request).await).await;
+        let mut ids: Vec<String> = body["items"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|item| item["id"].as_str().unwrap().to_string())
+            .collect();
+        ids.sort();
+        ids
+    };
+
+    assert_eq!(list("namespace=team-a").await, ["team-a~one", "team-a~two"]);
+    assert_eq!(list("owner_key=key-b").await, ["three"]);
+}
+
+async fn list_page(app: &TestApp, query: &str) -> (StatusCode, Value) {
+    let request = Request::get(format!("/admin/urls?{}", query))
+        .header("x-api-key", ADMIN_KEY)
+        .body(Body::empty())
+        .unwrap();
+    let response = app.send(request).await;
+    (response.status(), json_body(response).await)
+}
+
+#[tokio::test]
+async fn cursor_pages_skip_nothing_while_links_are_created() {
+    let app = admin_app().await;
+    for i in 0..25 {
+        seed_link(&app, &format!("seed{:02}", i), &[]).await;
+    }
+    // Ties on created_at are ordered by id
+    sqlx::query("UPDATE urls SET created_at = ? WHERE id < 'seed10'")
+        .bind(Utc::now() - Duration::hours(1))
+        .execute(&app.state.pool)
+        .await
+        .unwrap();
+
+    let mut seen = Vec::new();
+    let mut query = "limit=4".to_string();
+    for page in 0.. {
+        let (status, body) = list_page(&app, &query).await;
+        assert_eq!(status, StatusCode::OK);
+        if page > 0 {
+            assert_eq!(body["next_offset"], Value::Null);
+        }
+        for item in body["items"].as_array().unwrap() {
+            seen.push(item["id"].as_str().unwrap().to_string());
+        }
+        // New links would push rows onto the next page under offset paging;
+        // one of them is backdated to land among the rows still to come
+        let fresh = format!("fresh{}", page);
+        seed_link(&app, &fresh, &[]).await;
+        if page == 2 {
+            sqlx::query("UPDATE urls SET created_at = ? WHERE id = ?")
+                .bind(Utc::now() - Duration::days(1))
+                .bind(&fresh)
+                .execute(&app.state.pool)
+                .await
+                .unwrap();
+        }
+        match body["next_cursor"].as_str() {
+            Some(cursor) => query = format!("limit=4&cursor={}", cursor),
+            None => break,
+        }
+    }
+
+    let mut expected: Vec<String> = (0..25).map(|i| format!("seed{:02}", i)).collect();
+    expected.push("fresh2".to_string());
+    let mut sorted = seen.clone();
+    sorted.sort();
+    sorted.dedup();
+    assert_eq!(sorted.len(), seen.len(), "repeated rows: {:?}", seen);
+    expected.sort();
+    assert_eq!(sorted, expected);
+    // The tied, older links come last, by id
+    let tail: Vec<String> = (0..10).map(|i| format!("seed{:02}", i)).collect();
+    assert_eq!(seen[seen.len() - 11..seen.len() - 1], tail[..]);
+    assert_eq!(seen.last().unwrap(), "fresh2");
+}
+
+#[tokio::test]
+async fn malformed_cursors_are_rejected() {
+    let app = admin_app().await;
+    for i in 0..3 {
+        seed_link(&app, &format!("link{}", i), &[]).await;
+    }
+    let (_, body) = list_page(&app, "limit=2").await;
+    let cursor = body["next_cursor"].as_str().unwrap().to_string();
+    assert_eq!(body["next_offset"], 2);
+
+    // Not base64, base64 of something else, a truncated cursor
+    for bad in ["not*base64", "aGVsbG8", &cursor[..cursor.len() - 3]] {
+        let (status, body) = list_page(&app, &format!("cursor={}", bad)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", bad);
+        assert_eq!(body["code"], "BAD_REQUEST");
+    }
+    let (status, _) = list_page(&app, &format!("cursor={}&offset=2", cursor)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let (status, body) = list_page(&app, &format!("limit=2&cursor={}", cursor)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["items"].as_array().unwrap().len(), 1);
+    assert_eq!(body["next_cursor"], Value::Null);
+    assert_eq!(body["total"], 3);
+}
+
+#[tokio::test]
+async fn listing_searches_destinations_and_code_prefixes() {
+    let app = admin_app().await;
+    for (code, url) in [
+        ("promo-a", "https://example.com/Pricing?plan=pro"),
+        ("promo-b", "https://example.com/blog"),
+        ("Promo-c", "https://example.com/pricing_100%"),
+        ("pricing", "https://example.com/about"),
+        ("other", "https://example.com/contact"),
+    ] {
+        app.shorten(json!({ "url": url, "custom_code": code })).await;
+    }
+    let search = |query: &'static str| {
+        let app = &app;
+        async move {
+            let (status, body) = list_page(app, query).await;
+            assert_eq!(status, StatusCode::OK, "{}", query);
+            let mut items: Vec<(String, Value)> = body["items"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|item| (item["id"].as_str().unwrap().to_string(), item["matched"].clone()))
+                .collect();
+            items.sort_by(|a, b| a.0.cmp(&b.0));
+            items
+        }
+    };
+
+    // Case-insensitive, on the destination or the code
+    assert_eq!(
+        search("q=PRICING").await,
+        [
+            ("Promo-c".to_string(), json!(["original_url"])),
+            ("pricing".to_string(), json!(["id"])),
+            ("promo-a".to_string(), json!(["original_url"])),
+        ]
+    );
+    // Wildcards are literal
+    assert_eq!(search("q=_100%25").await.len(), 1);
+    assert_eq!(search("q=%25").await.len(), 1);
+    // Prefixes are case-sensitive and combine with q and the other filters
+    assert_eq!(
+        search("code_prefix=promo").await,
+        [("promo-a".to_string(), json!(["id"])), ("promo-b".to_string(), json!(["id"]))]
+    );
+    assert_eq!(
+        search("code_prefix=promo&q=pricing").await,
+        [("promo-a".to_string(), json!(["original_url", "id"]))]
+    );
+    assert_eq!(search("code_prefix=promo&url_contains=blog").await.len(), 1);
+    // Without a search nothing is marked
+    assert_eq!(search("").await.iter().filter(|(_, matched)| !matched.is_null()).count(), 0);
+}
+
+async fn reset(app: &TestApp, code: &str, key: &str) -> (StatusCode, Value) {
+    let request = Request::delete(format!("/admin/urls/{}/visits", code))
+        .header("x-api-key", key)
+        .body(Body::empty())
+        .unwrap();
+    let response = app.send(request).await;
+    (response.status(), json_body(response).await)
+}
+
+#[tokio::test]
+async fn resetting_visits_keeps_the_link_and_zeroes_its_counters() {
+    let app = admin_app().await;
+    seed_link(&app, "launch", &times(4, Duration::hours(1))).await;
+    seed_link(&app, "other", &times(2, Duration::hours(1))).await;
+    sqlx::query("UPDATE urls SET untracked_visits = 3, total_visits = total_visits + 3, max_visits = 5, visit_count = 5 WHERE id = 'launch'")
+        .execute(&app.state.pool)
+        .await
+        .unwrap();
+    // Load the exhausted record into the redirect cache
+    assert_eq!(app.get("/launch").await.status(), StatusCode::GONE);
+
+    assert_eq!(reset(&app, "launch", "wrong").await.0, StatusCode::UNAUTHORIZED);
+    let (status, body) = reset(&app, "launch", ADMIN_KEY).await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, json!({ "deleted": 4 }));
+    let counters: (i64, i64, i64) = sqlx::query_as(
+        "SELECT total_visits, untracked_visits, (SELECT COUNT(*) FROM visits WHERE url_id = id) FROM urls WHERE id = 'launch'",
+    )
+    .fetch_one(&app.state.pool)
+    .await
+    .unwrap();
+    assert_eq!(counters, (0, 0, 0));
+    let (_, summary) = get_summary(&app, "").await;
+    assert_eq!(summary["total_visits"], 2);
+    // The max_visits allowance starts over
+    assert_eq!(app.get("/launch").await.status(), StatusCode::TEMPORARY_REDIRECT);
+
+    let (status, body) = reset(&app, "missing", ADMIN_KEY).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["code"], "URL_NOT_FOUND");
+}
+
+async fn audit_entries(app: &TestApp, query: &str) -> Vec<Value> {
+    let request = Request::get(format!("/admin/audit{}", query))
+        .header("x-api-key", ADMIN_KEY)
+        .body(Body::empty())
+        .unwrap();
+    let response = app.send(request).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    json_body(response).await["items"].as_array().unwrap().clone()
+}
+
+#[tokio::test]
+async fn link_changes_are_audited_with_before_and_after() {
+    let app = admin_app().await;
+    let link = app
+        .shorten(json!({ "url": "https://example.com/a", "custom_code": "audited", "tags": ["spring"] }))
+        .await;
+    app.shorten(json!({ "url": "https://example.com/b", "custom_code": "bystander" })).await;
+    let token = link["delete_token"].as_str().unwrap();
+    let patch = |body: Value| {
+        Request::patch("/urls/audited")
+            .header("content-type", "application/json")
+            .header("authorization", format!("Bearer {}", token))
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    };
+    assert_eq!(app.send(patch(json!({ "url": "https://example.com/c" }))).await.status(), StatusCode::OK);
+    assert_eq!(app.send(patch(json!({ "active": false }))).await.status(), StatusCode::OK);
+    let delete = Request::delete(format!("/audited?token={}", token)).body(Body::empty()).unwrap();
+    assert_eq!(app.send(delete).await.status(), StatusCode::NO_CONTENT);
+
+    let entries = audit_entries(&app, "?code=audited").await;
+
+    let actions: Vec<_> = entries.iter().map(|entry| entry["action"].as_str().unwrap()).collect();
+    assert_eq!(actions, ["delete", "disable", "update", "create"]);
+    let create = &entries[3];
+    assert_eq!(create["actor"], "anonymous");
+    assert_eq!(create["before"], Value::Null);
+    assert_eq!(create["after"]["original_url"], "https://example.com/a");
+    assert_eq!(create["after"]["tags"], json!(["spring"]));
+    assert!(!create.to_string().contains(token));
+    let update = &entries[2];
+    assert_eq!(update["actor"], "delete_token");
+    assert_eq!(update["before"]["original_url"], "https://example.com/a");
+    assert_eq!(update["after"]["original_url"], "https://example.com/c");
+    assert_eq!(entries[1]["after"]["active"], false);
+    assert_eq!(entries[0]["before"]["original_url"], "https://example.com/c");
+    assert_eq!(entries[0]["after"], Value::Null);
+
+    let (status, _) = reset(&app, "bystander", ADMIN_KEY).await;
+    assert_eq!(status, StatusCode::OK);
+    let latest = audit_entries(&app, "?limit=1").await;
+    assert_eq!(latest.len(), 1);
+    assert_eq!(latest[0]["action"], "reset_visits");
+    assert_eq!(latest[0]["code"], "bystander");
+    assert!(latest[0]["actor"].as_str().unwrap().starts_with("key:"));
+}
+
+#[tokio::test]
+async fn changes_fail_when_the_audit_entry_cant_be_written() {
+    let app = admin_app().await;
+    sqlx::query("ALTER TABLE audit_log RENAME TO audit_log_gone")
+        .execute(&app.state.pool)
+        .await
+        .unwrap();
+
+    let request = Request::post("/shorten")
+        .header("content-type", "application/json")
+        .body(Body::from(json!({ "url": "https://example.com/", "custom_code": "unaudited" }).to_string()))
+        .unwrap();
+    let response = app.send(request).await;
+
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert_eq!(app.get("/info/unaudited").await.status(), StatusCode::NOT_FOUND);
+}
+
+async fn export(app: &TestApp) -> Vec<u8> {
+    let request = Request::get("/admin/export")
+        .header("x-api-key", ADMIN_KEY)
+        .body(Body::empty())
+        .unwrap();
+    let response = app.send(request).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["content-type"], "application/x-ndjson");
+    body_bytes(response).await
+}
+
+async fn import(app: &TestApp, conflict: &str, body: Vec<u8>) -> (StatusCode, Value) {
+    let request = Request::post(format!("/admin/import?conflict={}", conflict))
+        .header("x-api-key", ADMIN_KEY)
+        .header("content-type", "application/x-ndjson")
+        .body(Body::from(body))
+        .unwrap();
+    let response = app.send(request).await;
+    (response.status(), json_body(response).await)
+}
+
+#[tokio::test]
+async fn export_and_import_round_trip() {
+    let app = admin_app().await;
+    app.shorten(json!({
+        "url": "https://example.com/tagged",
+        "custom_code": "tagged",
+        "tags": ["spring", "promo"],
+        "expires_at": "2099-01-01T00:00:00Z",
+        "redirect_type": 301,
+        "stats_visibility": "public"
+    }))
+    .await;
+    seed_link(&app, "visited", &times(3, Duration::hours(1))).await;
+    app.shorten(json!({ "url": "https://example.org/", "max_visits": 5 })).await;
+    let locked = app
+        .shorten(json!({
+            "destinations": [
+                { "url": "https://example.com/a", "weight": 3 },
+                { "url": "https://example.com/b", "weight": 1 }
+            ],
+            "rotation": "sticky",
+            "custom_code": "locked",
+            "password": "hunter22",
+            "ios_url": "https://apps.apple.com/app/id1",
+            "webhook_url": "https://example.org/hook",
+            "stats_visibility": "private"
+        }))
+        .await;
+
+    let dump = export(&app).await;
+    let lines: Vec<Value> = dump
+        .split(|byte| *byte == b'\n')
+        .filter(|line| !line.is_empty())
+        .map(|line| serde_json::from_slice(line).unwrap())
+        .collect();
+    assert_eq!(lines.len(), 4);
+    assert_eq!(lines[0]["short_code"], "tagged");
+    assert_eq!(lines[0]["tags"], json!(["promo", "spring"]));
+    assert_eq!(lines[0]["redirect_type"], 301);
+    assert_eq!(lines[1]["total_visits"], 3);
+    assert_eq!(lines[3]["rotation"], "sticky");
+    assert_eq!(lines[3]["destinations"][0], json!({ "url": "https://example.com/a", "weight": 3 }));
+    assert!(lines[3]["password_hash"].is_string());
+
+    for table in ["url_tags", "url_destinations", "visits", "urls"] {
+        sqlx::query(&format!("DELETE FROM {}", table))
+            .execute(&app.state.pool)
+            .await
+            .unwrap();
+    }
+    let (status, summary) = import(&app, "fail", dump.clone()).await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(summary["created"], 4);
+    assert_eq!(summary["failed"], 0);
+    assert_eq!(export(&app).await, dump);
+    let redirect = app.get("/tagged").await;
+    assert_eq!(redirect.status(), StatusCode::MOVED_PERMANENTLY);
+
+    // The audit log gets the link without its secrets
+    let imported = audit_entries(&app, "?code=locked").await;
+    assert_eq!(imported[0]["action"], "import");
+    let mut public = lines[3].clone();
+    for secret in ["webhook_secret", "created_by_key", "password_hash", "delete_token_hash", "stats_token_hash"] {
+        public[secret] = Value::Null;
+    }
+    assert_eq!(imported[0]["after"], public);
+
+    // Still locked, and the original password and stats token still work
+    assert_eq!(app.get("/locked").await.status(), StatusCode::OK);
+    let unlock = Request::post("/locked/unlock")
+        .header("content-type", "application/x-www-form-urlencoded")
+        .body(Body::from("password=hunter22"))
+        .unwrap();
+    let unlocked = app.send(unlock).await;
+    assert_eq!(unlocked.status(), StatusCode::SEE_OTHER);
+    let location = unlocked.headers()["location"].to_str().unwrap();
+    assert!(["https://example.com/a", "https://example.com/b"].contains(&location), "{}", location);
+    let stats = Request::get("/stats/locked")
+        .header("x-stats-token", locked["stats_token"].as_str().unwrap())
+        .body(Body::empty())
+        .unwrap();
+    assert_eq!(app.send(stats).await.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn import_resolves_conflicts_as_asked() {
+    let app = admin_app().await;
+    app.shorten(json!({ "url": "https://example.com/old", "custom_code": "taken" }))
+        .await;
+    let body = [
+        json!({ "short_code": "taken", "original_url": "https://example.com/new", "created_at": "2023-01-01T00:00:00Z" }).to_string(),
+        "not json".to_string(),
+        json!({ "short_code": "fresh", "original_url": "https://example.com/fresh", "created_at": "2023-01-01T00:00:00Z" }).to_string(),
+    ]
+    .join("\n")
+    .into_bytes();
+
+    let (status, error) = import(&app, "fail", body.clone()).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(error["code"], "IMPORT_CONFLICT");
+    assert_eq!(error["details"]["conflicts"], json!([{ "line": 1, "short_code": "taken" }]));
+    assert_eq!(app.get("/info/fresh").await.status(), StatusCode::NOT_FOUND);
+
+    let (status, summary) = import(&app, "skip", body.clone()).await;
+    assert_eq!(status, StatusCode::OK);
+    let statuses: Vec<_> = summary["results"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|result| result["status"].as_str().unwrap())
+        .collect();
+    assert_eq!(statuses, ["skipped", "invalid", "created"]);
+    assert_eq!(summary["results"][1]["line"], 2);
+    assert_eq!(app.get("/taken").await.headers()["location"], "https://example.com/old");
+
+    let (_, summary) = import(&app, "overwrite", body).await;
+    assert_eq!(summary["overwritten"], 2);
+    assert_eq!(summary["failed"], 1);
+    assert_eq!(app.get("/taken").await.headers()["location"], "https://example.com/new");
+}
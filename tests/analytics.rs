@@ -0,0 +1,182 @@
+mod common;
+
+use chrono::Utc;
+use common::{spawn_app, spawn_app_with, TempDb};
+use serde_json::json;
+use short_url_service::{
+    analytics::{is_busy, write_visits},
+    utils::Platform,
+    visits::{spawn_recorder, NewVisit, RecorderConfig, VisitSource},
+};
+use sqlx::{
+    sqlite::SqliteConnectOptions,
+    ConnectOptions, Connection, SqliteConnection,
+};
+use std::{str::FromStr, time::Duration};
+
+fn visit(code: &str, detailed: bool) -> NewVisit {
+    NewVisit {
+        url_id: code.to_string(),
+        ip_address: "203.0.113.7".to_string(),
+        user_agent: Some("curl/8.0".to_string()),
+        referrer: None,
+        visitor_hash: "hash".to_string(),
+        source: VisitSource::Redirect,
+        destination: None,
+        platform: Platform::Other,
+        visited_at: Utc::now(),
+        country: None,
+        is_bot: false,
+        detailed,
+    }
+}
+
+async fn stored_visits(pool: &sqlx::SqlitePool, code: &str) -> i64 {
+    sqlx::query_scalar("SELECT COUNT(*) FROM visits WHERE url_id = ?")
+        .bind(code)
+        .fetch_one(pool)
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn writes_detailed_and_count_only_visits() {
+    let app = spawn_app().await;
+    let link = app
+        .shorten(json!({ "url": "https://example.com/", "custom_code": "written" }))
+        .await;
+
+    let mut batch = vec![visit("written", true), visit("written", true), visit("written", false)];
+    let dropped = write_visits(&app.state.pool, &mut batch).await;
+
+    assert_eq!(dropped, 0);
+    assert!(batch.is_empty());
+    assert_eq!(stored_visits(&app.state.pool, "written").await, 2);
+    let stats = app.stats_after_visits(&link, 3).await;
+    assert_eq!(stats["untracked_visits"], 1);
+}
+
+#[tokio::test]
+async fn retries_while_the_database_is_locked() {
+    let db = TempDb::new();
+    let url = db.url();
+    // No busy timeout, so every locked write fails at once and only the
+    // retries can get it through
+    let app = spawn_app_with(&[("DATABASE_URL", &url), ("SQLITE_BUSY_TIMEOUT_MS", "0")]).await;
+    app.shorten(json!({ "url": "https://example.com/", "custom_code": "locked" }))
+        .await;
+
+    let mut blocker = SqliteConnection::connect(&url).await.unwrap();
+    sqlx::query("BEGIN EXCLUSIVE").execute(&mut blocker).await.unwrap();
+    let release = tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(40)).await;
+        sqlx::query("COMMIT").execute(&mut blocker).await.unwrap();
+    });
+
+    let mut batch = vec![visit("locked", true)];
+    let dropped = write_visits(&app.state.pool, &mut batch).await;
+    release.await.unwrap();
+
+    assert_eq!(dropped, 0);
+    assert_eq!(stored_visits(&app.state.pool, "locked").await, 1);
+}
+
+#[tokio::test]
+async fn gives_up_and_counts_drops_on_a_broken_pool() {
+    let app = spawn_app().await;
+    let pool = app.state.pool.clone();
+    pool.close().await;
+
+    let mut batch = vec![visit("gone", true), visit("gone", true), visit("gone", false)];
+    let dropped = write_visits(&pool, &mut batch).await;
+
+    assert_eq!(dropped, 3);
+    assert!(batch.is_empty());
+    let metrics = app.state.metrics.render();
+    assert!(
+        metrics.contains("visits_dropped_total{reason=\"write_failed\"}"),
+        "{}",
+        metrics
+    );
+}
+
+#[tokio::test]
+async fn only_lock_errors_are_retryable() {
+    let db = TempDb::new();
+    let url = db.url();
+    let mut first = SqliteConnection::connect(&url).await.unwrap();
+    sqlx::query("CREATE TABLE t (x INTEGER)").execute(&mut first).await.unwrap();
+    sqlx::query("BEGIN EXCLUSIVE").execute(&mut first).await.unwrap();
+
+    let mut second = SqliteConnectOptions::from_str(&url)
+        .unwrap()
+        .busy_timeout(Duration::ZERO)
+        .connect()
+        .await
+        .unwrap();
+    let busy = sqlx::query("INSERT INTO t VALUES (1)").execute(&mut second).await.unwrap_err();
+    assert!(is_busy(&busy), "{:?}", busy);
+
+    let missing = sqlx::query("INSERT INTO nope VALUES (1)").execute(&mut first).await.unwrap_err();
+    assert!(!is_busy(&missing));
+    assert!(!is_busy(&sqlx::Error::PoolClosed));
+}
+
+#[tokio::test]
+async fn keeps_the_batch_when_one_of_its_links_was_deleted() {
+    let app = spawn_app().await;
+    let kept = app
+        .shorten(json!({ "url": "https://example.com/", "custom_code": "kept" }))
+        .await;
+    app.shorten(json!({ "url": "https://example.org/", "custom_code": "deleted" }))
+        .await;
+    sqlx::query("DELETE FROM urls WHERE id = 'deleted'")
+        .execute(&app.state.pool)
+        .await
+        .unwrap();
+
+    let mut batch = vec![
+        visit("kept", true),
+        visit("deleted", true),
+        visit("kept", true),
+        visit("deleted", false),
+        visit("kept", false),
+    ];
+    let dropped = write_visits(&app.state.pool, &mut batch).await;
+
+    assert_eq!(dropped, 2);
+    assert_eq!(stored_visits(&app.state.pool, "kept").await, 2);
+    let stats = app.stats_after_visits(&kept, 3).await;
+    assert_eq!(stats["untracked_visits"], 1);
+    let metrics = app.state.metrics.render();
+    assert!(
+        metrics.contains("visits_dropped_total{reason=\"link_deleted\"} 2"),
+        "{}",
+        metrics
+    );
+}
+
+#[tokio::test]
+async fn shutdown_flushes_every_queued_visit() {
+    let app = spawn_app().await;
+    app.shorten(json!({ "url": "https://example.com/", "custom_code": "draining" }))
+        .await;
+    // Neither the interval nor the batch size would flush on their own
+    let (recorder, handle) = spawn_recorder(
+        app.state.pool.clone(),
+        RecorderConfig {
+            queue_capacity: 100,
+            batch_size: 100,
+            flush_interval: Duration::from_secs(3600),
+            geoip: None,
+        },
+    );
+
+    for _ in 0..25 {
+        recorder.record(visit("draining", true));
+    }
+    let flushed = handle.shutdown().await;
+
+    assert_eq!(flushed, 25);
+    assert_eq!(stored_visits(&app.state.pool, "draining").await, 25);
+}
@@ -0,0 +1,1442 @@
+mod common;
+
+use axum::{
+    body::Body,
+    http::{header, Request, StatusCode},
+    routing::get,
+    Router,
+};
+use chrono::{DateTime, Duration, Timelike, Utc};
+use common::{body_bytes, json_body, spawn_app, spawn_app_with, TempDb, BASE_URL};
+use futures_util::StreamExt;
+use short_url_service::{auth::hash_token, health, reserved::ROUTE_SEGMENTS};
+use serde_json::json;
+use tower::ServiceExt;
+
+#[tokio::test]
+async fn shorten_generates_a_code() {
+    let app = spawn_app().await;
+
+    let link = app.shorten(json!({ "url": "https://example.com/page" })).await;
+
+    let code = link["short_code"].as_str().unwrap();
+    assert_eq!(code.len(), 6);
+    assert_eq!(link["original_url"], "https://example.com/page");
+    assert_eq!(link["short_url"], format!("{}/{}", BASE_URL, code));
+    assert!(link["delete_token"].is_string());
+}
+
+#[tokio::test]
+async fn shorten_uses_custom_code() {
+    let app = spawn_app().await;
+
+    let link = app
+        .shorten(json!({ "url": "https://example.com/", "custom_code": "my-link" }))
+        .await;
+
+    assert_eq!(link["short_code"], "my-link");
+    assert_eq!(link["short_url"], format!("{}/my-link", BASE_URL));
+}
+
+#[tokio::test]
+async fn shorten_rejects_taken_custom_code() {
+    let app = spawn_app().await;
+    app.shorten(json!({ "url": "https://example.com/a", "custom_code": "taken" }))
+        .await;
+
+    let response = app
+        .post_json("/shorten", json!({ "url": "https://example.com/b", "custom_code": "taken" }))
+        .await;
+
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    let body = json_body(response).await;
+    assert_eq!(body["code"], "CODE_TAKEN");
+    assert_eq!(body["details"]["short_code"], "taken");
+    assert_eq!(body["details"]["same_url"], false);
+    assert_eq!(body["details"]["suggestions"].as_array().unwrap().len(), 3);
+    // Still at the top level for older clients
+    assert_eq!(body["short_code"], "taken");
+
+    // Each suggestion was free when offered
+    for suggestion in body["details"]["suggestions"].as_array().unwrap() {
+        let created = app
+            .post_json("/shorten", json!({ "url": "https://example.com/b", "custom_code": suggestion }))
+            .await;
+        assert_eq!(created.status(), StatusCode::CREATED, "{}", suggestion);
+    }
+}
+
+#[tokio::test]
+async fn hash_strategy_maps_a_url_to_one_code() {
+    let app = spawn_app().await;
+    let body = json!({ "url": "https://example.com/docs/", "code_strategy": "hash" });
+
+    let first = app.shorten(body.clone()).await;
+    let response = app.post_json("/shorten", body).await;
+
+    // The trailing slash is normalized away before hashing
+    assert_eq!(first["short_code"], short_url_service::utils::hash_code("https://example.com/docs", 6));
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(json_body(response).await["short_code"], first["short_code"]);
+    let stats = app.stats_after_visits(&first, 0).await;
+    assert_eq!(stats["code_strategy"], "hash");
+
+    let both = app
+        .post_json("/shorten", json!({ "url": "https://example.com/", "custom_code": "mine", "code_strategy": "hash" }))
+        .await;
+    assert_eq!(both.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn hash_strategy_lengthens_past_other_urls() {
+    let app = spawn_app().await;
+    let full = short_url_service::utils::hash_code("https://example.com/wanted", 20);
+    app.shorten(json!({ "url": "https://example.com/squatter", "custom_code": &full[..6] }))
+        .await;
+
+    let link = app
+        .shorten(json!({ "url": "https://example.com/wanted", "code_strategy": "hash" }))
+        .await;
+    let again = app
+        .post_json("/shorten", json!({ "url": "https://example.com/wanted", "code_strategy": "hash" }))
+        .await;
+
+    assert_eq!(link["short_code"], &full[..7]);
+    assert_eq!(again.status(), StatusCode::OK);
+    assert_eq!(json_body(again).await["short_code"], &full[..7]);
+}
+
+#[tokio::test]
+async fn hash_strategy_only_reuses_links_with_the_same_settings() {
+    let app = spawn_app().await;
+    let url = "https://example.com/guide";
+    let full = short_url_service::utils::hash_code(url, 20);
+    let plain = app.shorten(json!({ "url": url, "code_strategy": "hash" })).await;
+    assert_eq!(plain["short_code"], &full[..6]);
+
+    // Other settings, and a password, get a longer code of their own
+    let permanent = app
+        .shorten(json!({ "url": url, "code_strategy": "hash", "redirect_type": 301 }))
+        .await;
+    assert_eq!(permanent["short_code"], &full[..7]);
+    let locked = app
+        .shorten(json!({ "url": url, "code_strategy": "hash", "password": "hunter22" }))
+        .await;
+    assert_eq!(locked["short_code"], &full[..8]);
+    let again = app
+        .post_json("/shorten", json!({ "url": url, "code_strategy": "hash", "redirect_type": 301 }))
+        .await;
+    assert_eq!(again.status(), StatusCode::OK);
+    assert_eq!(json_body(again).await["short_code"], &full[..7]);
+
+    // A disabled link is passed over too
+    let disable = Request::patch(format!("/urls/{}", &full[..6]))
+        .header(header::AUTHORIZATION, format!("Bearer {}", plain["delete_token"].as_str().unwrap()))
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(json!({ "active": false }).to_string()))
+        .unwrap();
+    assert_eq!(app.send(disable).await.status(), StatusCode::OK);
+    let replacement = app.shorten(json!({ "url": url, "code_strategy": "hash" })).await;
+    assert_eq!(replacement["short_code"], &full[..9]);
+}
+
+#[tokio::test]
+async fn deduplicate_only_reuses_links_with_the_same_settings() {
+    let url = "https://example.com/article";
+    let options = [
+        json!({ "redirect_type": 301 }),
+        json!({ "max_visits": 5 }),
+        json!({ "expires_at": (Utc::now() + Duration::days(1)).with_nanosecond(0).unwrap() }),
+        json!({ "forward_query": false }),
+        json!({ "ios_url": "https://apps.apple.com/app/id1" }),
+        json!({ "android_url": "https://example.org/android" }),
+    ];
+    for option in options {
+        let app = spawn_app().await;
+        let plain = json!({ "url": url, "deduplicate": true });
+        let existing = app.shorten(plain.clone()).await;
+        let mut differing = plain.clone();
+        differing.as_object_mut().unwrap().extend(option.as_object().unwrap().clone());
+
+        // Each way round: the new link must behave as asked for
+        let created = app.post_json("/shorten", differing.clone()).await;
+        assert_eq!(created.status(), StatusCode::CREATED, "{}", option);
+        let created = json_body(created).await;
+        assert_ne!(created["short_code"], existing["short_code"], "{}", option);
+        let again = app.post_json("/shorten", differing).await;
+        assert_eq!(again.status(), StatusCode::OK, "{}", option);
+        assert_eq!(json_body(again).await["short_code"], created["short_code"], "{}", option);
+        let again = app.post_json("/shorten", plain).await;
+        assert_eq!(again.status(), StatusCode::OK, "{}", option);
+        assert_eq!(json_body(again).await["short_code"], existing["short_code"], "{}", option);
+    }
+
+    // A link with a webhook is never handed out, as its secret is gone
+    let app = spawn_app().await;
+    let hooked = json!({ "url": url, "deduplicate": true, "webhook_url": "https://example.org/hook" });
+    let first = app.shorten(json!({ "url": url, "webhook_url": "https://example.org/hook" })).await;
+    assert_ne!(app.shorten(hooked).await["short_code"], first["short_code"]);
+    assert_ne!(app.shorten(json!({ "url": url, "deduplicate": true })).await["short_code"], first["short_code"]);
+
+    // Nor is a used-up or a rotating one
+    let app = spawn_app().await;
+    let limited = json!({ "url": url, "deduplicate": true, "max_visits": 1 });
+    let used = app.shorten(limited.clone()).await;
+    let code = used["short_code"].as_str().unwrap();
+    assert_eq!(app.get(&format!("/{}", code)).await.status(), StatusCode::TEMPORARY_REDIRECT);
+    assert_eq!(app.get(&format!("/{}", code)).await.status(), StatusCode::GONE);
+    assert_ne!(app.shorten(limited).await["short_code"], used["short_code"]);
+    let rotating = app
+        .shorten(json!({ "destinations": [{ "url": "https://example.com/rotating", "weight": 1 }, { "url": "https://example.org/b", "weight": 1 }] }))
+        .await;
+    let fresh = app.shorten(json!({ "url": "https://example.com/rotating", "deduplicate": true })).await;
+    assert_ne!(fresh["short_code"], rotating["short_code"]);
+}
+
+#[tokio::test]
+async fn hash_links_keep_their_url() {
+    let app = spawn_app().await;
+    let link = app
+        .shorten(json!({ "url": "https://example.com/fixed", "code_strategy": "hash" }))
+        .await;
+    let patch = |body: serde_json::Value| {
+        Request::patch(format!("/urls/{}", link["short_code"].as_str().unwrap()))
+            .header(header::AUTHORIZATION, format!("Bearer {}", link["delete_token"].as_str().unwrap()))
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    };
+
+    let moved = app.send(patch(json!({ "url": "https://example.com/moved" }))).await;
+    assert_eq!(moved.status(), StatusCode::BAD_REQUEST);
+    // Only the normalized URL counts
+    let same = app.send(patch(json!({ "url": "https://example.com/fixed/" }))).await;
+    assert_eq!(same.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn bulk_shorten_reports_each_item_and_rolls_back_on_failure() {
+    let app = spawn_app().await;
+    app.shorten(json!({ "url": "https://example.com/", "custom_code": "taken" })).await;
+
+    let response = app
+        .post_json(
+            "/shorten/bulk",
+            json!([
+                { "url": "https://example.com/a", "custom_code": "bulk-a" },
+                { "url": "not a url" },
+                { "url": "http://127.0.0.1/admin" },
+                { "url": "https://example.com/b", "custom_code": "taken" },
+                { "url": "https://example.com/c", "custom_code": "bulk-c" },
+            ]),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let results = json_body(response).await;
+    let statuses: Vec<_> = results.as_array().unwrap().iter().map(|item| (item["index"].clone(), item["status"].clone())).collect();
+    assert_eq!(
+        statuses,
+        [(0, "created"), (1, "invalid"), (2, "invalid"), (3, "conflict"), (4, "created")].map(|(index, status)| (json!(index), json!(status)))
+    );
+    assert_eq!(results[1]["fields"]["url"][0], "url must be an absolute URL such as https://example.com");
+    assert_eq!(results[2]["code"], "PRIVATE_DESTINATION");
+    for code in ["bulk-a", "bulk-c"] {
+        assert_eq!(app.get(&format!("/{}", code)).await.status(), StatusCode::TEMPORARY_REDIRECT, "{}", code);
+    }
+
+    // A write failing halfway leaves nothing of the batch behind
+    sqlx::query(
+        "CREATE TRIGGER fail_insert BEFORE INSERT ON urls WHEN NEW.original_url = 'https://example.com/boom'
+         BEGIN SELECT RAISE(ABORT, 'disk full'); END",
+    )
+    .execute(&app.state.pool)
+    .await
+    .unwrap();
+    let response = app
+        .post_json(
+            "/shorten/bulk",
+            json!([
+                { "url": "https://example.com/d", "custom_code": "bulk-d" },
+                { "url": "https://example.com/boom" },
+                { "url": "https://example.com/e", "custom_code": "bulk-e" },
+            ]),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    for code in ["bulk-d", "bulk-e"] {
+        assert_eq!(app.get(&format!("/{}", code)).await.status(), StatusCode::NOT_FOUND, "{}", code);
+    }
+    let audited: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM audit_log WHERE target_code = 'bulk-d'")
+        .fetch_one(&app.state.pool)
+        .await
+        .unwrap();
+    assert_eq!(audited, 0);
+}
+
+#[tokio::test]
+async fn delete_tokens_are_stored_hashed() {
+    let db = TempDb::new();
+    let app = spawn_app_with(&[("DATABASE_URL", &db.url())]).await;
+    let link = app.shorten(json!({ "url": "https://example.com/", "custom_code": "hashed" })).await;
+    let token = link["delete_token"].as_str().unwrap();
+    let stored: (Option<String>, Option<String>) =
+        sqlx::query_as("SELECT delete_token, delete_token_hash FROM urls WHERE id = 'hashed'")
+            .fetch_one(&app.state.pool)
+            .await
+            .unwrap();
+    assert_eq!(stored, (None, Some(hash_token(token))));
+
+    // Tokens kept in plain text before hashing are moved over at startup
+    sqlx::query("INSERT INTO urls (id, original_url, normalized_url, created_at, delete_token) VALUES ('legacy', 'https://example.com/', 'https://example.com/', ?, 'old-plain-token')")
+        .bind(Utc::now())
+        .execute(&app.state.pool)
+        .await
+        .unwrap();
+    let restarted = spawn_app_with(&[("DATABASE_URL", &db.url())]).await;
+    let stored: (Option<String>, Option<String>) =
+        sqlx::query_as("SELECT delete_token, delete_token_hash FROM urls WHERE id = 'legacy'")
+            .fetch_one(&restarted.state.pool)
+            .await
+            .unwrap();
+    assert_eq!(stored, (None, Some(hash_token("old-plain-token"))));
+    let delete = |token: &str| {
+        Request::delete("/legacy")
+            .header(header::AUTHORIZATION, format!("Bearer {}", token))
+            .body(Body::empty())
+            .unwrap()
+    };
+    assert_eq!(restarted.send(delete("old-plain-tokem")).await.status(), StatusCode::FORBIDDEN);
+    assert_eq!(restarted.send(delete("old-plain-token")).await.status(), StatusCode::NO_CONTENT);
+}
+
+#[tokio::test]
+async fn links_from_before_normalization_are_deduplicated() {
+    let db = TempDb::new();
+    let app = spawn_app_with(&[("DATABASE_URL", &db.url())]).await;
+    app.shorten(json!({ "url": "https://example.com/", "custom_code": "legacy" })).await;
+    // As stored before links were normalized, and left by the migration
+    sqlx::query("UPDATE urls SET original_url = 'HTTPS://Example.COM/Docs/', normalized_url = NULL WHERE id = 'legacy'")
+        .execute(&app.state.pool)
+        .await
+        .unwrap();
+
+    let restarted = spawn_app_with(&[("DATABASE_URL", &db.url())]).await;
+    let normalized: String = sqlx::query_scalar("SELECT normalized_url FROM urls WHERE id = 'legacy'")
+        .fetch_one(&restarted.state.pool)
+        .await
+        .unwrap();
+    assert_eq!(normalized, "https://example.com/Docs");
+    let response = restarted
+        .post_json("/shorten", json!({ "url": "https://example.com/Docs", "deduplicate": true }))
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(json_body(response).await["short_code"], "legacy");
+}
+
+#[tokio::test]
+async fn words_style_codes_redirect() {
+    let app = spawn_app_with(&[("CODE_STYLE", "words")]).await;
+
+    let link = app.shorten(json!({ "url": "https://example.com/talk" })).await;
+    let random = app
+        .shorten(json!({ "url": "https://example.com/other", "code_style": "random" }))
+        .await;
+
+    let code = link["short_code"].as_str().unwrap();
+    assert_eq!(code.split('-').count(), 3, "{}", code);
+    assert_eq!(random["short_code"].as_str().unwrap().len(), 6);
+    let response = app.get(&format!("/{}", code)).await;
+    assert_eq!(response.headers()[header::LOCATION], "https://example.com/talk");
+}
+
+#[tokio::test]
+async fn shorten_rejects_invalid_url() {
+    let app = spawn_app().await;
+
+    // Not a URL at all fails validation; a URL with the wrong scheme parses
+    // but can't be shortened
+    let response = app.post_json("/shorten", json!({ "url": "not a url" })).await;
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+    for (url, scheme) in [("ftp://example.com/file", "ftp"), ("javascript:alert(1)", "javascript")] {
+        let response = app.post_json("/shorten", json!({ "url": url })).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{}", url);
+        let body = json_body(response).await;
+        assert_eq!(body["code"], "UNSUPPORTED_SCHEME");
+        assert_eq!(body["error"], format!("Only http and https URLs can be shortened, got '{}:'", scheme));
+        assert_eq!(body["details"], json!({ "scheme": scheme }));
+    }
+}
+
+#[tokio::test]
+async fn validation_errors_list_every_bad_field() {
+    let app = spawn_app().await;
+    const CODE_LENGTH: &str = "custom_code must be between 3 and 32 characters";
+    const CODE_CHARSET: &str = "custom_code may only contain letters, digits, '-' and '_'";
+    const NOT_A_URL: &str = "url must be an absolute URL such as https://example.com";
+    const EXPIRY: &str = "expires_in_secs must be between 1 and 315360000 (ten years)";
+    let many_tags: Vec<_> = (0..11).map(|i| format!("tag-{}", i)).collect();
+    let long_tag = "x".repeat(33);
+    let cases = [
+        (json!({ "url": "https://example.com/", "custom_code": "ab" }), json!({ "custom_code": [CODE_LENGTH] })),
+        (json!({ "url": "https://example.com/", "custom_code": "x".repeat(33) }), json!({ "custom_code": [CODE_LENGTH] })),
+        (json!({ "url": "https://example.com/", "custom_code": "no spaces!" }), json!({ "custom_code": [CODE_CHARSET] })),
+        (json!({ "url": "mailto example.com" }), json!({ "url": [NOT_A_URL] })),
+        (json!({ "url": "https://example.com/", "tags": many_tags }), json!({ "tags": ["at most 10 tags are allowed"] })),
+        (
+            json!({ "url": "https://example.com/", "tags": ["ok", &long_tag] }),
+            json!({ "tags": [format!("tag '{}' must be 1-32 lowercase letters, digits or '-'", long_tag)] }),
+        ),
+        (json!({ "url": "https://example.com/", "expires_in_secs": 0 }), json!({ "expires_in_secs": [EXPIRY] })),
+        (json!({ "url": "https://example.com/", "expires_in_secs": -60 }), json!({ "expires_in_secs": [EXPIRY] })),
+        // Everything wrong with a request is reported at once
+        (
+            json!({ "url": "not a url", "custom_code": "a!", "tags": ["Bad"], "expires_in_secs": -1, "max_visits": 0 }),
+            json!({
+                "custom_code": [CODE_LENGTH, CODE_CHARSET],
+                "expires_in_secs": [EXPIRY],
+                "max_visits": ["max_visits must be at least 1"],
+                "tags": ["tag 'Bad' must be 1-32 lowercase letters, digits or '-'"],
+                "url": [NOT_A_URL],
+            }),
+        ),
+    ];
+    for (request, fields) in cases {
+        let response = app.post_json("/shorten", request.clone()).await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY, "{}", request);
+        let mut body = json_body(response).await;
+        assert!(body.as_object_mut().unwrap().remove("request_id").is_some());
+        assert_eq!(
+            body,
+            json!({ "error": "Validation failed", "code": "VALIDATION_FAILED", "details": { "fields": fields }, "fields": fields }),
+            "{}",
+            request
+        );
+    }
+}
+
+#[tokio::test]
+async fn rate_limited_errors_say_when_to_retry() {
+    let app = spawn_app_with(&[("SHORTEN_RATE_LIMIT", "1/1h")]).await;
+    app.shorten(json!({ "url": "https://example.com/" })).await;
+
+    let response = app.post_json("/shorten", json!({ "url": "https://example.com/" })).await;
+
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    let retry_after: u64 = response.headers()[header::RETRY_AFTER].to_str().unwrap().parse().unwrap();
+    let body = json_body(response).await;
+    assert_eq!(body["code"], "RATE_LIMITED");
+    assert_eq!(body["details"]["retry_after_secs"], retry_after);
+}
+
+#[tokio::test]
+async fn password_attempts_are_rate_limited() {
+    let app = spawn_app_with(&[("UNLOCK_RATE_LIMIT", "2/1h")]).await;
+    app.shorten(json!({ "url": "https://example.com/", "custom_code": "guarded", "password": "hunter22" }))
+        .await;
+    let attempt = |password: &str| {
+        Request::post("/guarded/unlock")
+            .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(Body::from(format!("password={}", password)))
+            .unwrap()
+    };
+
+    for _ in 0..2 {
+        assert_eq!(app.send(attempt("guess")).await.status(), StatusCode::UNAUTHORIZED);
+    }
+    // Even the right password waits once the limit is hit
+    let response = app.send(attempt("hunter22")).await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert!(response.headers().contains_key(header::RETRY_AFTER));
+    assert_eq!(json_body(response).await["code"], "RATE_LIMITED");
+}
+
+#[tokio::test]
+async fn shorten_rejects_reserved_code() {
+    let app = spawn_app_with(&[("RESERVED_CODES", "pricing, Team")]).await;
+
+    // Every documented route's first segment is on the list
+    let docs = json_body(app.get("/api-docs/openapi.json").await).await;
+    for path in docs["paths"].as_object().unwrap().keys() {
+        let segment = path.trim_start_matches('/').split('/').next().unwrap();
+        assert!(segment == "{code}" || ROUTE_SEGMENTS.contains(&segment), "{}", path);
+    }
+
+    let words = ROUTE_SEGMENTS.iter().copied().chain(["index.html", "404.html", "pricing", "team"]);
+    for word in words {
+        for code in [word.to_string(), word.to_uppercase()] {
+            let response = app
+                .post_json("/shorten", json!({ "url": "https://example.com/", "custom_code": &code }))
+                .await;
+            assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY, "{}", code);
+            // Words no custom code could spell fail validation first
+            let expected = match (3..=32).contains(&code.len()) && !code.contains('.') {
+                true => "CODE_RESERVED",
+                false => "VALIDATION_FAILED",
+            };
+            assert_eq!(json_body(response).await["code"], expected, "{}", code);
+        }
+    }
+}
+
+#[tokio::test]
+async fn redirect_sends_to_destination() {
+    let app = spawn_app().await;
+    app.shorten(json!({ "url": "https://example.com/target", "custom_code": "go-here" }))
+        .await;
+
+    let response = app.get("/go-here").await;
+
+    assert_eq!(response.status(), StatusCode::TEMPORARY_REDIRECT);
+    assert_eq!(response.headers()[header::LOCATION], "https://example.com/target");
+}
+
+#[tokio::test]
+async fn redirect_uses_requested_status() {
+    let app = spawn_app().await;
+    app.shorten(json!({ "url": "https://example.com/", "custom_code": "moved", "redirect_type": 301 }))
+        .await;
+
+    let response = app.get("/moved").await;
+
+    assert_eq!(response.status(), StatusCode::MOVED_PERMANENTLY);
+}
+
+#[tokio::test]
+async fn redirect_unknown_code_is_not_found() {
+    let app = spawn_app().await;
+
+    let response = app.get("/nope404").await;
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn redirect_expired_link_is_gone() {
+    let app = spawn_app().await;
+    app.shorten(json!({ "url": "https://example.com/", "custom_code": "old-link" }))
+        .await;
+    sqlx::query("UPDATE urls SET expires_at = ? WHERE id = ?")
+        .bind(Utc::now() - Duration::minutes(1))
+        .bind("old-link")
+        .execute(&app.state.pool)
+        .await
+        .unwrap();
+
+    let response = app.get("/old-link").await;
+
+    assert_eq!(response.status(), StatusCode::GONE);
+}
+
+#[tokio::test]
+async fn links_expire_at_the_exact_second() {
+    let app = spawn_app().await;
+    let link = app
+        .shorten(json!({ "url": "https://example.com/", "custom_code": "boundary" }))
+        .await;
+    app.shorten(json!({ "url": "https://example.com/", "custom_code": "still-open" }))
+        .await;
+    let now = Utc::now().with_nanosecond(0).unwrap();
+    for (code, expires_at) in [("boundary", now), ("still-open", now + Duration::seconds(30))] {
+        sqlx::query("UPDATE urls SET expires_at = ? WHERE id = ?")
+            .bind(expires_at)
+            .bind(code)
+            .execute(&app.state.pool)
+            .await
+            .unwrap();
+    }
+
+    let redirect = app.get("/boundary").await;
+    assert_eq!(redirect.status(), StatusCode::GONE);
+    let body = json_body(redirect).await;
+    assert_eq!(body["code"], "URL_EXPIRED");
+    let expired_at: DateTime<Utc> = serde_json::from_value(body["details"]["expired_at"].clone()).unwrap();
+    assert_eq!(expired_at, now);
+
+    let stats = Request::get("/stats/boundary")
+        .header("x-stats-token", link["stats_token"].as_str().unwrap())
+        .body(Body::empty())
+        .unwrap();
+    let stats = app.send(stats).await;
+    assert_eq!(stats.status(), StatusCode::GONE);
+    assert_eq!(json_body(stats).await["details"]["expired_at"], body["details"]["expired_at"]);
+
+    assert_eq!(app.get("/still-open").await.status(), StatusCode::TEMPORARY_REDIRECT);
+}
+
+#[tokio::test]
+async fn redirect_sends_ios_visitors_to_ios_url() {
+    let app = spawn_app().await;
+    app.shorten(json!({
+        "url": "https://example.com/",
+        "custom_code": "app-link",
+        "ios_url": "https://apps.apple.com/app/id1"
+    }))
+    .await;
+
+    let iphone = Request::get("/app-link")
+        .header(
+            header::USER_AGENT,
+            "Mozilla/5.0 (iPhone; CPU iPhone OS 17_0 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Mobile/15E148",
+        )
+        .body(Body::empty())
+        .unwrap();
+    let response = app.send(iphone).await;
+    assert_eq!(response.headers()[header::LOCATION], "https://apps.apple.com/app/id1");
+
+    let response = app.get("/app-link").await;
+    assert_eq!(response.headers()[header::LOCATION], "https://example.com/");
+}
+
+#[tokio::test]
+async fn stats_count_redirects() {
+    let app = spawn_app().await;
+    let link = app
+        .shorten(json!({ "url": "https://example.com/", "custom_code": "counted" }))
+        .await;
+    app.get("/counted").await;
+    app.get("/counted").await;
+
+    let stats = app.stats_after_visits(&link, 2).await;
+
+    assert_eq!(stats["url"], "counted");
+    assert_eq!(stats["redirect_visits"], 2);
+    assert_eq!(stats["unique_visitors"], 1);
+    assert_eq!(stats["visits"].as_array().unwrap().len(), 2);
+    let today = stats["daily_visits"].as_array().unwrap().last().unwrap();
+    assert_eq!(today["count"], 2);
+}
+
+#[tokio::test]
+async fn head_and_prefetch_requests_are_not_counted() {
+    let app = spawn_app().await;
+    let link = app
+        .shorten(json!({ "url": "https://example.com/", "custom_code": "peek", "max_visits": 1 }))
+        .await;
+
+    let head = app
+        .send(Request::head("/peek").body(Body::empty()).unwrap())
+        .await;
+    assert_eq!(head.status(), StatusCode::TEMPORARY_REDIRECT);
+    assert_eq!(head.headers()[header::LOCATION], "https://example.com/");
+    for (name, value) in [("sec-purpose", "prefetch;prerender"), ("purpose", "prefetch"), ("x-purpose", "preview")] {
+        let prefetch = Request::get("/peek").header(name, value).body(Body::empty()).unwrap();
+        assert_eq!(app.send(prefetch).await.status(), StatusCode::TEMPORARY_REDIRECT, "{}", name);
+    }
+
+    // The single allowed visit is still there for the real click
+    assert_eq!(app.get("/peek").await.status(), StatusCode::TEMPORARY_REDIRECT);
+    let stats = app.stats_after_visits(&link, 1).await;
+    assert_eq!(stats["redirect_visits"], 1);
+}
+
+#[tokio::test]
+async fn failed_redirects_leave_the_visit_allowance_alone() {
+    let app = spawn_app().await;
+    app.shorten(json!({ "url": "https://example.com/", "custom_code": "once", "max_visits": 1 }))
+        .await;
+    // A stored destination that can't be sent as a Location header
+    sqlx::query("UPDATE urls SET original_url = 'https://example.com/' || char(10) WHERE id = 'once'")
+        .execute(&app.state.pool)
+        .await
+        .unwrap();
+
+    assert_eq!(app.get("/once").await.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    let visit_count: i64 = sqlx::query_scalar("SELECT visit_count FROM urls WHERE id = 'once'")
+        .fetch_one(&app.state.pool)
+        .await
+        .unwrap();
+    assert_eq!(visit_count, 0);
+}
+
+#[tokio::test]
+async fn links_report_their_age_and_remaining_lifetime() {
+    let app = spawn_app().await;
+    let before = Utc::now();
+    let expiring = app
+        .shorten(json!({ "url": "https://example.com/", "custom_code": "brief", "expires_in_secs": 3600 }))
+        .await;
+    let lasting = app.shorten(json!({ "url": "https://example.com/", "custom_code": "lasting" })).await;
+
+    let created_at: chrono::DateTime<Utc> = expiring["created_at"].as_str().unwrap().parse().unwrap();
+    assert!(created_at >= before - Duration::seconds(1) && created_at <= Utc::now());
+    let info = json_body(app.get("/info/brief").await).await;
+    assert_eq!(info["created_at"], expiring["created_at"]);
+
+    let stats = app.stats_after_visits(&expiring, 0).await;
+    assert_eq!(stats["created_at"], expiring["created_at"]);
+    assert_eq!(stats["expires_at"], expiring["expires_at"]);
+    let remaining = stats["expires_in_secs"].as_i64().unwrap();
+    assert!((3590..=3600).contains(&remaining), "{}", remaining);
+
+    // Links without an expiry still carry the fields, as null
+    let stats = app.stats_after_visits(&lasting, 0).await;
+    let stats = stats.as_object().unwrap();
+    assert!(stats["created_at"].is_string());
+    assert!(stats["expires_at"].is_null() && stats["expires_in_secs"].is_null());
+}
+
+#[tokio::test]
+async fn stats_report_the_visit_span_and_daily_average() {
+    let app = spawn_app().await;
+    let link = app
+        .shorten(json!({ "url": "https://example.com/", "custom_code": "span", "stats_visibility": "public" }))
+        .await;
+
+    let stats = app.stats_after_visits(&link, 0).await;
+    assert!(stats["first_visit_at"].is_null() && stats["last_visit_at"].is_null());
+    assert_eq!(stats["avg_visits_per_day"], 0.0);
+
+    // On day one the average is the total
+    app.get("/span").await;
+    let stats = app.stats_after_visits(&link, 1).await;
+    assert_eq!(stats["first_visit_at"], stats["visits"][0]["visited_at"]);
+    assert_eq!(stats["last_visit_at"], stats["first_visit_at"]);
+    assert_eq!(stats["avg_visits_per_day"], 1.0);
+
+    for _ in 0..7 {
+        app.get("/span").await;
+    }
+    app.stats_after_visits(&link, 8).await;
+    // Spread the visits over the link's last 4 days, beyond the visit page
+    sqlx::query("UPDATE visits SET visited_at = datetime('now', '-' || (id % 4) || ' days') WHERE url_id = 'span'")
+        .execute(&app.state.pool)
+        .await
+        .unwrap();
+    sqlx::query("UPDATE urls SET created_at = ? WHERE id = 'span'")
+        .bind(Utc::now() - Duration::days(4))
+        .execute(&app.state.pool)
+        .await
+        .unwrap();
+    let stats = json_body(app.get("/stats/span?limit=1").await).await;
+
+    let first: chrono::DateTime<Utc> = stats["first_visit_at"].as_str().unwrap().parse().unwrap();
+    let last: chrono::DateTime<Utc> = stats["last_visit_at"].as_str().unwrap().parse().unwrap();
+    assert_eq!((last - first).num_days(), 3);
+    assert_eq!(stats["avg_visits_per_day"], 2.0);
+}
+
+#[tokio::test]
+async fn stats_summary_matches_the_full_stats() {
+    let app = spawn_app().await;
+    let link = app.shorten(json!({ "url": "https://example.com/", "custom_code": "tally" })).await;
+    let token = link["stats_token"].as_str().unwrap();
+    for agent in ["curl/8.0", "curl/8.0", "Wget/1.21"] {
+        app.send(Request::get("/tally").header(header::USER_AGENT, agent).body(Body::empty()).unwrap())
+            .await;
+    }
+    let stats = app.stats_after_visits(&link, 3).await;
+
+    assert_eq!(app.get("/stats/tally/summary").await.status(), StatusCode::FORBIDDEN);
+    let response = app.get(&format!("/stats/tally/summary?stats_token={}", token)).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let summary = json_body(response).await;
+
+    for field in ["url", "original_url", "active", "created_at", "expires_at", "total_visits", "unique_visitors"] {
+        assert_eq!(summary[field], stats[field], "{}", field);
+    }
+    assert_eq!(summary["unique_visitors"], 2);
+    assert_eq!(summary["last_visited_at"], stats["visits"][0]["visited_at"]);
+    assert!(summary.get("visits").is_none());
+    assert_eq!(app.get("/stats/missing/summary").await.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn bulk_stats_summary_answers_each_code() {
+    let app = spawn_app().await;
+    let private = app.shorten(json!({ "url": "https://example.com/a", "custom_code": "first" })).await;
+    app.shorten(json!({ "url": "https://example.com/b", "custom_code": "second", "stats_visibility": "public" }))
+        .await;
+    app.shorten(json!({ "url": "https://example.com/c", "custom_code": "third" })).await;
+    app.get("/second").await;
+    let stats = app.stats_after_visits(&json!({ "short_code": "second" }), 1).await;
+
+    let response = app
+        .post_json(
+            "/stats/summary",
+            json!({
+                "codes": ["first", "second", "third", "missing", "second"],
+                "stats_tokens": { "first": private["stats_token"] }
+            }),
+        )
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let summaries = json_body(response).await;
+
+    assert_eq!(summaries.as_object().unwrap().len(), 4);
+    let single = json_body(
+        app.get(&format!("/stats/first/summary?stats_token={}", private["stats_token"].as_str().unwrap()))
+            .await,
+    )
+    .await;
+    assert_eq!(summaries["first"], single);
+    assert_eq!(summaries["second"]["total_visits"], stats["total_visits"]);
+    assert_eq!(summaries["second"]["last_visited_at"], stats["visits"][0]["visited_at"]);
+    assert_eq!(summaries["third"]["code"], "FORBIDDEN");
+    assert_eq!(summaries["missing"]["code"], "URL_NOT_FOUND");
+
+    for codes in [json!([]), json!(vec!["first"; 101])] {
+        let response = app.post_json("/stats/summary", json!({ "codes": codes })).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+}
+
+#[tokio::test]
+async fn stats_heatmap_buckets_visits_in_the_viewers_time_zone() {
+    let app = spawn_app().await;
+    app.shorten(json!({ "url": "https://example.com/", "custom_code": "clock", "stats_visibility": "public" }))
+        .await;
+    for visited_at in [
+        "2023-11-19T23:30:00Z", // Sunday
+        "2023-11-19T23:59:59Z",
+        "2023-11-20T09:10:00Z", // Monday
+        "2023-07-02T03:00:00Z", // Sunday, during US daylight saving time
+    ] {
+        sqlx::query("INSERT INTO visits (url_id, visited_at) VALUES ('clock', ?)")
+            .bind(visited_at.parse::<chrono::DateTime<Utc>>().unwrap())
+            .execute(&app.state.pool)
+            .await
+            .unwrap();
+    }
+    let cells = |stats: &serde_json::Value| -> Vec<(u64, u64, i64)> {
+        let heatmap = stats["heatmap"].as_array().unwrap();
+        assert_eq!(heatmap.len(), 7 * 24);
+        heatmap
+            .iter()
+            .filter(|cell| cell["count"] != 0)
+            .map(|cell| (cell["weekday"].as_u64().unwrap(), cell["hour"].as_u64().unwrap(), cell["count"].as_i64().unwrap()))
+            .collect()
+    };
+
+    let stats = json_body(app.get("/stats/clock?include=heatmap").await).await;
+    assert_eq!(cells(&stats), [(0, 3, 1), (0, 23, 2), (1, 9, 1)]);
+    // India is 5:30 ahead; New York is 4 hours behind in July and 5 in November
+    let stats = json_body(app.get("/stats/clock?include=heatmap&tz=Asia/Kolkata").await).await;
+    assert_eq!(cells(&stats), [(0, 8, 1), (1, 5, 2), (1, 14, 1)]);
+    let stats = json_body(app.get("/stats/clock?include=heatmap&tz=America/New_York").await).await;
+    assert_eq!(cells(&stats), [(0, 18, 2), (1, 4, 1), (6, 23, 1)]);
+
+    let stats = json_body(app.get("/stats/clock").await).await;
+    assert!(stats.get("heatmap").is_none());
+    for query in ["include=heatmap&tz=Mars/Olympus", "include=everything"] {
+        let response = app.get(&format!("/stats/clock?{}", query)).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{}", query);
+    }
+}
+
+#[tokio::test]
+async fn stats_unknown_code_is_not_found() {
+    let app = spawn_app().await;
+
+    let response = app.get("/stats/missing").await;
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn private_stats_need_the_stats_token() {
+    let app = spawn_app().await;
+    let link = app
+        .shorten(json!({ "url": "https://example.com/", "custom_code": "secret" }))
+        .await;
+    assert_eq!(link["stats_visibility"], "private");
+    let token = link["stats_token"].as_str().unwrap();
+
+    for uri in ["/stats/secret", "/stats/secret/export.csv", "/stats/secret/live"] {
+        assert_eq!(app.get(uri).await.status(), StatusCode::FORBIDDEN, "{}", uri);
+    }
+    let wrong = Request::get("/stats/secret")
+        .header("x-stats-token", "not-the-token")
+        .body(Body::empty())
+        .unwrap();
+    assert_eq!(app.send(wrong).await.status(), StatusCode::FORBIDDEN);
+
+    let by_header = Request::get("/stats/secret")
+        .header("x-stats-token", token)
+        .body(Body::empty())
+        .unwrap();
+    assert_eq!(app.send(by_header).await.status(), StatusCode::OK);
+    let export = app
+        .get(&format!("/stats/secret/export.csv?stats_token={}", token))
+        .await;
+    assert_eq!(export.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn live_stats_stream_each_visit_of_the_link() {
+    let app = spawn_app().await;
+    app.shorten(json!({ "url": "https://example.com/", "custom_code": "watched", "stats_visibility": "public" }))
+        .await;
+    app.shorten(json!({ "url": "https://example.org/", "custom_code": "ignored" }))
+        .await;
+    let response = app.get("/stats/watched/live").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[header::CONTENT_TYPE], "text/event-stream");
+    let mut frames = response.into_body().into_data_stream();
+
+    let visit = |code: &str, referrer: &str| {
+        Request::get(format!("/{}", code))
+            .header(header::REFERER, referrer)
+            .body(Body::empty())
+            .unwrap()
+    };
+    app.send(visit("ignored", "https://other.example/")).await;
+    app.send(visit("watched", "https://news.example/")).await;
+
+    let frame = tokio::time::timeout(std::time::Duration::from_secs(5), async {
+        loop {
+            let frame = frames.next().await.expect("stream stays open").unwrap();
+            let frame = String::from_utf8(frame.to_vec()).unwrap();
+            if frame.contains("data:") {
+                return frame;
+            }
+        }
+    })
+    .await
+    .expect("a visit event arrives");
+
+    assert!(frame.starts_with("event: visit\n"), "{}", frame);
+    let data = frame.lines().find_map(|line| line.strip_prefix("data: ")).unwrap();
+    let visit: serde_json::Value = serde_json::from_str(data).unwrap();
+    assert_eq!(visit["referrer"], "https://news.example/");
+    assert_eq!(visit["source"], "redirect");
+}
+
+#[tokio::test]
+async fn public_stats_are_open() {
+    let app = spawn_app().await;
+    let link = app
+        .shorten(json!({ "url": "https://example.com/", "custom_code": "open", "stats_visibility": "public" }))
+        .await;
+    assert_eq!(link["stats_visibility"], "public");
+    assert!(link.get("stats_token").is_none());
+
+    let response = app.get("/stats/open").await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn password_protected_destinations_are_only_shown_to_the_owner() {
+    let app = spawn_app().await;
+    let link = app
+        .shorten(json!({
+            "url": "https://example.com/secret",
+            "ios_url": "https://apps.apple.com/app/id1",
+            "custom_code": "hushed",
+            "password": "hunter22",
+            "stats_visibility": "public"
+        }))
+        .await;
+    let owner = format!("Bearer {}", link["delete_token"].as_str().unwrap());
+    let get = |uri: &str, auth: Option<&str>| {
+        let mut request = Request::get(uri);
+        if let Some(auth) = auth {
+            request = request.header(header::AUTHORIZATION, auth);
+        }
+        app.send(request.body(Body::empty()).unwrap())
+    };
+
+    for (uri, fields) in [
+        ("/info/hushed", &["original_url", "ios_url", "android_url", "title"][..]),
+        ("/stats/hushed", &["original_url"][..]),
+        ("/stats/hushed/summary", &["original_url", "title"][..]),
+    ] {
+        let public = json_body(get(uri, None).await).await;
+        for field in fields {
+            assert!(public[field].is_null(), "{} {}: {}", uri, field, public);
+        }
+        let strangers = json_body(get(uri, Some("Bearer wrong")).await).await;
+        assert!(strangers["original_url"].is_null(), "{}: {}", uri, strangers);
+
+        let owned = json_body(get(uri, Some(&owner)).await).await;
+        assert_eq!(owned["original_url"], "https://example.com/secret", "{}", uri);
+    }
+    let owned = json_body(get("/info/hushed", Some(&owner)).await).await;
+    assert_eq!(owned["ios_url"], "https://apps.apple.com/app/id1");
+    let by_param = json_body(get(&format!("/info/hushed?token={}", link["delete_token"].as_str().unwrap()), None).await).await;
+    assert_eq!(by_param["original_url"], "https://example.com/secret");
+}
+
+#[tokio::test]
+async fn password_protected_destinations_stay_out_of_exports_and_live_stats() {
+    let app = spawn_app().await;
+    let link = app
+        .shorten(json!({
+            "destinations": [{ "url": "https://example.com/secret", "weight": 1 }],
+            "custom_code": "hushed",
+            "password": "hunter22",
+            "stats_visibility": "public"
+        }))
+        .await;
+    let owner = format!("Bearer {}", link["delete_token"].as_str().unwrap());
+    let live = app.get("/stats/hushed/live").await;
+    let mut frames = live.into_body().into_data_stream();
+
+    let unlock = Request::post("/hushed/unlock")
+        .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+        .body(Body::from("password=hunter22"))
+        .unwrap();
+    assert_eq!(app.send(unlock).await.status(), StatusCode::SEE_OTHER);
+
+    let frame = tokio::time::timeout(std::time::Duration::from_secs(5), async {
+        loop {
+            let frame = frames.next().await.expect("stream stays open").unwrap();
+            let frame = String::from_utf8(frame.to_vec()).unwrap();
+            if frame.contains("data:") {
+                return frame;
+            }
+        }
+    })
+    .await
+    .expect("a visit event arrives");
+    let data = frame.lines().find_map(|line| line.strip_prefix("data: ")).unwrap();
+    let visit: serde_json::Value = serde_json::from_str(data).unwrap();
+    assert_eq!(visit["source"], "redirect");
+    assert!(visit["destination"].is_null(), "{}", visit);
+
+    app.stats_after_visits(&link, 1).await;
+    let export = |auth: Option<&str>| {
+        let mut request = Request::get("/stats/hushed/export.csv");
+        if let Some(auth) = auth {
+            request = request.header(header::AUTHORIZATION, auth);
+        }
+        app.send(request.body(Body::empty()).unwrap())
+    };
+    let public = String::from_utf8(body_bytes(export(None).await).await).unwrap();
+    assert_eq!(public.lines().count(), 2);
+    assert!(!public.contains("example.com/secret"), "{}", public);
+    let owned = String::from_utf8(body_bytes(export(Some(&owner)).await).await).unwrap();
+    assert!(owned.contains("https://example.com/secret"), "{}", owned);
+}
+
+#[tokio::test]
+async fn namespaced_keys_scope_custom_codes_and_stats() {
+    let app = spawn_app_with(&[("API_KEYS", "team-a:key-a,team-b:key-b,plain-key")]).await;
+    let shorten_as = |key: &'static str, url: &'static str| {
+        let request = Request::post("/shorten")
+            .header(header::CONTENT_TYPE, "application/json")
+            .header("x-api-key", key)
+            .body(Body::from(json!({ "url": url, "custom_code": "promo" }).to_string()))
+            .unwrap();
+        app.send(request)
+    };
+
+    let team_a = json_body(shorten_as("key-a", "https://example.com/a").await).await;
+    let team_b = json_body(shorten_as("key-b", "https://example.com/b").await).await;
+    let plain = json_body(shorten_as("plain-key", "https://example.com/c").await).await;
+
+    assert_eq!(team_a["short_code"], "team-a~promo");
+    assert_eq!(team_a["short_url"], format!("{}/team-a~promo", BASE_URL));
+    assert_eq!(team_b["short_code"], "team-b~promo");
+    assert_eq!(plain["short_code"], "promo");
+    let redirect = app.get("/team-b~promo").await;
+    assert_eq!(redirect.headers()[header::LOCATION], "https://example.com/b");
+
+    let taken = shorten_as("key-a", "https://example.com/other").await;
+    assert_eq!(taken.status(), StatusCode::CONFLICT);
+    let body = json_body(taken).await;
+    assert_eq!(body["details"]["short_code"], "team-a~promo");
+    // Suggestions are custom codes to send back as they are
+    assert!(!body["details"]["suggestions"][0].as_str().unwrap().contains('~'));
+
+    let stats_as = |key: &'static str| {
+        app.send(
+            Request::get("/stats/team-a~promo")
+                .header("x-api-key", key)
+                .body(Body::empty())
+                .unwrap(),
+        )
+    };
+    assert_eq!(stats_as("key-a").await.status(), StatusCode::OK);
+    assert_eq!(stats_as("key-b").await.status(), StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn qr_renders_svg_by_default() {
+    let app = spawn_app().await;
+    app.shorten(json!({ "url": "https://example.com/", "custom_code": "qr-svg" }))
+        .await;
+
+    let response = app.get("/qr/qr-svg").await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[header::CONTENT_TYPE], "image/svg+xml");
+    let body = String::from_utf8(body_bytes(response).await).unwrap();
+    assert!(body.contains("<svg"));
+}
+
+#[tokio::test]
+async fn qr_renders_png() {
+    let app = spawn_app().await;
+    app.shorten(json!({ "url": "https://example.com/", "custom_code": "qr-png" }))
+        .await;
+
+    let response = app.get("/qr/qr-png?format=png&size=128").await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[header::CONTENT_TYPE], "image/png");
+    assert!(body_bytes(response).await.starts_with(b"\x89PNG"));
+}
+
+#[tokio::test]
+async fn qr_answers_conditional_requests() {
+    let app = spawn_app().await;
+    app.shorten(json!({ "url": "https://example.com/", "custom_code": "qr-etag" }))
+        .await;
+    let get = |uri: &str, if_none_match: Option<&str>| {
+        let mut request = Request::get(uri);
+        if let Some(tag) = if_none_match {
+            request = request.header(header::IF_NONE_MATCH, tag);
+        }
+        app.send(request.body(Body::empty()).unwrap())
+    };
+
+    let response = get("/qr/qr-etag?format=png", None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[header::CACHE_CONTROL], "public, max-age=86400");
+    let etag = response.headers()[header::ETAG].to_str().unwrap().to_string();
+    assert!(etag.starts_with('"') && etag.ends_with('"'), "{}", etag);
+
+    // The same image is not sent again, whichever way the tag is listed
+    for tag in [etag.clone(), format!("W/{}", etag), "*".into(), format!("\"other\", {}", etag)] {
+        let response = get("/qr/qr-etag?format=png", Some(&tag)).await;
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED, "{}", tag);
+        assert_eq!(response.headers()[header::ETAG], etag.as_str());
+        assert_eq!(response.headers()[header::CACHE_CONTROL], "public, max-age=86400");
+        assert!(body_bytes(response).await.is_empty(), "{}", tag);
+    }
+    let response = get("/qr/qr-etag?format=png", Some("\"other\"")).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // Any change to the image is a new one
+    let mut tags = vec![etag.clone()];
+    for uri in ["/qr/qr-etag?format=png&size=512", "/qr/qr-etag", "/qr/qr-etag?format=png&fg=112233", "/qr/qr-etag?format=png&bg=ffeeee"] {
+        let response = get(uri, Some(&etag)).await;
+        assert_eq!(response.status(), StatusCode::OK, "{}", uri);
+        let changed = response.headers()[header::ETAG].to_str().unwrap().to_string();
+        assert!(!tags.contains(&changed), "{}", uri);
+        assert!(!body_bytes(response).await.is_empty(), "{}", uri);
+        tags.push(changed);
+    }
+}
+
+#[tokio::test]
+async fn qr_rejects_unknown_format_and_code() {
+    let app = spawn_app().await;
+    app.shorten(json!({ "url": "https://example.com/", "custom_code": "qr-bad" }))
+        .await;
+
+    let response = app.get("/qr/qr-bad?format=gif").await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let response = app.get("/qr/missing").await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn temporary_redirects_carry_privacy_and_cache_headers() {
+    let app = spawn_app().await;
+    app.shorten(json!({ "url": "https://example.com/", "custom_code": "headers" }))
+        .await;
+
+    let response = app.get("/headers").await;
+
+    assert_eq!(response.status(), StatusCode::TEMPORARY_REDIRECT);
+    let headers = response.headers();
+    assert_eq!(headers["x-robots-tag"], "noindex");
+    assert_eq!(headers[header::REFERRER_POLICY], "no-referrer");
+    assert_eq!(headers[header::CACHE_CONTROL], "private, max-age=90");
+}
+
+#[tokio::test]
+async fn permanent_redirects_skip_cache_control() {
+    let app = spawn_app().await;
+    app.shorten(json!({ "url": "https://example.com/", "custom_code": "forever", "redirect_type": 308 }))
+        .await;
+
+    let response = app.get("/forever").await;
+
+    assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT);
+    assert_eq!(response.headers()["x-robots-tag"], "noindex");
+    assert!(response.headers().get(header::CACHE_CONTROL).is_none());
+}
+
+#[tokio::test]
+async fn redirect_headers_follow_config() {
+    let app = spawn_app_with(&[
+        ("REFERRER_POLICY", "strict-origin"),
+        ("TEMPORARY_REDIRECT_CACHE_CONTROL", "no-store"),
+    ])
+    .await;
+    app.shorten(json!({ "url": "https://example.com/", "custom_code": "tuned" }))
+        .await;
+
+    let response = app.get("/tuned").await;
+
+    assert_eq!(response.headers()[header::REFERRER_POLICY], "strict-origin");
+    assert_eq!(response.headers()[header::CACHE_CONTROL], "no-store");
+}
+
+#[tokio::test]
+async fn readiness_follows_the_database() {
+    let app = spawn_app().await;
+    let ready = app.get("/readyz").await;
+    assert_eq!(ready.status(), StatusCode::OK);
+    assert_eq!(json_body(ready).await, json!({ "status": "ready", "checks": { "database": "ok" } }));
+
+    app.state.pool.close().await;
+    let dropped = app.get("/readyz").await;
+
+    assert_eq!(dropped.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(
+        json_body(dropped).await,
+        json!({ "status": "unavailable", "checks": { "database": "failed" } })
+    );
+    // Liveness doesn't depend on the database
+    assert_eq!(app.get("/healthz").await.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn pool_handlers_behave_the_same_on_app_state() {
+    let app = spawn_app().await;
+    // readyz extracts State<DbPool>; on AppState it gets the pool through FromRef
+    let on_state = Router::new().route("/readyz", get(health::readyz)).with_state(app.state.clone());
+    let on_pool = Router::new().route("/readyz", get(health::readyz)).with_state(app.state.pool.clone());
+    let readiness = |router: Router| async move {
+        let response = router.oneshot(Request::get("/readyz").body(Body::empty()).unwrap()).await.unwrap();
+        (response.status(), json_body(response).await)
+    };
+
+    let live = readiness(on_pool.clone()).await;
+    assert_eq!(live.0, StatusCode::OK);
+    assert_eq!(readiness(on_state.clone()).await, live);
+
+    app.state.pool.close().await;
+    let closed = readiness(on_pool).await;
+    assert_eq!(closed.0, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(readiness(on_state).await, closed);
+}
+
+#[tokio::test]
+async fn robots_txt_keeps_crawlers_off_short_links() {
+    let app = spawn_app().await;
+
+    let response = app.get("/robots.txt").await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = String::from_utf8(body_bytes(response).await).unwrap();
+    assert!(body.contains(BASE_URL));
+    assert!(body.contains("User-agent: *\nAllow: /$\nDisallow: /\n"));
+}
+
+async fn fetch_docs(app: &common::TestApp, accept_encoding: Option<&str>) -> (Option<String>, Vec<u8>) {
+    let mut request = Request::get("/api-docs/openapi.json");
+    if let Some(encoding) = accept_encoding {
+        request = request.header(header::ACCEPT_ENCODING, encoding);
+    }
+    let response = app.send(request.body(Body::empty()).unwrap()).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let encoding = response
+        .headers()
+        .get(header::CONTENT_ENCODING)
+        .map(|value| value.to_str().unwrap().to_string());
+    (encoding, body_bytes(response).await)
+}
+
+#[tokio::test]
+async fn responses_are_compressed_when_accepted() {
+    let app = spawn_app().await;
+
+    let (encoding, plain) = fetch_docs(&app, None).await;
+    assert_eq!(encoding, None);
+
+    let (encoding, gzip) = fetch_docs(&app, Some("gzip")).await;
+    assert_eq!(encoding.as_deref(), Some("gzip"));
+    assert_eq!(gzip[..2], [0x1f, 0x8b]);
+    assert!(gzip.len() < plain.len() / 2);
+
+    // Brotli wins when both are acceptable
+    let (encoding, br) = fetch_docs(&app, Some("gzip, br")).await;
+    assert_eq!(encoding.as_deref(), Some("br"));
+    assert!(br.len() < plain.len() / 2);
+
+    let (encoding, _) = fetch_docs(&app, Some("zstd")).await;
+    assert_eq!(encoding, None);
+}
+
+#[tokio::test]
+async fn compression_can_be_turned_off() {
+    let app = spawn_app_with(&[("COMPRESS_RESPONSES", "false")]).await;
+
+    let (encoding, _) = fetch_docs(&app, Some("gzip, br")).await;
+
+    assert_eq!(encoding, None);
+}
+
+#[tokio::test]
+async fn oversized_bodies_get_a_json_413() {
+    let app = spawn_app_with(&[("MAX_REQUEST_BODY_BYTES", "1024")]).await;
+    let body = json!({ "url": format!("https://example.com/{}", "a".repeat(2000)) }).to_string();
+
+    // Refused up front from the declared length
+    let declared = Request::post("/shorten")
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(header::CONTENT_LENGTH, body.len())
+        .body(Body::from(body.clone()))
+        .unwrap();
+    // And cut off while reading when there is no length
+    let streamed = Request::post("/shorten")
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body))
+        .unwrap();
+
+    for request in [declared, streamed] {
+        let response = app.send(request).await;
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+        let error = json_body(response).await;
+        assert_eq!(error["code"], "PAYLOAD_TOO_LARGE");
+    }
+    // Bodies under the limit still go through
+    app.shorten(json!({ "url": "https://example.com/" })).await;
+}
+
+#[tokio::test]
+async fn slow_requests_time_out_with_a_json_504() {
+    let app = spawn_app_with(&[("REQUEST_TIMEOUT_SECS", "1")]).await;
+    // Holding the pool's only connection stalls every handler that needs
+    // the database, like a long-running write would
+    let _held = app.state.pool.acquire().await.unwrap();
+
+    let started = std::time::Instant::now();
+    let response = app.post_json("/shorten", json!({ "url": "https://example.com/" })).await;
+
+    assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+    assert!(started.elapsed() < std::time::Duration::from_secs(5));
+    let error = json_body(response).await;
+    assert_eq!(error["code"], "REQUEST_TIMEOUT");
+    assert_eq!(error["error"], "Request timed out");
+}
+
+#[tokio::test]
+async fn requests_over_the_concurrency_limit_are_shed() {
+    let app = std::sync::Arc::new(spawn_app_with(&[("MAX_CONCURRENT_REQUESTS", "1"), ("REQUEST_TIMEOUT_SECS", "2")]).await);
+    let held = app.state.pool.acquire().await.unwrap();
+    let stalled = tokio::spawn({
+        let app = app.clone();
+        async move { app.get("/info/anything").await.status() }
+    });
+    // Let the stalled request take the only slot
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+
+    let response = app.get("/info/anything").await;
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(response.headers()[header::RETRY_AFTER], "1");
+    assert_eq!(json_body(response).await["code"], "OVERLOADED");
+    // Probes are outside the limit
+    assert_eq!(app.get("/healthz").await.status(), StatusCode::OK);
+
+    drop(held);
+    assert_eq!(stalled.await.unwrap(), StatusCode::NOT_FOUND);
+    assert_eq!(app.get("/info/anything").await.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn error_bodies_share_one_shape() {
+    let app = spawn_app_with(&[("ADMIN_API_KEYS", "admin-key")]).await;
+    let link = app
+        .shorten(json!({ "url": "https://example.com/", "custom_code": "taken", "stats_visibility": "public" }))
+        .await;
+    app.shorten(json!({ "url": "https://example.com/old", "custom_code": "stale" })).await;
+    sqlx::query("UPDATE urls SET expires_at = ? WHERE id = 'stale'")
+        .bind(Utc::now() - Duration::hours(1))
+        .execute(&app.state.pool)
+        .await
+        .unwrap();
+    let post = |uri: &str, body: &str| {
+        Request::post(uri)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    };
+    let get = |uri: &str| Request::get(uri).body(Body::empty()).unwrap();
+    let wrong_token = Request::delete("/taken")
+        .header(header::AUTHORIZATION, "Bearer wrong")
+        .body(Body::empty())
+        .unwrap();
+
+    let cases = [
+        (get("/stats/nope"), StatusCode::NOT_FOUND, "URL_NOT_FOUND", &[][..]),
+        (get("/stale"), StatusCode::GONE, "URL_EXPIRED", &["expired_at"][..]),
+        (post("/shorten", r#"{"url": "https://example.com/", "custom_code": "a!"}"#), StatusCode::UNPROCESSABLE_ENTITY, "VALIDATION_FAILED", &["fields"]),
+        (post("/shorten", r#"{"url": "ftp://example.com/"}"#), StatusCode::BAD_REQUEST, "UNSUPPORTED_SCHEME", &["scheme"]),
+        (post("/shorten", r#"{"url": "#), StatusCode::BAD_REQUEST, "BAD_REQUEST", &[]),
+        (get("/stats/taken?from=2024-02-01T00:00:00Z&to=2024-01-01T00:00:00Z"), StatusCode::BAD_REQUEST, "INVALID_TIME_RANGE", &[]),
+        (post("/shorten", r#"{"url": "https://example.com/", "custom_code": "taken"}"#), StatusCode::CONFLICT, "CODE_TAKEN", &["short_code", "same_url", "suggestions"]),
+        (post("/shorten", r#"{"url": "https://example.com/", "custom_code": "admin"}"#), StatusCode::UNPROCESSABLE_ENTITY, "CODE_RESERVED", &[]),
+        (get("/admin/summary"), StatusCode::UNAUTHORIZED, "UNAUTHORIZED", &[]),
+        (wrong_token, StatusCode::FORBIDDEN, "FORBIDDEN", &[]),
+    ];
+    for (request, status, code, details) in cases {
+        let uri = request.uri().to_string();
+        let response = app.send(request).await;
+        assert_eq!(response.status(), status, "{}", uri);
+        assert!(response.headers().get(header::RETRY_AFTER).is_none(), "{}", uri);
+        let id = response.headers()["x-request-id"].to_str().unwrap().to_string();
+        let body = json_body(response).await;
+        assert_eq!(body["code"], code, "{}: {}", uri, body);
+        assert!(!body["error"].as_str().unwrap().is_empty(), "{}", uri);
+        assert_eq!(body["request_id"], id, "{}", uri);
+        match body.get("details") {
+            Some(found) => {
+                let mut keys: Vec<_> = found.as_object().unwrap().keys().map(String::as_str).collect();
+                keys.sort_unstable();
+                let mut expected = details.to_vec();
+                expected.sort_unstable();
+                assert_eq!(keys, expected, "{}", uri);
+            }
+            None => assert!(details.is_empty(), "{}: {}", uri, body),
+        }
+    }
+    assert_eq!(link["short_code"], "taken");
+
+    // Retryable errors say when, in the header and the body
+    let app = spawn_app_with(&[("SHORTEN_RATE_LIMIT", "1/1h")]).await;
+    app.shorten(json!({ "url": "https://example.com/" })).await;
+    let response = app.post_json("/shorten", json!({ "url": "https://example.com/" })).await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    let retry_after: u64 = response.headers()[header::RETRY_AFTER].to_str().unwrap().parse().unwrap();
+    let id = response.headers()["x-request-id"].to_str().unwrap().to_string();
+    let body = json_body(response).await;
+    assert_eq!(body["code"], "RATE_LIMITED");
+    assert_eq!(body["details"], json!({ "retry_after_secs": retry_after }));
+    assert_eq!(body["request_id"], id);
+}
+
+#[tokio::test]
+async fn errors_carry_the_request_id() {
+    let app = spawn_app().await;
+    // Every query on links now fails, so lookups answer 500
+    sqlx::query("ALTER TABLE urls RENAME TO urls_gone")
+        .execute(&app.state.pool)
+        .await
+        .unwrap();
+
+    let response = app.get("/info/anything").await;
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    let id = response.headers()["x-request-id"].to_str().unwrap().to_string();
+    assert_eq!(id.len(), 36);
+    let error = json_body(response).await;
+    assert_eq!(error["code"], "INTERNAL_ERROR");
+    assert_eq!(error["request_id"], id);
+
+    // An id set by a proxy in front is kept
+    let request = Request::get("/info/anything")
+        .header("x-request-id", "edge-7f3a")
+        .body(Body::empty())
+        .unwrap();
+    let response = app.send(request).await;
+    assert_eq!(response.headers()["x-request-id"], "edge-7f3a");
+    assert_eq!(json_body(response).await["request_id"], "edge-7f3a");
+}
@@ -0,0 +1,99 @@
+mod common;
+
+use axum::{
+    body::Body,
+    http::{header, Request, StatusCode},
+};
+use common::{body_bytes, json_body, spawn_app_with};
+use serde_json::json;
+
+#[tokio::test]
+async fn blocklisted_domains_and_subdomains_are_refused() {
+    let app = spawn_app_with(&[("BLOCKED_DESTINATIONS", "evil.example, *.Phish.Example.")]).await;
+
+    for url in ["https://evil.example/", "https://login.EVIL.example./reset", "http://a.b.phish.example/"] {
+        let response = app.post_json("/shorten", json!({ "url": url })).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{}", url);
+        let body = json_body(response).await;
+        assert_eq!(body["code"], "BLOCKED_DESTINATION");
+        assert!(body["details"]["domain"].as_str().unwrap().ends_with(".example"));
+    }
+    // Only whole labels match
+    app.shorten(json!({ "url": "https://notevil.example/" })).await;
+
+    // Alternates and changed destinations too
+    let response = app
+        .post_json("/shorten", json!({ "url": "https://ok.example/", "ios_url": "https://evil.example/app" }))
+        .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let link = app.shorten(json!({ "url": "https://ok.example/" })).await;
+    let patch = Request::patch(format!("/urls/{}", link["short_code"].as_str().unwrap()))
+        .header("content-type", "application/json")
+        .header("authorization", format!("Bearer {}", link["delete_token"].as_str().unwrap()))
+        .body(Body::from(json!({ "url": "https://www.evil.example/" }).to_string()))
+        .unwrap();
+    assert_eq!(app.send(patch).await.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn flagged_links_warn_before_redirecting() {
+    let path = std::env::temp_dir().join(format!("blocklist-{}.txt", std::process::id()));
+    std::fs::write(&path, "# nothing yet\n").unwrap();
+    let app = spawn_app_with(&[("BLOCKED_DESTINATIONS_PATH", path.to_str().unwrap())]).await;
+    let link = app
+        .shorten(json!({ "url": "https://shop.flagged.example/deal", "custom_code": "deal" }))
+        .await;
+
+    // Flagged after the link was made
+    std::fs::write(&path, "flagged.example # reported 2023-11-20\n").unwrap();
+    assert_eq!(app.state.blocklist.reload().unwrap(), 1);
+    std::fs::remove_file(&path).unwrap();
+
+    let response = app.get("/deal").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[header::CACHE_CONTROL], "no-store");
+    let page = String::from_utf8(body_bytes(response).await).unwrap();
+    assert!(page.contains("flagged.example"));
+    assert!(page.contains(r#"action="/deal/continue""#));
+    let stats = json_body(
+        app.send(
+            Request::get("/stats/deal")
+                .header("x-stats-token", link["stats_token"].as_str().unwrap())
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await,
+    )
+    .await;
+    assert_eq!(stats["total_visits"], 0);
+
+    let response = app
+        .send(Request::post("/deal/continue").body(Body::empty()).unwrap())
+        .await;
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+    assert_eq!(response.headers()[header::LOCATION], "https://shop.flagged.example/deal");
+    app.stats_after_visits(&link, 1).await;
+
+    // A failed reload keeps the list in effect
+    assert!(app.state.blocklist.reload().is_err());
+    assert_eq!(app.get("/deal").await.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn continuing_still_needs_the_password() {
+    let path = std::env::temp_dir().join(format!("blocklist-locked-{}.txt", std::process::id()));
+    std::fs::write(&path, "").unwrap();
+    let app = spawn_app_with(&[("BLOCKED_DESTINATIONS_PATH", path.to_str().unwrap())]).await;
+    app.shorten(json!({ "url": "https://flagged.example/", "custom_code": "locked", "password": "hunter22" }))
+        .await;
+    std::fs::write(&path, "flagged.example").unwrap();
+    app.state.blocklist.reload().unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let response = app
+        .send(Request::post("/locked/continue").body(Body::empty()).unwrap())
+        .await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let page = String::from_utf8(body_bytes(response).await).unwrap();
+    assert!(page.contains(r#"action="/locked/unlock""#));
+}
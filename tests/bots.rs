@@ -0,0 +1,184 @@
+mod common;
+
+use axum::{
+    body::Body,
+    http::{header, Request},
+};
+use common::{json_body, spawn_app_with};
+use serde_json::json;
+use short_url_service::utils::is_bot;
+
+const BOTS: &[&str] = &[
+    "Mozilla/5.0 (compatible; Googlebot/2.1; +http://www.google.com/bot.html)",
+    "Mozilla/5.0 AppleWebKit/537.36 (KHTML, like Gecko; compatible; Googlebot/2.1; +http://www.google.com/bot.html) Chrome/119.0.6045.105 Safari/537.36",
+    "Mozilla/5.0 (compatible; bingbot/2.0; +http://www.bing.com/bingbot.htm)",
+    "Mozilla/5.0 (compatible; Yahoo! Slurp; http://help.yahoo.com/help/us/ysearch/slurp)",
+    "DuckDuckBot-Https/1.1; (+https://duckduckgo.com/duckduckbot)",
+    "Mozilla/5.0 (compatible; Baiduspider/2.0; +http://www.baidu.com/search/spider.html)",
+    "Mozilla/5.0 (compatible; YandexBot/3.0; +http://yandex.com/bots)",
+    "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_5) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/13.1.1 Safari/605.1.15 (Applebot/0.1; +http://www.apple.com/go/applebot)",
+    "facebookexternalhit/1.1 (+http://www.facebook.com/externalhit_uatext.php)",
+    "facebookexternalhit/1.1 Facebot Twitterbot/1.0",
+    "Twitterbot/1.0",
+    "Slackbot-LinkExpanding 1.0 (+https://api.slack.com/robots)",
+    "Slackbot 1.0 (+https://api.slack.com/robots)",
+    "LinkedInBot/1.0 (compatible; Mozilla/5.0; Apache-HttpClient +http://www.linkedin.com)",
+    "Mozilla/5.0 (compatible; Discordbot/2.0; +https://discordapp.com)",
+    "TelegramBot (like TwitterBot)",
+    "WhatsApp/2.23.20.0 A",
+    "Mozilla/5.0 (Windows NT 6.1; WOW64) SkypeUriPreview Preview/0.5 skype-url-preview@microsoft.com",
+    "Mozilla/5.0 (compatible; redditbot/1.0; +http://www.reddit.com/feedback)",
+    "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) HeadlessChrome/119.0.0.0 Safari/537.36",
+    "Mastodon/4.2.1 (http.rb/5.1.1; +https://mastodon.social/)",
+];
+
+const PEOPLE: &[&str] = &[
+    "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/119.0.0.0 Safari/537.36",
+    "Mozilla/5.0 (iPhone; CPU iPhone OS 17_1 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.1 Mobile/15E148 Safari/604.1",
+    "Mozilla/5.0 (Linux; Android 10; CUBOT P30) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/119.0.0.0 Mobile Safari/537.36",
+    "Mozilla/5.0 (X11; Linux x86_64; rv:120.0) Gecko/20100101 Firefox/120.0",
+    // In-app browsers of apps whose fetchers are bots
+    "Mozilla/5.0 (iPhone; CPU iPhone OS 17_1 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Mobile/15E148 [FBAN/FBIOS;FBAV/440.0.0.32.114]",
+    "Mozilla/5.0 (Linux; Android 13; Pixel 7) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/119.0.0.0 Mobile Safari/537.36 Instagram 307.0.0.34.111 Android",
+    "curl/8.4.0",
+    "",
+];
+
+#[test]
+fn classifies_crawlers_and_preview_fetchers() {
+    for ua in BOTS {
+        assert!(is_bot(Some(ua), &[]), "{}", ua);
+    }
+    for ua in PEOPLE {
+        assert!(!is_bot(Some(ua), &[]), "{}", ua);
+    }
+    assert!(!is_bot(None, &[]));
+}
+
+#[test]
+fn extra_patterns_extend_the_bundled_list() {
+    let extra = vec!["uptime-monitor".to_string()];
+
+    assert!(is_bot(Some("Our-Uptime-Monitor 2.0"), &extra));
+    assert!(!is_bot(Some("Our-Uptime-Monitor 2.0"), &[]));
+    assert!(is_bot(Some("Twitterbot/1.0"), &extra));
+}
+
+#[tokio::test]
+async fn bot_visits_are_left_out_of_totals() {
+    let app = spawn_app_with(&[("BOT_USER_AGENTS", " Uptime-Monitor ,")]).await;
+    let link = app
+        .shorten(json!({ "url": "https://example.com/", "custom_code": "viral", "stats_visibility": "public" }))
+        .await;
+
+    // The person visits last, so once it's stored the bots are too
+    for agent in ["Slackbot-LinkExpanding 1.0 (+https://api.slack.com/robots)", "Twitterbot/1.0", "uptime-monitor/1", PEOPLE[0]] {
+        app.send(Request::get("/viral").header(header::USER_AGENT, agent).body(Body::empty()).unwrap())
+            .await;
+    }
+    // Count-only visits are classified too
+    let untracked = Request::get("/viral")
+        .header(header::USER_AGENT, "facebookexternalhit/1.1")
+        .header("dnt", "1")
+        .body(Body::empty())
+        .unwrap();
+    app.send(untracked).await;
+    app.send(Request::get("/viral").header(header::USER_AGENT, PEOPLE[1]).body(Body::empty()).unwrap())
+        .await;
+    let stats = app.stats_after_visits(&link, 2).await;
+
+    assert_eq!(stats["bot_visits"], 4);
+    assert_eq!(stats["untracked_visits"], 1);
+    let flags: Vec<_> = stats["visits"].as_array().unwrap().iter().map(|visit| visit["is_bot"].clone()).collect();
+    assert_eq!(flags, [json!(false), json!(false), json!(true), json!(true), json!(true)]);
+
+    let raw = json_body(app.get("/stats/viral?include_bots=true").await).await;
+    assert_eq!(raw["total_visits"], 6);
+    let summary = json_body(app.get("/stats/viral/summary").await).await;
+    assert_eq!(summary["total_visits"], 2);
+}
+
+#[tokio::test]
+async fn bot_visits_are_left_out_of_every_breakdown() {
+    let app = spawn_app_with(&[]).await;
+    let link = app
+        .shorten(json!({ "url": "https://example.com/", "custom_code": "crawled", "stats_visibility": "public" }))
+        .await;
+    let visits = [("Twitterbot/1.0", "https://t.co/abc"), (PEOPLE[3], "https://news.ycombinator.com/item")];
+    for (agent, referrer) in visits {
+        let request = Request::get("/crawled")
+            .header(header::USER_AGENT, agent)
+            .header(header::REFERER, referrer)
+            .body(Body::empty())
+            .unwrap();
+        app.send(request).await;
+    }
+    // Only the person counts, and the bot visited first
+    app.stats_after_visits(&link, 1).await;
+
+    let sum = |counts: &serde_json::Value, field: &str| {
+        counts.as_array().unwrap().iter().map(|count| count[field].as_i64().unwrap()).sum::<i64>()
+    };
+    for (query, expected) in [("", 1), ("&include_bots=true", 2)] {
+        let stats = json_body(app.get(&format!("/stats/crawled?include=heatmap{}", query)).await).await;
+        assert_eq!(stats["total_visits"], expected, "{}", query);
+        assert_eq!(stats["redirect_visits"], expected, "{}", query);
+        assert_eq!(stats["beacon_visits"], 0, "{}", query);
+        assert_eq!(stats["unique_visitors"], expected, "{}", query);
+        assert_eq!(sum(&stats["daily_visits"], "count"), expected, "{}", query);
+        assert_eq!(sum(&stats["top_referrers"], "count"), expected, "{}", query);
+        assert_eq!(sum(&stats["browsers"], "count"), expected, "{}", query);
+        assert_eq!(sum(&stats["operating_systems"], "count"), expected, "{}", query);
+        assert_eq!(sum(&stats["device_types"], "count"), expected, "{}", query);
+        assert_eq!(sum(&stats["platforms"], "count"), expected, "{}", query);
+        assert_eq!(sum(&stats["heatmap"], "count"), expected, "{}", query);
+        // The list keeps both, flagged
+        assert_eq!(stats["visits"].as_array().unwrap().len(), 2, "{}", query);
+    }
+    let stats = json_body(app.get("/stats/crawled").await).await;
+    assert_eq!(stats["top_referrers"], json!([{ "referrer": "news.ycombinator.com", "count": 1 }]));
+    assert_eq!(stats["browsers"], json!([{ "name": "Firefox", "count": 1 }]));
+
+    let summary = json_body(app.get("/stats/crawled/summary").await).await;
+    assert_eq!((summary["total_visits"].clone(), summary["unique_visitors"].clone()), (json!(1), json!(1)));
+    let summary = json_body(app.get("/stats/crawled/summary?include_bots=true").await).await;
+    assert_eq!((summary["total_visits"].clone(), summary["unique_visitors"].clone()), (json!(2), json!(2)));
+    let bulk = json_body(app.post_json("/stats/summary", json!({ "codes": ["crawled"], "include_bots": true })).await).await;
+    assert_eq!(bulk["crawled"]["unique_visitors"], 2);
+}
+
+#[tokio::test]
+async fn bot_visits_are_left_out_of_campaign_and_admin_totals() {
+    let app = spawn_app_with(&[("ADMIN_API_KEYS", "admin-key")]).await;
+    let admin = |method: &str, uri: &str, body: Option<serde_json::Value>| {
+        Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("x-api-key", "admin-key")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(body.map_or_else(Body::empty, |body| Body::from(body.to_string())))
+            .unwrap()
+    };
+    let campaign = json_body(app.send(admin("POST", "/admin/campaigns", Some(json!({ "name": "Launch" })))).await).await;
+    let id = campaign["id"].as_i64().unwrap();
+    let link = app
+        .shorten(json!({ "url": "https://example.com/", "custom_code": "indexed", "stats_visibility": "public", "campaign_id": id }))
+        .await;
+    for agent in ["Googlebot/2.1 (+http://www.google.com/bot.html)", PEOPLE[2]] {
+        app.send(Request::get("/indexed").header(header::USER_AGENT, agent).body(Body::empty()).unwrap())
+            .await;
+    }
+    app.stats_after_visits(&link, 1).await;
+
+    for (query, expected) in [("", 1), ("include_bots=true", 2)] {
+        let stats = json_body(app.send(admin("GET", &format!("/admin/campaigns/{}/stats?{}", id, query), None)).await).await;
+        assert_eq!(stats["total_visits"], expected, "{}", query);
+        assert_eq!(stats["links"][0]["total_visits"], expected, "{}", query);
+        let daily: i64 = stats["daily_visits"].as_array().unwrap().iter().map(|day| day["count"].as_i64().unwrap()).sum();
+        assert_eq!(daily, expected, "{}", query);
+
+        let summary = json_body(app.send(admin("GET", &format!("/admin/summary?{}", query), None)).await).await;
+        assert_eq!(summary["total_visits"], expected, "{}", query);
+        assert_eq!(summary["top_links"][0]["visits"], expected, "{}", query);
+    }
+}
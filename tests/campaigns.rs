@@ -0,0 +1,141 @@
+mod common;
+
+use axum::{
+    body::Body,
+    http::{Method, Request, StatusCode},
+};
+use chrono::{Duration, Utc};
+use common::{json_body, spawn_app_with, TestApp};
+use serde_json::{json, Value};
+
+const ADMIN_KEY: &str = "admin-key";
+
+async fn admin(app: &TestApp, method: Method, uri: &str, body: Option<Value>) -> (StatusCode, Value) {
+    let request = Request::builder()
+        .method(method)
+        .uri(uri)
+        .header("x-api-key", ADMIN_KEY)
+        .header("content-type", "application/json")
+        .body(body.map_or_else(Body::empty, |body| Body::from(body.to_string())))
+        .unwrap();
+    let response = app.send(request).await;
+    let status = response.status();
+    let body = if status == StatusCode::NO_CONTENT {
+        Value::Null
+    } else {
+        json_body(response).await
+    };
+    (status, body)
+}
+
+async fn insert_visits(app: &TestApp, code: &str, ago: Duration, count: i64) {
+    for _ in 0..count {
+        sqlx::query("INSERT INTO visits (url_id, visited_at) VALUES (?, ?)")
+            .bind(code)
+            .bind(Utc::now() - ago)
+            .execute(&app.state.pool)
+            .await
+            .unwrap();
+    }
+    sqlx::query("UPDATE urls SET total_visits = total_visits + ? WHERE id = ?")
+        .bind(count)
+        .bind(code)
+        .execute(&app.state.pool)
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn campaign_stats_add_up_their_links() {
+    let app = spawn_app_with(&[("ADMIN_API_KEYS", ADMIN_KEY)]).await;
+    let (status, campaign) = admin(&app, Method::POST, "/admin/campaigns", Some(json!({ "name": " Q3 launch " }))).await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(campaign["name"], "Q3 launch");
+    assert_eq!(campaign["links"], 0);
+    let id = campaign["id"].as_i64().unwrap();
+
+    let launch = app
+        .shorten(json!({ "url": "https://example.com/launch", "custom_code": "launch", "campaign_id": id }))
+        .await;
+    assert_eq!(launch["campaign_id"], id);
+    let blog = app.shorten(json!({ "url": "https://example.com/blog", "custom_code": "blog" })).await;
+    assert!(blog["campaign_id"].is_null());
+    app.shorten(json!({ "url": "https://example.com/other", "custom_code": "other" })).await;
+    // Joining later, through the owner's PATCH
+    let token = blog["delete_token"].as_str().unwrap();
+    let patch = Request::patch("/urls/blog")
+        .header("content-type", "application/json")
+        .header("authorization", format!("Bearer {}", token))
+        .body(Body::from(json!({ "campaign_id": id }).to_string()))
+        .unwrap();
+    let response = app.send(patch).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(json_body(response).await["campaign_id"], id);
+
+    insert_visits(&app, "launch", Duration::zero(), 3).await;
+    insert_visits(&app, "launch", Duration::days(2), 2).await;
+    insert_visits(&app, "blog", Duration::zero(), 1).await;
+    insert_visits(&app, "other", Duration::zero(), 7).await;
+
+    let (status, stats) = admin(&app, Method::GET, &format!("/admin/campaigns/{}/stats?days=3", id), None).await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(stats["name"], "Q3 launch");
+    assert_eq!(stats["total_visits"], 6);
+    assert_eq!(
+        stats["links"],
+        json!([
+            { "short_code": "launch", "original_url": "https://example.com/launch", "total_visits": 5 },
+            { "short_code": "blog", "original_url": "https://example.com/blog", "total_visits": 1 },
+        ])
+    );
+    let daily: Vec<i64> = stats["daily_visits"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|day| day["count"].as_i64().unwrap())
+        .collect();
+    assert_eq!(daily, [2, 0, 4]);
+
+    let (_, listed) = admin(&app, Method::GET, "/admin/campaigns", None).await;
+    assert_eq!(listed["items"][0]["links"], 2);
+}
+
+#[tokio::test]
+async fn deleting_a_campaign_keeps_its_links() {
+    let app = spawn_app_with(&[("ADMIN_API_KEYS", ADMIN_KEY)]).await;
+    let (_, campaign) = admin(&app, Method::POST, "/admin/campaigns", Some(json!({ "name": "Spring" }))).await;
+    let id = campaign["id"].as_i64().unwrap();
+    app.shorten(json!({ "url": "https://example.com/", "custom_code": "spring", "campaign_id": id }))
+        .await;
+    let uri = format!("/admin/campaigns/{}", id);
+
+    let (status, renamed) = admin(&app, Method::PATCH, &uri, Some(json!({ "name": "Spring sale" }))).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(renamed["name"], "Spring sale");
+    assert_eq!(renamed["links"], 1);
+    assert_eq!(admin(&app, Method::DELETE, &uri, None).await.0, StatusCode::NO_CONTENT);
+
+    let info = json_body(app.get("/info/spring").await).await;
+    assert_eq!(info["short_code"], "spring");
+    assert!(info["campaign_id"].is_null());
+    assert_eq!(app.get("/spring").await.status(), StatusCode::TEMPORARY_REDIRECT);
+    let (status, body) = admin(&app, Method::GET, &uri, None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["code"], "CAMPAIGN_NOT_FOUND");
+    assert_eq!(admin(&app, Method::DELETE, &uri, None).await.0, StatusCode::NOT_FOUND);
+    assert_eq!(admin(&app, Method::GET, "/admin/campaigns/spring/stats", None).await.0, StatusCode::NOT_FOUND);
+
+    // Unknown campaigns and bad names are refused
+    let response = app
+        .post_json("/shorten", json!({ "url": "https://example.com/", "campaign_id": id }))
+        .await;
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    assert!(json_body(response).await["details"]["fields"]["campaign_id"].is_array());
+    for name in ["", "   ", &"x".repeat(101)] {
+        let (status, _) = admin(&app, Method::POST, "/admin/campaigns", Some(json!({ "name": name }))).await;
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    }
+    let response = app.get("/admin/campaigns").await;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
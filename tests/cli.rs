@@ -0,0 +1,121 @@
+mod common;
+
+use chrono::{Duration, Utc};
+use common::{spawn_app, spawn_seeded_app, TempDb};
+use serde_json::{json, Value};
+use short_url_service::{
+    cli::{self, Expiry},
+    error::AppError,
+};
+use std::process::{Command, Output};
+
+#[test]
+fn expiries_are_times_or_durations() {
+    assert_eq!(cli::parse_expiry("90s"), Ok(Expiry::In(90)));
+    assert_eq!(cli::parse_expiry("12h"), Ok(Expiry::In(12 * 3600)));
+    assert_eq!(cli::parse_expiry("7d"), Ok(Expiry::In(7 * 86_400)));
+    assert_eq!(
+        cli::parse_expiry("2030-01-02T03:04:05+01:00"),
+        Ok(Expiry::At("2030-01-02T02:04:05Z".parse().unwrap()))
+    );
+    for bad in ["", "7", "0d", "-1h", "soon", "3w", "99999999999999999d"] {
+        assert!(cli::parse_expiry(bad).is_err(), "{}", bad);
+    }
+}
+
+#[tokio::test]
+async fn commands_share_the_api_logic() {
+    let app = spawn_app().await;
+
+    let link = cli::shorten(&app.state, "https://example.com/".into(), Some("cli".into()), Some(Expiry::In(3600)))
+        .await
+        .unwrap();
+    assert_eq!(link.short_url, "http://sho.rt/cli");
+    assert!(link.stats_token.is_some());
+    assert!(cli::render_link(&link).starts_with("http://sho.rt/cli -> https://example.com/\n  expires:"));
+
+    // Same validation as POST /shorten
+    let taken = cli::shorten(&app.state, "https://example.org/".into(), Some("cli".into()), None).await;
+    assert!(matches!(taken, Err(AppError::CodeAlreadyExists { .. })), "{:?}", taken);
+    let (status, message) = cli::render_error(taken.unwrap_err(), true);
+    assert_eq!(status, cli::EXIT_FAILURE);
+    assert_eq!(serde_json::from_str::<Value>(&message).unwrap()["code"], "CODE_TAKEN");
+
+    // Private stats need no token here
+    let summary = cli::stats(&app.state, "cli").await.unwrap();
+    assert_eq!(summary.total_visits, 0);
+    assert!(cli::render_summary(&summary).contains("  last visit:      never\n"));
+
+    let missing = cli::stats(&app.state, "nope").await.unwrap_err();
+    assert_eq!(cli::render_error(missing, false), (cli::EXIT_NOT_FOUND, "error: URL not found".to_string()));
+}
+
+#[tokio::test]
+async fn purge_and_export_work_on_the_database() {
+    let app = spawn_seeded_app().await;
+    // Long past the one-week grace period
+    let expired = Utc::now() - Duration::days(30);
+    sqlx::query("UPDATE urls SET expires_at = ? WHERE id = 'sqlite'")
+        .bind(expired)
+        .execute(&app.state.pool)
+        .await
+        .unwrap();
+
+    assert!(cli::render_summary(&cli::stats(&app.state, "sqlite").await.unwrap()).contains("(expired)"));
+    assert_eq!(cli::purge_expired(&app.state).await.unwrap(), 1);
+    assert_eq!(cli::purge_expired(&app.state).await.unwrap(), 0);
+
+    let mut out = Vec::new();
+    cli::export(&app.state, &mut out).await.unwrap();
+    let codes: Vec<String> = String::from_utf8(out)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str::<Value>(line).unwrap()["short_code"].as_str().unwrap().to_string())
+        .collect();
+    assert_eq!(codes.len(), 4);
+    assert!(!codes.contains(&"sqlite".to_string()));
+}
+
+fn run(db: &TempDb, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_short-url-service"))
+        .args(args)
+        .env("DATABASE_URL", db.url())
+        .env("BASE_URL", "http://sho.rt")
+        .env("VISITOR_HASH_SALT", "test-salt")
+        .env("ALLOWED_PRIVATE_DESTINATIONS", "example.com")
+        .env_remove("RUST_LOG")
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn the_binary_reports_results_and_exit_codes() {
+    let db = TempDb::new();
+
+    let created = run(&db, &["shorten", "https://example.com/docs", "--code", "bin", "--json"]);
+    assert!(created.status.success(), "{}", String::from_utf8_lossy(&created.stderr));
+    let link: Value = serde_json::from_slice(&created.stdout).unwrap();
+    assert_eq!(link["short_url"], "http://sho.rt/bin");
+
+    let stats = run(&db, &["stats", "bin"]);
+    assert_eq!(stats.status.code(), Some(0));
+    assert!(String::from_utf8(stats.stdout).unwrap().starts_with("bin -> https://example.com/docs\n"));
+
+    let missing = run(&db, &["stats", "nope", "--json"]);
+    assert_eq!(missing.status.code(), Some(3));
+    assert!(missing.stdout.is_empty());
+    assert_eq!(
+        serde_json::from_slice::<Value>(&missing.stderr)
+            .unwrap_or_else(|e| panic!("{}: {}", e, String::from_utf8_lossy(&missing.stderr))),
+        json!({ "error": "URL not found", "code": "URL_NOT_FOUND" })
+    );
+
+    let taken = run(&db, &["shorten", "https://example.com/", "--code", "bin"]);
+    assert_eq!(taken.status.code(), Some(1));
+    assert_eq!(run(&db, &["shorten", "https://example.com/", "--expires", "soon"]).status.code(), Some(2));
+
+    let export = run(&db, &["export", "--format", "jsonl"]);
+    assert_eq!(String::from_utf8(export.stdout).unwrap().lines().count(), 1);
+    let purged = run(&db, &["purge-expired", "--json"]);
+    assert_eq!(serde_json::from_slice::<Value>(&purged.stdout).unwrap(), json!({ "purged": 0 }));
+}
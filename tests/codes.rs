@@ -0,0 +1,108 @@
+use short_url_service::{
+    models::is_code_char,
+    utils::{hash_code, suggest_codes, CodeAlphabet, CodeGenerator, CodeStyle, HASH_CODE_MAX_LEN, MIN_CODE_SPACE},
+    words,
+};
+use std::collections::HashSet;
+
+#[test]
+fn hash_codes_are_stable() {
+    // Pinned so a change to the encoding, which would move every hash link,
+    // can't slip through
+    assert_eq!(hash_code("https://example.com/docs", 8), "5vS3EUWd");
+    assert_eq!(hash_code("https://example.com/docs", 8), hash_code("https://example.com/docs", 8));
+}
+
+#[test]
+fn longer_hash_codes_extend_shorter_ones() {
+    let full = hash_code("https://example.com/docs", HASH_CODE_MAX_LEN);
+
+    assert_eq!(full, "5vS3EUWdssjONpYNGB0dwoVaucueVZHjStlrpyCLoeq");
+    for len in 1..HASH_CODE_MAX_LEN {
+        assert_eq!(hash_code("https://example.com/docs", len), full[..len]);
+    }
+    assert_eq!(hash_code("https://example.com/docs", 100), full);
+}
+
+#[test]
+fn hash_codes_are_base62_and_differ_per_url() {
+    let codes: Vec<String> = (0..200)
+        .map(|i| hash_code(&format!("https://example.com/page/{}", i), 6))
+        .collect();
+
+    for code in &codes {
+        assert_eq!(code.len(), 6);
+        assert!(code.chars().all(|c| c.is_ascii_alphanumeric()), "{}", code);
+    }
+    let mut distinct = codes.clone();
+    distinct.sort();
+    distinct.dedup();
+    assert_eq!(distinct.len(), codes.len());
+    assert_eq!(hash_code("", 0), "");
+}
+
+#[test]
+fn random_style_uses_the_alphabet_and_length() {
+    let generator = CodeGenerator::new(CodeStyle::Random, CodeAlphabet::Unambiguous, 9);
+
+    for _ in 0..200 {
+        let code = generator.generate();
+        assert_eq!(code.len(), 9);
+        assert!(code.bytes().all(|b| CodeAlphabet::Unambiguous.chars().contains(&b)), "{}", code);
+    }
+}
+
+#[test]
+fn words_style_builds_adjective_noun_number() {
+    let generator = CodeGenerator::new(CodeStyle::Words, CodeAlphabet::Base62, 6);
+    let digits = words::number_digits() as usize;
+
+    for _ in 0..200 {
+        let code = generator.generate();
+        let parts: Vec<&str> = code.split('-').collect();
+        assert_eq!(parts.len(), 3, "{}", code);
+        assert!(words::adjectives().contains(&parts[0]), "{}", code);
+        assert!(words::nouns().contains(&parts[1]), "{}", code);
+        assert_eq!(parts[2].len(), digits, "{}", code);
+        assert!(parts[2].bytes().all(|b| b.is_ascii_digit()), "{}", code);
+        // Still a valid custom code, so it can be recreated by hand
+        assert!(code.len() <= 32, "{}", code);
+    }
+}
+
+#[test]
+fn word_lists_clear_the_entropy_floor() {
+    for list in [words::adjectives(), words::nouns()] {
+        let distinct: HashSet<_> = list.iter().collect();
+        assert_eq!(distinct.len(), list.len());
+        for word in list {
+            assert!((2..=7).contains(&word.len()), "{}", word);
+            assert!(word.bytes().all(|b| b.is_ascii_lowercase()), "{}", word);
+        }
+    }
+    let space = (words::adjectives().len() * words::nouns().len()) as f64 * 10f64.powi(words::number_digits() as i32);
+    assert!(space >= MIN_CODE_SPACE);
+    // The longest possible code stays short enough to read out
+    assert!(7 + 1 + 7 + 1 + words::number_digits() <= 20);
+}
+
+#[test]
+fn suggestions_are_distinct_valid_custom_codes() {
+    let long = "a".repeat(40);
+    for code in ["sale", "spring_sale-2024", long.as_str()] {
+        for count in [0, 1, 3, 6] {
+            let suggestions = suggest_codes(code, count);
+            assert_eq!(suggestions.len(), count, "{}", code);
+            let distinct: HashSet<_> = suggestions.iter().collect();
+            assert_eq!(distinct.len(), count, "{:?}", suggestions);
+            for suggestion in &suggestions {
+                assert!((3..=32).contains(&suggestion.len()), "{}", suggestion);
+                assert!(suggestion.chars().all(is_code_char), "{}", suggestion);
+                assert_ne!(suggestion, code);
+            }
+        }
+    }
+    // The code stays recognizable, with only as much cut as the suffix needs
+    assert!(suggest_codes("sale", 1)[0].starts_with("sale-"));
+    assert!(suggest_codes(&long, 1)[0].starts_with(&long[..28]));
+}
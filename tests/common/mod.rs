@@ -0,0 +1,204 @@
+//! Shared harness: the real router over a fresh in-memory SQLite database,
+//! driven with `oneshot` so no server or external database is needed.
+
+#![allow(dead_code)]
+
+use axum::{
+    body::{to_bytes, Body},
+    extract::ConnectInfo,
+    http::{header, Request, Response, StatusCode},
+    Router,
+};
+use serde_json::Value;
+use short_url_service::{build_app, build_state, config::Config, seed, state::AppState, visits::RecorderHandle};
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU32, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tower::ServiceExt;
+
+pub const BASE_URL: &str = "http://sho.rt";
+const CLIENT_ADDR: &str = "203.0.113.7:50000";
+
+/// An on-disk database for tests that need real concurrent connections,
+/// removed (with its WAL files) on drop.
+pub struct TempDb {
+    path: PathBuf,
+}
+
+impl TempDb {
+    pub fn new() -> Self {
+        static NEXT: AtomicU32 = AtomicU32::new(0);
+        let name = format!(
+            "short-url-test-{}-{}.db",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        );
+        TempDb {
+            path: std::env::temp_dir().join(name),
+        }
+    }
+
+    pub fn url(&self) -> String {
+        format!("sqlite:{}?mode=rwc", self.path.display())
+    }
+}
+
+impl Drop for TempDb {
+    fn drop(&mut self) {
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = std::fs::remove_file(format!("{}{}", self.path.display(), suffix));
+        }
+    }
+}
+
+/// A loopback port that counts the connections made to it and answers none
+/// of them, for checking what the service's outgoing requests connect to.
+pub struct ConnectionCounter {
+    pub port: u16,
+    connections: Arc<AtomicUsize>,
+}
+
+impl ConnectionCounter {
+    pub async fn spawn() -> Self {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let connections = Arc::new(AtomicUsize::new(0));
+        let accepted = connections.clone();
+        tokio::spawn(async move {
+            while listener.accept().await.is_ok() {
+                accepted.fetch_add(1, Ordering::SeqCst);
+            }
+        });
+        ConnectionCounter { port, connections }
+    }
+
+    /// `http://localhost:<port>` followed by `path`.
+    pub fn url(&self, path: &str) -> String {
+        format!("http://localhost:{}{}", self.port, path)
+    }
+
+    pub fn connections(&self) -> usize {
+        self.connections.load(Ordering::SeqCst)
+    }
+
+    /// Polls until something has connected, failing the test after two
+    /// seconds.
+    pub async fn wait_for_connection(&self) {
+        for _ in 0..100 {
+            if self.connections() > 0 {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("nothing connected to port {}", self.port);
+    }
+}
+
+pub struct TestApp {
+    pub router: Router,
+    pub state: AppState,
+    // Dropping the handle would stop the visit writer
+    _visit_worker: RecorderHandle,
+}
+
+/// App with the default test settings.
+pub async fn spawn_app() -> TestApp {
+    spawn_app_with(&[]).await
+}
+
+/// App with the default test settings, overridden or extended by `vars`.
+pub async fn spawn_app_with(vars: &[(&str, &str)]) -> TestApp {
+    let mut env: HashMap<String, String> = [
+        ("DATABASE_URL", "sqlite::memory:"),
+        ("BASE_URL", BASE_URL),
+        ("VISITOR_HASH_SALT", "test-salt"),
+        ("VISIT_FLUSH_INTERVAL_MS", "10"),
+        ("PURGE_EXPIRED", "false"),
+        // Allow-listed hosts skip the DNS lookup of the destination check, so
+        // tests never wait on a resolver
+        ("ALLOWED_PRIVATE_DESTINATIONS", "example.com,example.org,apps.apple.com"),
+    ]
+    .into_iter()
+    .chain(vars.iter().copied())
+    .map(|(name, value)| (name.to_string(), value.to_string()))
+    .collect();
+    // Every connection of the pool has to see the same in-memory database
+    env.entry("DATABASE_MAX_CONNECTIONS".into()).or_insert_with(|| "1".into());
+
+    let config = Config::from_lookup(|name| env.get(name).cloned()).expect("test config is valid");
+    let (state, visit_worker) = build_state(Arc::new(config)).await.expect("state builds");
+    TestApp {
+        router: build_app(state.clone()),
+        state,
+        _visit_worker: visit_worker,
+    }
+}
+
+/// App with the default test settings and the `--dev` sample links.
+pub async fn spawn_seeded_app() -> TestApp {
+    let app = spawn_app().await;
+    seed::run(&app.state).await.expect("sample data is seeded");
+    app
+}
+
+impl TestApp {
+    /// Sends a request as if it came from a single remote client.
+    pub async fn send(&self, mut request: Request<Body>) -> Response<Body> {
+        let addr: SocketAddr = CLIENT_ADDR.parse().unwrap();
+        request.extensions_mut().insert(ConnectInfo(addr));
+        self.router.clone().oneshot(request).await.expect("router is infallible")
+    }
+
+    pub async fn get(&self, uri: &str) -> Response<Body> {
+        self.send(Request::get(uri).body(Body::empty()).unwrap()).await
+    }
+
+    pub async fn post_json(&self, uri: &str, body: Value) -> Response<Body> {
+        let request = Request::post(uri)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        self.send(request).await
+    }
+
+    /// Creates a link and returns the `POST /shorten` response body.
+    pub async fn shorten(&self, body: Value) -> Value {
+        let response = self.post_json("/shorten", body).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        json_body(response).await
+    }
+
+    /// Polls the stats of `link` (a `POST /shorten` response, whose
+    /// `stats_token` is sent along) until the background writer has stored
+    /// `visits` visits, and returns that response.
+    pub async fn stats_after_visits(&self, link: &Value, visits: i64) -> Value {
+        let code = link["short_code"].as_str().expect("link has a short_code");
+        for _ in 0..100 {
+            let mut request = Request::get(format!("/stats/{}", code));
+            if let Some(token) = link["stats_token"].as_str() {
+                request = request.header("x-stats-token", token);
+            }
+            let stats = json_body(self.send(request.body(Body::empty()).unwrap()).await).await;
+            if stats["total_visits"] == visits {
+                return stats;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("visits for {} were never recorded", code);
+    }
+}
+
+pub async fn body_bytes(response: Response<Body>) -> Vec<u8> {
+    to_bytes(response.into_body(), usize::MAX).await.unwrap().to_vec()
+}
+
+pub async fn json_body(response: Response<Body>) -> Value {
+    serde_json::from_slice(&body_bytes(response).await).expect("body is JSON")
+}
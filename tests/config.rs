@@ -0,0 +1,202 @@
+use axum::http::{HeaderValue, Method};
+use short_url_service::{config::Config, cors::AllowedOrigins, telemetry::LogFormat, visits::AnalyticsMode};
+use sqlx::sqlite::{SqliteJournalMode, SqliteSynchronous};
+use std::{collections::HashMap, net::SocketAddr, time::Duration};
+
+fn load(vars: &[(&str, &str)]) -> Result<Config, Vec<String>> {
+    let env: HashMap<&str, &str> = vars.iter().copied().collect();
+    Config::from_lookup(|name| env.get(name).map(|value| value.to_string())).map_err(|e| e.0)
+}
+
+#[test]
+fn defaults_apply_when_only_database_is_set() {
+    let config = load(&[("DATABASE_URL", "sqlite:links.db")]).unwrap();
+
+    assert_eq!(config.listen_addr, "127.0.0.1:3000".parse::<SocketAddr>().unwrap());
+    assert_eq!(config.database_max_connections, 5);
+    assert_eq!(config.sqlite_journal_mode, SqliteJournalMode::Wal);
+    assert_eq!(config.sqlite_synchronous, SqliteSynchronous::Normal);
+    assert_eq!(config.sqlite_busy_timeout, Duration::from_secs(5));
+    assert!(config.sqlite_foreign_keys);
+    assert_eq!(config.code_length, 6);
+    assert_eq!(config.hit_rate_limit.spec, "60/60s");
+    assert_eq!(config.unlock_rate_limit.spec, "10/60s");
+    assert!(config.shorten_rate_limit.is_none());
+    assert!(config.purge_expired);
+    assert_eq!(config.shutdown_timeout, Duration::from_secs(30));
+    assert!(matches!(config.analytics_mode, AnalyticsMode::Full));
+}
+
+#[test]
+fn empty_values_count_as_unset() {
+    let config = load(&[("DATABASE_URL", "sqlite:links.db"), ("PORT", ""), ("BASE_URL", "  ")]).unwrap();
+
+    assert_eq!(config.listen_addr.port(), 3000);
+    assert!(config.base_url.is_none());
+}
+
+#[test]
+fn listen_addr_wins_over_host_and_port() {
+    let config = load(&[
+        ("DATABASE_URL", "sqlite:links.db"),
+        ("LISTEN_ADDR", "[::]:8080"),
+        ("HOST", "0.0.0.0"),
+        ("PORT", "9000"),
+    ])
+    .unwrap();
+
+    assert_eq!(config.listen_addr, "[::]:8080".parse::<SocketAddr>().unwrap());
+}
+
+#[test]
+fn host_accepts_bracketed_ipv6() {
+    let config = load(&[("DATABASE_URL", "sqlite:links.db"), ("HOST", "[::1]"), ("PORT", "4000")]).unwrap();
+
+    assert_eq!(config.listen_addr, "[::1]:4000".parse::<SocketAddr>().unwrap());
+}
+
+#[test]
+fn listen_socket_mode_is_octal() {
+    let config = load(&[("DATABASE_URL", "sqlite:links.db"), ("LISTEN_SOCKET", "/run/shorturl.sock")]).unwrap();
+    assert_eq!(config.listen_socket.as_deref(), Some(std::path::Path::new("/run/shorturl.sock")));
+    assert_eq!(config.listen_socket_mode, 0o660);
+
+    for (raw, mode) in [("600", 0o600), ("0o666", 0o666), ("0770", 0o770)] {
+        let config = load(&[("DATABASE_URL", "sqlite:links.db"), ("LISTEN_SOCKET_MODE", raw)]).unwrap();
+        assert_eq!(config.listen_socket_mode, mode, "{}", raw);
+    }
+    for bad in ["rw-rw----", "680", "1777"] {
+        let problems = load(&[("DATABASE_URL", "sqlite:links.db"), ("LISTEN_SOCKET_MODE", bad)]).unwrap_err();
+        assert!(problems[0].starts_with("LISTEN_SOCKET_MODE"), "{}", bad);
+    }
+}
+
+#[test]
+fn dev_mode_needs_no_database_url() {
+    let config = load(&[("DEV_MODE", "1")]).unwrap();
+    assert!(config.dev_mode);
+    assert_eq!(config.database_url, short_url_service::db::DEV_DATABASE_URL);
+
+    let config = load(&[("DEV_MODE", "true"), ("DATABASE_URL", "sqlite:links.db")]).unwrap();
+    assert_eq!(config.database_url, short_url_service::db::DEV_DATABASE_URL);
+}
+
+#[test]
+fn every_problem_is_reported_at_once() {
+    let problems = load(&[
+        ("PORT", "http"),
+        ("SHORTEN_RATE_LIMIT", "lots"),
+        ("ANALYTICS_MODE", "some"),
+        ("COOKIE_SECRET", "short"),
+        ("MAX_LINK_TTL_DAYS", "0"),
+        ("SQLITE_JOURNAL_MODE", "fast"),
+    ])
+    .unwrap_err();
+
+    assert_eq!(problems.len(), 7, "{:?}", problems);
+    for name in ["DATABASE_URL", "PORT", "SHORTEN_RATE_LIMIT", "ANALYTICS_MODE", "COOKIE_SECRET", "MAX_LINK_TTL_DAYS", "SQLITE_JOURNAL_MODE"] {
+        assert!(problems.iter().any(|p| p.starts_with(name)), "no problem for {}: {:?}", name, problems);
+    }
+}
+
+#[test]
+fn code_length_must_fit_alphabet() {
+    let problems = load(&[("DATABASE_URL", "sqlite:links.db"), ("CODE_LENGTH", "1")]).unwrap_err();
+
+    assert_eq!(problems.len(), 1);
+}
+
+#[test]
+fn referrer_policy_must_be_known() {
+    let config = load(&[("DATABASE_URL", "sqlite:links.db"), ("REFERRER_POLICY", "no-referrer, Strict-Origin")]).unwrap();
+    assert_eq!(config.referrer_policy, "no-referrer, strict-origin");
+
+    let problems = load(&[("DATABASE_URL", "sqlite:links.db"), ("REFERRER_POLICY", "everyone")]).unwrap_err();
+    assert_eq!(problems.len(), 1);
+}
+
+#[test]
+fn api_keys_may_carry_a_namespace() {
+    let config = load(&[("DATABASE_URL", "sqlite:links.db"), ("API_KEYS", "team-a:secret-a, secret-b")]).unwrap();
+    let entries: Vec<_> = config
+        .api_keys
+        .iter()
+        .map(|entry| (entry.key.as_str(), entry.namespace.as_deref()))
+        .collect();
+    assert_eq!(entries, [("secret-a", Some("team-a")), ("secret-b", None)]);
+
+    let problems = load(&[("DATABASE_URL", "sqlite:links.db"), ("API_KEYS", "team a:secret")]).unwrap_err();
+    assert_eq!(problems.len(), 1);
+    assert!(problems[0].starts_with("API_KEYS"));
+}
+
+#[test]
+fn code_denylist_is_read_from_a_file() {
+    let path = std::env::temp_dir().join(format!("denylist-{}.txt", std::process::id()));
+    std::fs::write(&path, "wombat\n").unwrap();
+    let config = load(&[("DATABASE_URL", "sqlite:links.db"), ("CODE_DENYLIST_PATH", path.to_str().unwrap())]).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(config.code_denylist.as_deref(), Some("wombat\n"));
+
+    let problems = load(&[("DATABASE_URL", "sqlite:links.db"), ("CODE_DENYLIST_PATH", "/no/such/file")]).unwrap_err();
+    assert_eq!(problems.len(), 1);
+    assert!(problems[0].starts_with("CODE_DENYLIST_PATH"));
+}
+
+#[test]
+fn log_format_must_be_known() {
+    let config = load(&[("DATABASE_URL", "sqlite:links.db"), ("LOG_FORMAT", "JSON")]).unwrap();
+    assert_eq!(config.log_format, LogFormat::Json);
+
+    let problems = load(&[("DATABASE_URL", "sqlite:links.db"), ("LOG_FORMAT", "xml")]).unwrap_err();
+    assert_eq!(problems, ["LOG_FORMAT must be pretty or json, got \"xml\""]);
+}
+
+#[test]
+fn cors_origins_are_normalized_and_checked() {
+    let config = load(&[
+        ("DATABASE_URL", "sqlite:links.db"),
+        ("CORS_ALLOWED_ORIGINS", "https://App.Example.com:443/, http://localhost:5173"),
+        ("CORS_ALLOWED_METHODS", "get, post"),
+    ])
+    .unwrap();
+    assert_eq!(
+        config.cors.origins,
+        AllowedOrigins::List(vec![
+            HeaderValue::from_static("https://app.example.com"),
+            HeaderValue::from_static("http://localhost:5173"),
+        ])
+    );
+    assert_eq!(config.cors.methods, [Method::GET, Method::POST]);
+    let config = load(&[("DATABASE_URL", "sqlite:links.db"), ("CORS_ALLOWED_ORIGINS", " * ")]).unwrap();
+    assert_eq!(config.cors.origins, AllowedOrigins::Any);
+
+    for bad in ["https://app.example.com/dashboard", "*, https://app.example.com", "app.example.com", "ftp://files.example.com"] {
+        let problems = load(&[("DATABASE_URL", "sqlite:links.db"), ("CORS_ALLOWED_ORIGINS", bad)]).unwrap_err();
+        assert_eq!(problems.len(), 1, "{}", bad);
+        assert!(problems[0].starts_with("CORS_ALLOWED_ORIGINS"));
+    }
+}
+
+#[test]
+fn security_headers_follow_the_base_url_scheme() {
+    let config = load(&[("DATABASE_URL", "sqlite:links.db"), ("BASE_URL", "http://sho.rt")]).unwrap();
+    assert!(config.security_headers.hsts.is_none());
+    assert_eq!(config.security_headers.frame_options, Some(HeaderValue::from_static("DENY")));
+    assert_eq!(config.security_headers.embeddable_routes, ["/qr/{code}"]);
+
+    let config = load(&[
+        ("DATABASE_URL", "sqlite:links.db"),
+        ("BASE_URL", "https://sho.rt"),
+        ("X_FRAME_OPTIONS", "off"),
+        ("FRAME_EMBEDDABLE_ROUTES", "/qr/{code}, /{code}/preview"),
+    ])
+    .unwrap();
+    assert_eq!(config.security_headers.hsts, Some(HeaderValue::from_static("max-age=31536000; includeSubDomains")));
+    assert!(config.security_headers.frame_options.is_none());
+    assert_eq!(config.security_headers.embeddable_routes, ["/qr/{code}", "/{code}/preview"]);
+
+    let problems = load(&[("DATABASE_URL", "sqlite:links.db"), ("X_FRAME_OPTIONS", "allow-from")]).unwrap_err();
+    assert_eq!(problems.len(), 1);
+    assert!(problems[0].starts_with("X_FRAME_OPTIONS"));
+}
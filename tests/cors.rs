@@ -0,0 +1,87 @@
+mod common;
+
+use axum::{
+    body::Body,
+    http::{header, Request, Response, StatusCode},
+};
+use common::{spawn_app, spawn_app_with, TestApp};
+use serde_json::json;
+
+async fn preflight(app: &TestApp, origin: &str) -> Response<Body> {
+    let request = Request::options("/shorten")
+        .header(header::ORIGIN, origin)
+        .header(header::ACCESS_CONTROL_REQUEST_METHOD, "POST")
+        .header(header::ACCESS_CONTROL_REQUEST_HEADERS, "content-type, x-api-key")
+        .body(Body::empty())
+        .unwrap();
+    app.send(request).await
+}
+
+async fn shorten_from(app: &TestApp, origin: &str) -> Response<Body> {
+    let request = Request::post("/shorten")
+        .header(header::ORIGIN, origin)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(json!({ "url": "https://example.com/" }).to_string()))
+        .unwrap();
+    app.send(request).await
+}
+
+#[tokio::test]
+async fn cross_origin_calls_are_refused_by_default() {
+    let app = spawn_app().await;
+
+    let response = preflight(&app, "https://evil.example").await;
+    assert!(response.headers().get(header::ACCESS_CONTROL_ALLOW_ORIGIN).is_none());
+
+    // The request itself still runs; the browser just can't read the answer
+    let response = shorten_from(&app, "https://evil.example").await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    assert!(response.headers().get(header::ACCESS_CONTROL_ALLOW_ORIGIN).is_none());
+}
+
+#[tokio::test]
+async fn listed_origins_pass_the_preflight() {
+    let app = spawn_app_with(&[
+        ("CORS_ALLOWED_ORIGINS", "https://app.example.com,http://localhost:5173"),
+        ("CORS_MAX_AGE_SECS", "120"),
+    ])
+    .await;
+
+    let response = preflight(&app, "https://app.example.com").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let headers = response.headers();
+    assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_ORIGIN], "https://app.example.com");
+    assert!(headers[header::ACCESS_CONTROL_ALLOW_METHODS].to_str().unwrap().contains("POST"));
+    let allowed = headers[header::ACCESS_CONTROL_ALLOW_HEADERS].to_str().unwrap();
+    assert!(allowed.contains("content-type") && allowed.contains("x-api-key"));
+    assert_eq!(headers[header::ACCESS_CONTROL_MAX_AGE], "120");
+    assert!(headers.get(header::ACCESS_CONTROL_ALLOW_CREDENTIALS).is_none());
+
+    for origin in ["https://evil.example", "https://app.example.com.evil.example", "http://app.example.com"] {
+        let response = preflight(&app, origin).await;
+        assert!(response.headers().get(header::ACCESS_CONTROL_ALLOW_ORIGIN).is_none(), "{}", origin);
+    }
+
+    let response = shorten_from(&app, "http://localhost:5173").await;
+    assert_eq!(response.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN], "http://localhost:5173");
+    assert!(response.headers()[header::ACCESS_CONTROL_EXPOSE_HEADERS].to_str().unwrap().contains("x-request-id"));
+}
+
+#[tokio::test]
+async fn methods_outside_the_list_are_not_offered() {
+    let app = spawn_app_with(&[("CORS_ALLOWED_ORIGINS", "*"), ("CORS_ALLOWED_METHODS", "GET")]).await;
+
+    let response = preflight(&app, "https://anywhere.example").await;
+
+    assert_eq!(response.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+    assert_eq!(response.headers()[header::ACCESS_CONTROL_ALLOW_METHODS], "GET");
+}
+
+#[tokio::test]
+async fn permissive_mode_allows_any_origin() {
+    let app = spawn_app_with(&[("CORS_PERMISSIVE", "true"), ("CORS_ALLOWED_ORIGINS", "https://app.example.com")]).await;
+
+    let response = preflight(&app, "https://evil.example").await;
+
+    assert_eq!(response.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+}
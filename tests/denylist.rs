@@ -0,0 +1,89 @@
+use short_url_service::{
+    denylist::{fold, CodeFilter, MAX_REJECTED_CANDIDATES},
+    utils::{CodeAlphabet, CodeGenerator, CodeStyle},
+    words,
+};
+use std::cell::Cell;
+
+#[test]
+fn look_alikes_fold_to_letters() {
+    assert_eq!(fold("5H1T"), "shit");
+    assert_eq!(fold("sh-1t"), "shit");
+    assert_eq!(fold("@55"), "ass");
+    assert_eq!(fold("B00B"), "boob");
+    assert_eq!(fold("Cl1T"), "ciit");
+}
+
+#[test]
+fn known_bad_strings_are_blocked() {
+    let filter = CodeFilter::new(None);
+
+    for code in ["fuck", "xFuCkx", "5H1T", "sh-1t", "a55", "8u77", "b00b9"] {
+        assert!(filter.is_blocked(code), "{}", code);
+    }
+    for code in ["aB3xYz", "kunt", "Qw7Rt2"] {
+        assert!(!filter.is_blocked(code), "{}", code);
+    }
+}
+
+#[test]
+fn extra_words_extend_the_bundled_list() {
+    let bundled = CodeFilter::new(None);
+    let filter = CodeFilter::new(Some("# local additions\nkunt\n\n  W0MBAT  \n"));
+
+    assert_eq!(filter.len(), bundled.len() + 2);
+    assert!(filter.is_blocked("xKUNTx"));
+    assert!(filter.is_blocked("wombat9"));
+    // The bundled words still apply
+    assert!(filter.is_blocked("fuck"));
+}
+
+#[test]
+fn rejected_candidates_are_redrawn() {
+    let filter = CodeFilter::new(None);
+    let mut candidates = ["5H1T", "xfuckx", "a55h0l3", "aB3xYz"].into_iter();
+    let draws = Cell::new(0);
+
+    let code = filter.pick(|| {
+        draws.set(draws.get() + 1);
+        candidates.next().unwrap().to_string()
+    });
+
+    assert_eq!(code.as_deref(), Some("aB3xYz"));
+    assert_eq!(draws.get(), 4);
+}
+
+#[test]
+fn rejections_have_a_bounded_budget() {
+    let filter = CodeFilter::new(None);
+    let draws = Cell::new(0);
+
+    let code = filter.pick(|| {
+        draws.set(draws.get() + 1);
+        "shit".to_string()
+    });
+
+    assert_eq!(code, None);
+    assert_eq!(draws.get(), MAX_REJECTED_CANDIDATES + 1);
+}
+
+#[test]
+fn picked_codes_never_contain_bad_words() {
+    let filter = CodeFilter::new(None);
+    // Long codes make denylisted words common enough to hit
+    let generator = CodeGenerator::new(CodeStyle::Random, CodeAlphabet::Base62, 24);
+
+    for _ in 0..2000 {
+        let code = filter.pick(|| generator.generate()).unwrap();
+        assert!(!filter.is_blocked(&code), "{}", code);
+    }
+}
+
+#[test]
+fn word_lists_pass_the_filter() {
+    let filter = CodeFilter::new(None);
+
+    for word in words::adjectives().iter().chain(words::nouns()) {
+        assert!(!filter.is_blocked(word), "{}", word);
+    }
+}
@@ -0,0 +1,145 @@
+mod common;
+
+use axum::{body::Body, http::Request};
+use common::{spawn_app_with, TestApp};
+use serde_json::json;
+use short_url_service::geoip::GeoIp;
+use std::{collections::HashMap, path::PathBuf};
+
+// MaxMind DB encoding of the few types the test database needs
+fn string(s: &str) -> Vec<u8> {
+    [vec![0x40 | s.len() as u8], s.as_bytes().to_vec()].concat()
+}
+
+fn map(entries: &[(&str, Vec<u8>)]) -> Vec<u8> {
+    let mut out = vec![0xe0 | entries.len() as u8];
+    for (key, value) in entries {
+        out.extend(string(key));
+        out.extend(value);
+    }
+    out
+}
+
+fn uint(type_byte: &[u8], value: u64, width: usize) -> Vec<u8> {
+    [type_byte, &value.to_be_bytes()[8 - width..]].concat()
+}
+
+// A minimal IPv4 database mapping each /24 to a country code.
+fn country_db(networks: &[([u8; 3], &str)]) -> Vec<u8> {
+    // No record points back at the root, so 0 marks an empty one
+    const EMPTY: usize = 0;
+    let mut data = Vec::new();
+    let mut offsets = HashMap::new();
+    for (_, code) in networks {
+        offsets.entry(*code).or_insert_with(|| {
+            let offset = data.len();
+            data.extend(map(&[("country", map(&[("iso_code", string(code))]))]));
+            offset
+        });
+    }
+
+    // Node 0 is the root; leaves hold `!offset` until the node count is known
+    let mut nodes = vec![[EMPTY; 2]];
+    for (prefix, code) in networks {
+        let mut node = 0;
+        for bit in 0..24 {
+            let side = ((prefix[bit / 8] >> (7 - bit % 8)) & 1) as usize;
+            if bit == 23 {
+                nodes[node][side] = !offsets[code];
+            } else {
+                if nodes[node][side] == EMPTY {
+                    nodes.push([EMPTY; 2]);
+                    nodes[node][side] = nodes.len() - 1;
+                }
+                node = nodes[node][side];
+            }
+        }
+    }
+    let node_count = nodes.len();
+    let mut db = Vec::new();
+    for record in nodes.iter().flatten() {
+        let value = match *record {
+            EMPTY => node_count,
+            child if child < node_count => child,
+            leaf => node_count + 16 + !leaf,
+        };
+        db.extend(&(value as u32).to_be_bytes()[1..]);
+    }
+    db.extend([0; 16]);
+    db.extend(data);
+    db.extend(b"\xab\xcd\xefMaxMind.com");
+    db.extend(map(&[
+        ("binary_format_major_version", uint(&[0xa2], 2, 2)),
+        ("binary_format_minor_version", uint(&[0xa2], 0, 2)),
+        ("build_epoch", uint(&[0x08, 0x02], 0, 8)),
+        ("database_type", string("Test-Country")),
+        ("description", map(&[])),
+        ("ip_version", uint(&[0xa2], 4, 2)),
+        ("languages", vec![0x00, 0x04]),
+        ("node_count", uint(&[0xc4], node_count as u64, 4)),
+        ("record_size", uint(&[0xa2], 24, 2)),
+    ]));
+    db
+}
+
+fn write_db(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("{}-{}.mmdb", name, std::process::id()));
+    std::fs::write(&path, country_db(&[([203, 0, 113], "AU"), ([198, 51, 100], "DE"), ([192, 0, 2], "DE")])).unwrap();
+    path
+}
+
+async fn visit_from(app: &TestApp, code: &str, ip: &str) {
+    let request = Request::get(format!("/{}", code))
+        .header("x-forwarded-for", ip)
+        .body(Body::empty())
+        .unwrap();
+    app.send(request).await;
+}
+
+#[test]
+fn lookups_resolve_countries() {
+    let path = write_db("lookups");
+    let geoip = GeoIp::open(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    assert_eq!(geoip.country("203.0.113.7").as_deref(), Some("AU"));
+    assert_eq!(geoip.country("::ffff:198.51.100.200").as_deref(), Some("DE"));
+    // Anonymized to the network, as ANALYTICS_MODE=anonymized stores it
+    assert_eq!(geoip.country("192.0.2.0").as_deref(), Some("DE"));
+    for unknown in ["10.1.2.3", "2001:db8::1", "not an ip", ""] {
+        assert_eq!(geoip.country(unknown), None, "{}", unknown);
+    }
+}
+
+#[tokio::test]
+async fn stats_count_visits_by_country() {
+    let path = write_db("stats");
+    let app = spawn_app_with(&[
+        ("GEOIP_DB_PATH", path.to_str().unwrap()),
+        ("TRUSTED_PROXIES", "203.0.113.7"),
+    ])
+    .await;
+    std::fs::remove_file(&path).unwrap();
+    let link = app.shorten(json!({ "url": "https://example.com/", "custom_code": "world" })).await;
+
+    for ip in ["198.51.100.1", "192.0.2.44", "203.0.113.9", "10.0.0.1"] {
+        visit_from(&app, "world", ip).await;
+    }
+    let stats = app.stats_after_visits(&link, 4).await;
+
+    assert_eq!(
+        stats["countries"],
+        json!([{ "code": "DE", "count": 2 }, { "code": "AU", "count": 1 }])
+    );
+}
+
+#[tokio::test]
+async fn countries_stay_empty_without_a_database() {
+    let app = spawn_app_with(&[("GEOIP_DB_PATH", "/no/such/GeoLite2-Country.mmdb")]).await;
+    let link = app.shorten(json!({ "url": "https://example.com/", "custom_code": "nowhere" })).await;
+
+    visit_from(&app, "nowhere", "198.51.100.1").await;
+    let stats = app.stats_after_visits(&link, 1).await;
+
+    assert_eq!(stats["countries"], json!([]));
+}
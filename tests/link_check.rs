@@ -0,0 +1,283 @@
+mod common;
+
+use axum::{
+    body::Body,
+    http::{header, HeaderMap, Request, StatusCode},
+    response::{IntoResponse, Redirect},
+    routing::get,
+    Router,
+};
+use common::{json_body, spawn_app, spawn_app_with, ConnectionCounter, TestApp, BASE_URL};
+use serde_json::json;
+use short_url_service::{
+    destination::DestinationPolicy,
+    link_check::{CheckSummary, LinkChecker},
+};
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+#[derive(Default)]
+struct Counters {
+    flaky_calls: AtomicUsize,
+    slow_in_flight: AtomicUsize,
+    slow_peak: AtomicUsize,
+}
+
+// A destination server on a local port, with a route per behavior
+async fn spawn_destinations(counters: Arc<Counters>) -> String {
+    let flaky = counters.clone();
+    let slow = counters;
+    let router = Router::new()
+        .route("/ok", get(|| async { "ok" }))
+        .route("/gone", get(|| async { StatusCode::NOT_FOUND }))
+        .route("/moved", get(|| async { Redirect::permanent("/ok") }))
+        .route(
+            "/no-head",
+            get(|headers: HeaderMap| async move {
+                match headers.get(header::RANGE) {
+                    Some(_) => StatusCode::PARTIAL_CONTENT.into_response(),
+                    None => StatusCode::BAD_REQUEST.into_response(),
+                }
+            })
+            .head(|| async { StatusCode::METHOD_NOT_ALLOWED }),
+        )
+        .route(
+            "/flaky",
+            get(move || async move {
+                // Fails the first attempt only
+                match flaky.flaky_calls.fetch_add(1, Ordering::SeqCst) {
+                    0 => StatusCode::SERVICE_UNAVAILABLE,
+                    _ => StatusCode::OK,
+                }
+            }),
+        )
+        .route(
+            "/slow/{n}",
+            get(move || async move {
+                let now = slow.slow_in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                slow.slow_peak.fetch_max(now, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                slow.slow_in_flight.fetch_sub(1, Ordering::SeqCst);
+                StatusCode::OK
+            }),
+        );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+    format!("http://{}", address)
+}
+
+async fn health(app: &TestApp, code: &str) -> (String, serde_json::Value) {
+    let info = json_body(app.get(&format!("/info/{}", code)).await).await;
+    (info["health"].as_str().unwrap().to_string(), info["last_status"].clone())
+}
+
+#[tokio::test]
+async fn checks_mark_dead_destinations_broken() {
+    let app = spawn_app_with(&[("ADMIN_API_KEYS", "admin-key"), ("ALLOWED_PRIVATE_DESTINATIONS", "127.0.0.1")]).await;
+    let counters = Arc::new(Counters::default());
+    let server = spawn_destinations(counters.clone()).await;
+    for (code, path) in [("fine", "ok"), ("gone", "gone"), ("moved", "moved"), ("no-head", "no-head"), ("flaky", "flaky")] {
+        app.shorten(json!({ "url": format!("{}/{}", server, path), "custom_code": code }))
+            .await;
+    }
+    for n in 0..6 {
+        app.shorten(json!({ "url": format!("{}/slow/{}", server, n), "custom_code": format!("slow{}", n) }))
+            .await;
+    }
+    assert_eq!(health(&app, "fine").await.0, "unknown");
+
+    let checker = LinkChecker::new(app.state.destinations.clone(), BASE_URL, &[Duration::from_millis(10)]);
+    let summary = checker.check_all(&app.state.pool).await.unwrap();
+
+    assert_eq!(summary, CheckSummary { checked: 11, broken: 1 });
+    assert_eq!(health(&app, "fine").await, ("healthy".to_string(), json!(200)));
+    assert_eq!(health(&app, "gone").await, ("broken".to_string(), json!(404)));
+    // Redirects are followed; servers refusing HEAD get a ranged GET
+    assert_eq!(health(&app, "moved").await, ("healthy".to_string(), json!(200)));
+    assert_eq!(health(&app, "no-head").await, ("healthy".to_string(), json!(206)));
+    // One failure is retried before the link counts as broken
+    assert_eq!(health(&app, "flaky").await, ("healthy".to_string(), json!(200)));
+    assert_eq!(counters.flaky_calls.load(Ordering::SeqCst), 2);
+    // All six slow links share a host
+    assert!(counters.slow_peak.load(Ordering::SeqCst) <= 2);
+    let info = json_body(app.get("/info/fine").await).await;
+    assert!(info["last_checked_at"].is_string());
+
+    let request = Request::get("/admin/urls?health=broken")
+        .header("x-api-key", "admin-key")
+        .body(Body::empty())
+        .unwrap();
+    let listed = json_body(app.send(request).await).await;
+    assert_eq!(listed["items"].as_array().unwrap().len(), 1);
+    assert_eq!(listed["items"][0]["id"], "gone");
+    assert_eq!(listed["items"][0]["health"], "broken");
+    let request = Request::get("/admin/urls?health=dead")
+        .header("x-api-key", "admin-key")
+        .body(Body::empty())
+        .unwrap();
+    assert_eq!(app.send(request).await.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn changing_the_destination_forgets_its_health() {
+    let app = spawn_app_with(&[("ALLOWED_PRIVATE_DESTINATIONS", "127.0.0.1")]).await;
+    let server = spawn_destinations(Arc::default()).await;
+    let link = app
+        .shorten(json!({ "url": format!("{}/gone", server), "custom_code": "moving" }))
+        .await;
+    let checker = LinkChecker::new(app.state.destinations.clone(), BASE_URL, &[]);
+    checker.check_all(&app.state.pool).await.unwrap();
+    assert_eq!(health(&app, "moving").await.0, "broken");
+
+    let patch = Request::patch("/urls/moving")
+        .header("content-type", "application/json")
+        .header("authorization", format!("Bearer {}", link["delete_token"].as_str().unwrap()))
+        .body(Body::from(json!({ "url": format!("{}/ok", server) }).to_string()))
+        .unwrap();
+    assert_eq!(app.send(patch).await.status(), StatusCode::OK);
+
+    assert_eq!(health(&app, "moving").await, ("unknown".to_string(), json!(null)));
+}
+
+#[tokio::test]
+async fn checks_cover_every_destination_of_a_link() {
+    let app = spawn_app_with(&[("ALLOWED_PRIVATE_DESTINATIONS", "127.0.0.1")]).await;
+    let server = spawn_destinations(Arc::default()).await;
+    let (ok, gone, moved) = (format!("{}/ok", server), format!("{}/gone", server), format!("{}/moved", server));
+    let app_link = app
+        .shorten(json!({ "url": ok, "ios_url": gone, "android_url": moved, "custom_code": "store" }))
+        .await;
+    app.shorten(json!({ "destinations": [{ "url": ok, "weight": 3 }, { "url": gone, "weight": 1 }], "custom_code": "split" }))
+        .await;
+    app.shorten(json!({ "destinations": [{ "url": ok, "weight": 1 }, { "url": moved, "weight": 1 }], "custom_code": "even" }))
+        .await;
+
+    let checker = LinkChecker::new(app.state.destinations.clone(), BASE_URL, &[]);
+    let summary = checker.check_all(&app.state.pool).await.unwrap();
+
+    // Only a secondary destination is dead, but that's enough
+    assert_eq!(summary, CheckSummary { checked: 3, broken: 2 });
+    assert_eq!(health(&app, "store").await, ("broken".to_string(), json!(404)));
+    let info = json_body(app.get("/info/store").await).await;
+    let checks: Vec<_> = info["destination_checks"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|check| (check["url"].as_str().unwrap(), check["health"].as_str().unwrap(), check["last_status"].clone()))
+        .collect();
+    assert_eq!(checks, [(ok.as_str(), "healthy", json!(200)), (gone.as_str(), "broken", json!(404)), (moved.as_str(), "healthy", json!(200))]);
+    assert!(info["destination_checks"][0]["checked_at"].is_string());
+    assert_eq!(health(&app, "split").await, ("broken".to_string(), json!(404)));
+    let info = json_body(app.get("/info/split").await).await;
+    assert_eq!(info["destination_checks"][1]["url"], gone.as_str());
+    assert_eq!(info["destination_checks"][1]["health"], "broken");
+    assert_eq!(health(&app, "even").await, ("healthy".to_string(), json!(200)));
+
+    // Replacing the dead app-store URL forgets the old results
+    let patch = Request::patch("/urls/store")
+        .header("content-type", "application/json")
+        .header("authorization", format!("Bearer {}", app_link["delete_token"].as_str().unwrap()))
+        .body(Body::from(json!({ "ios_url": ok }).to_string()))
+        .unwrap();
+    assert_eq!(app.send(patch).await.status(), StatusCode::OK);
+    assert_eq!(health(&app, "store").await, ("unknown".to_string(), json!(null)));
+    let info = json_body(app.get("/info/store").await).await;
+    assert_eq!(info["destination_checks"].as_array().unwrap().len(), 2);
+    assert!(info["destination_checks"].as_array().unwrap().iter().all(|check| check["url"] != gone.as_str()));
+}
+
+#[tokio::test]
+async fn verify_refuses_dead_destinations() {
+    let app = spawn_app_with(&[("ALLOWED_PRIVATE_DESTINATIONS", "127.0.0.1")]).await;
+    let server = spawn_destinations(Arc::default()).await;
+    // A port nothing listens on
+    let closed = {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        format!("http://{}/", listener.local_addr().unwrap())
+    };
+
+    let response = app
+        .post_json("/shorten", json!({ "url": format!("{}/gone", server), "verify": true }))
+        .await;
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    let body = json_body(response).await;
+    assert_eq!(body["code"], "DESTINATION_UNREACHABLE");
+    assert_eq!(body["details"]["status"], 404);
+
+    let response = app.post_json("/shorten", json!({ "url": closed, "verify": true })).await;
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(json_body(response).await["details"]["status"], json!(null));
+
+    for path in ["ok", "moved", "no-head"] {
+        let response = app
+            .post_json("/shorten", json!({ "url": format!("{}/{}", server, path), "verify": true }))
+            .await;
+        assert_eq!(response.status(), StatusCode::CREATED, "{}", path);
+    }
+    // Without verify nothing is probed
+    let response = app.post_json("/shorten", json!({ "url": format!("{}/gone", server) })).await;
+    assert_eq!(response.status(), StatusCode::CREATED);
+    // Destinations the policy refuses keep their own error
+    let response = app
+        .post_json("/shorten", json!({ "url": "http://10.0.0.1/", "verify": true }))
+        .await;
+    assert_eq!(json_body(response).await["code"], "PRIVATE_DESTINATION");
+}
+
+#[tokio::test]
+async fn verify_probes_only_requests_that_pass_every_other_check() {
+    let app = spawn_app_with(&[
+        ("ALLOWED_PRIVATE_DESTINATIONS", "localhost,127.0.0.1"),
+        ("BLOCKED_DESTINATIONS", "localhost"),
+    ])
+    .await;
+    let counter = ConnectionCounter::spawn().await;
+
+    let response = app
+        .post_json("/shorten", json!({ "url": format!("http://127.0.0.1:{}/", counter.port), "custom_code": "x", "verify": true }))
+        .await;
+    assert_eq!(json_body(response).await["code"], "VALIDATION_FAILED");
+    let response = app.post_json("/shorten", json!({ "url": counter.url("/"), "verify": true })).await;
+    assert_eq!(json_body(response).await["code"], "BLOCKED_DESTINATION");
+    assert_eq!(counter.connections(), 0);
+
+    // A valid request is probed
+    app.post_json("/shorten", json!({ "url": format!("http://127.0.0.1:{}/", counter.port), "verify": true }))
+        .await;
+    assert!(counter.connections() > 0);
+}
+
+#[tokio::test]
+async fn probes_only_connect_to_addresses_the_policy_allows() {
+    let app = spawn_app().await;
+    let counter = ConnectionCounter::spawn().await;
+
+    // Stored while the names still resolved somewhere public
+    app.shorten(json!({ "url": "https://example.com/a", "custom_code": "rebound" })).await;
+    app.shorten(json!({ "url": "https://example.com/b", "custom_code": "vanished" })).await;
+    for (code, url) in [("rebound", counter.url("/")), ("vanished", "http://nowhere.invalid/".to_string())] {
+        sqlx::query("UPDATE urls SET original_url = ? WHERE id = ?")
+            .bind(url)
+            .bind(code)
+            .execute(&app.state.pool)
+            .await
+            .unwrap();
+    }
+
+    let checker = LinkChecker::new(app.state.destinations.clone(), BASE_URL, &[]);
+    assert_eq!(checker.check_all(&app.state.pool).await.unwrap(), CheckSummary { checked: 2, broken: 2 });
+    assert_eq!(health(&app, "rebound").await, ("broken".to_string(), json!(null)));
+    assert_eq!(health(&app, "vanished").await, ("broken".to_string(), json!(null)));
+    assert_eq!(counter.connections(), 0);
+
+    // The same probe does connect once the name is allowed
+    let allowing = LinkChecker::new(Arc::new(DestinationPolicy::parse("localhost")), BASE_URL, &[]);
+    allowing.check_all(&app.state.pool).await.unwrap();
+    assert!(counter.connections() > 0);
+}
@@ -0,0 +1,135 @@
+mod common;
+
+use common::{body_bytes, spawn_app, BASE_URL};
+use serde_json::{json, Value};
+use short_url_service::{destination::DestinationPolicy, metadata};
+use std::{
+    io,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tracing_subscriber::{fmt::MakeWriter, layer::SubscriberExt};
+
+// Collects everything the JSON layer writes
+#[derive(Clone, Default)]
+struct Captured(Arc<Mutex<Vec<u8>>>);
+
+impl io::Write for Captured {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> MakeWriter<'a> for Captured {
+    type Writer = Captured;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.clone()
+    }
+}
+
+impl Captured {
+    fn lines(&self) -> Vec<Value> {
+        let bytes = self.0.lock().unwrap().clone();
+        String::from_utf8(bytes)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+}
+
+#[tokio::test]
+async fn json_logs_leave_out_tokens_and_destinations() {
+    let captured = Captured::default();
+    let subscriber = tracing_subscriber::registry()
+        .with(tracing_subscriber::filter::LevelFilter::DEBUG)
+        .with(tracing_subscriber::fmt::layer().json().with_writer(captured.clone()));
+    // The test runtime is single-threaded, so the whole request runs under it
+    let _guard = tracing::subscriber::set_default(subscriber);
+
+    let app = spawn_app().await;
+    let link = app
+        .shorten(json!({ "url": "https://example.com/secret-destination", "custom_code": "quiet" }))
+        .await;
+    let token = link["stats_token"].as_str().unwrap();
+    let redirect = app.get("/quiet").await;
+    body_bytes(redirect).await;
+    let stats = app.get(&format!("/stats/quiet?stats_token={}", token)).await;
+    body_bytes(stats).await;
+    app.get("/info/missing").await;
+
+    let lines = captured.lines();
+    let responses: Vec<(&str, &str, u64)> = lines
+        .iter()
+        .filter(|line| line["target"] == "short_url_service::telemetry")
+        .map(|line| {
+            (
+                line["span"]["path"].as_str().unwrap(),
+                line["level"].as_str().unwrap(),
+                line["fields"]["status"].as_u64().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        responses,
+        [
+            ("/shorten", "INFO", 201),
+            ("/quiet", "DEBUG", 307),
+            ("/stats/quiet", "INFO", 200),
+            ("/info/missing", "WARN", 404),
+        ]
+    );
+    // Lines from handler and query spans carry the id of the request around them
+    assert!(lines.iter().all(|line| line["spans"][0]["request_id"].is_string() || line.get("span").is_none()));
+
+    let raw = lines.iter().map(Value::to_string).collect::<String>();
+    assert!(!raw.contains(token));
+    assert!(!raw.contains("secret-destination"));
+    // The address every test request comes from
+    assert!(!raw.contains("203.0.113.7"));
+}
+
+#[tokio::test]
+async fn failed_metadata_fetches_leave_out_destinations() {
+    let captured = Captured::default();
+    let subscriber = tracing_subscriber::registry()
+        .with(tracing_subscriber::filter::LevelFilter::DEBUG)
+        .with(tracing_subscriber::fmt::layer().json().with_writer(captured.clone()));
+    let _guard = tracing::subscriber::set_default(subscriber);
+
+    let app = spawn_app().await;
+    // A loopback port nothing listens on any more
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let page = format!("http://localhost:{}/secret-destination", listener.local_addr().unwrap().port());
+    drop(listener);
+    // Refused by the policy, then refused by the closed port
+    metadata::spawn_fetcher(app.state.pool.clone(), app.state.destinations.clone()).fetch("blocked", &page, BASE_URL);
+    let allowing = Arc::new(DestinationPolicy::parse("localhost"));
+    metadata::spawn_fetcher(app.state.pool.clone(), allowing).fetch("refused", &page, BASE_URL);
+
+    let failed = |lines: &[Value]| {
+        lines
+            .iter()
+            .filter(|line| line["fields"]["message"].as_str().is_some_and(|m| m.starts_with("metadata fetch failed")))
+            .count()
+    };
+    let mut lines = captured.lines();
+    for _ in 0..100 {
+        if failed(&lines) == 2 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        lines = captured.lines();
+    }
+    assert_eq!(failed(&lines), 2, "{:?}", lines);
+
+    let raw = lines.iter().map(Value::to_string).collect::<String>();
+    assert!(!raw.contains("secret-destination"));
+    assert!(!raw.contains("localhost:"));
+}
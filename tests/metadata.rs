@@ -0,0 +1,64 @@
+mod common;
+
+use axum::{
+    body::Body,
+    http::{header, Request, StatusCode},
+};
+use chrono::Utc;
+use common::{json_body, spawn_app, spawn_app_with, ConnectionCounter, BASE_URL};
+use serde_json::{json, Value};
+use short_url_service::{destination::DestinationPolicy, metadata};
+use std::{sync::Arc, time::Duration};
+
+#[tokio::test]
+async fn fetches_only_connect_to_addresses_the_policy_allows() {
+    let app = spawn_app().await;
+    let counter = ConnectionCounter::spawn().await;
+    app.shorten(json!({ "url": "https://example.com/", "custom_code": "rebound" })).await;
+
+    metadata::spawn_fetcher(app.state.pool.clone(), app.state.destinations.clone())
+        .fetch("rebound", &counter.url("/page"), BASE_URL);
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert_eq!(counter.connections(), 0);
+    assert_eq!(json_body(app.get("/info/rebound").await).await["title"], Value::Null);
+
+    // The same fetch does connect once the name is allowed
+    let allowing = Arc::new(DestinationPolicy::parse("localhost"));
+    metadata::spawn_fetcher(app.state.pool.clone(), allowing).fetch("rebound", &counter.url("/page"), BASE_URL);
+    counter.wait_for_connection().await;
+}
+
+#[tokio::test]
+async fn changing_the_url_replaces_the_old_metadata() {
+    let app = spawn_app_with(&[("ALLOWED_PRIVATE_DESTINATIONS", "example.com,localhost")]).await;
+    let counter = ConnectionCounter::spawn().await;
+    let mut tokens = Vec::new();
+    for code in ["described", "plain"] {
+        let link = app.shorten(json!({ "url": "https://example.com/", "custom_code": code })).await;
+        tokens.push(link["delete_token"].as_str().unwrap().to_string());
+    }
+    // As left by a finished fetch
+    sqlx::query("UPDATE urls SET title = 'Old page', description = 'Old', metadata_fetched_at = ? WHERE id = 'described'")
+        .bind(Utc::now())
+        .execute(&app.state.pool)
+        .await
+        .unwrap();
+
+    let patch = |code: &str, token: &str| {
+        Request::patch(format!("/urls/{}", code))
+            .header(header::CONTENT_TYPE, "application/json")
+            .header(header::AUTHORIZATION, format!("Bearer {}", token))
+            .body(Body::from(json!({ "url": counter.url("/new") }).to_string()))
+            .unwrap()
+    };
+    // Links that never had metadata don't start fetching it
+    assert_eq!(app.send(patch("plain", &tokens[1])).await.status(), StatusCode::OK);
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert_eq!(counter.connections(), 0);
+
+    assert_eq!(app.send(patch("described", &tokens[0])).await.status(), StatusCode::OK);
+    let info = json_body(app.get("/info/described").await).await;
+    assert_eq!((info["title"].clone(), info["description"].clone()), (Value::Null, Value::Null));
+    assert_eq!(info["metadata_fetched_at"], Value::Null);
+    counter.wait_for_connection().await;
+}
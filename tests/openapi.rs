@@ -0,0 +1,137 @@
+mod common;
+
+use axum::{
+    body::Body,
+    http::{Method, Request, StatusCode},
+};
+use common::{json_body, spawn_app};
+use std::collections::BTreeSet;
+
+// Everything `build_app` serves besides Swagger UI, `/robots.txt`, `/metrics`
+// and static files
+const ROUTES: &[(&str, &str)] = &[
+    ("post", "/shorten"),
+    ("post", "/shorten/bulk"),
+    ("get", "/{code}"),
+    ("head", "/{code}"),
+    ("delete", "/{code}"),
+    ("post", "/{code}/unlock"),
+    ("post", "/{code}/continue"),
+    ("get", "/hit/{code}"),
+    ("post", "/hit/{code}"),
+    ("get", "/info/{code}"),
+    ("patch", "/urls/{code}"),
+    ("get", "/stats/{code}"),
+    ("get", "/stats/{code}/summary"),
+    ("post", "/stats/summary"),
+    ("get", "/stats/{code}/export.csv"),
+    ("get", "/stats/{code}/live"),
+    ("get", "/qr/{code}"),
+    ("get", "/admin/urls"),
+    ("get", "/admin/summary"),
+    ("delete", "/admin/urls/{code}/visits"),
+    ("post", "/admin/privacy/erase"),
+    ("get", "/admin/audit"),
+    ("get", "/admin/campaigns"),
+    ("post", "/admin/campaigns"),
+    ("get", "/admin/campaigns/{id}"),
+    ("patch", "/admin/campaigns/{id}"),
+    ("delete", "/admin/campaigns/{id}"),
+    ("get", "/admin/campaigns/{id}/stats"),
+    ("get", "/admin/export"),
+    ("post", "/admin/import"),
+    ("get", "/healthz"),
+    ("get", "/readyz"),
+];
+
+#[tokio::test]
+async fn openapi_documents_every_route() {
+    let app = spawn_app().await;
+    let docs = json_body(app.get("/api-docs/openapi.json").await).await;
+
+    let mut documented = BTreeSet::new();
+    for (path, item) in docs["paths"].as_object().unwrap() {
+        for method in item.as_object().unwrap().keys() {
+            documented.insert((method.clone(), path.clone()));
+        }
+    }
+    let expected: BTreeSet<_> = ROUTES
+        .iter()
+        .map(|(method, path)| (method.to_string(), path.to_string()))
+        .collect();
+    assert_eq!(documented, expected);
+
+    // And every documented operation is actually routed
+    for (method, path) in &documented {
+        let uri = path.replace("{code}", "no-such-code");
+        let method = Method::from_bytes(method.to_uppercase().as_bytes()).unwrap();
+        let request = Request::builder().method(method.clone()).uri(&uri).body(Body::empty()).unwrap();
+        let response = app.send(request).await;
+        assert_ne!(response.status(), StatusCode::METHOD_NOT_ALLOWED, "{} {}", method, uri);
+    }
+}
+
+#[tokio::test]
+async fn error_responses_reference_the_error_schema() {
+    let app = spawn_app().await;
+    let docs = json_body(app.get("/api-docs/openapi.json").await).await;
+
+    for (path, item) in docs["paths"].as_object().unwrap() {
+        for (method, operation) in item.as_object().unwrap() {
+            for (status, response) in operation["responses"].as_object().unwrap() {
+                // The readiness probe answers 503 with its own status body
+                if !(status.starts_with('4') || status.starts_with('5')) || path == "/readyz" {
+                    continue;
+                }
+                let content = response["content"].as_object();
+                assert!(content.is_some(), "{} {} {} has no body", method, path, status);
+                // The password form is re-rendered as HTML
+                if let Some(json) = content.unwrap().get("application/json") {
+                    let schema = json["schema"]["$ref"].as_str().unwrap_or_default();
+                    assert!(
+                        schema.ends_with("/ErrorResponse") || schema.ends_with("/ValidationErrorResponse"),
+                        "{} {} {} uses {:?}",
+                        method,
+                        path,
+                        status,
+                        schema
+                    );
+                }
+            }
+        }
+    }
+    assert!(docs["components"]["schemas"]["ErrorResponse"]["properties"]["code"].is_object());
+}
+
+#[tokio::test]
+async fn errors_carry_a_code() {
+    let app = spawn_app().await;
+
+    let response = app.get("/info/no-such-code").await;
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let body = json_body(response).await;
+    assert_eq!(body["code"], "URL_NOT_FOUND");
+    assert_eq!(body["error"], "URL not found");
+}
+
+#[tokio::test]
+async fn code_patterns_match_what_validation_accepts() {
+    let app = spawn_app().await;
+    let docs = json_body(app.get("/api-docs/openapi.json").await).await;
+    let schemas = &docs["components"]["schemas"];
+
+    assert_eq!(schemas["CreateUrlRequest"]["properties"]["custom_code"]["pattern"], "^[A-Za-z0-9_-]{3,32}$");
+    assert_eq!(schemas["LinkExport"]["properties"]["short_code"]["pattern"], "^[A-Za-z0-9_~-]{1,64}$");
+    for (code, status) in [
+        ("ab".to_string(), StatusCode::UNPROCESSABLE_ENTITY),
+        ("a-b_32".to_string(), StatusCode::CREATED),
+        ("c".repeat(32), StatusCode::CREATED),
+        ("c".repeat(33), StatusCode::UNPROCESSABLE_ENTITY),
+    ] {
+        let response = app
+            .post_json("/shorten", serde_json::json!({ "url": "https://example.com/", "custom_code": &code }))
+            .await;
+        assert_eq!(response.status(), status, "{}", code);
+    }
+}
@@ -0,0 +1,97 @@
+mod common;
+
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+};
+use common::{json_body, spawn_app_with, TestApp};
+use serde_json::{json, Value};
+use short_url_service::utils::visitor_hash;
+
+const ADMIN_KEY: &str = "admin-key";
+const SUBJECT: &str = "198.51.100.23";
+
+async fn erase(app: &TestApp, body: Value) -> (StatusCode, Value) {
+    let request = Request::post("/admin/privacy/erase")
+        .header("x-api-key", ADMIN_KEY)
+        .header("content-type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap();
+    let response = app.send(request).await;
+    (response.status(), json_body(response).await)
+}
+
+async fn insert_visit(app: &TestApp, ip: Option<&str>, user_agent: Option<&str>, hash: &str) {
+    sqlx::query("INSERT INTO visits (url_id, ip_address, user_agent, visitor_hash, visited_at) VALUES ('promo', ?, ?, ?, CURRENT_TIMESTAMP)")
+        .bind(ip)
+        .bind(user_agent)
+        .bind(hash)
+        .execute(&app.state.pool)
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn erasure_clears_every_form_of_the_address() {
+    let app = spawn_app_with(&[("ADMIN_API_KEYS", ADMIN_KEY)]).await;
+    app.shorten(json!({ "url": "https://example.com/", "custom_code": "promo" })).await;
+    let salt = app.state.visitor_salt.clone();
+    let hash = visitor_hash(&salt, SUBJECT, Some("Firefox/120"));
+
+    insert_visit(&app, Some(SUBJECT), Some("Firefox/120"), &hash).await;
+    insert_visit(&app, Some(SUBJECT), None, &visitor_hash(&salt, SUBJECT, None)).await;
+    insert_visit(&app, Some("::ffff:198.51.100.23"), Some("curl/8"), "unrelated-hash").await;
+    // Already stripped by retention; only the hash is left
+    insert_visit(&app, None, None, &hash).await;
+    // Stored in anonymized mode
+    insert_visit(&app, Some("198.51.100.0"), Some("Firefox"), "other-hash").await;
+    insert_visit(&app, Some("192.0.2.1"), Some("Firefox/120"), "bystander").await;
+    for visitor in [hash.as_str(), "bystander"] {
+        sqlx::query("INSERT INTO webhook_failures (url_id, webhook_url, payload, attempts, last_error, failed_at) VALUES ('promo', 'https://hooks.example.com/', ?, 3, 'timeout', CURRENT_TIMESTAMP)")
+            .bind(json!({ "event": "visit", "visitor_hash": visitor }).to_string())
+            .execute(&app.state.pool)
+            .await
+            .unwrap();
+    }
+
+    let (status, body) = erase(&app, json!({ "ip_address": SUBJECT })).await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, json!({ "visits": 5, "webhook_failures": 1 }));
+    let left: Vec<(Option<String>, Option<String>, Option<String>)> =
+        sqlx::query_as("SELECT ip_address, user_agent, visitor_hash FROM visits WHERE ip_address IS NOT NULL OR visitor_hash IS NOT NULL")
+            .fetch_all(&app.state.pool)
+            .await
+            .unwrap();
+    assert_eq!(
+        left,
+        [(Some("192.0.2.1".to_string()), Some("Firefox/120".to_string()), Some("bystander".to_string()))]
+    );
+    // The rows themselves stay, so visit counts don't change
+    let rows: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM visits").fetch_one(&app.state.pool).await.unwrap();
+    assert_eq!(rows, 6);
+
+    let (actor, action, after): (String, String, String) =
+        sqlx::query_as("SELECT actor, action, after_json FROM audit_log WHERE action = 'erase'")
+            .fetch_one(&app.state.pool)
+            .await
+            .unwrap();
+    assert!(actor.starts_with("key:") && !actor.contains(ADMIN_KEY));
+    assert_eq!(action, "erase");
+    assert!(!after.contains("198.51.100"));
+}
+
+#[tokio::test]
+async fn erasure_needs_an_admin_key_and_an_address() {
+    let app = spawn_app_with(&[("ADMIN_API_KEYS", ADMIN_KEY)]).await;
+
+    let (status, body) = erase(&app, json!({ "ip_address": "not-an-ip" })).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["code"], "BAD_REQUEST");
+
+    let request = Request::post("/admin/privacy/erase")
+        .header("content-type", "application/json")
+        .body(Body::from(json!({ "ip_address": SUBJECT }).to_string()))
+        .unwrap();
+    assert_eq!(app.send(request).await.status(), StatusCode::UNAUTHORIZED);
+}
@@ -0,0 +1,195 @@
+mod common;
+
+use axum::{
+    body::Body,
+    http::{header, Method, Request, StatusCode},
+};
+use chrono::{Duration, Utc};
+use common::{json_body, spawn_app, TestApp};
+use serde_json::json;
+use short_url_service::utils::{detect_platform, merge_query, Platform};
+
+#[test]
+fn merging_nothing_keeps_the_destination() {
+    for query in ["", "?"] {
+        assert_eq!(merge_query("https://example.com/p?a=1#top", query), "https://example.com/p?a=1#top");
+    }
+    assert_eq!(merge_query("https://example.com/", "&&"), "https://example.com/");
+}
+
+#[test]
+fn request_params_are_appended_in_order() {
+    assert_eq!(
+        merge_query("https://example.com/p", "utm_source=x&utm_medium=y"),
+        "https://example.com/p?utm_source=x&utm_medium=y"
+    );
+    assert_eq!(
+        merge_query("https://example.com/p?page=2", "?utm_source=x"),
+        "https://example.com/p?page=2&utm_source=x"
+    );
+}
+
+#[test]
+fn request_params_win_on_duplicate_keys() {
+    assert_eq!(
+        merge_query("https://example.com/p?utm_source=site&page=2&utm_source=again", "utm_source=x"),
+        "https://example.com/p?page=2&utm_source=x"
+    );
+    // Repeats within the request all survive, as do bare keys
+    assert_eq!(merge_query("https://example.com/p?tag=a&debug=1", "tag=b&tag=c&debug"), "https://example.com/p?tag=b&tag=c&debug");
+    // Keys match however they were encoded
+    assert_eq!(merge_query("https://example.com/p?utm%5Fsource=site", "utm_source=x"), "https://example.com/p?utm_source=x");
+}
+
+#[test]
+fn fragments_stay_at_the_end() {
+    assert_eq!(merge_query("https://example.com/p#section", "a=1"), "https://example.com/p?a=1#section");
+    assert_eq!(merge_query("https://example.com/p?a=1#s?x=1", "b=2"), "https://example.com/p?a=1&b=2#s?x=1");
+}
+
+#[test]
+fn percent_encoding_is_passed_through() {
+    assert_eq!(
+        merge_query("https://example.com/p?next=%2Fhome%3Fa%3D1", "q=caf%C3%A9&x=a%26b+c"),
+        "https://example.com/p?next=%2Fhome%3Fa%3D1&q=caf%C3%A9&x=a%26b+c"
+    );
+}
+
+#[test]
+fn platforms_are_detected_from_the_user_agent() {
+    let cases = [
+        ("Mozilla/5.0 (iPhone; CPU iPhone OS 17_1 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.1 Mobile/15E148 Safari/604.1", Platform::Ios),
+        ("Mozilla/5.0 (iPad; CPU OS 16_6 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) CriOS/119.0.6045.109 Mobile/15E148 Safari/604.1", Platform::Ios),
+        ("Mozilla/5.0 (iPod touch; CPU iPhone OS 15_7 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Mobile/15E148", Platform::Ios),
+        // iPads asking for the desktop site look like a Mac
+        ("Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.1 Safari/605.1.15", Platform::Other),
+        ("Mozilla/5.0 (Linux; Android 14; Pixel 8) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/119.0.0.0 Mobile Safari/537.36", Platform::Android),
+        ("Mozilla/5.0 (Linux; Android 13; SM-X700) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/119.0.0.0 Safari/537.36", Platform::Android),
+        ("Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/119.0.0.0 Safari/537.36", Platform::Other),
+        ("Mozilla/5.0 (X11; Linux x86_64; rv:120.0) Gecko/20100101 Firefox/120.0", Platform::Other),
+        ("Mozilla/5.0 (Windows Phone 10.0; Android 6.0.1; Microsoft; Lumia 950) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/52.0.2743.116 Mobile Safari/537.36 Edge/15.15063", Platform::Other),
+        ("curl/8.4.0", Platform::Other),
+        ("", Platform::Other),
+    ];
+    for (user_agent, platform) in cases {
+        assert_eq!(detect_platform(Some(user_agent)), platform, "{}", user_agent);
+    }
+    assert_eq!(detect_platform(None), Platform::Other);
+}
+
+#[tokio::test]
+async fn redirects_forward_the_query_unless_turned_off() {
+    let app = spawn_app().await;
+    app.shorten(json!({ "url": "https://example.com/p?ref=link#top", "custom_code": "campaign" }))
+        .await;
+    app.shorten(json!({ "url": "https://example.com/p?ref=link", "custom_code": "strict", "forward_query": false }))
+        .await;
+
+    let forwarded = app.get("/campaign?ref=mail&utm_source=x").await;
+    assert_eq!(forwarded.status(), StatusCode::TEMPORARY_REDIRECT);
+    assert_eq!(forwarded.headers()[header::LOCATION], "https://example.com/p?ref=mail&utm_source=x#top");
+    let kept = app.get("/strict?utm_source=x").await;
+    assert_eq!(kept.headers()[header::LOCATION], "https://example.com/p?ref=link");
+}
+
+#[tokio::test]
+async fn non_ascii_destinations_redirect_percent_encoded() {
+    let app = spawn_app().await;
+    let link = app
+        .shorten(json!({ "url": "https://example.com/café?q=ü", "custom_code": "cafe", "ios_url": "https://apps.apple.com/app/café" }))
+        .await;
+    assert_eq!(link["original_url"], "https://example.com/caf%C3%A9?q=%C3%BC");
+    let response = app.get("/cafe").await;
+    assert_eq!(response.status(), StatusCode::TEMPORARY_REDIRECT);
+    assert_eq!(response.headers()[header::LOCATION], "https://example.com/caf%C3%A9?q=%C3%BC");
+    let iphone = Request::get("/cafe")
+        .header(header::USER_AGENT, "Mozilla/5.0 (iPhone; CPU iPhone OS 17_0 like Mac OS X)")
+        .body(Body::empty())
+        .unwrap();
+    assert_eq!(app.send(iphone).await.headers()[header::LOCATION], "https://apps.apple.com/app/caf%C3%A9");
+
+    let token = link["delete_token"].as_str().unwrap();
+    let status = change(&app, Method::PATCH, "cafe", token, json!({ "url": "https://example.com/naïve" })).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(app.get("/cafe").await.headers()[header::LOCATION], "https://example.com/na%C3%AFve");
+
+    app.shorten(json!({
+        "destinations": [{ "url": "https://example.com/über", "weight": 1 }, { "url": "https://example.org/über", "weight": 1 }],
+        "custom_code": "rotating"
+    }))
+    .await;
+    let response = app.get("/rotating").await;
+    let location = response.headers()[header::LOCATION].to_str().unwrap();
+    assert!(location.ends_with("/%C3%BCber"), "{}", location);
+}
+
+// Creates `code` and answers one redirect, so the cache holds its record
+async fn cached_link(app: &TestApp, code: &str) -> String {
+    let link = app
+        .shorten(json!({ "url": "https://example.com/before", "custom_code": code }))
+        .await;
+    let response = app.get(&format!("/{}", code)).await;
+    assert_eq!(response.headers()[header::LOCATION], "https://example.com/before");
+    link["delete_token"].as_str().unwrap().to_string()
+}
+
+async fn change(app: &TestApp, method: Method, code: &str, token: &str, body: serde_json::Value) -> StatusCode {
+    let uri = match method {
+        Method::DELETE => format!("/{}", code),
+        _ => format!("/urls/{}", code),
+    };
+    let request = Request::builder()
+        .method(method)
+        .uri(uri)
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(header::AUTHORIZATION, format!("Bearer {}", token))
+        .body(Body::from(body.to_string()))
+        .unwrap();
+    app.send(request).await.status()
+}
+
+#[tokio::test]
+async fn redirects_are_served_from_the_cache() {
+    let app = spawn_app().await;
+    cached_link(&app, "warm").await;
+    // Written behind the service's back, so only a cache miss would see it
+    sqlx::query("UPDATE urls SET original_url = 'https://example.com/after' WHERE id = 'warm'")
+        .execute(&app.state.pool)
+        .await
+        .unwrap();
+
+    let response = app.get("/warm").await;
+    assert_eq!(response.headers()[header::LOCATION], "https://example.com/before");
+}
+
+#[tokio::test]
+async fn changing_a_link_replaces_its_cached_redirect() {
+    let app = spawn_app().await;
+
+    let token = cached_link(&app, "updated").await;
+    let status = change(&app, Method::PATCH, "updated", &token, json!({ "url": "https://example.com/after" })).await;
+    assert_eq!(status, StatusCode::OK);
+    let response = app.get("/updated").await;
+    assert_eq!(response.status(), StatusCode::TEMPORARY_REDIRECT);
+    assert_eq!(response.headers()[header::LOCATION], "https://example.com/after");
+
+    let token = cached_link(&app, "paused").await;
+    assert_eq!(change(&app, Method::PATCH, "paused", &token, json!({ "active": false })).await, StatusCode::OK);
+    let response = app.get("/paused").await;
+    assert_eq!(response.status(), StatusCode::GONE);
+    assert_eq!(json_body(response).await["code"], "LINK_DISABLED");
+    // And back again
+    assert_eq!(change(&app, Method::PATCH, "paused", &token, json!({ "active": true })).await, StatusCode::OK);
+    assert_eq!(app.get("/paused").await.status(), StatusCode::TEMPORARY_REDIRECT);
+
+    let token = cached_link(&app, "deleted").await;
+    assert_eq!(change(&app, Method::DELETE, "deleted", &token, json!({})).await, StatusCode::NO_CONTENT);
+    assert_eq!(app.get("/deleted").await.status(), StatusCode::NOT_FOUND);
+
+    let token = cached_link(&app, "expired").await;
+    let expires_at = Utc::now() - Duration::seconds(1);
+    assert_eq!(change(&app, Method::PATCH, "expired", &token, json!({ "expires_at": expires_at })).await, StatusCode::OK);
+    let response = app.get("/expired").await;
+    assert_eq!(response.status(), StatusCode::GONE);
+    assert_eq!(json_body(response).await["code"], "URL_EXPIRED");
+}
@@ -0,0 +1,35 @@
+mod common;
+
+use chrono::{Duration, Utc};
+use common::spawn_app;
+use serde_json::json;
+use short_url_service::retention::scrub_visits;
+
+#[tokio::test]
+async fn scrub_clears_visitor_details_past_retention() {
+    let app = spawn_app().await;
+    let link = app
+        .shorten(json!({ "url": "https://example.com/", "custom_code": "kept" }))
+        .await;
+    app.get("/kept").await;
+    app.get("/kept").await;
+    app.stats_after_visits(&link, 2).await;
+    // Age one of the two visits past the retention window
+    sqlx::query("UPDATE visits SET visited_at = ? WHERE id = (SELECT MIN(id) FROM visits)")
+        .bind(Utc::now() - Duration::days(31))
+        .execute(&app.state.pool)
+        .await
+        .unwrap();
+
+    let scrubbed = scrub_visits(&app.state.pool, std::time::Duration::from_secs(30 * 24 * 3600))
+        .await
+        .unwrap();
+
+    assert_eq!(scrubbed, 1);
+    let stats = app.stats_after_visits(&link, 2).await;
+    let visits = stats["visits"].as_array().unwrap();
+    let scrubbed: Vec<_> = visits.iter().filter(|visit| visit["ip_address"].is_null()).collect();
+    assert_eq!(scrubbed.len(), 1);
+    assert!(scrubbed[0]["user_agent"].is_null());
+    assert_eq!(scrubbed[0]["source"], "redirect");
+}
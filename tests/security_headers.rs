@@ -0,0 +1,69 @@
+mod common;
+
+use axum::{
+    body::Body,
+    http::{header, HeaderMap, Request, StatusCode},
+};
+use common::{spawn_app, spawn_app_with};
+use serde_json::json;
+
+fn assert_hardened(headers: &HeaderMap) {
+    assert_eq!(headers[header::X_CONTENT_TYPE_OPTIONS], "nosniff");
+    assert_eq!(headers[header::X_FRAME_OPTIONS], "DENY");
+}
+
+#[tokio::test]
+async fn api_redirect_and_static_responses_carry_the_headers() {
+    let app = spawn_app().await;
+    app.shorten(json!({ "url": "https://example.com/", "custom_code": "safe" })).await;
+
+    let api = app.get("/urls/safe").await;
+    assert_hardened(api.headers());
+    assert!(api.headers().get(header::CONTENT_SECURITY_POLICY).is_none());
+
+    let redirect = app.send(Request::get("/safe").body(Body::empty()).unwrap()).await;
+    assert!(redirect.status().is_redirection());
+    assert_hardened(redirect.headers());
+
+    // The not-found page a browser gets for an unknown code is HTML too
+    for page in ["/", "/index.html", "/nope"] {
+        let response = app.send(Request::get(page).header(header::ACCEPT, "text/html").body(Body::empty()).unwrap()).await;
+        assert_hardened(response.headers());
+        let csp = response.headers()[header::CONTENT_SECURITY_POLICY].to_str().unwrap();
+        assert!(csp.contains("default-src 'self'"), "{}", page);
+    }
+    // Plain http, so no HSTS
+    assert!(api.headers().get(header::STRICT_TRANSPORT_SECURITY).is_none());
+}
+
+#[tokio::test]
+async fn qr_codes_can_be_framed() {
+    let app = spawn_app().await;
+    app.shorten(json!({ "url": "https://example.com/", "custom_code": "framed" })).await;
+
+    let response = app.get("/qr/framed").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[header::X_CONTENT_TYPE_OPTIONS], "nosniff");
+    assert!(response.headers().get(header::X_FRAME_OPTIONS).is_none());
+
+    // Narrowing the exemption list puts the header back
+    let app = spawn_app_with(&[("FRAME_EMBEDDABLE_ROUTES", "/{code}")]).await;
+    app.shorten(json!({ "url": "https://example.com/", "custom_code": "framed" })).await;
+    assert_eq!(app.get("/qr/framed").await.headers()[header::X_FRAME_OPTIONS], "DENY");
+}
+
+#[tokio::test]
+async fn https_deployments_send_hsts_and_configured_values() {
+    let app = spawn_app_with(&[
+        ("BASE_URL", "https://sho.rt"),
+        ("STRICT_TRANSPORT_SECURITY", "max-age=600"),
+        ("X_FRAME_OPTIONS", "sameorigin"),
+        ("CONTENT_SECURITY_POLICY", "default-src 'none'"),
+    ])
+    .await;
+
+    let response = app.get("/").await;
+    assert_eq!(response.headers()[header::STRICT_TRANSPORT_SECURITY], "max-age=600");
+    assert_eq!(response.headers()[header::X_FRAME_OPTIONS], "SAMEORIGIN");
+    assert_eq!(response.headers()[header::CONTENT_SECURITY_POLICY], "default-src 'none'");
+}
@@ -0,0 +1,42 @@
+mod common;
+
+use common::{json_body, spawn_app_with, spawn_seeded_app};
+use serde_json::json;
+use short_url_service::seed;
+
+#[tokio::test]
+async fn sample_links_redirect_and_have_stats() {
+    let app = spawn_seeded_app().await;
+
+    // Public, so no stats token is needed; one in six sample visitors is a bot
+    let stats = json_body(app.get("/stats/rust?days=14").await).await;
+    assert_eq!(stats["bot_visits"], 10);
+    assert_eq!(stats["total_visits"], seed::MAX_VISITS as i64 - 10);
+    let days = stats["daily_visits"].as_array().unwrap();
+    assert!(days.iter().filter(|day| day["count"] != 0).count() > 7, "{:?}", days);
+    assert!(stats["countries"].as_array().is_some_and(|countries| !countries.is_empty()));
+
+    let stats = json_body(app.get("/stats/sqlite").await).await;
+    assert_eq!(stats["total_visits"].as_i64().unwrap() + stats["bot_visits"].as_i64().unwrap(), 12);
+
+    for (code, url, _) in seed::LINKS {
+        let response = app.get(&format!("/{}", code)).await;
+        assert!(response.status().is_redirection(), "{}", code);
+        assert_eq!(response.headers()["location"], *url);
+    }
+}
+
+#[tokio::test]
+async fn dev_mode_pools_connections_over_one_memory_database() {
+    // DATABASE_URL is ignored, and several connections see the seeded rows
+    let app = spawn_app_with(&[("DEV_MODE", "true"), ("DATABASE_MAX_CONNECTIONS", "4")]).await;
+    assert!(app.state.config.dev_mode);
+    seed::run(&app.state).await.unwrap();
+
+    let uris: Vec<String> = seed::LINKS.iter().map(|(code, _, _)| format!("/stats/{}/summary", code)).collect();
+    for response in futures_util::future::join_all(uris.iter().map(|uri| app.get(uri))).await {
+        assert_eq!(response.status(), 200);
+    }
+    let link = app.shorten(json!({ "url": "https://example.com/", "custom_code": "fresh" })).await;
+    assert_eq!(link["short_code"], "fresh");
+}
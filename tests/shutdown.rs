@@ -0,0 +1,127 @@
+#![cfg(unix)]
+
+mod common;
+
+use common::TempDb;
+use serde_json::{json, Value};
+use std::{process::Stdio, time::Duration};
+use tokio::{
+    io::{AsyncBufReadExt, BufReader, Lines},
+    process::{Child, ChildStdout, Command},
+};
+
+const VISITS: usize = 25;
+
+// The real binary on `db`, with visits only written when it stops
+struct Server {
+    child: Child,
+    logs: Lines<BufReader<ChildStdout>>,
+    base: String,
+}
+
+impl Server {
+    async fn start(db: &TempDb) -> Self {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_short-url-service"))
+            .arg("serve")
+            .env("DATABASE_URL", db.url())
+            .env("LISTEN_ADDR", "127.0.0.1:0")
+            .env("BASE_URL", "http://sho.rt")
+            .env("VISITOR_HASH_SALT", "test-salt")
+            .env("ALLOWED_PRIVATE_DESTINATIONS", "example.com")
+            .env("VISIT_FLUSH_INTERVAL_MS", "3600000")
+            .env("VISIT_BATCH_SIZE", "10000")
+            .env("PURGE_EXPIRED", "false")
+            .env("LOG_FORMAT", "json")
+            .env("RUST_LOG", "info")
+            .stdout(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .unwrap();
+        let mut logs = BufReader::new(child.stdout.take().unwrap()).lines();
+        let addr = loop {
+            let message = next_message(&mut logs).await.expect("server logs where it listens");
+            if let Some(addr) = message.strip_prefix("listening on ") {
+                break addr.to_string();
+            }
+        };
+        Server { child, logs, base: format!("http://{}", addr) }
+    }
+
+    // Sends SIGTERM, as a container runtime would, and returns the messages
+    // logged until the process exited
+    async fn terminate(mut self) -> Vec<String> {
+        let pid = self.child.id().unwrap().to_string();
+        let sent = Command::new("kill").args(["-TERM", &pid]).status().await.unwrap();
+        assert!(sent.success());
+        let mut messages = Vec::new();
+        while let Some(message) = next_message(&mut self.logs).await {
+            messages.push(message);
+        }
+        let status = tokio::time::timeout(Duration::from_secs(10), self.child.wait())
+            .await
+            .expect("server stops after SIGTERM")
+            .unwrap();
+        assert!(status.success(), "{:?}: {:?}", status, messages);
+        messages
+    }
+}
+
+async fn next_message(logs: &mut Lines<BufReader<ChildStdout>>) -> Option<String> {
+    let line = tokio::time::timeout(Duration::from_secs(10), logs.next_line())
+        .await
+        .expect("server keeps logging")
+        .unwrap()?;
+    let line: Value = serde_json::from_str(&line).unwrap_or_else(|e| panic!("{}: {}", e, line));
+    Some(line["fields"]["message"].as_str().unwrap_or_default().to_string())
+}
+
+async fn json_body(request: reqwest::RequestBuilder) -> Value {
+    let body = request.send().await.unwrap().bytes().await.unwrap();
+    serde_json::from_slice(&body).expect("body is JSON")
+}
+
+#[tokio::test]
+async fn sigterm_writes_queued_visits_before_exiting() {
+    let db = TempDb::new();
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .build()
+        .unwrap();
+
+    let server = Server::start(&db).await;
+    let link = json_body(
+        client
+            .post(format!("{}/shorten", server.base))
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(json!({ "url": "https://example.com/", "custom_code": "kept" }).to_string()),
+    )
+    .await;
+    for _ in 0..VISITS {
+        let response = client.get(format!("{}/kept", server.base)).send().await.unwrap();
+        assert_eq!(response.status(), reqwest::StatusCode::TEMPORARY_REDIRECT);
+    }
+
+    // The signal stops the server first, then the queue is written out
+    let messages = server.terminate().await;
+    let position = |wanted: &str| {
+        messages
+            .iter()
+            .position(|message| message == wanted)
+            .unwrap_or_else(|| panic!("'{}' not logged: {:?}", wanted, messages))
+    };
+    let received = position("shutdown signal received, draining in-flight requests");
+    let flushed = position(&format!("flushed {} pending visits", VISITS));
+    let complete = position("shutdown complete");
+    assert!(received < flushed && flushed < complete, "{:?}", messages);
+
+    // Nothing was lost across the restart
+    let server = Server::start(&db).await;
+    let stats = json_body(
+        client
+            .get(format!("{}/stats/kept", server.base))
+            .header("x-stats-token", link["stats_token"].as_str().unwrap()),
+    )
+    .await;
+    assert_eq!(stats["total_visits"], VISITS);
+    server.terminate().await;
+}
@@ -0,0 +1,161 @@
+mod common;
+
+use axum::http::StatusCode;
+use common::{spawn_app_with, TempDb};
+use serde_json::json;
+use std::sync::Arc;
+
+const LINKS: usize = 5;
+const ROUNDS: usize = 40;
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn concurrent_redirects_and_shortens_never_fail() {
+    let db = TempDb::new();
+    let url = db.url();
+    let app = Arc::new(spawn_app_with(&[
+        ("DATABASE_URL", &url),
+        ("DATABASE_MAX_CONNECTIONS", "5"),
+        // Every redirect has to read the database and every visit is its own write
+        ("REDIRECT_CACHE_CAPACITY", "0"),
+        ("VISIT_BATCH_SIZE", "1"),
+    ])
+    .await);
+
+    let mode: String = sqlx::query_scalar("PRAGMA journal_mode")
+        .fetch_one(&app.state.pool)
+        .await
+        .unwrap();
+    assert_eq!(mode, "wal");
+
+    let mut links = Vec::new();
+    for i in 0..LINKS {
+        links.push(
+            app.shorten(json!({ "url": format!("https://example.com/{}", i), "custom_code": format!("busy-{}", i) }))
+                .await,
+        );
+    }
+
+    let mut tasks = Vec::new();
+    for round in 0..ROUNDS {
+        for i in 0..LINKS {
+            let app = app.clone();
+            tasks.push(tokio::spawn(async move {
+                let response = app.get(&format!("/busy-{}", i)).await;
+                assert_eq!(response.status(), StatusCode::TEMPORARY_REDIRECT);
+            }));
+        }
+        let app = app.clone();
+        tasks.push(tokio::spawn(async move {
+            let response = app
+                .post_json("/shorten", json!({ "url": format!("https://example.org/{}", round) }))
+                .await;
+            assert_eq!(response.status(), StatusCode::CREATED);
+        }));
+    }
+    for task in tasks {
+        task.await.expect("no request failed");
+    }
+
+    let total = LINKS * ROUNDS;
+    let mut recorded = 0;
+    for link in &links {
+        let stats = app.stats_after_visits(link, ROUNDS as i64).await;
+        recorded += stats["total_visits"].as_i64().unwrap() as usize;
+    }
+    assert_eq!(recorded, total);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn parallel_redirects_are_all_counted() {
+    let db = TempDb::new();
+    let url = db.url();
+    let app = Arc::new(spawn_app_with(&[
+        ("DATABASE_URL", &url),
+        ("DATABASE_MAX_CONNECTIONS", "5"),
+        ("VISIT_BATCH_SIZE", "7"),
+    ])
+    .await);
+    let link = app
+        .shorten(json!({ "url": "https://example.com/", "custom_code": "popular" }))
+        .await;
+
+    let tasks: Vec<_> = (0..100)
+        .map(|_| {
+            let app = app.clone();
+            tokio::spawn(async move { app.get("/popular").await.status() })
+        })
+        .collect();
+    for task in tasks {
+        assert_eq!(task.await.unwrap(), StatusCode::TEMPORARY_REDIRECT);
+    }
+
+    app.stats_after_visits(&link, 100).await;
+    let (counter, rows): (i64, i64) = sqlx::query_as(
+        "SELECT total_visits, (SELECT COUNT(*) FROM visits WHERE url_id = urls.id) FROM urls WHERE id = 'popular'",
+    )
+    .fetch_one(&app.state.pool)
+    .await
+    .unwrap();
+    assert_eq!(counter, 100);
+    assert_eq!(rows, 100);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn racing_requests_for_one_custom_code_get_one_link() {
+    let db = TempDb::new();
+    let url = db.url();
+    let app = Arc::new(spawn_app_with(&[("DATABASE_URL", &url), ("DATABASE_MAX_CONNECTIONS", "5")]).await);
+
+    let tasks: Vec<_> = (0..20)
+        .map(|i| {
+            let app = app.clone();
+            tokio::spawn(async move {
+                let response = app
+                    .post_json("/shorten", json!({ "url": format!("https://example.com/{}", i), "custom_code": "contested" }))
+                    .await;
+                (response.status(), common::json_body(response).await)
+            })
+        })
+        .collect();
+    let mut created = 0;
+    for task in tasks {
+        let (status, body) = task.await.unwrap();
+        match status {
+            StatusCode::CREATED => created += 1,
+            StatusCode::CONFLICT => assert_eq!(body["code"], "CODE_TAKEN", "{}", body),
+            other => panic!("unexpected {}: {}", other, body),
+        }
+    }
+    assert_eq!(created, 1);
+    let stored: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM urls WHERE id = 'contested'")
+        .fetch_one(&app.state.pool)
+        .await
+        .unwrap();
+    assert_eq!(stored, 1);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn racing_redirects_on_a_single_use_link_let_one_through() {
+    let db = TempDb::new();
+    let url = db.url();
+    let app = Arc::new(spawn_app_with(&[("DATABASE_URL", &url), ("DATABASE_MAX_CONNECTIONS", "5")]).await);
+
+    for round in 0..ROUNDS {
+        let code = format!("once-{}", round);
+        app.shorten(json!({ "url": "https://example.com/", "custom_code": code, "max_visits": 1 }))
+            .await;
+        let tasks: Vec<_> = (0..2)
+            .map(|_| {
+                let app = app.clone();
+                let path = format!("/{}", code);
+                tokio::spawn(async move { app.get(&path).await.status() })
+            })
+            .collect();
+        let mut statuses = Vec::new();
+        for task in tasks {
+            statuses.push(task.await.unwrap());
+        }
+        statuses.sort();
+        assert_eq!(statuses, [StatusCode::TEMPORARY_REDIRECT, StatusCode::GONE], "{}", code);
+    }
+}
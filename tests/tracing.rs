@@ -0,0 +1,93 @@
+mod common;
+
+use common::spawn_app;
+use opentelemetry::{trace::Status, Value};
+use opentelemetry_sdk::trace::{InMemorySpanExporter, SdkTracerProvider, SpanData};
+use serde_json::json;
+use std::time::Duration;
+use short_url_service::otel;
+use tracing_subscriber::layer::SubscriberExt;
+
+fn attribute<'a>(span: &'a SpanData, key: &str) -> Option<&'a Value> {
+    span.attributes
+        .iter()
+        .find(|attribute| attribute.key.as_str() == key)
+        .map(|attribute| &attribute.value)
+}
+
+fn find<'a>(spans: &'a [SpanData], name: &str) -> &'a SpanData {
+    spans
+        .iter()
+        .find(|span| span.name == name)
+        .unwrap_or_else(|| panic!("no {} span", name))
+}
+
+// SQLite's connection worker can hold on to the span of the last command it
+// ran, so the last request can close a moment after its response is back.
+async fn spans_until(exporter: &InMemorySpanExporter, name: &str) -> Vec<SpanData> {
+    for _ in 0..100 {
+        let spans = exporter.get_finished_spans().unwrap();
+        if spans.iter().any(|span| span.name == name) {
+            return spans;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    panic!("no {} span", name);
+}
+
+#[tokio::test]
+async fn requests_handlers_and_queries_are_exported() {
+    let exporter = InMemorySpanExporter::default();
+    // Exported as each span ends; a batch could still be in flight otherwise
+    let provider = SdkTracerProvider::builder()
+        .with_simple_exporter(exporter.clone())
+        .build();
+    let subscriber = tracing_subscriber::registry().with(otel::layer(&provider));
+    // Global, as in main: query spans can close on SQLite's worker thread,
+    // which a thread-local default wouldn't reach. This file has one test
+    // so nothing else shares it.
+    tracing::subscriber::set_global_default(subscriber).unwrap();
+
+    let app = spawn_app().await;
+    app.shorten(json!({ "url": "https://example.com/", "custom_code": "traced" }))
+        .await;
+    // The failing request goes first: the last one can still be open when
+    // its response arrives
+    sqlx::query("ALTER TABLE urls RENAME TO urls_gone")
+        .execute(&app.state.pool)
+        .await
+        .unwrap();
+    app.get("/info/traced").await;
+    sqlx::query("ALTER TABLE urls_gone RENAME TO urls")
+        .execute(&app.state.pool)
+        .await
+        .unwrap();
+    app.get("/traced").await;
+    let spans = spans_until(&exporter, "GET /{code}").await;
+
+    let created = find(&spans, "shorten_url");
+    assert_eq!(attribute(created, "code"), Some(&Value::from("traced")));
+
+    // The redirect: request → handler → query, each tagged with the code
+    let request = find(&spans, "GET /{code}");
+    assert_eq!(attribute(request, "http.response.status_code"), Some(&Value::I64(307)));
+    let handler = find(&spans, "redirect_url");
+    assert_eq!(handler.parent_span_id, request.span_context.span_id());
+    let lookup = spans
+        .iter()
+        .find(|span| span.name == "find_url" && span.parent_span_id == handler.span_context.span_id())
+        .unwrap();
+    assert_eq!(attribute(lookup, "code"), Some(&Value::from("traced")));
+    assert_eq!(attribute(lookup, "db.rows"), Some(&Value::I64(1)));
+    assert_eq!(lookup.status, Status::Unset);
+
+    // A failing query marks its span and the request as errors
+    let failed_request = find(&spans, "GET /info/{code}");
+    assert!(matches!(failed_request.status, Status::Error { .. }));
+    let failed_lookup = spans
+        .iter()
+        .filter(|span| span.name == "find_url")
+        .find(|span| matches!(span.status, Status::Error { .. }))
+        .unwrap();
+    assert!(attribute(failed_lookup, "db.rows").is_none());
+}
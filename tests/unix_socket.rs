@@ -0,0 +1,64 @@
+#![cfg(unix)]
+
+mod common;
+
+use common::spawn_app;
+use serde_json::json;
+use short_url_service::unix_socket;
+use std::{os::unix::fs::PermissionsExt, path::PathBuf};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{UnixListener, UnixStream},
+};
+
+fn socket_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("short-url-{}-{}.sock", name, std::process::id()))
+}
+
+// One HTTP/1.1 request, returning the raw response
+async fn request(path: &PathBuf, head: &str) -> String {
+    let mut stream = UnixStream::connect(path).await.unwrap();
+    stream
+        .write_all(format!("{}\r\nHost: sho.rt\r\nConnection: close\r\n\r\n", head).as_bytes())
+        .await
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    response
+}
+
+#[tokio::test]
+async fn serves_the_router_and_takes_client_ips_from_the_proxy() {
+    let path = socket_path("serve");
+    let (listener, file) = unix_socket::bind(&path, 0o600).await.unwrap();
+    assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+
+    let app = spawn_app().await;
+    let link = app.shorten(json!({ "url": "https://example.com/", "custom_code": "socket" })).await;
+    tokio::spawn(axum::serve(listener, app.router.clone().into_make_service()).into_future());
+
+    let response = request(&path, "GET /socket HTTP/1.1\r\nX-Forwarded-For: 198.51.100.4").await;
+    assert!(response.starts_with("HTTP/1.1 307"), "{}", response);
+    let stats = app.stats_after_visits(&link, 1).await;
+    assert_eq!(stats["visits"][0]["ip_address"], "198.51.100.4");
+
+    drop(file);
+    assert!(!path.exists());
+}
+
+#[tokio::test]
+async fn replaces_stale_sockets_but_not_live_ones() {
+    let path = socket_path("stale");
+    // Bound and dropped without cleanup, as after a crash
+    drop(UnixListener::bind(&path).unwrap());
+    let (_listener, file) = unix_socket::bind(&path, 0o660).await.unwrap();
+
+    let err = unix_socket::bind(&path, 0o660).await.unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::AddrInUse);
+    drop(file);
+
+    std::fs::write(&path, "not a socket").unwrap();
+    let err = unix_socket::bind(&path, 0o660).await.unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::AlreadyExists);
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,78 @@
+mod common;
+
+use chrono::Utc;
+use common::{spawn_app, ConnectionCounter};
+use serde_json::json;
+use short_url_service::{
+    db,
+    destination::DestinationPolicy,
+    utils::Platform,
+    visits::{NewVisit, VisitSource},
+    webhooks,
+};
+use std::{sync::Arc, time::Duration};
+
+fn visit(code: &str) -> NewVisit {
+    NewVisit {
+        url_id: code.to_string(),
+        ip_address: "203.0.113.7".into(),
+        user_agent: Some("curl/8.0".into()),
+        referrer: None,
+        visitor_hash: "0123456789abcdef".into(),
+        source: VisitSource::Redirect,
+        destination: None,
+        platform: Platform::Other,
+        visited_at: Utc::now(),
+        country: None,
+        is_bot: false,
+        detailed: true,
+    }
+}
+
+#[tokio::test]
+async fn deliveries_only_connect_to_addresses_the_policy_allows() {
+    let app = spawn_app().await;
+    // Nothing answers, so deliveries fail and retry
+    let counter = ConnectionCounter::spawn().await;
+    app.shorten(json!({ "url": "https://example.com/", "custom_code": "hooked", "webhook_url": "https://example.org/hook" }))
+        .await;
+    let mut link = db::find_url(&app.state.pool, "hooked").await.unwrap().unwrap();
+    link.webhook_url = Some(counter.url("/hook"));
+
+    webhooks::spawn_dispatcher(app.state.pool.clone(), app.state.destinations.clone()).visit(&link, &visit("hooked"));
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert_eq!(counter.connections(), 0);
+
+    // The same delivery does connect once the name is allowed
+    let allowing = Arc::new(DestinationPolicy::parse("localhost"));
+    webhooks::spawn_dispatcher(app.state.pool.clone(), allowing).visit(&link, &visit("hooked"));
+    counter.wait_for_connection().await;
+}
+
+#[tokio::test]
+async fn deliveries_waiting_to_retry_leave_room_for_others() {
+    let app = spawn_app().await;
+    let failing = ConnectionCounter::spawn().await;
+    let answered = ConnectionCounter::spawn().await;
+    app.shorten(json!({ "url": "https://example.com/", "custom_code": "hooked", "webhook_url": "https://example.org/hook" }))
+        .await;
+    let mut link = db::find_url(&app.state.pool, "hooked").await.unwrap().unwrap();
+    let dispatcher = webhooks::spawn_dispatcher(app.state.pool.clone(), Arc::new(DestinationPolicy::parse("localhost")));
+
+    // More failing deliveries than run at once, all now waiting out their first backoff
+    link.webhook_url = Some(failing.url("/hook"));
+    for _ in 0..16 {
+        dispatcher.visit(&link, &visit("hooked"));
+    }
+    for _ in 0..100 {
+        if failing.connections() >= 16 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert_eq!(failing.connections(), 16);
+
+    link.webhook_url = Some(answered.url("/hook"));
+    dispatcher.visit(&link, &visit("hooked"));
+    answered.wait_for_connection().await;
+}